@@ -0,0 +1,298 @@
+use crate::{
+    core::config::config_dir,
+    protocol::bencode::BencodeValue,
+    storage::resume::{Envelope, read_with_backup, write_atomic},
+};
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Format tag of the bandwidth log file
+pub const BANDWIDTH_LOG_FORMAT: &str = "sekiro-bandwidth";
+
+pub const BANDWIDTH_LOG_VERSION: i64 = 1;
+
+/// Dates are written as `YYYY-MM-DD` , in UTC
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Where the bandwidth log is kept between runs , None if there is no config directory
+pub fn default_bandwidth_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("bandwidth.dat"))
+}
+
+/// Bytes moved , for one torrent on one day or summed up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub uploaded: u64,
+    pub downloaded: u64,
+}
+
+impl Usage {
+    pub fn total(&self) -> u64 {
+        self.uploaded.saturating_add(self.downloaded)
+    }
+
+    fn add(&mut self, other: Usage) {
+        self.uploaded = self.uploaded.saturating_add(other.uploaded);
+        self.downloaded = self.downloaded.saturating_add(other.downloaded);
+    }
+}
+
+/// Traffic per torrent per day , for users on metered connections
+///
+/// Kept across runs in its own state file , torrents removed from the session stay in the log
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BandwidthLog {
+    days: BTreeMap<(NaiveDate, [u8; 20]), Usage>,
+    /// Torrent names by info hash , the log outlives the torrents
+    names: BTreeMap<[u8; 20], String>,
+}
+
+impl BandwidthLog {
+    /// Adds bytes moved by a torrent on `day`
+    pub fn record(&mut self, info_hash: [u8; 20], name: &str, day: NaiveDate, usage: Usage) {
+        if usage.total() == 0 {
+            return;
+        }
+        self.names.insert(info_hash, name.to_string());
+        self.days.entry((day, info_hash)).or_default().add(usage);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.days.is_empty()
+    }
+
+    /// Name the torrent had when it last moved data , the hex info hash if unknown
+    pub fn name(&self, info_hash: &[u8; 20]) -> String {
+        self.names
+            .get(info_hash)
+            .cloned()
+            .unwrap_or_else(|| hex::encode(info_hash))
+    }
+
+    /// Rows of the report for `grouping` , oldest day first
+    pub fn report(&self, grouping: ReportGrouping) -> Vec<UsageRow> {
+        let mut rows: BTreeMap<(Option<NaiveDate>, Option<[u8; 20]>), Usage> = BTreeMap::new();
+        for (&(day, info_hash), &usage) in &self.days {
+            let key = match grouping {
+                ReportGrouping::TorrentDay => (Some(day), Some(info_hash)),
+                ReportGrouping::Day => (Some(day), None),
+                ReportGrouping::Torrent => (None, Some(info_hash)),
+            };
+            rows.entry(key).or_default().add(usage);
+        }
+
+        rows.into_iter()
+            .map(|((day, info_hash), usage)| UsageRow {
+                day,
+                name: info_hash.map(|hash| self.name(&hash)),
+                info_hash,
+                usage,
+            })
+            .collect()
+    }
+
+    /// Everything ever recorded
+    pub fn total(&self) -> Usage {
+        let mut total = Usage::default();
+        for usage in self.days.values() {
+            total.add(*usage);
+        }
+        total
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let entries = self
+            .days
+            .iter()
+            .map(|((day, info_hash), usage)| {
+                let count =
+                    |bytes: u64| BencodeValue::Integer(i64::try_from(bytes).unwrap_or(i64::MAX));
+                BencodeValue::dict(vec![
+                    (
+                        b"day",
+                        BencodeValue::bytes(day.format(DATE_FORMAT).to_string().as_bytes()),
+                    ),
+                    (b"info_hash", BencodeValue::bytes(info_hash)),
+                    (b"uploaded", count(usage.uploaded)),
+                    (b"downloaded", count(usage.downloaded)),
+                ])
+            })
+            .collect();
+        let names = self
+            .names
+            .iter()
+            .map(|(info_hash, name)| {
+                BencodeValue::dict(vec![
+                    (b"info_hash", BencodeValue::bytes(info_hash)),
+                    (b"name", BencodeValue::bytes(name.as_bytes())),
+                ])
+            })
+            .collect();
+
+        let chunks = BencodeValue::dict(vec![
+            (b"days", BencodeValue::List(entries)),
+            (b"names", BencodeValue::List(names)),
+        ]);
+        let envelope = Envelope::new(BANDWIDTH_LOG_FORMAT, BANDWIDTH_LOG_VERSION, chunks);
+        write_atomic(path, &envelope.encode())
+    }
+
+    /// Reads the log , or its backup if the file is corrupt
+    pub fn load(path: &Path) -> Result<Self> {
+        read_with_backup(path, Self::decode).map(|read| read.value)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let envelope = Envelope::decode(bytes, BANDWIDTH_LOG_FORMAT)?;
+
+        if envelope.version != BANDWIDTH_LOG_VERSION {
+            return Err(anyhow!(
+                "Unsupported bandwidth log version {}",
+                envelope.version
+            ));
+        }
+
+        let list = |key: &[u8]| {
+            envelope
+                .chunks
+                .get(key)
+                .and_then(|v| v.as_list())
+                .cloned()
+                .unwrap_or_default()
+        };
+        let info_hash = |entry: &BencodeValue| -> Option<[u8; 20]> {
+            entry
+                .get(b"info_hash")?
+                .as_bytes()?
+                .as_ref()
+                .try_into()
+                .ok()
+        };
+
+        let mut log = Self::default();
+        // Entries that don't parse are skipped , the rest of the log is still worth having
+        for entry in list(b"days") {
+            let Some(info_hash) = info_hash(&entry) else {
+                continue;
+            };
+            let Some(day) = entry
+                .get(b"day")
+                .and_then(|v| v.as_bytes())
+                .and_then(|day| std::str::from_utf8(day).ok())
+                .and_then(|day| NaiveDate::parse_from_str(day, DATE_FORMAT).ok())
+            else {
+                continue;
+            };
+            let count = |key: &[u8]| {
+                entry
+                    .get(key)
+                    .and_then(|v| v.as_integer())
+                    .and_then(|n| u64::try_from(n).ok())
+                    .unwrap_or(0)
+            };
+            log.days.entry((day, info_hash)).or_default().add(Usage {
+                uploaded: count(b"uploaded"),
+                downloaded: count(b"downloaded"),
+            });
+        }
+        for entry in list(b"names") {
+            if let Some(info_hash) = info_hash(&entry)
+                && let Some(name) = entry.get(b"name").and_then(|v| v.as_bytes())
+            {
+                log.names
+                    .insert(info_hash, String::from_utf8_lossy(name).into_owned());
+            }
+        }
+
+        Ok(log)
+    }
+
+    /// Loads the saved log , or starts empty when there is none or it's unreadable
+    pub fn load_or_default(path: Option<&Path>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+
+        match Self::load(path) {
+            Ok(log) => log,
+            Err(e) => {
+                if path.exists() {
+                    crate::log_line!(
+                        "Could not load bandwidth log from {} : {}",
+                        path.display(),
+                        e
+                    );
+                }
+                Self::default()
+            }
+        }
+    }
+}
+
+/// What one row of a bandwidth report covers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportGrouping {
+    /// One row per torrent per day
+    #[default]
+    TorrentDay,
+    /// One row per day , all torrents summed
+    Day,
+    /// One row per torrent , all days summed
+    Torrent,
+}
+
+/// One row of a bandwidth report , fields a grouping sums over are None
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageRow {
+    pub day: Option<NaiveDate>,
+    pub info_hash: Option<[u8; 20]>,
+    pub name: Option<String>,
+    pub usage: Usage,
+}
+
+impl UsageRow {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "day": self.day.map(|day| day.format(DATE_FORMAT).to_string()),
+            "info_hash": self.info_hash.map(hex::encode),
+            "name": self.name,
+            "uploaded": self.usage.uploaded,
+            "downloaded": self.usage.downloaded,
+        })
+    }
+}
+
+/// Report as a JSON array , one object per row
+pub fn report_to_json(rows: &[UsageRow]) -> Value {
+    Value::Array(rows.iter().map(UsageRow::to_json).collect())
+}
+
+/// Report as CSV with a header line , columns a grouping sums over are left empty
+pub fn report_to_csv(rows: &[UsageRow]) -> String {
+    let mut csv = String::from("day,info_hash,name,uploaded,downloaded\n");
+    for row in rows {
+        let day = row
+            .day
+            .map(|day| day.format(DATE_FORMAT).to_string())
+            .unwrap_or_default();
+        let info_hash = row.info_hash.map(hex::encode).unwrap_or_default();
+        let name = row.name.as_deref().map(csv_field).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            day, info_hash, name, row.usage.uploaded, row.usage.downloaded
+        ));
+    }
+    csv
+}
+
+/// Quotes a field that holds a comma , quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
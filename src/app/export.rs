@@ -0,0 +1,197 @@
+use crate::{
+    protocol::bencode::BencodeValue,
+    storage::resume::{Envelope, write_atomic},
+};
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Format tag of session archives
+pub const SESSION_ARCHIVE_FORMAT: &str = "sekiro-export";
+
+pub const SESSION_ARCHIVE_VERSION: i64 = 1;
+
+/// A torrent as it sits in a session archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedTorrent {
+    /// The .torrent file
+    pub metainfo: Vec<u8>,
+    pub label: Option<String>,
+    pub queued: bool,
+    pub paused: bool,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// Download directory the torrent was added with , None for the session's
+    pub output_dir: Option<PathBuf>,
+    /// Resume file as it was on disk , None when the torrent had none
+    pub resume: Option<Vec<u8>>,
+}
+
+/// Everything needed to set a client up again on another machine , or to roll one back
+///
+/// Holds each torrent's metainfo , resume data and state plus the settings file as the user wrote
+/// it. Downloaded data isn't part of it , resume data picks that up wherever it's copied to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionArchive {
+    /// In session order , so queued torrents keep their place in line
+    pub torrents: Vec<ArchivedTorrent>,
+    /// Text of the settings file , None when there was none
+    pub settings: Option<String>,
+}
+
+/// What importing does with a torrent or settings file that's already there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnCollision {
+    /// What's there stays , the archived copy is skipped
+    #[default]
+    Keep,
+    /// The archived copy wins
+    Replace,
+}
+
+/// What `Session::import_state` did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Ids of torrents new to the session
+    pub added: Vec<usize>,
+    /// Ids of torrents already there whose state was replaced
+    pub replaced: Vec<usize>,
+    /// Names of torrents already there that were left alone
+    pub kept: Vec<String>,
+    /// Whether the settings file was written and applied
+    pub settings_applied: bool,
+}
+
+impl SessionArchive {
+    pub fn encode(&self) -> Vec<u8> {
+        let flag = |set: bool| BencodeValue::Integer(set as i64);
+        let count = |bytes: u64| BencodeValue::Integer(i64::try_from(bytes).unwrap_or(i64::MAX));
+
+        let torrents = self
+            .torrents
+            .iter()
+            .map(|torrent| {
+                let mut entries: Vec<(&[u8], BencodeValue)> = vec![
+                    (b"metainfo", BencodeValue::bytes(&torrent.metainfo)),
+                    (b"queued", flag(torrent.queued)),
+                    (b"paused", flag(torrent.paused)),
+                    (b"uploaded", count(torrent.uploaded)),
+                    (b"downloaded", count(torrent.downloaded)),
+                ];
+                if let Some(label) = &torrent.label {
+                    entries.push((b"label", BencodeValue::bytes(label.as_bytes())));
+                }
+                if let Some(dir) = &torrent.output_dir {
+                    entries.push((
+                        b"output_dir",
+                        BencodeValue::bytes(dir.to_string_lossy().as_bytes()),
+                    ));
+                }
+                if let Some(resume) = &torrent.resume {
+                    entries.push((b"resume", BencodeValue::bytes(resume)));
+                }
+                BencodeValue::dict(entries)
+            })
+            .collect();
+
+        let mut chunks: Vec<(&[u8], BencodeValue)> =
+            vec![(b"torrents", BencodeValue::List(torrents))];
+        if let Some(settings) = &self.settings {
+            chunks.push((b"settings", BencodeValue::bytes(settings.as_bytes())));
+        }
+        Envelope::new(
+            SESSION_ARCHIVE_FORMAT,
+            SESSION_ARCHIVE_VERSION,
+            BencodeValue::dict(chunks),
+        )
+        .encode()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let envelope = Envelope::decode(bytes, SESSION_ARCHIVE_FORMAT)?;
+        if envelope.version != SESSION_ARCHIVE_VERSION {
+            return Err(anyhow!(
+                "Unsupported session archive version {}",
+                envelope.version
+            ));
+        }
+
+        let entries = envelope
+            .chunks
+            .get(b"torrents")
+            .and_then(|v| v.as_list())
+            .ok_or_else(|| anyhow!("Session archive has no torrents chunk"))?;
+        let mut torrents = Vec::new();
+        for (index, entry) in entries.iter().enumerate() {
+            let bytes = |key: &[u8]| {
+                entry
+                    .get(key)
+                    .and_then(|v| v.as_bytes())
+                    .map(|b| b.to_vec())
+            };
+            let integer = |key: &[u8]| entry.get(key).and_then(|v| v.as_integer()).unwrap_or(0);
+            let label = bytes(b"label")
+                .map(String::from_utf8)
+                .transpose()
+                .map_err(|_| {
+                    anyhow!(
+                        "Torrent {} in the archive has a label that isn't UTF-8",
+                        index
+                    )
+                })?;
+            let output_dir = bytes(b"output_dir")
+                .map(String::from_utf8)
+                .transpose()
+                .map_err(|_| {
+                    anyhow!(
+                        "Torrent {} in the archive has an output dir that isn't UTF-8",
+                        index
+                    )
+                })?
+                .map(PathBuf::from);
+
+            torrents.push(ArchivedTorrent {
+                metainfo: bytes(b"metainfo")
+                    .ok_or_else(|| anyhow!("Torrent {} in the archive has no metainfo", index))?,
+                label,
+                queued: integer(b"queued") != 0,
+                paused: integer(b"paused") != 0,
+                uploaded: integer(b"uploaded").max(0) as u64,
+                downloaded: integer(b"downloaded").max(0) as u64,
+                output_dir,
+                resume: bytes(b"resume"),
+            });
+        }
+
+        let settings = envelope
+            .chunks
+            .get(b"settings")
+            .and_then(|v| v.as_bytes())
+            .map(|text| String::from_utf8(text.to_vec()))
+            .transpose()
+            .map_err(|_| anyhow!("Settings in the archive aren't UTF-8"))?;
+
+        Ok(Self { torrents, settings })
+    }
+
+    /// Writes the archive atomically , an archive already at `path` is kept as backup
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, &self.encode())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)
+            .map_err(|e| anyhow!("Could not read session archive {} : {}", path.display(), e))?;
+        Self::decode(&bytes)
+    }
+}
+
+/// Contents of a file , None when it doesn't exist
+pub fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow!("Could not read {} : {}", path.display(), e)),
+    }
+}
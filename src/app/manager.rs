@@ -0,0 +1,292 @@
+#[cfg(feature = "dht")]
+use crate::net::dht::{mutable::MutableTorrent, scrape::SwarmEstimate};
+use crate::{
+    app::{slot_filler::SlotFiller, state::SavedActivity},
+    core::config::SeedTarget,
+    net::{
+        block_manager::BlockManager,
+        peer_candidates::PeerCandidates,
+        swarm_health::SwarmHealth,
+        tracker::{Tracker, TrackerEvent, TrackerRequest},
+        tracker_manager::TrackerManager,
+    },
+    protocol::torrent::Torrent,
+};
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A torrent that has been added to the session
+#[derive(Debug, Clone)]
+pub struct ManagedTorrent {
+    /// Session wide id of the torrent
+    pub id: usize,
+    pub torrent: Torrent,
+    /// The torrent's tracker tiers plus any backups added , failed over when a tracker stops answering
+    pub trackers: TrackerManager,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// Has every selected file but not the whole torrent , so it only uploads
+    pub partial_seed: bool,
+    /// Waiting in the session queue , not announced or connected until it's taken out
+    pub queued: bool,
+    /// Category the user filed the torrent under , picks its seeding targets
+    pub label: Option<String>,
+    /// Where the torrent's data goes , None for the session's download directory
+    pub output_dir: Option<PathBuf>,
+    /// When to stop seeding , from the label unless set by hand
+    pub seed_target: SeedTarget,
+    /// When the download finished , seeding time counts from here
+    pub completed_at: Option<Instant>,
+    /// Trackers heard our `completed` event , later announces carry no event
+    pub completion_announced: bool,
+    /// Stopped by hand or by the idle policy , keeps its data but announces and connects nothing
+    pub paused: bool,
+    /// The torrent's logic panicked , it stays stopped until the error is cleared
+    pub error: Option<String>,
+    /// When the torrent was added or last resumed , idle time counts from here until it transfers something
+    pub active_since: Option<Instant>,
+    pub last_upload: Option<Instant>,
+    pub last_download: Option<Instant>,
+    /// Upload and download counters of the torrent's BlockManager at the last `Session::sync_transfer`
+    pub transfer_synced: Option<(u64, u64)>,
+    /// Peers discovered for this torrent that we haven't connected to yet
+    pub candidates: PeerCandidates,
+    /// Open (or opening) peer connections , kept up to date by whoever owns the connections
+    pub connected_peers: usize,
+    pub slot_filler: SlotFiller,
+    /// Swarm size from DHT scrapes , the only count a trackerless torrent gets
+    #[cfg(feature = "dht")]
+    pub swarm_estimate: SwarmEstimate,
+    /// DHT item this torrent follows (BEP 46) , None for ordinary torrents
+    #[cfg(feature = "dht")]
+    pub mutable: Option<MutableTorrent>,
+    /// Newer info hash the publisher moved to , waiting for its metadata
+    #[cfg(feature = "dht")]
+    pub pending_update: Option<[u8; 20]>,
+}
+
+impl ManagedTorrent {
+    pub fn new(id: usize, torrent: Torrent, peer_id: [u8; 20], listen_port: u16) -> Self {
+        Self {
+            id,
+            trackers: TrackerManager::from_torrent(&torrent, peer_id),
+            torrent,
+            uploaded: 0,
+            downloaded: 0,
+            partial_seed: false,
+            queued: false,
+            label: None,
+            output_dir: None,
+            seed_target: SeedTarget::default(),
+            completed_at: None,
+            completion_announced: false,
+            paused: false,
+            error: None,
+            active_since: None,
+            last_upload: None,
+            last_download: None,
+            transfer_synced: None,
+            candidates: PeerCandidates::new(peer_id, listen_port),
+            connected_peers: 0,
+            slot_filler: SlotFiller::default(),
+            #[cfg(feature = "dht")]
+            swarm_estimate: SwarmEstimate::default(),
+            #[cfg(feature = "dht")]
+            mutable: None,
+            #[cfg(feature = "dht")]
+            pending_update: None,
+        }
+    }
+
+    /// Tracker announces currently go to
+    pub fn tracker(&self) -> &Tracker {
+        self.trackers.current()
+    }
+
+    /// Adds a backup tracker to fail over to , false if it's already there
+    pub fn add_tracker(&mut self, announce_url: &str) -> bool {
+        let current = self.tracker();
+        let mut tracker = Tracker::with_peer_id(announce_url.to_string(), current.get_peer_id());
+        tracker.set_bind(self.tracker().bind().cloned());
+        self.trackers.add(tracker)
+    }
+
+    /// Uploaded over the torrent's size
+    pub fn ratio(&self) -> f64 {
+        if self.torrent.length == 0 {
+            return 0.0;
+        }
+        self.uploaded as f64 / self.torrent.length as f64
+    }
+
+    /// Whether the torrent finished and seeded up to its target
+    pub fn seed_target_reached(&self, now: Instant) -> bool {
+        let Some(completed_at) = self.completed_at else {
+            return false;
+        };
+        self.seed_target
+            .reached(self.ratio(), now.saturating_duration_since(completed_at))
+    }
+
+    /// Neither queued , paused nor failed , so it announces and takes connections
+    pub fn is_active(&self) -> bool {
+        !self.queued && !self.paused && self.error.is_none()
+    }
+
+    /// Stops the torrent after its logic panicked , nothing runs for it until the error is cleared
+    pub fn fail(&mut self, message: String) {
+        crate::log_line!("{} stopped after a panic : {}", self.torrent.name, message);
+        self.error = Some(message);
+    }
+
+    /// Counts bytes sent to peers , anything above 0 marks the torrent as active
+    pub fn record_upload(&mut self, bytes: u64, now: Instant) {
+        if bytes > 0 {
+            self.uploaded += bytes;
+            self.last_upload = Some(now);
+        }
+    }
+
+    /// Counts bytes received from peers , anything above 0 marks the torrent as active
+    pub fn record_download(&mut self, bytes: u64, now: Instant) {
+        if bytes > 0 {
+            self.downloaded += bytes;
+            self.last_download = Some(now);
+        }
+    }
+
+    /// Latest of the last upload , the last download and when the torrent was added or resumed
+    pub fn last_activity(&self) -> Option<Instant> {
+        [self.active_since, self.last_upload, self.last_download]
+            .into_iter()
+            .flatten()
+            .max()
+    }
+
+    /// Time since the last activity , None if nothing was ever recorded
+    pub fn idle_for(&self, now: Instant) -> Option<Duration> {
+        self.last_activity()
+            .map(|last| now.saturating_duration_since(last))
+    }
+
+    /// Activity timestamps as unix times for the session state , `wall` is the system time at `now`
+    pub fn saved_activity(&self, now: Instant, wall: SystemTime) -> SavedActivity {
+        let unix = |at: Option<Instant>| {
+            let at = wall.checked_sub(now.saturating_duration_since(at?))?;
+            Some(at.duration_since(UNIX_EPOCH).ok()?.as_secs())
+        };
+        SavedActivity {
+            active_since: unix(self.active_since),
+            last_upload: unix(self.last_upload),
+            last_download: unix(self.last_download),
+        }
+    }
+
+    /// Takes over activity saved by an earlier run , so time spent idle before the restart counts.
+    /// Times too far back for an Instant on this system are left as they are
+    pub fn restore_activity(&mut self, saved: &SavedActivity, now: Instant, wall: SystemTime) {
+        let instant = |at: Option<u64>| {
+            let ago = wall
+                .duration_since(UNIX_EPOCH + Duration::from_secs(at?))
+                .ok()?;
+            now.checked_sub(ago)
+        };
+        self.active_since = instant(saved.active_since).or(self.active_since);
+        self.last_upload = instant(saved.last_upload).or(self.last_upload);
+        self.last_download = instant(saved.last_download).or(self.last_download);
+    }
+
+    /// Snapshot of the torrent's activity timestamps , relative to `now`
+    pub fn activity(&self, now: Instant) -> TorrentActivity {
+        let ago = |at: Option<Instant>| at.map(|at| now.saturating_duration_since(at));
+        TorrentActivity {
+            last_upload: ago(self.last_upload),
+            last_download: ago(self.last_download),
+            idle_for: self.idle_for(now),
+            paused: self.paused,
+        }
+    }
+
+    /// Swarm summary from the torrent's connected peers plus what discovery turned up
+    pub fn swarm_health(&self, manager: &BlockManager) -> SwarmHealth {
+        manager
+            .swarm_health()
+            .with_known_sources(self.candidates.known_by_source(), self.candidates.known())
+    }
+
+    /// Bytes we still need , 0 once the download finished (data we had on disk never counts as downloaded)
+    pub fn left(&self) -> u64 {
+        if self.completed_at.is_some() {
+            return 0;
+        }
+        (self.torrent.length as u64).saturating_sub(self.downloaded)
+    }
+
+    /// Builds the announce request for this torrent
+    pub fn tracker_request(&self, port: u16, event: Option<TrackerEvent>) -> TrackerRequest {
+        self.tracker_request_for(self.torrent.info_hashes().primary(), port, event)
+    }
+
+    /// One announce request per swarm the torrent belongs to , hybrid torrents get two
+    pub fn tracker_requests(&self, port: u16, event: Option<TrackerEvent>) -> Vec<TrackerRequest> {
+        self.torrent
+            .info_hashes()
+            .announce_hashes()
+            .into_iter()
+            .map(|hash| self.tracker_request_for(hash, port, event.clone()))
+            .collect()
+    }
+
+    fn tracker_request_for(
+        &self,
+        info_hash: [u8; 20],
+        port: u16,
+        event: Option<TrackerEvent>,
+    ) -> TrackerRequest {
+        // Partial seeds announce as paused so private trackers don't count them as leechers
+        let event = match event {
+            Some(TrackerEvent::Stopped) => Some(TrackerEvent::Stopped),
+            _ if self.partial_seed => Some(TrackerEvent::Paused),
+            None if self.completed_at.is_some() && !self.completion_announced => {
+                Some(TrackerEvent::Completed)
+            }
+            event => event,
+        };
+
+        TrackerRequest {
+            info_hash,
+            left: self.left(),
+            uploaded: self.uploaded,
+            downloaded: self.downloaded,
+            port,
+            compact: true,
+            event,
+        }
+    }
+}
+
+/// How long ago a torrent last moved data , see `ManagedTorrent::activity`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TorrentActivity {
+    /// Since the last upload , None if it never uploaded
+    pub last_upload: Option<Duration>,
+    /// Since the last download , None if it never downloaded
+    pub last_download: Option<Duration>,
+    /// Since the last upload , download or resume
+    pub idle_for: Option<Duration>,
+    pub paused: bool,
+}
+
+impl TorrentActivity {
+    /// Ages in whole seconds , null for never
+    pub fn to_json(&self) -> Value {
+        let secs = |age: Option<Duration>| age.map(|age| age.as_secs());
+        json!({
+            "last_upload_secs_ago": secs(self.last_upload),
+            "last_download_secs_ago": secs(self.last_download),
+            "idle_secs": secs(self.idle_for),
+            "paused": self.paused,
+        })
+    }
+}
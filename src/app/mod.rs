@@ -0,0 +1,6 @@
+pub mod bandwidth;
+pub mod export;
+pub mod manager;
+pub mod session;
+pub mod slot_filler;
+pub mod state;
@@ -0,0 +1,1272 @@
+#[cfg(feature = "dht")]
+use crate::core::config::DhtConfig;
+#[cfg(feature = "dht")]
+use crate::net::dht::{
+    mutable::MutableTorrent,
+    node::Dht,
+    routing::{NodeId, RoutingTable},
+    state::default_state_path,
+};
+#[cfg(feature = "http-tracker")]
+use crate::net::{
+    announce_pool::{AnnounceJob, AnnouncePool},
+    torrent_fetch::TorrentFetch,
+    tracker::{TrackerEvent, TrackerResponse},
+};
+#[cfg(feature = "dht")]
+use crate::protocol::{
+    bencode::BencodeValue,
+    peer::{DiscoveredPeer, PeerHost, PeerSource},
+};
+use crate::{
+    app::{
+        bandwidth::{BandwidthLog, Usage, default_bandwidth_path},
+        export::{ArchivedTorrent, ImportReport, OnCollision, SessionArchive, read_if_exists},
+        manager::{ManagedTorrent, TorrentActivity},
+        slot_filler::SlotAction,
+        state::{SavedActivity, SessionState, default_session_path},
+    },
+    core::{
+        clock::{SharedClock, system_clock},
+        config::{Config, MetadataConfig, NetworkConfig, SeedTarget, default_download_dir},
+        isolate::catch_panic,
+        resources::ResourceUsage,
+    },
+    net::{
+        block_manager::DownloadStats,
+        encryption::{ConnectMode, ConnectModes},
+        metadata_fetch::MetadataFetch,
+        rate_limit::{RateLimits, Rates},
+        tracker::Tracker,
+        wire_dump::WireDump,
+    },
+    protocol::{
+        handshake::{Extensions, Handshake},
+        magnet::MagnetUri,
+        torrent::Torrent,
+    },
+    storage::resume::{default_resume_dir, resume_path, write_atomic},
+    util::humanize,
+};
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Default port we tell trackers we're listening on
+pub const DEFAULT_LISTEN_PORT: u16 = 6881;
+
+/// Snapshot of what a session holds , see `Session::debug_resource_report`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceReport {
+    pub torrents: usize,
+    /// Discovered peers waiting to be connected , over all torrents
+    pub candidates: usize,
+    /// Connections the torrents were told about
+    pub connected_peers: usize,
+    /// Tasks , sockets and files alive in the whole process
+    pub usage: ResourceUsage,
+}
+
+impl fmt::Display for ResourceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} torrents , {} candidates , {} connected peers | {}",
+            self.torrents, self.candidates, self.connected_peers, self.usage
+        )
+    }
+}
+
+/// Owns every torrent the client is working on
+#[derive(Debug)]
+pub struct Session {
+    pub torrents: Vec<ManagedTorrent>,
+    pub listen_port: u16,
+    /// One peer id for the whole session
+    pub peer_id: [u8; 20],
+    #[cfg(feature = "http-tracker")]
+    announce_pool: AnnouncePool,
+    next_id: usize,
+    clock: SharedClock,
+    network: NetworkConfig,
+    /// Caps on magnet metadata fetches , from the config
+    metadata: MetadataConfig,
+    /// Set by the kill switch while the bound interface is missing , nothing goes out until it's back
+    network_paused: bool,
+    /// Seeding targets per label , from the config
+    labels: HashMap<String, SeedTarget>,
+    /// Idle time after which a torrent is paused , from the config
+    idle_pause: Option<Duration>,
+    /// Encryption policy and the mode each peer accepted last
+    connect_modes: ConnectModes,
+    /// Upload and download caps over every torrent , plus the cap each peer gets
+    rate_limits: RateLimits,
+    /// Where queue order is saved on shutdown , None to keep it in memory only
+    pub session_state_path: Option<PathBuf>,
+    /// Queue order from the last run , applied to torrents as they're added again
+    saved_queue: Vec<[u8; 20]>,
+    /// Download directories from the last run , applied to torrents added again without one
+    saved_output_dirs: HashMap<[u8; 20], PathBuf>,
+    /// Activity timestamps from the last run , applied to torrents as they're added again
+    saved_activity: HashMap<[u8; 20], SavedActivity>,
+    /// Where torrents go unless they're added with their own directory , from the config
+    pub download_dir: PathBuf,
+    /// Traffic per torrent per day , saved with the session state
+    bandwidth: BandwidthLog,
+    /// Where the bandwidth log is saved , None to keep it in memory only
+    pub bandwidth_path: Option<PathBuf>,
+    /// Where each torrent's resume file is kept , None to keep no resume data
+    pub resume_dir: Option<PathBuf>,
+    /// Settings file , exported and imported with the session. None when there is no config directory
+    pub config_path: Option<PathBuf>,
+    /// Debug option , when set every peer connection records its messages here (see WireDump)
+    pub wire_dump_dir: Option<PathBuf>,
+    /// Advertise the extension protocol (BEP 10) in handshakes
+    extension_protocol: bool,
+    /// DHT routing table , carried over between runs so we don't bootstrap from nothing every time
+    #[cfg(feature = "dht")]
+    pub dht: RoutingTable,
+    /// Where the routing table is saved on shutdown , None to keep it in memory only
+    #[cfg(feature = "dht")]
+    pub dht_state_path: Option<PathBuf>,
+    #[cfg(feature = "dht")]
+    dht_config: DhtConfig,
+    /// The DHT socket , bound on first use
+    #[cfg(feature = "dht")]
+    dht_node: Option<Dht>,
+}
+
+impl Session {
+    pub fn new(listen_port: u16) -> Self {
+        #[cfg(feature = "dht")]
+        let dht_state_path = default_state_path();
+        let session_state_path = default_session_path();
+        let saved = SessionState::load_or_default(session_state_path.as_deref());
+        let bandwidth_path = default_bandwidth_path();
+
+        Self {
+            torrents: Vec::new(),
+            listen_port,
+            peer_id: Tracker::generate_peer_id(),
+            #[cfg(feature = "http-tracker")]
+            announce_pool: AnnouncePool::default(),
+            next_id: 0,
+            clock: system_clock(),
+            network: NetworkConfig::default(),
+            metadata: MetadataConfig::default(),
+            network_paused: false,
+            labels: HashMap::new(),
+            idle_pause: None,
+            connect_modes: ConnectModes::default(),
+            rate_limits: RateLimits::unlimited(),
+            session_state_path,
+            saved_queue: saved.queue,
+            saved_output_dirs: saved.output_dirs,
+            saved_activity: saved.activity,
+            download_dir: default_download_dir(),
+            bandwidth: BandwidthLog::load_or_default(bandwidth_path.as_deref()),
+            bandwidth_path,
+            resume_dir: default_resume_dir(),
+            config_path: Config::default_path(),
+            wire_dump_dir: None,
+            extension_protocol: true,
+            #[cfg(feature = "dht")]
+            dht: RoutingTable::load_or_new(dht_state_path.as_deref()),
+            #[cfg(feature = "dht")]
+            dht_state_path,
+            #[cfg(feature = "dht")]
+            dht_config: DhtConfig::default(),
+            #[cfg(feature = "dht")]
+            dht_node: None,
+        }
+    }
+
+    /// Replaces the clock used for rescans , announce spacing and rate limits
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.rate_limits = self.rate_limits.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    pub fn network(&self) -> &NetworkConfig {
+        &self.network
+    }
+
+    /// Limits to hand every torrent's PeerManager (`with_rate_limits`) , clones share them so the
+    /// setters below reach connections that are already open
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
+    /// Caps in bytes per second over all torrents together , None lifts a cap
+    pub fn set_rate_limits(&mut self, rates: Rates) {
+        self.rate_limits.set_global(rates);
+    }
+
+    /// Caps in bytes per second for each single peer , on top of the global ones
+    pub fn set_peer_rate_limits(&mut self, rates: Rates) {
+        self.rate_limits.set_per_peer(rates);
+    }
+
+    pub fn is_network_paused(&self) -> bool {
+        self.network_paused
+    }
+
+    /// Kill switch check , meant to run on a timer. Returns true when networking was paused or resumed
+    pub fn check_network(&mut self) -> bool {
+        let available = match &self.network.bind {
+            Some(bind) if self.network.kill_switch => bind.is_available(),
+            _ => true,
+        };
+
+        if available != self.network_paused {
+            return false;
+        }
+
+        self.network_paused = !available;
+        match (&self.network.bind, self.network_paused) {
+            (Some(bind), true) => crate::log_line!("{} went away , networking paused", bind),
+            (Some(bind), false) => crate::log_line!("{} is back , networking resumed", bind),
+            _ => {}
+        }
+        true
+    }
+
+    /// Periodic rescan of every torrent's free connection slots
+    ///
+    /// Meant to be called on a timer , each torrent's SlotFiller decides whether it's due. Returns what
+    /// the caller should do : connect to queued peers , announce early , start a DHT lookup or shut
+    /// down a torrent that was paused for sitting idle (see `pause_idle_torrents`)
+    pub fn fill_idle_slots(&mut self) -> Vec<(usize, SlotAction)> {
+        let mut actions: Vec<_> = self
+            .pause_idle_torrents()
+            .into_iter()
+            .map(|id| (id, SlotAction::Pause))
+            .collect();
+        if self.network_paused {
+            return actions;
+        }
+
+        let now = self.clock.now();
+        let dht_enabled = cfg!(feature = "dht");
+
+        for torrent in self.torrents.iter_mut().filter(|t| t.is_active()) {
+            let ticked = catch_panic(|| {
+                torrent.slot_filler.tick(
+                    now,
+                    torrent.connected_peers,
+                    &mut torrent.candidates,
+                    dht_enabled,
+                )
+            });
+            match ticked {
+                Ok(ticked) => actions.extend(ticked.into_iter().map(|action| (torrent.id, action))),
+                // Only this torrent stops , the others still get their slots filled
+                Err(message) => torrent.fail(message),
+            }
+        }
+        actions
+    }
+
+    /// Switches the port we tell the swarm about , after the listener rebound or port mapping
+    /// handed us a different external port
+    ///
+    /// Every torrent re-announces to its trackers and the DHT on the next `fill_idle_slots`
+    /// instead of leaving the old port in the swarm until the regular interval. Returns false
+    /// when the port didn't change
+    pub fn set_listen_port(&mut self, port: u16) -> bool {
+        if port == self.listen_port {
+            return false;
+        }
+
+        crate::log_line!(
+            "Listen port changed {} -> {} , re-announcing",
+            self.listen_port,
+            port
+        );
+        self.listen_port = port;
+        for torrent in &mut self.torrents {
+            torrent.candidates.set_listen_port(port);
+            torrent.slot_filler.force_announce();
+        }
+        true
+    }
+
+    /// Applies settings from the config file
+    pub fn with_config(mut self, config: &Config) -> Self {
+        self.reload_config(config);
+        self
+    }
+
+    /// Applies a config to a running session , e.g after the file changed on disk
+    ///
+    /// Everything is taken as given , use `ConfigReload` to keep restart-required settings.
+    /// A running DHT node picks up read-only mode and the bootstrap nodes
+    pub fn reload_config(&mut self, config: &Config) {
+        self.network = config.network.clone();
+        self.metadata = config.metadata;
+        self.labels = config.labels.clone();
+        self.idle_pause = config.idle_pause;
+        self.download_dir = config.download_dir();
+        self.connect_modes.set_policy(self.network.encryption);
+        for torrent in &mut self.torrents {
+            torrent.trackers.set_bind(self.network.bind.clone());
+            torrent
+                .candidates
+                .set_port_policy(self.network.ports.clone());
+            if let Some(target) = torrent.label.as_ref().and_then(|l| self.labels.get(l)) {
+                torrent.seed_target = *target;
+            }
+        }
+
+        #[cfg(feature = "dht")]
+        {
+            self.dht_config = config.dht.clone();
+            if let Some(node) = self.dht_node.as_mut() {
+                node.set_config(&self.dht_config);
+            }
+        }
+    }
+
+    #[cfg(feature = "dht")]
+    pub fn dht_config(&self) -> &DhtConfig {
+        &self.dht_config
+    }
+
+    /// Changes DHT settings at runtime , rejected as a whole if any field is invalid.
+    /// A new port takes effect the next time the DHT socket is bound
+    #[cfg(feature = "dht")]
+    pub fn set_dht_config(&mut self, config: DhtConfig) -> Result<()> {
+        config.validate()?;
+        self.dht_config = config;
+        Ok(())
+    }
+
+    /// UDP port of the DHT , the peer port unless configured otherwise
+    #[cfg(feature = "dht")]
+    pub fn dht_port(&self) -> u16 {
+        self.dht_config.listen_port(self.listen_port)
+    }
+
+    /// Lookup targets for DHT buckets that went quiet , the node runs a find_node for each
+    #[cfg(feature = "dht")]
+    pub fn dht_refresh_targets(&mut self) -> Vec<NodeId> {
+        self.dht.due_refreshes()
+    }
+
+    /// Binds the DHT socket and joins the DHT , a no-op once it's running.
+    /// Returns how many nodes the routing table holds
+    #[cfg(feature = "dht")]
+    pub async fn start_dht(&mut self) -> Result<usize> {
+        // Checked first , lookups and refreshes on a running node go through here too
+        if self.network_paused {
+            return Err(anyhow!("Networking is paused , not using the DHT"));
+        }
+        if self.dht_node.is_some() {
+            return Ok(self.dht.len());
+        }
+        if !self.dht_config.enabled {
+            return Err(anyhow!("DHT is disabled in the config"));
+        }
+
+        let node = Dht::bind(
+            self.dht_port(),
+            self.dht.own_id(),
+            self.network.bind.as_ref(),
+        )
+        .await?
+        .with_config(&self.dht_config)
+        .with_clock(self.clock.clone());
+        let node = self.dht_node.insert(node);
+        node.bootstrap(&mut self.dht).await
+    }
+
+    /// Runs a find_node for every bucket due a refresh
+    #[cfg(feature = "dht")]
+    pub async fn refresh_dht(&mut self) -> Result<()> {
+        self.start_dht().await?;
+        let Some(node) = self.dht_node.as_mut() else {
+            return Ok(());
+        };
+        for target in self.dht.due_refreshes() {
+            node.find_node(&mut self.dht, target).await?;
+        }
+        Ok(())
+    }
+
+    /// Asks the DHT for a torrent's peers , for when its trackers are down or it has none.
+    /// Returns how many new candidates that gave the torrent
+    #[cfg(feature = "dht")]
+    pub async fn dht_get_peers(&mut self, id: usize) -> Result<usize> {
+        self.dht_lookup(id, false).await
+    }
+
+    /// Like `dht_get_peers` , and also announces us to the nodes closest to the torrent
+    #[cfg(feature = "dht")]
+    pub async fn dht_announce(&mut self, id: usize) -> Result<usize> {
+        self.dht_lookup(id, true).await
+    }
+
+    #[cfg(feature = "dht")]
+    async fn dht_lookup(&mut self, id: usize, announce: bool) -> Result<usize> {
+        let info_hash = self
+            .get_torrent(id)
+            .ok_or_else(|| anyhow!("No torrent with id {}", id))?
+            .torrent
+            .info_hashes()
+            .primary();
+        self.start_dht().await?;
+        let node = self
+            .dht_node
+            .as_mut()
+            .ok_or_else(|| anyhow!("DHT is not running"))?;
+
+        let lookup = if announce {
+            node.announce_peer(&mut self.dht, info_hash, Some(self.listen_port))
+                .await?
+        } else {
+            node.get_peers(&mut self.dht, info_hash).await?
+        };
+
+        let torrent = self
+            .get_torrent_mut(id)
+            .ok_or_else(|| anyhow!("No torrent with id {}", id))?;
+        if lookup.swarm.responses > 0 {
+            torrent.swarm_estimate = lookup.swarm;
+        }
+        let peers = lookup.peers.iter().map(|addr| {
+            DiscoveredPeer::new(PeerHost::Ip(addr.ip()), addr.port()).with_source(PeerSource::Dht)
+        });
+        Ok(torrent.candidates.extend(peers))
+    }
+
+    /// Makes a torrent follow a BEP 46 mutable item , its updates replace the torrent
+    #[cfg(feature = "dht")]
+    pub fn follow_mutable(&mut self, id: usize, mutable: MutableTorrent) -> bool {
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return false;
+        };
+        torrent.mutable = Some(mutable);
+        true
+    }
+
+    /// Feeds a DHT `get` reply to a torrent following a mutable item
+    ///
+    /// Returns the info hash the publisher moved to , the caller fetches its metadata and hands it to
+    /// `switch_to_update`
+    #[cfg(feature = "dht")]
+    pub fn apply_mutable_reply(
+        &mut self,
+        id: usize,
+        reply: &BencodeValue,
+    ) -> Result<Option<[u8; 20]>> {
+        let torrent = self
+            .get_torrent_mut(id)
+            .ok_or_else(|| anyhow!("No torrent with id {}", id))?;
+        let mutable = torrent
+            .mutable
+            .as_mut()
+            .ok_or_else(|| anyhow!("Torrent {} does not follow a mutable item", id))?;
+
+        let update = mutable.apply_reply(reply)?;
+        if let Some(info_hash) = update
+            && !torrent.torrent.info_hashes().matches(&info_hash)
+        {
+            torrent.pending_update = Some(info_hash);
+            return Ok(Some(info_hash));
+        }
+        Ok(None)
+    }
+
+    /// Asks the DHT for the newest version of the item a torrent follows , see `follow_mutable`
+    ///
+    /// Returns the info hash the publisher moved to , like `apply_mutable_reply`. Replies that fail
+    /// the signature check are skipped
+    #[cfg(feature = "dht")]
+    pub async fn dht_check_mutable(&mut self, id: usize) -> Result<Option<[u8; 20]>> {
+        let mutable = self
+            .get_torrent(id)
+            .ok_or_else(|| anyhow!("No torrent with id {}", id))?
+            .mutable
+            .as_ref()
+            .ok_or_else(|| anyhow!("Torrent {} does not follow a mutable item", id))?;
+        let (target, seq) = (mutable.target(), mutable.seq);
+        self.start_dht().await?;
+        let node = self
+            .dht_node
+            .as_mut()
+            .ok_or_else(|| anyhow!("DHT is not running"))?;
+
+        let lookup = node.get_item(&mut self.dht, target, seq).await?;
+        let mut update = None;
+        for item in &lookup.items {
+            match self.apply_mutable_reply(id, item) {
+                Ok(Some(info_hash)) => update = Some(info_hash),
+                Ok(None) => {}
+                Err(e) => crate::log_line!("Ignoring DHT item for torrent {} : {}", id, e),
+            }
+        }
+        Ok(update)
+    }
+
+    /// Swaps a followed torrent for the version its publisher announced , keeping the session id
+    #[cfg(feature = "dht")]
+    pub fn switch_to_update(&mut self, id: usize, torrent: Torrent) -> Result<()> {
+        let replacement = self.managed_torrent(id, torrent);
+        let current = self
+            .get_torrent_mut(id)
+            .ok_or_else(|| anyhow!("No torrent with id {}", id))?;
+
+        let expected = current
+            .pending_update
+            .ok_or_else(|| anyhow!("Torrent {} has no pending update", id))?;
+        if !replacement.torrent.info_hashes().matches(&expected) {
+            return Err(anyhow!(
+                "Torrent {} is not the announced update {}",
+                hex::encode(replacement.torrent.info_hashes().primary()),
+                hex::encode(expected)
+            ));
+        }
+
+        let mutable = current.mutable.take();
+        *current = replacement;
+        current.mutable = mutable;
+        Ok(())
+    }
+
+    /// Saves state that outlives the session
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.save_state()?;
+        #[cfg(feature = "dht")]
+        if let Some(path) = &self.dht_state_path {
+            self.dht.save(path)?;
+        }
+        Ok(())
+    }
+
+    /// Handshake modes to try with a peer , in order. When one is rejected the caller retries with
+    /// the next and reports back through `connect_result`
+    pub fn connect_attempts(&self, addr: &SocketAddr) -> Vec<ConnectMode> {
+        self.connect_modes.attempts(addr)
+    }
+
+    /// Records how a handshake in `mode` went , so the next connect to the peer starts with what worked
+    pub fn connect_result(&mut self, addr: SocketAddr, mode: ConnectMode, accepted: bool) {
+        if accepted {
+            self.connect_modes.accepted(addr, mode);
+        } else {
+            self.connect_modes.rejected(&addr, mode);
+        }
+    }
+
+    /// Files a torrent under a label , or clears it. The label's seeding targets apply from now on ,
+    /// a label without configured targets leaves the torrent's targets as they were
+    pub fn set_label(&mut self, id: usize, label: Option<&str>) -> bool {
+        let target = label.and_then(|label| self.labels.get(label)).copied();
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return false;
+        };
+
+        torrent.label = label.map(String::from);
+        if let Some(target) = target {
+            torrent.seed_target = target;
+        }
+        true
+    }
+
+    /// Marks a torrent's download as finished , its seeding time starts now
+    ///
+    /// The torrent keeps announcing , as a seed (`left` 0) and with the `completed` event on the next
+    /// `fill_idle_slots`
+    pub fn mark_complete(&mut self, id: usize) {
+        let now = self.clock.now();
+        if let Some(torrent) = self.get_torrent_mut(id)
+            && torrent.completed_at.is_none()
+        {
+            torrent.completed_at = Some(now);
+            torrent.slot_filler.force_announce();
+        }
+    }
+
+    /// Records whether a torrent has every wanted piece but not the whole torrent , from
+    /// `BlockManager::is_partial_seed`. Partial seeds announce `paused` so private trackers don't
+    /// count them as leechers , trackers hear about a change on the next `fill_idle_slots`
+    pub fn set_partial_seed(&mut self, id: usize, partial_seed: bool) -> bool {
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return false;
+        };
+        if torrent.partial_seed == partial_seed {
+            return false;
+        }
+        torrent.partial_seed = partial_seed;
+        torrent.slot_filler.force_announce();
+        true
+    }
+
+    /// Torrents that seeded up to their target , the caller stops them
+    pub fn seed_targets_reached(&self) -> Vec<usize> {
+        let now = self.clock.now();
+        self.torrents
+            .iter()
+            .filter(|t| t.seed_target_reached(now))
+            .map(|t| t.id)
+            .collect()
+    }
+
+    /// Counts bytes moved for a torrent , its idle time starts over.
+    /// The bytes also go into the bandwidth log under today's (UTC) date
+    pub fn record_transfer(&mut self, id: usize, uploaded: u64, downloaded: u64) {
+        let now = self.clock.now();
+        let Some(torrent) = self.torrents.iter_mut().find(|t| t.id == id) else {
+            return;
+        };
+        torrent.record_upload(uploaded, now);
+        torrent.record_download(downloaded, now);
+
+        self.bandwidth.record(
+            torrent.torrent.info_hashes().primary(),
+            &torrent.torrent.name,
+            Utc::now().date_naive(),
+            Usage {
+                uploaded,
+                downloaded,
+            },
+        );
+    }
+
+    /// Records what a torrent's BlockManager moved since the last sync , see `record_transfer`.
+    /// Meant to run with every stats update. The first sync only takes the counters as a starting
+    /// point , bytes from the resume file were recorded by the run that moved them
+    pub fn sync_transfer(&mut self, id: usize, stats: &DownloadStats) {
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return;
+        };
+        let totals = (stats.uploaded_bytes, stats.total_downloaded);
+        let Some((uploaded, downloaded)) = torrent.transfer_synced.replace(totals) else {
+            return;
+        };
+        self.record_transfer(
+            id,
+            totals.0.saturating_sub(uploaded),
+            totals.1.saturating_sub(downloaded),
+        );
+    }
+
+    /// Traffic recorded so far , this run and the ones before
+    pub fn bandwidth(&self) -> &BandwidthLog {
+        &self.bandwidth
+    }
+
+    /// Activity timestamps of a torrent , relative to now
+    pub fn activity(&self, id: usize) -> Option<TorrentActivity> {
+        let now = self.clock.now();
+        self.get_torrent(id).map(|t| t.activity(now))
+    }
+
+    /// Pauses or resumes a torrent , false if nothing changed
+    ///
+    /// A resumed torrent counts as active from now and announces on the next `fill_idle_slots`.
+    /// Pausing only flips the flag , whoever owns the torrent's connections closes them
+    pub fn set_paused(&mut self, id: usize, paused: bool) -> bool {
+        let now = self.clock.now();
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return false;
+        };
+        if torrent.paused == paused {
+            return false;
+        }
+
+        torrent.paused = paused;
+        if !paused {
+            torrent.active_since = Some(now);
+            torrent.slot_filler.force_announce();
+        }
+        true
+    }
+
+    /// Pauses torrents that moved no data for longer than the configured idle time , `fill_idle_slots`
+    /// runs it. Returns their ids so the caller can close their connections and announce them stopped
+    pub fn pause_idle_torrents(&mut self) -> Vec<usize> {
+        let Some(limit) = self.idle_pause else {
+            return Vec::new();
+        };
+        let now = self.clock.now();
+
+        let mut paused = Vec::new();
+        for torrent in self.torrents.iter_mut().filter(|t| t.is_active()) {
+            if torrent.idle_for(now).is_some_and(|idle| idle >= limit) {
+                crate::log_line!(
+                    "{} idle for {} , pausing",
+                    torrent.torrent.name,
+                    humanize::duration(limit)
+                );
+                torrent.paused = true;
+                paused.push(torrent.id);
+            }
+        }
+        paused
+    }
+
+    /// Writes the queue order , download directories and activity times to the session state file and
+    /// the bandwidth log , where there is one
+    pub fn save_state(&self) -> Result<()> {
+        if let Some(path) = &self.bandwidth_path {
+            self.bandwidth.save(path)?;
+        }
+        let Some(path) = &self.session_state_path else {
+            return Ok(());
+        };
+
+        let queue = self
+            .queue()
+            .into_iter()
+            .filter_map(|id| self.get_torrent(id))
+            .map(|t| t.torrent.info_hashes().primary())
+            .collect();
+        let output_dirs = self
+            .torrents
+            .iter()
+            .filter_map(|t| Some((t.torrent.info_hashes().primary(), t.output_dir.clone()?)))
+            .collect();
+        let (now, wall) = (self.clock.now(), SystemTime::now());
+        let activity = self
+            .torrents
+            .iter()
+            .map(|t| {
+                let hash = t.torrent.info_hashes().primary();
+                (hash, t.saved_activity(now, wall))
+            })
+            .collect();
+        SessionState {
+            queue,
+            output_dirs,
+            activity,
+        }
+        .save(path)
+    }
+
+    /// Where a torrent's data goes , its own directory or the session's. None for unknown ids
+    pub fn output_dir(&self, id: usize) -> Option<PathBuf> {
+        let torrent = self.get_torrent(id)?;
+        Some(
+            torrent
+                .output_dir
+                .clone()
+                .unwrap_or_else(|| self.download_dir.clone()),
+        )
+    }
+
+    /// Gives a torrent its own download directory , None goes back to the session's. False for
+    /// unknown ids
+    ///
+    /// Data already downloaded isn't moved , the BlockManager has to be reopened on the new directory
+    pub fn set_output_dir(&mut self, id: usize, output_dir: Option<PathBuf>) -> bool {
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return false;
+        };
+        torrent.output_dir = output_dir;
+        true
+    }
+
+    /// Resume file of a torrent , None when resume data isn't kept
+    pub fn resume_path(&self, info_hash: &[u8; 20]) -> Option<PathBuf> {
+        self.resume_dir
+            .as_ref()
+            .map(|dir| resume_path(dir, info_hash))
+    }
+
+    /// Bundles every torrent with its resume data and state , plus the settings file , into one archive
+    pub fn export_state(&self) -> Result<SessionArchive> {
+        let mut torrents = Vec::new();
+        for managed in &self.torrents {
+            let resume = match self.resume_path(&managed.torrent.info_hash) {
+                Some(path) => read_if_exists(&path)?,
+                None => None,
+            };
+            torrents.push(ArchivedTorrent {
+                metainfo: managed.torrent.to_bytes(),
+                label: managed.label.clone(),
+                queued: managed.queued,
+                paused: managed.paused,
+                uploaded: managed.uploaded,
+                downloaded: managed.downloaded,
+                output_dir: managed.output_dir.clone(),
+                resume,
+            });
+        }
+
+        let settings = match &self.config_path {
+            Some(path) => read_if_exists(path)?
+                .map(String::from_utf8)
+                .transpose()
+                .map_err(|_| anyhow!("{} isn't UTF-8", path.display()))?,
+            None => None,
+        };
+        Ok(SessionArchive { torrents, settings })
+    }
+
+    /// Adds the torrents and settings of an archive from `export_state`
+    ///
+    /// Torrents already in the session (same info hash) and an existing settings file are handled as
+    /// `on_collision` says. Every torrent and the settings are checked first , an archive with
+    /// anything unusable is rejected as a whole before the session is touched
+    pub fn import_state(
+        &mut self,
+        archive: &SessionArchive,
+        on_collision: OnCollision,
+    ) -> Result<ImportReport> {
+        let mut torrents = Vec::new();
+        for (index, archived) in archive.torrents.iter().enumerate() {
+            let torrent = Torrent::from_bytes(&archived.metainfo)
+                .map_err(|e| anyhow!("Torrent {} in the archive is invalid : {}", index, e))?;
+            torrents.push((torrent, archived));
+        }
+        let settings = archive
+            .settings
+            .as_ref()
+            .map(|text| Config::from_json(text).map(|config| (text, config)))
+            .transpose()
+            .map_err(|e| anyhow!("Settings in the archive are invalid : {}", e))?;
+
+        let mut report = ImportReport::default();
+        for (torrent, archived) in torrents {
+            let existing = self
+                .find_by_handshake_hash(&torrent.info_hashes().primary())
+                .map(|t| t.id);
+            let id = match (existing, on_collision) {
+                (Some(_), OnCollision::Keep) => {
+                    report.kept.push(torrent.name);
+                    continue;
+                }
+                (Some(id), OnCollision::Replace) => {
+                    report.replaced.push(id);
+                    id
+                }
+                (None, _) => {
+                    let id = self.add_torrent(torrent);
+                    report.added.push(id);
+                    id
+                }
+            };
+            self.apply_archived(id, archived)?;
+        }
+
+        if let (Some((text, config)), Some(path)) = (settings, self.config_path.clone())
+            && (on_collision == OnCollision::Replace || !path.exists())
+        {
+            write_atomic(&path, text.as_bytes())?;
+            self.reload_config(&config);
+            report.settings_applied = true;
+        }
+        Ok(report)
+    }
+
+    /// Takes an archived torrent's label , counters , queue state and resume data
+    fn apply_archived(&mut self, id: usize, archived: &ArchivedTorrent) -> Result<()> {
+        self.set_label(id, archived.label.as_deref());
+        self.set_queued(id, archived.queued);
+        self.set_paused(id, archived.paused);
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return Ok(());
+        };
+        torrent.uploaded = archived.uploaded;
+        torrent.downloaded = archived.downloaded;
+        torrent.output_dir = archived.output_dir.clone();
+
+        let info_hash = torrent.torrent.info_hash;
+        if let (Some(resume), Some(path)) = (&archived.resume, self.resume_path(&info_hash)) {
+            write_atomic(&path, resume)?;
+        }
+        Ok(())
+    }
+
+    /// Ids of queued torrents , first in line first
+    pub fn queue(&self) -> Vec<usize> {
+        self.torrents
+            .iter()
+            .filter(|t| t.queued)
+            .map(|t| t.id)
+            .collect()
+    }
+
+    /// Place of a torrent in the queue , 0 starts next. None if it isn't queued
+    pub fn queue_position(&self, id: usize) -> Option<usize> {
+        self.queue().iter().position(|&queued| queued == id)
+    }
+
+    /// Puts a torrent at the back of the queue , or takes it out. False if nothing changed
+    pub fn set_queued(&mut self, id: usize, queued: bool) -> bool {
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return false;
+        };
+        if torrent.queued == queued {
+            return false;
+        }
+
+        torrent.queued = queued;
+        if queued {
+            self.move_bottom(id);
+        }
+        true
+    }
+
+    /// Moves a queued torrent one place towards the front , false if it can't move
+    pub fn move_up(&mut self, id: usize) -> bool {
+        self.move_in_queue(id, |from, _| from.saturating_sub(1))
+    }
+
+    pub fn move_down(&mut self, id: usize) -> bool {
+        self.move_in_queue(id, |from, len| (from + 1).min(len - 1))
+    }
+
+    pub fn move_top(&mut self, id: usize) -> bool {
+        self.move_in_queue(id, |_, _| 0)
+    }
+
+    pub fn move_bottom(&mut self, id: usize) -> bool {
+        self.move_in_queue(id, |_, len| len - 1)
+    }
+
+    /// `target` gets (current position , queue length) and returns the new position
+    fn move_in_queue(&mut self, id: usize, target: impl FnOnce(usize, usize) -> usize) -> bool {
+        let mut queue = self.queue();
+        let Some(from) = queue.iter().position(|&queued| queued == id) else {
+            return false;
+        };
+
+        let to = target(from, queue.len());
+        if to == from {
+            return false;
+        }
+
+        queue.remove(from);
+        queue.insert(to, id);
+        self.apply_queue_order(&queue);
+        true
+    }
+
+    /// Reorders queued torrents to match `queue`
+    ///
+    /// Queued torrents keep the slots they had in the list , only their order among themselves changes
+    fn apply_queue_order(&mut self, queue: &[usize]) {
+        let mut pending: Vec<Option<ManagedTorrent>> = std::mem::take(&mut self.torrents)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut next = queue.iter();
+
+        for slot in 0..pending.len() {
+            let queued = pending[slot].as_ref().is_none_or(|t| t.queued);
+            let torrent = if queued {
+                let id = next.next().copied();
+                let index = pending
+                    .iter()
+                    .position(|t| t.as_ref().is_some_and(|t| Some(t.id) == id));
+                index.and_then(|index| pending[index].take())
+            } else {
+                pending[slot].take()
+            };
+            self.torrents.extend(torrent);
+        }
+    }
+
+    /// Caps how many announces run at the same time
+    #[cfg(feature = "http-tracker")]
+    pub fn set_announce_concurrency(&mut self, limit: usize) {
+        self.announce_pool = AnnouncePool::new(limit);
+    }
+
+    /// Opens a wire dump for a new connection when dumping is switched on
+    pub fn open_wire_dump(&self, peer: SocketAddr) -> Option<WireDump> {
+        WireDump::open(self.wire_dump_dir.as_ref()?, peer)
+    }
+
+    /// Adds a torrent and returns its id
+    ///
+    /// Its data goes to the directory it had last run , or the session's download directory
+    pub fn add_torrent(&mut self, torrent: Torrent) -> usize {
+        self.add_torrent_to(torrent, None)
+    }
+
+    /// Adds a torrent whose data goes to `output_dir` , None behaves like `add_torrent`
+    pub fn add_torrent_to(&mut self, torrent: Torrent, output_dir: Option<PathBuf>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut managed = self.managed_torrent(id, torrent);
+        let hash = managed.torrent.info_hashes().primary();
+        let rank = self.saved_queue.iter().position(|saved| saved == &hash);
+        managed.output_dir = output_dir.or_else(|| self.saved_output_dirs.get(&hash).cloned());
+        if let Some(saved) = self.saved_activity.get(&hash) {
+            managed.restore_activity(saved, self.clock.now(), SystemTime::now());
+        }
+
+        // Queued last run , goes back to the place it had
+        managed.queued = rank.is_some();
+        self.torrents.push(managed);
+
+        if rank.is_some() {
+            let mut queue = self.queue();
+            queue.sort_by_key(|&queued| self.saved_rank(queued).unwrap_or(usize::MAX));
+            self.apply_queue_order(&queue);
+        }
+        id
+    }
+
+    /// Downloads a .torrent over http(s) and adds it , sent through the configured bind like announces
+    #[cfg(feature = "http-tracker")]
+    pub async fn add_torrent_url(&mut self, fetch: TorrentFetch) -> Result<usize> {
+        if self.network_paused {
+            return Err(anyhow!("Networking is paused , can't fetch {}", fetch.url));
+        }
+
+        let torrent = fetch.with_bind(self.network.bind.clone()).fetch().await?;
+        Ok(self.add_torrent(torrent))
+    }
+
+    /// Fetches a magnet link's metadata from `peers` and adds the torrent it describes
+    ///
+    /// Peers from the link's `x.pe` are asked before `peers`
+    pub async fn add_magnet(&mut self, magnet: MagnetUri, peers: &[SocketAddr]) -> Result<usize> {
+        if self.network_paused {
+            return Err(anyhow!(
+                "Networking is paused , can't fetch metadata for {}",
+                magnet.name()
+            ));
+        }
+
+        let mut candidates = magnet.peers.clone();
+        candidates.extend(peers.iter().filter(|addr| !magnet.peers.contains(addr)));
+        let fetch = MetadataFetch::new(magnet, self.peer_id)
+            .with_config(&self.metadata)
+            .with_listen_port(self.listen_port)
+            .with_bind(self.network.bind.clone())
+            .with_clock(self.clock.clone());
+        let torrent = fetch.fetch(&candidates).await?;
+        Ok(self.add_torrent(torrent))
+    }
+
+    /// Removes a torrent from the session , returning it
+    ///
+    /// Whoever owns the torrent's connections and BlockManager has to drop them too , the counts in
+    /// `debug_resource_report` show whether they did
+    pub fn remove_torrent(&mut self, id: usize) -> Option<ManagedTorrent> {
+        let position = self.torrents.iter().position(|t| t.id == id)?;
+        Some(self.torrents.remove(position))
+    }
+
+    /// What the session and the process are holding on to , for tracking down leaks
+    pub fn debug_resource_report(&self) -> ResourceReport {
+        ResourceReport {
+            torrents: self.torrents.len(),
+            candidates: self.torrents.iter().map(|t| t.candidates.len()).sum(),
+            connected_peers: self.torrents.iter().map(|t| t.connected_peers).sum(),
+            usage: ResourceUsage::now(),
+        }
+    }
+
+    /// Where a torrent sat in the queue last run
+    fn saved_rank(&self, id: usize) -> Option<usize> {
+        let hash = self.get_torrent(id)?.torrent.info_hashes().primary();
+        self.saved_queue.iter().position(|saved| saved == &hash)
+    }
+
+    fn managed_torrent(&self, id: usize, torrent: Torrent) -> ManagedTorrent {
+        let mut managed = ManagedTorrent::new(id, torrent, self.peer_id, self.listen_port);
+        managed.active_since = Some(self.clock.now());
+        managed.trackers.set_bind(self.network.bind.clone());
+        managed
+            .candidates
+            .set_port_policy(self.network.ports.clone());
+        managed
+    }
+
+    /// Runs torrent logic with panics caught
+    ///
+    /// A panic stops only this torrent : it gets the panic message as its error and is left alone
+    /// until `clear_error`. Fails for unknown and already failed torrents
+    pub fn run_isolated<T>(
+        &mut self,
+        id: usize,
+        f: impl FnOnce(&mut ManagedTorrent) -> T,
+    ) -> Result<T> {
+        let torrent = self
+            .get_torrent_mut(id)
+            .ok_or_else(|| anyhow!("No torrent with id {}", id))?;
+        if let Some(error) = &torrent.error {
+            return Err(anyhow!(
+                "{} failed earlier : {}",
+                torrent.torrent.name,
+                error
+            ));
+        }
+
+        catch_panic(|| f(&mut *torrent)).map_err(|message| {
+            let error = anyhow!("{} stopped : {}", torrent.torrent.name, message);
+            torrent.fail(message);
+            error
+        })
+    }
+
+    /// Clears a torrent's error so it runs again , false if it had none
+    pub fn clear_error(&mut self, id: usize) -> bool {
+        let now = self.clock.now();
+        let Some(torrent) = self.get_torrent_mut(id) else {
+            return false;
+        };
+        if torrent.error.take().is_none() {
+            return false;
+        }
+        torrent.active_since = Some(now);
+        torrent.slot_filler.force_announce();
+        true
+    }
+
+    /// Torrents stopped by a panic in their logic
+    pub fn failed_torrents(&self) -> Vec<usize> {
+        self.torrents
+            .iter()
+            .filter(|t| t.error.is_some())
+            .map(|t| t.id)
+            .collect()
+    }
+
+    pub fn get_torrent(&self, id: usize) -> Option<&ManagedTorrent> {
+        self.torrents.iter().find(|t| t.id == id)
+    }
+
+    pub fn get_torrent_mut(&mut self, id: usize) -> Option<&mut ManagedTorrent> {
+        self.torrents.iter_mut().find(|t| t.id == id)
+    }
+
+    /// Whether a handshake's peer id is our own , i.e we connected to ourselves
+    pub fn is_self_connection(&self, remote_peer_id: &[u8; 20]) -> bool {
+        remote_peer_id == &self.peer_id
+    }
+
+    /// Finds the torrent an incoming handshake is for , hybrid torrents match either of their hashes
+    pub fn find_by_handshake_hash(&self, info_hash: &[u8; 20]) -> Option<&ManagedTorrent> {
+        self.torrents
+            .iter()
+            .find(|t| t.torrent.info_hashes().matches(info_hash))
+    }
+
+    /// Reserved bits we set in handshakes , only for subsystems that are on in this session
+    pub fn extensions(&self) -> Extensions {
+        Extensions {
+            extension_protocol: self.extension_protocol,
+            dht: self.advertised_dht_port().is_some(),
+            fast: false,
+        }
+    }
+
+    /// Turns the extension protocol (BEP 10) on or off for connections made from now on
+    pub fn set_extension_protocol(&mut self, enabled: bool) {
+        self.extension_protocol = enabled;
+    }
+
+    /// Port sent to peers in Port messages , None when the DHT is off or not built in
+    pub fn advertised_dht_port(&self) -> Option<u16> {
+        #[cfg(feature = "dht")]
+        if self.dht_config.enabled {
+            return Some(self.dht_port());
+        }
+        None
+    }
+
+    /// Our handshake for connecting out on a torrent's swarm
+    pub fn handshake_for(&self, id: usize) -> Option<Handshake> {
+        let torrent = self.get_torrent(id)?;
+        let handshake = Handshake::new(torrent.torrent.info_hashes().primary(), self.peer_id);
+        Some(handshake.with_extensions(self.extensions()))
+    }
+
+    /// Our answer to an incoming handshake , echoing the hash the peer used so hybrid torrents work
+    /// from either swarm. None for torrents we don't have or that are queued or paused
+    pub fn answer_handshake(&self, info_hash: &[u8; 20]) -> Option<Handshake> {
+        let torrent = self.find_by_handshake_hash(info_hash)?;
+        torrent
+            .is_active()
+            .then(|| Handshake::new(*info_hash, self.peer_id).with_extensions(self.extensions()))
+    }
+
+    /// Announces every torrent concurrently , results are (torrent id , response)
+    ///
+    /// Hybrid torrents are announced under both hashes so they show up in both swarms.
+    /// Peers from successful responses are queued on their torrent , duplicates and ourselves are dropped
+    #[cfg(feature = "http-tracker")]
+    pub async fn announce_all(
+        &mut self,
+        event: Option<TrackerEvent>,
+    ) -> Vec<(usize, Result<TrackerResponse>)> {
+        if self.network_paused {
+            return Vec::new();
+        }
+
+        // Queued torrents haven't started , the swarm doesn't know them yet
+        let jobs = self
+            .torrents
+            .iter()
+            .filter(|t| t.is_active())
+            .flat_map(|t| {
+                t.tracker_requests(self.listen_port, event.clone())
+                    .into_iter()
+                    .map(|request| AnnounceJob {
+                        id: t.id,
+                        tracker: t.tracker().clone(),
+                        request,
+                    })
+            })
+            .collect();
+
+        // Each result is applied as it comes in , a dead tracker doesn't hold up the other torrents
+        let torrents = &mut self.torrents;
+        let clock = &self.clock;
+        self.announce_pool
+            .announce_all(jobs, |id, result| {
+                if let Some(torrent) = torrents.iter_mut().find(|t| t.id == id) {
+                    apply_announce(torrent, result, event.is_none(), clock.now());
+                }
+            })
+            .await
+    }
+}
+
+/// Takes an announce result into the torrent's trackers , slot filler and peer candidates.
+/// `regular` is an announce without an event of its own
+#[cfg(feature = "http-tracker")]
+fn apply_announce(
+    torrent: &mut ManagedTorrent,
+    result: &Result<TrackerResponse>,
+    regular: bool,
+    now: std::time::Instant,
+) {
+    if let Ok(response) = result {
+        torrent.trackers.record_peers(response.peers.len());
+    }
+    // Failing over means the new tracker is asked right away , not at the next interval
+    if torrent.trackers.record_result(result) {
+        torrent.slot_filler.force_announce();
+    }
+
+    match result {
+        Ok(response) => {
+            // Without an event of their own , finished torrents sent `completed`
+            torrent.completion_announced |=
+                regular && torrent.completed_at.is_some() && !torrent.partial_seed;
+            torrent.slot_filler.announced(now, response.min_interval);
+            torrent.candidates.extend(response.peers.iter().cloned());
+        }
+        Err(_) => torrent.slot_filler.announced(now, None),
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(DEFAULT_LISTEN_PORT)
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{net::peer_candidates::PeerCandidates, protocol::peer::DiscoveredPeer};
+use std::time::{Duration, Instant};
+
+/// Connections we aim to keep open per torrent
+pub const DEFAULT_TARGET_PEERS: usize = 30;
+
+/// How often idle slots are checked
+pub const DEFAULT_RESCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Announcing early is never done more often than this , even if the tracker allows it
+pub const MIN_EARLY_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Spacing between DHT lookups started because we ran out of peers
+pub const DHT_LOOKUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// What the session should do to fill a torrent's free connection slots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotAction {
+    Connect(DiscoveredPeer),
+    /// Announce before the regular interval , the candidate pool is empty
+    Reannounce,
+    /// Ask the DHT for peers (`get_peers`)
+    DhtGetPeers,
+    /// Tell the DHT where we listen now (`announce_peer`) , sent after the listen port changed
+    DhtAnnounce,
+    /// The torrent sat idle too long and is paused now , close its connections and announce it stopped
+    Pause,
+}
+
+/// Keeps a torrent's swarm saturated
+///
+/// Every rescan it tops connections up from the candidate pool , and when the pool has run dry it asks
+/// trackers or the DHT for more , no sooner than they allow
+#[derive(Debug, Clone)]
+pub struct SlotFiller {
+    pub target_peers: usize,
+    pub rescan_interval: Duration,
+    last_scan: Option<Instant>,
+    last_announce: Option<Instant>,
+    /// Minimum gap between announces the tracker asked for
+    tracker_min_interval: Duration,
+    last_dht_lookup: Option<Instant>,
+    /// Swarm has stale connectivity info for us , announce on the next tick whatever the interval
+    announce_now: bool,
+}
+
+impl SlotFiller {
+    pub fn new(target_peers: usize) -> Self {
+        Self {
+            target_peers,
+            rescan_interval: DEFAULT_RESCAN_INTERVAL,
+            last_scan: None,
+            last_announce: None,
+            tracker_min_interval: MIN_EARLY_ANNOUNCE_INTERVAL,
+            last_dht_lookup: None,
+            announce_now: false,
+        }
+    }
+
+    /// Makes the next tick announce to trackers and the DHT , e.g because our listen port changed
+    pub fn force_announce(&mut self) {
+        self.announce_now = true;
+    }
+
+    /// Call whenever an announce went out , `min_interval` is the tracker's `min interval` if it sent one
+    pub fn announced(&mut self, now: Instant, min_interval: Option<u64>) {
+        self.last_announce = Some(now);
+        self.tracker_min_interval = min_interval
+            .map(Duration::from_secs)
+            .unwrap_or_default()
+            .max(MIN_EARLY_ANNOUNCE_INTERVAL);
+    }
+
+    /// Earliest time the trackers may be asked again , None when nothing was announced yet or an
+    /// announce is due on the next tick
+    pub fn next_announce(&self) -> Option<Instant> {
+        if self.announce_now {
+            return None;
+        }
+        self.last_announce
+            .map(|last| last + self.tracker_min_interval)
+    }
+
+    /// Works out what to do this round , nothing if the last rescan was too recent
+    ///
+    /// `connected` counts open and in-progress connections. Peers handed out as Connect are taken
+    /// off the candidate pool
+    pub fn tick(
+        &mut self,
+        now: Instant,
+        connected: usize,
+        candidates: &mut PeerCandidates,
+        dht_enabled: bool,
+    ) -> Vec<SlotAction> {
+        let mut actions = Vec::new();
+        if self.announce_now {
+            self.announce_now = false;
+            self.last_announce = Some(now);
+            actions.push(SlotAction::Reannounce);
+            if dht_enabled {
+                actions.push(SlotAction::DhtAnnounce);
+            }
+        }
+
+        if self
+            .last_scan
+            .is_some_and(|last| now.duration_since(last) < self.rescan_interval)
+        {
+            return actions;
+        }
+        self.last_scan = Some(now);
+
+        let mut free = self.target_peers.saturating_sub(connected);
+
+        while free > 0 {
+            let Some(peer) = candidates.next_candidate() else {
+                break;
+            };
+            actions.push(SlotAction::Connect(peer));
+            free -= 1;
+        }
+
+        if free == 0 {
+            return actions;
+        }
+
+        // Out of candidates with slots to spare , go looking for more
+        let may_announce = self
+            .last_announce
+            .is_none_or(|last| now.duration_since(last) >= self.tracker_min_interval);
+        if may_announce && !actions.contains(&SlotAction::Reannounce) {
+            actions.push(SlotAction::Reannounce);
+            self.last_announce = Some(now);
+        }
+
+        let may_lookup = self
+            .last_dht_lookup
+            .is_none_or(|last| now.duration_since(last) >= DHT_LOOKUP_INTERVAL);
+        if dht_enabled && may_lookup {
+            actions.push(SlotAction::DhtGetPeers);
+            self.last_dht_lookup = Some(now);
+        }
+
+        actions
+    }
+}
+
+impl Default for SlotFiller {
+    fn default() -> Self {
+        Self::new(DEFAULT_TARGET_PEERS)
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    core::config::config_dir,
+    protocol::bencode::BencodeValue,
+    storage::resume::{Envelope, read_with_backup, write_atomic},
+};
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Format tag of the session state file
+pub const SESSION_STATE_FORMAT: &str = "sekiro-session";
+
+pub const SESSION_STATE_VERSION: i64 = 1;
+
+/// Where session state is kept between runs , None if there is no config directory
+pub fn default_session_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("session.dat"))
+}
+
+/// Session wide state that outlives the process
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionState {
+    /// Info hashes of queued torrents , first in line first
+    pub queue: Vec<[u8; 20]>,
+    /// Download directories of torrents added with their own , by info hash
+    pub output_dirs: HashMap<[u8; 20], PathBuf>,
+    /// When each torrent last moved data , by info hash
+    pub activity: HashMap<[u8; 20], SavedActivity>,
+}
+
+/// A torrent's activity timestamps as unix times , so idle detection carries over restarts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SavedActivity {
+    pub active_since: Option<u64>,
+    pub last_upload: Option<u64>,
+    pub last_download: Option<u64>,
+}
+
+impl SessionState {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let queue = self
+            .queue
+            .iter()
+            .map(|hash| BencodeValue::bytes(hash))
+            .collect();
+
+        let mut output_dirs: Vec<_> = self.output_dirs.iter().collect();
+        output_dirs.sort();
+        let output_dirs = output_dirs
+            .into_iter()
+            .map(|(hash, dir)| {
+                BencodeValue::dict(vec![
+                    (b"info_hash", BencodeValue::bytes(hash)),
+                    (
+                        b"path",
+                        BencodeValue::bytes(dir.to_string_lossy().as_bytes()),
+                    ),
+                ])
+            })
+            .collect();
+
+        let mut activity: Vec<_> = self.activity.iter().collect();
+        activity.sort_by_key(|(hash, _)| **hash);
+        let activity = activity
+            .into_iter()
+            .map(|(hash, saved)| {
+                let mut entry = vec![(b"info_hash".as_slice(), BencodeValue::bytes(hash))];
+                let times = [
+                    (b"active_since".as_slice(), saved.active_since),
+                    (b"last_upload", saved.last_upload),
+                    (b"last_download", saved.last_download),
+                ];
+                for (key, at) in times {
+                    if let Some(at) = at {
+                        entry.push((key, BencodeValue::Integer(at as i64)));
+                    }
+                }
+                BencodeValue::dict(entry)
+            })
+            .collect();
+
+        let chunks = BencodeValue::dict(vec![
+            (b"queue", BencodeValue::List(queue)),
+            (b"output_dirs", BencodeValue::List(output_dirs)),
+            (b"activity", BencodeValue::List(activity)),
+        ]);
+        let envelope = Envelope::new(SESSION_STATE_FORMAT, SESSION_STATE_VERSION, chunks);
+
+        // A crash mid write keeps the previous state , which also stays around as backup
+        write_atomic(path, &envelope.encode())
+    }
+
+    /// Reads the state file , or its backup if the file is corrupt
+    pub fn load(path: &Path) -> Result<Self> {
+        read_with_backup(path, Self::decode).map(|read| read.value)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let envelope = Envelope::decode(bytes, SESSION_STATE_FORMAT)?;
+
+        if envelope.version != SESSION_STATE_VERSION {
+            return Err(anyhow!(
+                "Unsupported session state version {}",
+                envelope.version
+            ));
+        }
+
+        let queue = envelope
+            .chunks
+            .get(b"queue")
+            .and_then(|v| v.as_list())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| entry.as_bytes()?.as_ref().try_into().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Missing from state files written before output dirs were kept
+        let output_dirs = envelope
+            .chunks
+            .get(b"output_dirs")
+            .and_then(|v| v.as_list())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| {
+                        let hash = entry
+                            .get(b"info_hash")?
+                            .as_bytes()?
+                            .as_ref()
+                            .try_into()
+                            .ok()?;
+                        let path =
+                            String::from_utf8(entry.get(b"path")?.as_bytes()?.to_vec()).ok()?;
+                        Some((hash, PathBuf::from(path)))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Missing from state files written before activity was kept
+        let activity = envelope
+            .chunks
+            .get(b"activity")
+            .and_then(|v| v.as_list())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| {
+                        let hash = entry
+                            .get(b"info_hash")?
+                            .as_bytes()?
+                            .as_ref()
+                            .try_into()
+                            .ok()?;
+                        let time = |key: &[u8]| {
+                            let at = entry.get(key)?.as_integer()?;
+                            u64::try_from(at).ok()
+                        };
+                        let saved = SavedActivity {
+                            active_since: time(b"active_since"),
+                            last_upload: time(b"last_upload"),
+                            last_download: time(b"last_download"),
+                        };
+                        Some((hash, saved))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            queue,
+            output_dirs,
+            activity,
+        })
+    }
+
+    /// Loads saved state , or starts empty when there is none or it's unreadable
+    pub fn load_or_default(path: Option<&Path>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+
+        match Self::load(path) {
+            Ok(state) => state,
+            Err(e) => {
+                if path.exists() {
+                    crate::log_line!(
+                        "Could not load session state from {} : {}",
+                        path.display(),
+                        e
+                    );
+                }
+                Self::default()
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use clap::{Args, ValueEnum};
+use color_eyre::{Result, eyre::eyre};
+use mini_p2p_file_transfer_system::{
+    app::bandwidth::{
+        BandwidthLog, ReportGrouping, default_bandwidth_path, report_to_csv, report_to_json,
+    },
+    util::humanize,
+};
+use std::{fs, path::PathBuf};
+
+#[derive(Args, Debug, Clone)]
+pub struct BandwidthArgs {
+    #[arg(long, value_enum, default_value_t = Grouping::TorrentDay, help = "What one row covers")]
+    pub by: Grouping,
+    #[arg(long, value_enum, default_value_t = Format::Csv, help = "Output format")]
+    pub format: Format,
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Write the report here instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Bandwidth log to read (defaults to the config dir)"
+    )]
+    pub log: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Grouping {
+    TorrentDay,
+    Day,
+    Torrent,
+}
+
+impl From<Grouping> for ReportGrouping {
+    fn from(grouping: Grouping) -> Self {
+        match grouping {
+            Grouping::TorrentDay => ReportGrouping::TorrentDay,
+            Grouping::Day => ReportGrouping::Day,
+            Grouping::Torrent => ReportGrouping::Torrent,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// Exports the traffic saved by past sessions
+pub fn run(args: BandwidthArgs) -> Result<()> {
+    let path = args
+        .log
+        .or_else(default_bandwidth_path)
+        .ok_or_else(|| eyre!("No config directory , pass the log with --log"))?;
+    let log = BandwidthLog::load(&path).map_err(|e| eyre!("{} : {}", path.display(), e))?;
+
+    let rows = log.report(args.by.into());
+    let report = match args.format {
+        Format::Csv => report_to_csv(&rows),
+        Format::Json => format!("{:#}\n", report_to_json(&rows)),
+    };
+
+    match args.output {
+        Some(output) => {
+            fs::write(&output, report)?;
+            let total = log.total();
+            println!(
+                "Wrote {} rows to {} ({} up , {} down)",
+                rows.len(),
+                output.display(),
+                humanize::bytes(total.uploaded),
+                humanize::bytes(total.downloaded)
+            );
+        }
+        None => print!("{}", report),
+    }
+    Ok(())
+}
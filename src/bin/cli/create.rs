@@ -0,0 +1,156 @@
+use clap::{Args, ValueEnum};
+use color_eyre::{Result, eyre::eyre};
+use mini_p2p_file_transfer_system::{
+    core::events::{Event, EventBus},
+    protocol::{creator::TorrentCreator, info_hash::InfoHashes, torrent::TorrentVersion},
+    storage::hash_cache::HashCache,
+    util::humanize,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    thread,
+};
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Args, Debug, Clone)]
+pub struct CreateArgs {
+    #[arg(value_name = "PATH", help = "File or directory to share")]
+    pub source: PathBuf,
+    #[arg(short, long, help = "Tracker announce url")]
+    pub announce: String,
+    #[arg(short, long, value_name = "FILE", help = "Where to write the .torrent")]
+    pub output: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Piece length in bytes (picked from the total size when left out)"
+    )]
+    pub piece_length: Option<usize>,
+    #[arg(long, help = "Hashing threads (defaults to one per core)")]
+    pub threads: Option<usize>,
+    #[arg(long, help = "Mark the torrent private")]
+    pub private: bool,
+    #[arg(long, help = "Source tag , e.g for cross-seeding")]
+    pub source_tag: Option<String>,
+    #[arg(long, help = "Don't reuse or store cached piece hashes")]
+    pub no_cache: bool,
+    #[arg(long, value_enum, default_value_t = Version::V1, help = "Metadata version to write")]
+    pub version: Version,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Version {
+    V1,
+    V2,
+    Hybrid,
+}
+
+impl From<Version> for TorrentVersion {
+    fn from(version: Version) -> Self {
+        match version {
+            Version::V1 => TorrentVersion::V1,
+            Version::V2 => TorrentVersion::V2,
+            Version::Hybrid => TorrentVersion::Hybrid,
+        }
+    }
+}
+
+/// Width of the progress bar in characters
+const BAR_WIDTH: usize = 40;
+
+/// Creates a torrent , drawing a progress bar from the hashing events
+pub fn run(args: CreateArgs) -> Result<()> {
+    let events = EventBus::default();
+    let mut progress = events.subscribe();
+
+    let mut creator = TorrentCreator::new(&args.source, &args.announce)
+        .with_private(args.private)
+        .with_version(args.version.into())
+        .with_events(events);
+    if let Some(piece_length) = args.piece_length {
+        creator = creator.with_piece_length(piece_length);
+    }
+    if let Some(threads) = args.threads {
+        creator = creator.with_threads(threads);
+    }
+    if let Some(tag) = &args.source_tag {
+        creator = creator.with_source(tag);
+    }
+    if !args.no_cache
+        && let Some(cache) = HashCache::open_default()
+    {
+        creator = creator.with_hash_cache(cache);
+    }
+
+    // The creator owns the only sender , the channel closes once it is done
+    let worker = thread::spawn(move || creator.create_report());
+
+    loop {
+        match progress.blocking_recv() {
+            Ok(Event::HashProgress {
+                hashed_pieces,
+                total_pieces,
+                ..
+            }) => draw_bar(hashed_pieces, total_pieces),
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        }
+    }
+    eprintln!();
+
+    let report = worker
+        .join()
+        .map_err(|_| eyre!("Torrent creation panicked"))?
+        .map_err(|e| eyre!("Could not create torrent : {}", e))?;
+
+    let output = args.output.unwrap_or_else(|| {
+        let name = args
+            .source
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        PathBuf::from(format!("{}.torrent", name))
+    });
+    fs::write(&output, &report.bytes)?;
+
+    let hashes = InfoHashes::of_metainfo(&report.bytes).map_err(|e| eyre!("{}", e))?;
+    println!("Created {}", output.display());
+    println!(
+        "  piece length : {}{} , {} pieces over {}",
+        humanize::bytes(report.piece_length as u64),
+        if report.auto_piece_length {
+            " (auto)"
+        } else {
+            ""
+        },
+        report.piece_count,
+        humanize::bytes(report.total_bytes)
+    );
+    if let Some(v1) = hashes.v1 {
+        println!("  v1 info hash : {}", hex::encode(v1));
+    }
+    if let Some(v2) = hashes.v2 {
+        println!("  v2 info hash : {}", hex::encode(v2));
+    }
+    Ok(())
+}
+
+fn draw_bar(done: usize, total: usize) {
+    let filled = (done * BAR_WIDTH).checked_div(total).unwrap_or(BAR_WIDTH);
+    let percent = if total == 0 {
+        100.0
+    } else {
+        done as f64 * 100.0 / total as f64
+    };
+
+    eprint!(
+        "\rHashing [{}{}] {:.1}% ({}/{} pieces)",
+        "=".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        percent,
+        done,
+        total
+    );
+    let _ = io::stderr().flush();
+}
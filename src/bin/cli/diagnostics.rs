@@ -0,0 +1,41 @@
+use mini_p2p_file_transfer_system::{net::diagnostics::PeerDiagnostics, util::humanize};
+
+/// Outstanding requests listed under each peer , the oldest are the ones worth seeing
+const REQUESTS_PER_PEER: usize = 5;
+
+/// Text for the diagnostics pane , `keys` is the line of key hints under the title
+pub fn render(peers: &[PeerDiagnostics], keys: &str) -> String {
+    let outstanding: usize = peers.iter().map(|peer| peer.outstanding.len()).sum();
+    let mut content = format!(
+        "Diagnostics ({} peers) - {} requests outstanding\n",
+        peers.len(),
+        outstanding
+    );
+    content.push_str(&format!("  {}\n\n", keys));
+
+    if peers.is_empty() {
+        content.push_str("No peers connected\n");
+        return content;
+    }
+
+    for peer in peers {
+        content.push_str(&format!("{}\n", peer));
+        for request in peer.outstanding.iter().take(REQUESTS_PER_PEER) {
+            content.push_str(&format!(
+                "    piece {} @ {} ({}) , {}\n",
+                request.piece_index,
+                request.begin,
+                humanize::bytes(request.length as u64),
+                humanize::duration(request.age)
+            ));
+        }
+        if peer.outstanding.len() > REQUESTS_PER_PEER {
+            content.push_str(&format!(
+                "    ... {} more\n",
+                peer.outstanding.len() - REQUESTS_PER_PEER
+            ));
+        }
+    }
+
+    content
+}
@@ -0,0 +1,72 @@
+use clap::Args;
+use color_eyre::{Result, eyre::eyre};
+use mini_p2p_file_transfer_system::{
+    core::config::Config,
+    net::torrent_fetch::{DEFAULT_MAX_TORRENT_FILE_SIZE, TorrentFetch, is_url},
+    protocol::torrent::Torrent,
+};
+use std::{env, fs, path::PathBuf};
+
+#[derive(Args, Debug, Clone)]
+pub struct DownloadArgs {
+    #[arg(value_name = "SOURCE", help = "Path or http(s) url of a .torrent file")]
+    pub source: String,
+    #[arg(
+        long = "header",
+        value_name = "NAME: VALUE",
+        help = "Extra header sent when fetching a url , can be repeated"
+    )]
+    pub headers: Vec<String>,
+    #[arg(
+        long,
+        help = "Cookie sent when fetching a url , for private tracker links"
+    )]
+    pub cookie: Option<String>,
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_TORRENT_FILE_SIZE,
+        help = "Largest .torrent fetched from a url , in bytes"
+    )]
+    pub max_size: usize,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Where the torrent is downloaded , instead of the configured download dir"
+    )]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Resolves the source to a local .torrent for the TUI to open
+///
+/// Urls are fetched and saved to the temp dir under their info hash , paths are used as they are
+pub fn run(args: DownloadArgs) -> Result<PathBuf> {
+    if !is_url(&args.source) {
+        return Ok(PathBuf::from(args.source));
+    }
+
+    let config = Config::load_default().map_err(|e| eyre!("{}", e))?;
+    let mut fetch = TorrentFetch::new(args.source.clone())
+        .with_max_size(args.max_size)
+        .with_bind(config.network.bind.clone());
+    for header in &args.headers {
+        fetch = fetch.with_header_line(header).map_err(|e| eyre!("{}", e))?;
+    }
+    if let Some(cookie) = args.cookie {
+        fetch = fetch.with_cookie(cookie);
+    }
+
+    println!("Fetching {}", args.source);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let bytes = runtime
+        .block_on(fetch.fetch_bytes())
+        .map_err(|e| eyre!("{}", e))?;
+    let torrent = Torrent::from_bytes(&bytes)
+        .map_err(|e| eyre!("{} is not a valid torrent : {}", args.source, e))?;
+
+    let path = env::temp_dir().join(format!("{}.torrent", hex::encode(torrent.info_hash)));
+    fs::write(&path, &bytes)?;
+    println!("Saved {} to {}", torrent.name, path.display());
+    Ok(path)
+}
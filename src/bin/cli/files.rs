@@ -0,0 +1,65 @@
+use mini_p2p_file_transfer_system::{
+    core::priority::FilePriority, net::block_manager::FileProgress, util::humanize,
+};
+
+/// Selection in the files pane
+#[derive(Debug, Clone, Default)]
+pub struct FilesView {
+    pub selected: usize,
+}
+
+impl FilesView {
+    /// Moves the selection , staying on the list
+    pub fn select_next(&mut self, files: usize) {
+        self.selected = (self.selected + 1).min(files.saturating_sub(1));
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Text for the files pane , `keys` is the line of key hints under the title
+    pub fn render(&self, files: &[FileProgress], keys: &str) -> String {
+        let skipped = files
+            .iter()
+            .filter(|file| file.priority.is_skipped())
+            .count();
+        let mut content = format!("Files ({}) - {} skipped\n", files.len(), skipped);
+        content.push_str(&format!("  {}\n\n", keys));
+
+        for file in files {
+            let done = if file.pieces_total > 0 {
+                file.pieces_done as f64 * 100.0 / file.pieces_total as f64
+            } else {
+                100.0
+            };
+            content.push_str(&format!(
+                "{} {:<8} {:>6.1}% {:>10}  {}{}\n",
+                if file.index == self.selected {
+                    ">"
+                } else {
+                    " "
+                },
+                priority_label(file.priority),
+                done,
+                humanize::bytes(file.length as u64),
+                file.path.display(),
+                if file.quarantined {
+                    " [quarantined]"
+                } else {
+                    ""
+                }
+            ));
+        }
+
+        content
+    }
+}
+
+/// e.g "[high]" , skipped files stand out with a dash
+fn priority_label(priority: FilePriority) -> String {
+    match priority {
+        FilePriority::Skip => String::from("[-skip-]"),
+        priority => format!("[{}]", priority),
+    }
+}
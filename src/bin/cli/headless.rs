@@ -0,0 +1,807 @@
+use clap::{Args, ValueEnum};
+use color_eyre::{Result, eyre::eyre};
+use mini_p2p_file_transfer_system::{
+    app::{
+        bandwidth::{BandwidthLog, Usage, default_bandwidth_path},
+        session::DEFAULT_LISTEN_PORT,
+    },
+    core::{
+        bind::BindTarget,
+        clock::SuspendDetector,
+        config::Config,
+        config_watch::ConfigWatcher,
+        events::{CorruptionCheck, Event, EventBus},
+        resources::ResourceUsage,
+    },
+    logging::console::log_to_stderr,
+    net::{
+        announcer::{AnnounceOutcome, Announcer},
+        block_manager::{BlockManager, DownloadStats},
+        diagnostics::PeerDiagnostics,
+        listener::{Answer, PeerListener},
+        peer_candidates::PeerCandidates,
+        peer_manager::{PeerEvent, PeerManager},
+        rate_limit::{RateLimits, Rates},
+        tracker::{Tracker, TrackerEvent, TrackerRequest},
+        tracker_manager::TrackerManager,
+    },
+    protocol::{
+        handshake::{Extensions, Handshake},
+        torrent::Torrent,
+    },
+    storage::{
+        files::FileStorage,
+        resume::{default_resume_dir, resume_path},
+    },
+    util::humanize,
+};
+#[cfg(feature = "dht")]
+use mini_p2p_file_transfer_system::{
+    core::runtime::tokio_runtime,
+    net::dht::{
+        routing::RoutingTable, scrape::SwarmEstimate, service::DhtService,
+        state::default_state_path,
+    },
+};
+use serde_json::{Value, json};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::broadcast::error::TryRecvError;
+
+#[derive(Args, Debug, Clone)]
+pub struct HeadlessArgs {
+    #[arg(value_name = "FILE", help = "Path to the .torrent file")]
+    pub torrent: PathBuf,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Where the torrent is downloaded , instead of the configured download dir"
+    )]
+    pub output_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ProgressFormat::Text,
+        help = "How progress is printed on stdout"
+    )]
+    pub progress_format: ProgressFormat,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 1.0,
+        help = "Time between progress reports"
+    )]
+    pub progress_interval: f64,
+    #[arg(
+        long,
+        help = "Print outstanding requests , send queue and RTT of every peer with each progress report"
+    )]
+    pub diagnostics: bool,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Record every message of every peer connection to a JSONL file in DIR , for protocol debugging"
+    )]
+    pub wire_dump: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// One readable line per report
+    Text,
+    /// One JSON object per line , for GUIs and bots wrapping the client
+    Jsonl,
+}
+
+/// How often connections are polled
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the kill switch looks for the bound address or interface
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Settings taken over from an edited config , every other change waits for a restart
+const RELOADABLE: &[&str] = &["verify_writes"];
+
+/// How long aborted connection tasks get to wind down before the leak check
+const TEARDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// How long the DHT node gets to finish a lookup on shutdown , its routing table isn't saved after that
+#[cfg(feature = "dht")]
+const DHT_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Downloads a torrent without the TUI , printing progress and engine events on stdout
+///
+/// With `--progress-format jsonl` every report is a JSON object on its own line with a "type" of
+/// progress , peer , event , error , complete or diagnostics. The engine's own log lines go to
+/// stderr then , so stdout holds nothing but JSON
+pub fn run(args: HeadlessArgs, rates: Rates) -> Result<()> {
+    log_to_stderr(args.progress_format == ProgressFormat::Jsonl);
+    let config = Config::load_default().map_err(|e| eyre!("{}", e))?;
+    let bytes = fs::read(&args.torrent)?;
+    let torrent = Torrent::from_bytes(&bytes).map_err(|e| eyre!("{}", e))?;
+    let download_dir = args
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| config.download_dir());
+
+    let events = EventBus::default();
+    let storage = Box::new(FileStorage::from(torrent.clone(), download_dir));
+    let manager = match default_resume_dir() {
+        Some(dir) => {
+            let path = resume_path(&dir, &torrent.info_hash);
+            BlockManager::with_resume_file_and_events(
+                torrent.clone(),
+                storage,
+                path,
+                events.clone(),
+            )
+        }
+        None => BlockManager::with_storage(torrent.clone(), storage)
+            .map(|manager| manager.with_events(events.clone())),
+    }
+    .map_err(|e| eyre!("Failed to init block manager : {}", e))?
+    .with_write_verification(config.verify_writes);
+
+    let limits = RateLimits::unlimited();
+    limits.set_global(rates);
+
+    let interval = Duration::try_from_secs_f64(args.progress_interval)
+        .map_err(|_| eyre!("Invalid progress interval : {}", args.progress_interval))?;
+    let mut reporter = Reporter::new(args.progress_format, args.diagnostics);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let options = ReportOptions {
+        interval,
+        wire_dump: args.wire_dump,
+    };
+    runtime.block_on(download(
+        torrent,
+        manager,
+        &config,
+        limits,
+        events,
+        options,
+        &mut reporter,
+    ))
+}
+
+async fn download(
+    torrent: Torrent,
+    mut manager: BlockManager,
+    config: &Config,
+    limits: RateLimits,
+    events: EventBus,
+    options: ReportOptions,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    let ReportOptions {
+        interval,
+        wire_dump,
+    } = options;
+    let peer_id = Tracker::generate_peer_id();
+    let mut trackers = TrackerManager::from_torrent(&torrent, peer_id);
+    trackers.set_bind(config.network.bind.clone());
+    let mut announcer = Announcer::new(trackers);
+    let mut candidates = PeerCandidates::new(peer_id, DEFAULT_LISTEN_PORT);
+    candidates.set_port_policy(config.network.ports.clone());
+
+    // Peers from the DHT on top of the trackers' , the DHT bit is only set while the node runs
+    #[cfg(feature = "dht")]
+    let mut dht = start_dht(config, &torrent, reporter).await;
+    #[cfg(feature = "dht")]
+    let dht_port = dht.as_ref().map(DhtService::port);
+    #[cfg(not(feature = "dht"))]
+    let dht_port = None;
+
+    let handshake =
+        Handshake::new(torrent.info_hashes().primary(), peer_id).with_extensions(Extensions {
+            extension_protocol: true,
+            dht: dht_port.is_some(),
+            fast: false,
+        });
+    let mut listener = listen(&torrent, handshake, config, reporter);
+    let mut peers = PeerManager::new(handshake, DEFAULT_LISTEN_PORT)
+        .with_bind(config.network.bind.clone())
+        .with_encryption(config.network.encryption)
+        .with_dht_port(dht_port)
+        .with_wire_dump(wire_dump)
+        .with_port_policy(config.network.ports.clone())
+        .with_limit_lan_peers(config.network.limit_lan_peers)
+        .with_rate_limits(limits);
+    let mut engine_events = events.subscribe();
+
+    // Traffic of this run goes into the log `cli bandwidth` reads , the totals before it came
+    // from the resume file and were logged back then
+    let bandwidth_path = default_bandwidth_path();
+    let mut bandwidth = BandwidthLog::load_or_default(bandwidth_path.as_deref());
+    let mut logged = transferred(&manager.get_stats());
+
+    let mut next_report = Instant::now();
+    let mut suspend = SuspendDetector::new();
+    let mut network_paused = false;
+    let mut next_network_check = Instant::now();
+    let mut config_watcher =
+        Config::default_path().map(|path| ConfigWatcher::new(path, config.clone()));
+    // Ctrl-C ends the loop early , trackers still hear `stopped` below
+    let interrupt = tokio::signal::ctrl_c();
+    tokio::pin!(interrupt);
+    let mut stopped_by: Option<Result<()>> = None;
+
+    while !manager.is_download_complete() {
+        let now = Instant::now();
+        // Timers start over and trackers are asked again , instead of everything timing out at once
+        if let Some(away) = suspend.check(now, SystemTime::now()) {
+            reporter.resumed(away);
+            for event in peers.resumed(&mut manager) {
+                reporter.peer(&event);
+            }
+            announcer.resumed();
+            #[cfg(feature = "dht")]
+            if let Some(dht) = &dht {
+                dht.lookup_now();
+            }
+        }
+
+        // Kill switch , nothing goes out while the bound address or interface is gone
+        if now >= next_network_check {
+            next_network_check = now + NETWORK_CHECK_INTERVAL;
+            if let Some(bind) = &config.network.bind
+                && config.network.kill_switch
+                && bind.is_available() == network_paused
+            {
+                network_paused = !network_paused;
+                reporter.network(bind, network_paused);
+                peers.set_network_paused(network_paused);
+                if network_paused {
+                    listener = None;
+                    let addrs: Vec<_> = peers.addrs().copied().collect();
+                    for addr in addrs {
+                        peers.disconnect(&addr, &mut manager);
+                        reporter.peer(&PeerEvent::Disconnected {
+                            addr,
+                            reason: String::from("networking paused"),
+                        });
+                    }
+                } else {
+                    listener = listen(&torrent, handshake, config, reporter);
+                }
+            }
+        }
+
+        announcer.set_partial_seed(manager.is_partial_seed());
+        if !network_paused {
+            for outcome in announcer.poll(&announce_request(&torrent, &manager)) {
+                reporter.announce(outcome, &mut candidates);
+            }
+        }
+        #[cfg(feature = "dht")]
+        if let Some(dht) = dht.as_mut() {
+            candidates.extend(dht.poll());
+            if let Some(estimate) = dht.take_estimate() {
+                reporter.swarm(&estimate);
+            }
+        }
+
+        peers.dial_candidates(&mut candidates);
+        if let Some(listener) = listener.as_mut() {
+            for connection in listener.poll() {
+                let addr = connection.addr();
+                if peers.add_incoming(connection, &mut manager) {
+                    reporter.peer(&PeerEvent::Connected(addr));
+                }
+            }
+        }
+        for event in peers.poll(&mut manager) {
+            reporter.peer(&event);
+        }
+
+        loop {
+            match engine_events.try_recv() {
+                Ok(event) => reporter.event(&event),
+                Err(TryRecvError::Lagged(skipped)) => {
+                    reporter.error(&format!("Missed {} engine events", skipped))
+                }
+                Err(_) => break,
+            }
+        }
+
+        if now >= next_report {
+            reload_config(&mut config_watcher, &mut manager, &events, reporter);
+            let stats = manager.get_stats();
+            log_bandwidth(&mut bandwidth, &mut logged, &torrent, &stats);
+            reporter.progress(&stats, peers.len());
+            if reporter.diagnostics {
+                reporter.diagnostics(&peers.diagnostics(&manager));
+            }
+            next_report = now + interval;
+        }
+
+        // Waiting for Ctrl-C doubles as the poll interval sleep
+        if let Ok(signal) = tokio::time::timeout(POLL_INTERVAL, &mut interrupt).await {
+            stopped_by = Some(match signal {
+                Ok(()) => Err(eyre!("Interrupted")),
+                Err(e) => Err(eyre!("Could not listen for Ctrl-C : {}", e)),
+            });
+            break;
+        }
+    }
+
+    manager.wait_for_verifications();
+    if let Err(e) = manager.save_resume_data() {
+        reporter.error(&format!("Could not save resume data : {}", e));
+    }
+    // We exit once the download is done , trackers hear `completed` and then `stopped`.
+    // An interrupted download only sends `stopped`
+    if stopped_by.is_none() {
+        announcer.completed();
+    }
+    for outcome in announcer.stop(&announce_request(&torrent, &manager)).await {
+        reporter.announce(outcome, &mut candidates);
+    }
+    #[cfg(feature = "dht")]
+    if let Some(dht) = dht {
+        stop_dht(dht, reporter).await;
+    }
+
+    let stats = manager.get_stats();
+    reporter.progress(&stats, peers.len());
+    log_bandwidth(&mut bandwidth, &mut logged, &torrent, &stats);
+    if let Some(path) = &bandwidth_path
+        && let Err(e) = bandwidth.save(path)
+    {
+        reporter.error(&format!("Could not save bandwidth log : {}", e));
+    }
+
+    // Every connection , task and open file of the torrent goes away with it
+    drop(listener);
+    drop(peers);
+    drop(manager);
+    if cfg!(debug_assertions) {
+        let usage = ResourceUsage::settled(TEARDOWN_GRACE).await;
+        debug_assert!(usage.is_idle(), "Left behind after teardown : {}", usage);
+    }
+
+    if let Some(result) = stopped_by {
+        return result;
+    }
+    reporter.complete(&stats);
+    Ok(())
+}
+
+/// Listens for peers on our port , None when the port can't be bound
+fn listen(
+    torrent: &Torrent,
+    handshake: Handshake,
+    config: &Config,
+    reporter: &Reporter,
+) -> Option<PeerListener> {
+    // Hybrid torrents are reached under either hash , we answer with the one the peer used
+    let info_hashes = torrent.info_hashes();
+    let answer: Answer = Arc::new(move |info_hash: &[u8; 20]| {
+        info_hashes.matches(info_hash).then(|| {
+            Handshake::new(*info_hash, handshake.peer_id).with_extensions(handshake.extensions())
+        })
+    });
+    PeerListener::bind(DEFAULT_LISTEN_PORT, config.network.bind.as_ref(), answer)
+        .map_err(|e| reporter.error(&format!("Not accepting peers : {}", e)))
+        .ok()
+}
+
+/// Starts the DHT node for the torrent with the saved routing table , None when it's off or failed
+#[cfg(feature = "dht")]
+async fn start_dht(config: &Config, torrent: &Torrent, reporter: &Reporter) -> Option<DhtService> {
+    if !config.dht.enabled {
+        return None;
+    }
+    let table = RoutingTable::load_or_new(default_state_path().as_deref());
+    let started = DhtService::start(
+        &config.dht,
+        table,
+        torrent.info_hashes().primary(),
+        DEFAULT_LISTEN_PORT,
+        config.network.bind.as_ref(),
+        tokio_runtime(),
+    )
+    .await;
+    started
+        .map_err(|e| reporter.error(&format!("DHT not started : {}", e)))
+        .ok()
+}
+
+/// Stops the DHT node and keeps its routing table for the next run
+#[cfg(feature = "dht")]
+async fn stop_dht(dht: DhtService, reporter: &Reporter) {
+    let Ok(Some(table)) = tokio::time::timeout(DHT_STOP_TIMEOUT, dht.stop()).await else {
+        return;
+    };
+    if let Some(path) = default_state_path()
+        && let Err(e) = table.save(&path)
+    {
+        reporter.error(&format!("Could not save DHT state : {}", e));
+    }
+}
+
+/// Takes over what changed in the config file , the outcome goes out as an engine event
+fn reload_config(
+    watcher: &mut Option<ConfigWatcher>,
+    manager: &mut BlockManager,
+    events: &EventBus,
+    reporter: &Reporter,
+) {
+    let Some(reload) = watcher.as_mut().and_then(ConfigWatcher::poll) else {
+        return;
+    };
+    let reload = match reload {
+        Ok(reload) => reload.applying(RELOADABLE),
+        Err(e) => {
+            reporter.error(&format!("Config not reloaded : {}", e));
+            return;
+        }
+    };
+    if reload.is_empty() {
+        return;
+    }
+
+    if reload.applied.iter().any(|name| name == "verify_writes") {
+        manager.set_write_verification(reload.config.verify_writes);
+    }
+    events.publish(reload.to_event());
+}
+
+/// Bytes moved for the torrent , over every run
+fn transferred(stats: &DownloadStats) -> Usage {
+    Usage {
+        uploaded: stats.uploaded_bytes,
+        downloaded: stats.total_downloaded,
+    }
+}
+
+/// Adds what moved since the last call to the bandwidth log , under today's (UTC) date
+fn log_bandwidth(
+    log: &mut BandwidthLog,
+    logged: &mut Usage,
+    torrent: &Torrent,
+    stats: &DownloadStats,
+) {
+    let now = transferred(stats);
+    let delta = Usage {
+        uploaded: now.uploaded.saturating_sub(logged.uploaded),
+        downloaded: now.downloaded.saturating_sub(logged.downloaded),
+    };
+    *logged = now;
+    if delta.total() > 0 {
+        log.record(
+            torrent.info_hashes().primary(),
+            &torrent.name,
+            chrono::Utc::now().date_naive(),
+            delta,
+        );
+    }
+}
+
+/// Current totals , the announcer fills in the event
+fn announce_request(torrent: &Torrent, manager: &BlockManager) -> TrackerRequest {
+    let stats = manager.get_stats();
+    TrackerRequest {
+        info_hash: torrent.info_hashes().primary(),
+        left: stats.total_bytes.saturating_sub(stats.downloaded_bytes) as u64,
+        uploaded: stats.uploaded_bytes,
+        downloaded: stats.total_downloaded,
+        port: DEFAULT_LISTEN_PORT,
+        compact: true,
+        event: None,
+    }
+}
+
+/// Prints reports in the chosen format , rates are worked out between two progress reports
+/// What the download loop writes out besides the data , from the command line
+struct ReportOptions {
+    /// Time between progress reports
+    interval: Duration,
+    /// Where connections dump their messages , see `--wire-dump`
+    wire_dump: Option<PathBuf>,
+}
+
+struct Reporter {
+    format: ProgressFormat,
+    /// When the last progress report was made , with the byte counters at the time
+    last: Option<(Instant, u64, u64)>,
+    /// Per peer diagnostics follow every progress report
+    diagnostics: bool,
+}
+
+impl Reporter {
+    fn new(format: ProgressFormat, diagnostics: bool) -> Self {
+        Self {
+            format,
+            last: None,
+            diagnostics,
+        }
+    }
+
+    fn progress(&mut self, stats: &DownloadStats, peers: usize) {
+        let now = Instant::now();
+        let (download_rate, upload_rate) = match self.last {
+            Some((at, downloaded, uploaded)) if now > at => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                (
+                    stats.total_downloaded.saturating_sub(downloaded) as f64 / elapsed,
+                    stats.uploaded_bytes.saturating_sub(uploaded) as f64 / elapsed,
+                )
+            }
+            _ => (0.0, 0.0),
+        };
+        self.last = Some((now, stats.total_downloaded, stats.uploaded_bytes));
+
+        match self.format {
+            ProgressFormat::Text => println!(
+                "{:>5.1}% {}/{} pieces , {} / {} , down {} , up {} , {} peers , ETA {}",
+                stats.progress_percentage(),
+                stats.verified_pieces,
+                stats.total_pieces,
+                humanize::bytes(stats.downloaded_bytes as u64),
+                humanize::bytes(stats.total_bytes as u64),
+                humanize::rate(download_rate),
+                humanize::rate(upload_rate),
+                peers,
+                humanize::eta(stats.eta_seconds())
+            ),
+            ProgressFormat::Jsonl => emit(json!({
+                "type": "progress",
+                "percent": stats.progress_percentage(),
+                "verified_pieces": stats.verified_pieces,
+                "total_pieces": stats.total_pieces,
+                "downloaded_bytes": stats.downloaded_bytes,
+                "total_bytes": stats.total_bytes,
+                "uploaded_bytes": stats.uploaded_bytes,
+                "download_rate": download_rate.round() as u64,
+                "upload_rate": upload_rate.round() as u64,
+                "peers": peers,
+                "eta_seconds": stats.eta_seconds(),
+            })),
+        }
+    }
+
+    fn diagnostics(&self, peers: &[PeerDiagnostics]) {
+        for peer in peers {
+            match self.format {
+                ProgressFormat::Text => println!("  {}", peer),
+                ProgressFormat::Jsonl => emit(json!({
+                    "type": "diagnostics",
+                    "addr": peer.addr.to_string(),
+                    "outstanding": peer
+                        .outstanding
+                        .iter()
+                        .map(|request| json!({
+                            "piece_index": request.piece_index,
+                            "begin": request.begin,
+                            "length": request.length,
+                            "age_ms": request.age.as_millis() as u64,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "pending_uploads": peer.pending_uploads,
+                    "queued_messages": peer.queued_messages,
+                    "rtt_ms": peer.rtt.map(|rtt| rtt.as_millis() as u64),
+                    "pipeline_depth": peer.pipeline_depth,
+                    "peer_choking": peer.peer_choking,
+                    "snubbed": peer.snubbed,
+                })),
+            }
+        }
+    }
+
+    /// Reports an announce , peers it returned are queued on `candidates`
+    fn announce(&self, outcome: AnnounceOutcome, candidates: &mut PeerCandidates) {
+        let (tracker, event, response) = match outcome {
+            AnnounceOutcome::Announced {
+                tracker,
+                event,
+                response,
+            } => (tracker, event, response),
+            AnnounceOutcome::Failed {
+                tracker,
+                event,
+                error,
+            } => {
+                return self.error(&format!(
+                    "Announce{} to {} failed : {}",
+                    event_label(event.as_ref()),
+                    tracker,
+                    error
+                ));
+            }
+        };
+
+        let new_peers = candidates.extend(response.peers);
+        match self.format {
+            ProgressFormat::Text => println!(
+                "{} returned {} new peers{}",
+                tracker,
+                new_peers,
+                event_label(event.as_ref())
+            ),
+            ProgressFormat::Jsonl => emit(json!({
+                "type": "event",
+                "event": "announced",
+                "tracker": tracker,
+                "tracker_event": event.as_ref().map(TrackerEvent::as_str),
+                "new_peers": new_peers,
+                "interval_seconds": response.interval,
+            })),
+        }
+    }
+
+    fn peer(&self, event: &PeerEvent) {
+        match (self.format, event) {
+            (ProgressFormat::Text, PeerEvent::Connected(addr)) => println!("Connected to {}", addr),
+            (ProgressFormat::Text, PeerEvent::Disconnected { addr, reason }) => {
+                println!("Disconnected from {} : {}", addr, reason)
+            }
+            (ProgressFormat::Jsonl, PeerEvent::Connected(addr)) => emit(json!({
+                "type": "peer",
+                "event": "connected",
+                "addr": addr.to_string(),
+            })),
+            (ProgressFormat::Jsonl, PeerEvent::Disconnected { addr, reason }) => emit(json!({
+                "type": "peer",
+                "event": "disconnected",
+                "addr": addr.to_string(),
+                "reason": reason,
+            })),
+        }
+    }
+
+    fn event(&self, event: &Event) {
+        let (name, mut fields) = event_fields(event);
+        match self.format {
+            ProgressFormat::Text => println!("{} {}", name, fields),
+            ProgressFormat::Jsonl => {
+                fields["type"] = json!("event");
+                fields["event"] = json!(name);
+                emit(fields)
+            }
+        }
+    }
+
+    #[cfg(feature = "dht")]
+    fn swarm(&self, estimate: &SwarmEstimate) {
+        match self.format {
+            ProgressFormat::Text => println!(
+                "Swarm (DHT estimate): ~{} seeds , ~{} peers from {} nodes",
+                estimate.seeds(),
+                estimate.peers(),
+                estimate.responses
+            ),
+            ProgressFormat::Jsonl => emit(json!({
+                "type": "swarm",
+                "seeds": estimate.seeds(),
+                "peers": estimate.peers(),
+                "nodes": estimate.responses,
+            })),
+        }
+    }
+
+    /// The kill switch paused or resumed networking
+    fn network(&self, bind: &BindTarget, paused: bool) {
+        match (self.format, paused) {
+            (ProgressFormat::Text, true) => println!("{} went away , networking paused", bind),
+            (ProgressFormat::Text, false) => println!("{} is back , networking resumed", bind),
+            (ProgressFormat::Jsonl, paused) => emit(json!({
+                "type": "network",
+                "bind": bind.to_string(),
+                "paused": paused,
+            })),
+        }
+    }
+
+    fn resumed(&self, away: Duration) {
+        match self.format {
+            ProgressFormat::Text => println!(
+                "Resumed after {} , re-checking peers and trackers",
+                humanize::duration(away)
+            ),
+            ProgressFormat::Jsonl => emit(json!({
+                "type": "resumed",
+                "away_seconds": away.as_secs(),
+            })),
+        }
+    }
+
+    fn error(&self, message: &str) {
+        match self.format {
+            ProgressFormat::Text => println!("ERROR: {}", message),
+            ProgressFormat::Jsonl => emit(json!({ "type": "error", "message": message })),
+        }
+    }
+
+    fn complete(&self, stats: &DownloadStats) {
+        match self.format {
+            ProgressFormat::Text => println!(
+                "Download complete : {}",
+                humanize::bytes(stats.total_bytes as u64)
+            ),
+            ProgressFormat::Jsonl => emit(json!({
+                "type": "complete",
+                "total_bytes": stats.total_bytes,
+                "uploaded_bytes": stats.uploaded_bytes,
+            })),
+        }
+    }
+}
+
+/// e.g " (started)" , empty for a regular announce
+fn event_label(event: Option<&TrackerEvent>) -> String {
+    event.map_or_else(String::new, |event| format!(" ({})", event.as_str()))
+}
+
+/// One line on stdout , with the time it was written
+fn emit(mut line: Value) {
+    line["time"] = json!(chrono::Utc::now().to_rfc3339());
+    println!("{}", line);
+}
+
+/// Snake case name of an engine event and what it carries
+fn event_fields(event: &Event) -> (&'static str, Value) {
+    match event {
+        Event::HashProgress {
+            hashed_pieces,
+            total_pieces,
+            hashed_bytes,
+            total_bytes,
+        } => (
+            "hash_progress",
+            json!({
+                "hashed_pieces": hashed_pieces,
+                "total_pieces": total_pieces,
+                "hashed_bytes": hashed_bytes,
+                "total_bytes": total_bytes,
+            }),
+        ),
+        Event::PieceCorrupted {
+            piece_index,
+            reason,
+            found_by,
+        } => (
+            "piece_corrupted",
+            json!({
+                "piece_index": piece_index,
+                "reason": reason,
+                "found_by": match found_by {
+                    CorruptionCheck::Scrub => "scrub",
+                    CorruptionCheck::Upload => "upload",
+                },
+            }),
+        ),
+        Event::StoragePaused { reason, retry_in } => (
+            "storage_paused",
+            json!({ "reason": reason, "retry_in_seconds": retry_in.as_secs() }),
+        ),
+        Event::StorageResumed => ("storage_resumed", json!({})),
+        Event::FileQuarantined {
+            file_index,
+            path,
+            reason,
+        } => (
+            "file_quarantined",
+            json!({
+                "file_index": file_index,
+                "path": path.display().to_string(),
+                "reason": reason,
+            }),
+        ),
+        Event::ResumeDataRejected { path, reason } => (
+            "resume_data_rejected",
+            json!({ "path": path.display().to_string(), "reason": reason }),
+        ),
+        Event::ConfigReloaded { applied, rejected } => (
+            "config_reloaded",
+            json!({ "applied": applied, "rejected": rejected }),
+        ),
+    }
+}
@@ -0,0 +1,304 @@
+use crate::peers::View;
+use color_eyre::{Result, eyre::eyre};
+use ratatui::crossterm::event::KeyCode;
+use std::collections::BTreeMap;
+
+/// Something a key can do in the TUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Quit,
+    Previous,
+    Next,
+    ToggleView,
+    Reload,
+    DownloadStep,
+    ShowStats,
+    Help,
+    SortPeers,
+    ReversePeers,
+    FilterPeers,
+    CyclePriority,
+    QueueUp,
+    QueueDown,
+    QueueTop,
+    QueueBottom,
+}
+
+impl Action {
+    pub const ALL: [Action; 16] = [
+        Action::Quit,
+        Action::Previous,
+        Action::Next,
+        Action::ToggleView,
+        Action::Reload,
+        Action::DownloadStep,
+        Action::ShowStats,
+        Action::Help,
+        Action::SortPeers,
+        Action::ReversePeers,
+        Action::FilterPeers,
+        Action::CyclePriority,
+        Action::QueueUp,
+        Action::QueueDown,
+        Action::QueueTop,
+        Action::QueueBottom,
+    ];
+
+    /// Name used in the `keymap` section of the config file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Previous => "previous",
+            Action::Next => "next",
+            Action::ToggleView => "toggle_view",
+            Action::Reload => "reload",
+            Action::DownloadStep => "download_step",
+            Action::ShowStats => "show_stats",
+            Action::Help => "help",
+            Action::SortPeers => "sort_peers",
+            Action::ReversePeers => "reverse_peers",
+            Action::FilterPeers => "filter_peers",
+            Action::CyclePriority => "cycle_priority",
+            Action::QueueUp => "queue_up",
+            Action::QueueDown => "queue_down",
+            Action::QueueTop => "queue_top",
+            Action::QueueBottom => "queue_bottom",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Previous => "Select previous",
+            Action::Next => "Select next",
+            Action::ToggleView => "Switch view",
+            Action::Reload => "Reload torrent",
+            Action::DownloadStep => "Download next piece",
+            Action::ShowStats => "Show statistics",
+            Action::Help => "Show this help",
+            Action::SortPeers => "Sort column",
+            Action::ReversePeers => "Reverse sort",
+            Action::FilterPeers => "Filter peers",
+            Action::CyclePriority => "Change file priority",
+            Action::QueueUp => "Move up the queue",
+            Action::QueueDown => "Move down the queue",
+            Action::QueueTop => "Move to the front of the queue",
+            Action::QueueBottom => "Move to the back of the queue",
+        }
+    }
+
+    /// Pane the action works in , None for everywhere
+    pub fn view(&self) -> Option<View> {
+        match self {
+            Action::SortPeers | Action::ReversePeers | Action::FilterPeers => Some(View::Peers),
+            Action::CyclePriority => Some(View::Files),
+            Action::QueueUp | Action::QueueDown | Action::QueueTop | Action::QueueBottom => {
+                Some(View::Queue)
+            }
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    fn default_keys(&self) -> Vec<KeyCode> {
+        match self {
+            Action::Quit => vec![KeyCode::Char('q'), KeyCode::Esc],
+            Action::Previous => vec![KeyCode::Char('p')],
+            Action::Next => vec![KeyCode::Char('n')],
+            Action::ToggleView => vec![KeyCode::Char('v')],
+            Action::Reload => vec![KeyCode::Char('r')],
+            Action::DownloadStep => vec![KeyCode::Char('d')],
+            Action::ShowStats => vec![KeyCode::Char('s')],
+            Action::Help => vec![KeyCode::Char('?')],
+            Action::SortPeers => vec![KeyCode::Char('o')],
+            Action::ReversePeers => vec![KeyCode::Char('x')],
+            Action::FilterPeers => vec![KeyCode::Char('f')],
+            Action::CyclePriority => vec![KeyCode::Char('t')],
+            Action::QueueUp => vec![KeyCode::Char('+')],
+            Action::QueueDown => vec![KeyCode::Char('-')],
+            Action::QueueTop => vec![KeyCode::Home],
+            Action::QueueBottom => vec![KeyCode::End],
+        }
+    }
+
+    /// Whether a key can mean both actions at once , i.e they share a pane
+    fn overlaps(&self, other: &Action) -> bool {
+        match (self.view(), other.view()) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+}
+
+/// Which keys trigger which action
+///
+/// Starts from the built in keys , the config file's `keymap` section replaces the keys of the
+/// actions it names. Two actions that share a pane can't share a key
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    keys: BTreeMap<Action, Vec<KeyCode>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            keys: Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Applies the config's overrides , every unknown name and conflict is reported at once
+    pub fn from_config(overrides: &BTreeMap<String, Vec<String>>) -> Result<Self> {
+        let mut keymap = Self::default();
+        let mut problems = Vec::new();
+
+        for (name, key_names) in overrides {
+            let Some(action) = Action::from_name(name) else {
+                problems.push(format!("unknown action \"{}\"", name));
+                continue;
+            };
+
+            let mut keys = Vec::new();
+            for key_name in key_names {
+                match parse_key(key_name) {
+                    Some(key) if !keys.contains(&key) => keys.push(key),
+                    Some(_) => {}
+                    None => problems.push(format!("unknown key \"{}\" for {}", key_name, name)),
+                }
+            }
+            keymap.keys.insert(action, keys);
+        }
+
+        problems.extend(keymap.conflicts());
+        if !problems.is_empty() {
+            return Err(eyre!("Invalid keymap : {}", problems.join(" , ")));
+        }
+        Ok(keymap)
+    }
+
+    /// Keys bound to two actions that can be active at the same time
+    pub fn conflicts(&self) -> Vec<String> {
+        let bindings: Vec<(Action, KeyCode)> = self
+            .keys
+            .iter()
+            .flat_map(|(action, keys)| keys.iter().map(move |key| (*action, *key)))
+            .collect();
+
+        let mut conflicts = Vec::new();
+        for (i, (first, key)) in bindings.iter().enumerate() {
+            for (second, other) in &bindings[i + 1..] {
+                if key == other && first != second && first.overlaps(second) {
+                    conflicts.push(format!(
+                        "\"{}\" is bound to both {} and {}",
+                        key_name(key),
+                        first.name(),
+                        second.name()
+                    ));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Action for a key pressed while `view` is on screen
+    pub fn action(&self, key: KeyCode, view: View) -> Option<Action> {
+        self.keys
+            .iter()
+            .filter(|(action, _)| action.view().is_none_or(|only| only == view))
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(action, _)| *action)
+    }
+
+    /// Keys of an action joined for display , e.g "q/esc"
+    pub fn keys_label(&self, action: Action) -> String {
+        self.keys
+            .get(&action)
+            .map(|keys| keys.iter().map(key_name).collect::<Vec<_>>().join("/"))
+            .unwrap_or_default()
+    }
+
+    /// One line per action with its keys , for the help overlay
+    pub fn help_text(&self) -> String {
+        let mut text = String::new();
+        for view in [
+            None,
+            Some(View::Peers),
+            Some(View::Files),
+            Some(View::Queue),
+        ] {
+            text.push_str(match view {
+                None => "Everywhere:\n",
+                Some(View::Peers) => "\nPeers view:\n",
+                Some(View::Queue) => "\nTorrents view:\n",
+                Some(_) => "\nFiles view:\n",
+            });
+            for action in Action::ALL.into_iter().filter(|a| a.view() == view) {
+                let keys = self.keys_label(action);
+                let keys = if keys.is_empty() { "(unbound)" } else { &keys };
+                text.push_str(&format!("  {:<12} {}\n", keys, action.description()));
+            }
+        }
+        text
+    }
+}
+
+/// Reads a key name from the config : a single character , or a name like "esc" , "up" or "f5"
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+
+    let key = match name.to_ascii_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "space" => KeyCode::Char(' '),
+        "backspace" => KeyCode::Backspace,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        other => {
+            let number = other.strip_prefix('f')?.parse().ok()?;
+            if !(1..=12).contains(&number) {
+                return None;
+            }
+            KeyCode::F(number)
+        }
+    };
+    Some(key)
+}
+
+/// How a key is written in the config and shown in the help
+fn key_name(key: &KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => "space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(number) => format!("f{}", number),
+        KeyCode::Esc => "esc".to_string(),
+        KeyCode::Enter => "enter".to_string(),
+        KeyCode::Tab => "tab".to_string(),
+        KeyCode::Backspace => "backspace".to_string(),
+        KeyCode::Up => "up".to_string(),
+        KeyCode::Down => "down".to_string(),
+        KeyCode::Left => "left".to_string(),
+        KeyCode::Right => "right".to_string(),
+        KeyCode::Home => "home".to_string(),
+        KeyCode::End => "end".to_string(),
+        KeyCode::PageUp => "pageup".to_string(),
+        KeyCode::PageDown => "pagedown".to_string(),
+        other => format!("{:?}", other),
+    }
+}
@@ -0,0 +1,87 @@
+use color_eyre::{Result, eyre::eyre};
+use mini_p2p_file_transfer_system::{
+    app::session::DEFAULT_LISTEN_PORT,
+    core::config::Config,
+    net::{
+        metadata_fetch::MetadataFetch,
+        tracker::{Tracker, TrackerRequest},
+    },
+    protocol::magnet::MagnetUri,
+};
+use std::{env, fs, net::SocketAddr, path::PathBuf};
+
+/// Peers asked for the metadata before giving up
+const MAX_METADATA_PEERS: usize = 30;
+
+/// Turns a magnet link into a local .torrent for the TUI to open
+///
+/// Peers come from the link's `x.pe` and from its trackers , the info dictionary is fetched from
+/// them and saved to the temp dir under the info hash
+pub fn run(uri: &str) -> Result<PathBuf> {
+    let magnet = MagnetUri::parse(uri).map_err(|e| eyre!("{}", e))?;
+    let config = Config::load_default().map_err(|e| eyre!("{}", e))?;
+    let peer_id = Tracker::generate_peer_id();
+    println!("Fetching metadata for {}", magnet.name());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let torrent = runtime.block_on(async {
+        let mut peers = magnet.peers.clone();
+        for url in &magnet.trackers {
+            match announce(url, &magnet, peer_id).await {
+                Ok(found) => {
+                    println!("{} returned {} peers", url, found.len());
+                    for addr in found {
+                        if !peers.contains(&addr) {
+                            peers.push(addr);
+                        }
+                    }
+                }
+                Err(e) => println!("{} failed : {}", url, e),
+            }
+            if peers.len() >= MAX_METADATA_PEERS {
+                break;
+            }
+        }
+        peers.truncate(MAX_METADATA_PEERS);
+
+        MetadataFetch::new(magnet.clone(), peer_id)
+            .with_config(&config.metadata)
+            .with_listen_port(DEFAULT_LISTEN_PORT)
+            .with_bind(config.network.bind.clone())
+            .fetch(&peers)
+            .await
+    });
+    let torrent = torrent.map_err(|e| eyre!("{}", e))?;
+
+    let path = env::temp_dir().join(format!("{}.torrent", hex::encode(torrent.info_hash)));
+    fs::write(&path, torrent.to_bytes())?;
+    println!("Saved {} to {}", torrent.name, path.display());
+    Ok(path)
+}
+
+async fn announce(
+    url: &str,
+    magnet: &MagnetUri,
+    peer_id: [u8; 20],
+) -> anyhow::Result<Vec<SocketAddr>> {
+    let request = TrackerRequest {
+        info_hash: magnet.info_hash,
+        // The size is unknown until the metadata arrives , anything above zero reads as a leecher
+        left: 1,
+        uploaded: 0,
+        downloaded: 0,
+        port: DEFAULT_LISTEN_PORT,
+        compact: true,
+        event: None,
+    };
+    let response = Tracker::with_peer_id(url.to_string(), peer_id)
+        .announce(request)
+        .await?;
+    Ok(response
+        .peers
+        .iter()
+        .filter_map(|peer| peer.socket_addr())
+        .collect())
+}
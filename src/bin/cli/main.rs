@@ -1,23 +1,135 @@
-use clap::Parser;
-use color_eyre::Result;
+mod bandwidth;
+mod create;
+mod diagnostics;
+#[cfg(feature = "http-tracker")]
+mod download;
+mod files;
+#[cfg(feature = "http-tracker")]
+mod headless;
+mod keymap;
+#[cfg(feature = "http-tracker")]
+mod magnet;
+mod peers;
+mod queue;
+#[cfg(feature = "http-tracker")]
+mod speedtest;
+mod swarm_stats;
+mod trackers;
+
+use clap::{Parser, Subcommand};
+use color_eyre::{Result, eyre::eyre};
+use files::FilesView;
+use keymap::{Action, Keymap};
+#[cfg(feature = "geoip")]
+use mini_p2p_file_transfer_system::net::geoip::GeoIp;
+#[cfg(feature = "http-tracker")]
+use mini_p2p_file_transfer_system::net::rate_limit::Rates;
 use mini_p2p_file_transfer_system::{
-    net::{block_manager::BlockManager, piece_manager::Block},
+    app::{
+        manager::ManagedTorrent,
+        session::{DEFAULT_LISTEN_PORT, Session},
+    },
+    core::{
+        config::{Config, default_download_dir},
+        config_watch::ConfigWatcher,
+        isolate::catch_panic,
+        peer::PeerSnapshot,
+    },
+    net::{
+        block_manager::{BlockManager, StatsReceiver},
+        piece_manager::Block,
+        rate_limit::parse_rate,
+        tracker_url::TrackerUrl,
+    },
     protocol::torrent::Torrent,
-    storage::files::FileStorage,
+    storage::{
+        files::FileStorage,
+        resume::{default_resume_dir, resume_path},
+    },
+    util::humanize,
 };
+use peers::{PeersView, View};
+use queue::QueueView;
 use ratatui::{
     DefaultTerminal,
     crossterm::event::{self, Event, KeyCode, KeyEventKind},
     prelude::*,
-    widgets::Paragraph,
+    widgets::{Borders, Clear, Paragraph},
 };
-use std::{fs, path::PathBuf, vec};
+use std::{
+    env, fs, panic,
+    path::PathBuf,
+    time::{Duration, Instant},
+    vec,
+};
+
+/// How often the config file is checked for edits while no key is pressed
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings the TUI takes over from an edited config , every other change waits for a restart
+#[cfg(not(feature = "geoip"))]
+const RELOADABLE: &[&str] = &["keymap", "verify_writes"];
+#[cfg(feature = "geoip")]
+const RELOADABLE: &[&str] = &["keymap", "verify_writes", "geoip_databases"];
+
+/// Folder in the temp dir holding verified pieces that couldn't be written yet
+const WRITE_SPOOL_DIR_NAME: &str = "sekiro-spool";
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long, value_name = "FILE", help = "Path to the .torrent file")]
-    path: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Path to the .torrent file , repeat it to queue more torrents behind the first"
+    )]
+    path: Vec<PathBuf>,
+    #[cfg(feature = "http-tracker")]
+    #[arg(
+        long,
+        value_name = "URI",
+        conflicts_with = "path",
+        help = "Magnet link , the metadata is fetched from peers"
+    )]
+    magnet: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "RATE",
+        value_parser = parse_rate_arg,
+        help = "Download cap over all peers in headless mode , e.g 500K or 2M per second. 0 for unlimited"
+    )]
+    max_down: Option<u64>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "RATE",
+        value_parser = parse_rate_arg,
+        help = "Upload cap over all peers in headless mode , e.g 500K or 2M per second. 0 for unlimited"
+    )]
+    max_up: Option<u64>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Export traffic per torrent and per day as CSV or JSON
+    Bandwidth(bandwidth::BandwidthArgs),
+    /// Create a .torrent from a file or directory
+    Create(create::CreateArgs),
+    /// Open a .torrent from a path or an http(s) url
+    #[cfg(feature = "http-tracker")]
+    Download(download::DownloadArgs),
+    /// Download without the TUI , printing progress and events on stdout
+    #[cfg(feature = "http-tracker")]
+    Headless(headless::HeadlessArgs),
+    /// Print piece availability across the swarm as histograms
+    SwarmStats(swarm_stats::SwarmStatsArgs),
+    /// Measure tracker , connection and download speed with a test torrent
+    #[cfg(feature = "http-tracker")]
+    Speedtest(speedtest::SpeedtestArgs),
 }
 
 #[derive(Debug)]
@@ -33,7 +145,29 @@ pub struct App {
     pub download_dir: PathBuf,
     pub status_message: Option<String>,
     pub block_manager: Option<BlockManager>,
+    /// Read pieces back after writing them , from the config
+    pub verify_writes: bool,
+    /// Latest stats published by the block manager
+    pub stats: Option<StatsReceiver>,
     pub error_message: Option<String>,
+    /// Pane currently on screen
+    pub view: View,
+    pub peers_view: PeersView,
+    pub files_view: FilesView,
+    pub queue_view: QueueView,
+    /// Torrents opened in the TUI , the open one plus those queued behind it
+    pub session: Session,
+    /// Which keys do what , from the config file
+    pub keymap: Keymap,
+    /// Help overlay is on screen , the next key closes it
+    pub show_help: bool,
+    /// Picks up edits to the config file , None without a config dir
+    pub config_watcher: Option<ConfigWatcher>,
+    /// The engine panicked on this torrent , nothing runs for it until it's reloaded
+    pub engine_error: Option<String>,
+    /// Country and ASN lookups for the peers pane , None without databases in the config
+    #[cfg(feature = "geoip")]
+    pub geoip: Option<GeoIp>,
 }
 
 impl App {
@@ -46,35 +180,247 @@ impl App {
             torrent: None,
             error_message: None,
             status_message: None,
-            download_dir: PathBuf::from("~/Downloads"),
+            download_dir: default_download_dir(),
+            verify_writes: false,
             file_storage: None,
             block_manager: None,
+            stats: None,
+            view: View::Torrent,
+            peers_view: PeersView::default(),
+            files_view: FilesView::default(),
+            queue_view: QueueView::default(),
+            session: Session::new(DEFAULT_LISTEN_PORT),
+            keymap: Keymap::default(),
+            show_help: false,
+            config_watcher: None,
+            engine_error: None,
+            #[cfg(feature = "geoip")]
+            geoip: None,
+        }
+    }
+
+    /// Runs engine work for the torrent , a panic stops the torrent instead of the whole TUI
+    fn run_isolated(&mut self, step: fn(&mut Self)) {
+        if let Some(error) = &self.engine_error {
+            self.error_message = Some(format!("Torrent stopped : {} (reload to retry)", error));
+            return;
+        }
+
+        // The terminal's panic hook would leave the alternate screen , a caught panic shouldn't
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let result = catch_panic(|| step(self));
+        panic::set_hook(hook);
+
+        if let Err(message) = result {
+            self.error_message = Some(format!("Torrent stopped after a panic : {}", message));
+            self.engine_error = Some(message);
         }
     }
 
+    /// Copies of the connected peers , with their location when GeoIP is set up
+    pub fn peer_snapshots(&self) -> Vec<PeerSnapshot> {
+        let Some(manager) = &self.block_manager else {
+            return Vec::new();
+        };
+        #[cfg_attr(not(feature = "geoip"), allow(unused_mut))]
+        let mut peers = manager.peer_snapshots();
+        #[cfg(feature = "geoip")]
+        if let Some(geoip) = &self.geoip {
+            geoip.annotate(&mut peers);
+        }
+        peers
+    }
+
+    /// Applies the config file if it changed , the status bar lists what took effect
+    pub fn reload_config(&mut self) {
+        let Some(reload) = self.config_watcher.as_mut().and_then(|w| w.poll()) else {
+            return;
+        };
+        let reload = match reload {
+            Ok(reload) => reload.applying(RELOADABLE),
+            Err(e) => {
+                self.error_message = Some(format!("Config not reloaded : {}", e));
+                return;
+            }
+        };
+        if reload.is_empty() {
+            return;
+        }
+
+        if reload.applied.iter().any(|name| name == "keymap") {
+            match Keymap::from_config(&reload.config.keymap) {
+                Ok(keymap) => self.keymap = keymap,
+                Err(e) => {
+                    // The old keys stay usable
+                    self.error_message = Some(e.to_string());
+                    return;
+                }
+            }
+        }
+        if reload.applied.iter().any(|name| name == "verify_writes") {
+            self.verify_writes = reload.config.verify_writes;
+            if let Some(manager) = self.block_manager.as_mut() {
+                manager.set_write_verification(self.verify_writes);
+            }
+        }
+        #[cfg(feature = "geoip")]
+        if reload.applied.iter().any(|name| name == "geoip_databases") {
+            match open_geoip(&reload.config) {
+                Ok(geoip) => self.geoip = geoip,
+                Err(e) => {
+                    self.error_message = Some(e.to_string());
+                    return;
+                }
+            }
+        }
+        self.status_message = Some(reload.summary());
+    }
+
     pub fn quit(&mut self) {
+        // A torrent that panicked may be half updated , nothing of it is saved
+        if self.engine_error.is_none()
+            && let Some(manager) = &mut self.block_manager
+        {
+            // Let pieces that are being hashed reach the disk
+            manager.wait_for_verifications();
+
+            // Keep the blocks of half finished pieces for the next run
+            if let Err(e) = manager.flush_partial_pieces() {
+                eprintln!("Could not save partial pieces : {}", e);
+            }
+            if let Err(e) = manager.save_resume_data() {
+                eprintln!("Could not save resume data : {}", e);
+            }
+        }
+        // Keeps the queue order for the next run
+        if let Err(e) = self.session.save_state() {
+            eprintln!("Could not save session state : {}", e);
+        }
         self.should_quit = true;
     }
 
     pub fn next(&mut self) {
+        if self.view == View::Files {
+            let files = self
+                .block_manager
+                .as_ref()
+                .map_or(0, |manager| manager.file_priorities().len());
+            self.files_view.select_next(files);
+            return;
+        }
+        if self.view == View::Queue {
+            self.queue_view.select_next(self.session.torrents.len());
+            return;
+        }
         self.selected_index = self.selected_index.saturating_add(1);
     }
 
     pub fn previous(&mut self) {
+        if self.view == View::Files {
+            self.files_view.select_previous();
+            return;
+        }
+        if self.view == View::Queue {
+            self.queue_view.select_previous();
+            return;
+        }
         self.selected_index = self.selected_index.saturating_sub(1);
     }
 
+    /// Steps the selected file to the next priority , skip -> low -> normal -> high -> skip
+    fn cycle_file_priority(&mut self) {
+        let Some(manager) = &mut self.block_manager else {
+            return;
+        };
+        let index = self.files_view.selected;
+        let Some(&priority) = manager.file_priorities().get(index) else {
+            return;
+        };
+
+        let priority = priority.next();
+        match manager.set_file_priority(index, priority) {
+            Ok(()) => self.status_message = Some(format!("File {} set to {}", index + 1, priority)),
+            Err(e) => self.error_message = Some(format!("Priority not changed : {}", e)),
+        }
+    }
+
+    /// Adds a torrent to the session unless it's there already , returns its id
+    fn add_to_session(&mut self, torrent: Torrent, queued: bool) -> usize {
+        let hash = torrent.info_hashes().primary();
+        if let Some(existing) = self.session.find_by_handshake_hash(&hash) {
+            return existing.id;
+        }
+        let id = self.session.add_torrent(torrent);
+        // A torrent queued last run is already back in its place , and the open one never waits
+        self.session.set_queued(id, queued);
+        id
+    }
+
+    /// The open torrent as the session has it , with its trackers
+    fn open_torrent(&self) -> Option<&ManagedTorrent> {
+        let hash = self.torrent.as_ref()?.info_hashes().primary();
+        self.session.find_by_handshake_hash(&hash)
+    }
+
+    /// Moves the selected torrent in the queue , only queued torrents have a place in it
+    fn move_in_queue(&mut self, action: Action) {
+        let Some(id) = self.queue_view.selected_id(&self.session) else {
+            return;
+        };
+        if self.session.queue_position(id).is_none() {
+            self.status_message = Some(String::from("Torrent is not queued"));
+            return;
+        }
+
+        let moved = match action {
+            Action::QueueUp => self.session.move_up(id),
+            Action::QueueDown => self.session.move_down(id),
+            Action::QueueTop => self.session.move_top(id),
+            _ => self.session.move_bottom(id),
+        };
+        if moved {
+            self.queue_view.follow(&self.session, id);
+        }
+    }
+
     pub fn handle_key_input(&mut self, key: KeyCode) {
-        match key {
-            KeyCode::Char('q') => self.quit(),
-            KeyCode::Char('p') => self.previous(),
-            KeyCode::Char('n') => self.next(),
-            KeyCode::Esc => self.quit(),
-            _ => {}
+        if self.show_help {
+            self.show_help = false;
+            return;
+        }
+
+        let Some(action) = self.keymap.action(key, self.view) else {
+            return;
+        };
+        match action {
+            Action::Quit => self.quit(),
+            Action::Previous => self.previous(),
+            Action::Next => self.next(),
+            Action::ToggleView => match self.view {
+                View::Torrent => self.view_peers(),
+                View::Peers => self.view = View::Files,
+                View::Files => self.view = View::Diagnostics,
+                View::Diagnostics => self.view = View::Trackers,
+                View::Trackers => self.view = View::Queue,
+                View::Queue => self.view_torrent_data(),
+            },
+            Action::Reload => self.load_torrent(),
+            Action::DownloadStep => self.run_isolated(Self::simulate_download_step),
+            Action::ShowStats => self.run_isolated(Self::show_stats),
+            Action::Help => self.show_help = true,
+            Action::SortPeers => self.peers_view.sort = self.peers_view.sort.next(),
+            Action::ReversePeers => self.peers_view.descending = !self.peers_view.descending,
+            Action::FilterPeers => self.peers_view.filter = self.peers_view.filter.next(),
+            Action::CyclePriority => self.run_isolated(Self::cycle_file_priority),
+            Action::QueueUp | Action::QueueDown | Action::QueueTop | Action::QueueBottom => {
+                self.move_in_queue(action)
+            }
         }
     }
 
     pub fn load_torrent(&mut self) {
+        self.engine_error = None;
         // Checks if the path exists
         if !self.path.exists() {
             self.error_message = Some(format!(
@@ -100,25 +446,33 @@ impl App {
             // Converts the 'READ' file to a Torrent
             Ok(bytes) => match Torrent::from_bytes(&bytes) {
                 Ok(torrent) => {
+                    self.add_to_session(torrent.clone(), false);
                     self.torrent = Some(torrent);
                     self.error_message = None;
                     let down_dir = self.download_dir.clone();
 
                     if let Some(torrent) = self.torrent.clone() {
-                        match FileStorage::from(torrent.clone(), down_dir) {
-                            Ok(storage) => match BlockManager::from(torrent, storage) {
-                                Ok(manager) => {
-                                    self.block_manager = Some(manager);
-                                    self.error_message = None;
-                                }
-                                Err(e) => {
-                                    self.error_message =
-                                        Some(format!("Failed to init block manager: {}", e));
-                                }
-                            },
+                        let storage = Box::new(FileStorage::from(torrent.clone(), down_dir));
+                        let manager = match default_resume_dir() {
+                            Some(dir) => {
+                                let path = resume_path(&dir, &torrent.info_hash);
+                                BlockManager::with_resume_file(torrent, storage, path)
+                            }
+                            None => BlockManager::with_storage(torrent, storage),
+                        };
+                        match manager {
+                            Ok(manager) => {
+                                // Pieces the download dir won't take wait here instead of being lost
+                                let manager = manager
+                                    .with_write_spool(env::temp_dir().join(WRITE_SPOOL_DIR_NAME))
+                                    .with_write_verification(self.verify_writes);
+                                self.stats = Some(manager.subscribe_stats());
+                                self.block_manager = Some(manager);
+                                self.error_message = None;
+                            }
                             Err(e) => {
                                 self.error_message =
-                                    Some(format!("File Storage could not be built : {}", e));
+                                    Some(format!("Failed to init block manager: {}", e));
                             }
                         }
                     }
@@ -137,6 +491,8 @@ impl App {
 
     pub fn simulate_download_step(&mut self) {
         if let Some(manager) = &mut self.block_manager {
+            manager.process_hash_results();
+
             // Get next piece to work on
             if let Some(piece_index) = manager.get_next_piece_to_download() {
                 // Get all blocks for this piece
@@ -187,21 +543,29 @@ impl App {
     }
 
     pub fn show_stats(&mut self) {
-        if let Some(manager) = &self.block_manager {
+        if let Some(manager) = &mut self.block_manager {
+            manager.process_hash_results();
             let stats = manager.get_stats();
 
             let message = format!(
                 "Progress: {}/{} pieces ({:.1}%)\n\
-                Downloaded: {} / {} bytes\n\
-                Speed: {:.2} KB/s\n\
-                Missing: {} pieces",
+                Downloaded: {} / {}\n\
+                Speed: {}\n\
+                ETA: {}\n\
+                Missing: {} pieces\n\n\
+                Seeding:\n\
+                Uploaded: {}\n\
+                {}",
                 stats.verified_pieces,
                 stats.total_pieces,
                 stats.progress_percentage(),
-                stats.downloaded_bytes,
-                stats.total_bytes,
-                stats.download_speed_bps() / 1024.0,
-                manager.get_missing_piece_count()
+                humanize::bytes(stats.downloaded_bytes as u64),
+                humanize::bytes(stats.total_bytes as u64),
+                humanize::rate(stats.download_speed_bps()),
+                humanize::eta(stats.eta_seconds()),
+                manager.get_missing_piece_count(),
+                humanize::bytes(stats.uploaded_bytes),
+                stats.slots
             );
 
             self.status_message = Some(message);
@@ -217,26 +581,110 @@ impl App {
         }
     }
 
-    fn view_peers(&self) {
-        todo!()
+    fn view_peers(&mut self) {
+        self.view = View::Peers;
     }
 
-    fn view_torrent_data(&self) {
-        todo!()
+    fn view_torrent_data(&mut self) {
+        self.view = View::Torrent;
     }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let path = args.path.unwrap_or_else(|| {
+    color_eyre::install()?;
+    // The TUI and the other commands never connect to peers , a cap there would silently do nothing
+    #[cfg(feature = "http-tracker")]
+    let headless = matches!(args.command, Some(Command::Headless(_)));
+    #[cfg(not(feature = "http-tracker"))]
+    let headless = false;
+    if !headless && (args.max_down.is_some() || args.max_up.is_some()) {
+        return Err(eyre!(
+            "--max-down and --max-up only apply to the headless command"
+        ));
+    }
+
+    if let Some(command) = args.command {
+        return match command {
+            Command::Bandwidth(bandwidth_args) => bandwidth::run(bandwidth_args),
+            Command::Create(create_args) => create::run(create_args),
+            #[cfg(feature = "http-tracker")]
+            Command::Download(download_args) => {
+                let output_dir = download_args.output_dir.clone();
+                run_tui(download::run(download_args)?, Vec::new(), output_dir)
+            }
+            #[cfg(feature = "http-tracker")]
+            Command::Headless(headless_args) => {
+                headless::run(headless_args, Rates::new(args.max_down, args.max_up))
+            }
+            Command::SwarmStats(stats_args) => swarm_stats::run(stats_args),
+            #[cfg(feature = "http-tracker")]
+            Command::Speedtest(speedtest_args) => speedtest::run(speedtest_args),
+        };
+    }
+
+    #[cfg(feature = "http-tracker")]
+    if let Some(uri) = args.magnet {
+        return run_tui(magnet::run(&uri)?, Vec::new(), None);
+    }
+
+    let mut paths = args.path.into_iter();
+    let path = paths.next().unwrap_or_else(|| {
         eprintln!("Path not provided, using current directory");
         PathBuf::from("./test.torrent")
     });
+    run_tui(path, paths.collect(), None)
+}
+
+/// clap parser for `--max-down` / `--max-up` , unlimited comes out as 0
+fn parse_rate_arg(text: &str) -> std::result::Result<u64, String> {
+    parse_rate(text)
+        .map(|rate| rate.unwrap_or(0))
+        .map_err(|e| e.to_string())
+}
+
+/// Databases listed in the config , None when there are none
+#[cfg(feature = "geoip")]
+fn open_geoip(config: &Config) -> Result<Option<GeoIp>> {
+    if config.geoip_databases.is_empty() {
+        return Ok(None);
+    }
+    let geoip = GeoIp::open(&config.geoip_databases).map_err(|e| eyre!("{}", e))?;
+    Ok(Some(geoip))
+}
+
+/// Opens the torrent at `path` in the TUI , downloading to `output_dir` or the configured download dir.
+/// The torrents at `queued` wait in the queue behind it
+fn run_tui(path: PathBuf, queued: Vec<PathBuf>, output_dir: Option<PathBuf>) -> Result<()> {
+    // Bad keymaps fail here , before the terminal is taken over
+    let config = Config::load_default().map_err(|e| eyre!("{}", e))?;
+    let keymap = Keymap::from_config(&config.keymap)?;
+    #[cfg(feature = "geoip")]
+    let geoip = open_geoip(&config)?;
 
-    color_eyre::install()?;
     let terminal = ratatui::init();
     let mut app = App::new(path, "BitTorrent Clone".to_string());
+    app.keymap = keymap;
+    app.download_dir = output_dir.unwrap_or_else(|| config.download_dir());
+    app.verify_writes = config.verify_writes;
+    #[cfg(feature = "geoip")]
+    {
+        app.geoip = geoip;
+    }
+    app.session.reload_config(&config);
+    app.config_watcher = Config::default_path().map(|path| ConfigWatcher::new(path, config));
     app.load_torrent();
+    for path in queued {
+        match fs::read(&path)
+            .map_err(|e| eyre!("{}", e))
+            .and_then(|bytes| Torrent::from_bytes(&bytes).map_err(|e| eyre!("{}", e)))
+        {
+            Ok(torrent) => {
+                app.add_to_session(torrent, true);
+            }
+            Err(e) => app.error_message = Some(format!("{} not queued : {}", path.display(), e)),
+        }
+    }
     let result = run(terminal, app);
     ratatui::restore();
     result
@@ -246,6 +694,11 @@ fn run(mut terminal: DefaultTerminal, mut app: App) -> Result<()> {
     loop {
         terminal.draw(|frame| render(frame, &app))?;
 
+        // Wake up now and then to notice config edits
+        if !event::poll(CONFIG_POLL_INTERVAL)? {
+            app.reload_config();
+            continue;
+        }
         if let Event::Key(key) = event::read()? {
             if key.kind == KeyEventKind::Press {
                 app.handle_key_input(key.code);
@@ -265,40 +718,200 @@ fn render(frame: &mut Frame, app: &App) {
 
     content.push_str("========== BitTorrent Clone ==========\n");
     content.push_str(&format!("Torrent: {}\n", app.path.display()));
-    content.push_str(&format!("Download Dir: {}\n\n", app.download_dir.display()));
+    content.push_str(&format!("Download Dir: {}\n", app.download_dir.display()));
+    content.push('\n');
 
     content.push_str("Controls:\n");
-    content.push_str("  q/Esc: Quit\n");
-    content.push_str("  r: Reload torrent\n");
-    content.push_str("  d: Download next piece\n");
-    content.push_str("  s: Show statistics\n\n");
+    for action in [
+        Action::Quit,
+        Action::ToggleView,
+        Action::Reload,
+        Action::DownloadStep,
+        Action::ShowStats,
+        Action::Help,
+    ] {
+        content.push_str(&format!(
+            "  {}: {}\n",
+            app.keymap.keys_label(action),
+            action.description()
+        ));
+    }
+    content.push('\n');
 
     if let Some(error) = &app.error_message {
         content.push_str(&format!("ERROR: {}\n\n", error));
     }
 
+    // Skipped files are listed up top , the download looks finished without them
+    let skipped = app
+        .block_manager
+        .as_ref()
+        .map(|manager| manager.quarantined_files())
+        .unwrap_or_default();
+    if !skipped.is_empty() {
+        content.push_str(&format!("SKIPPED FILES ({}):\n", skipped.len()));
+        for file in &skipped {
+            content.push_str(&format!(
+                "  ⚠️  {} : {}\n",
+                file.path.display(),
+                file.reason
+            ));
+        }
+        content.push('\n');
+    }
+
     if let Some(status) = &app.status_message {
         content.push_str(&format!("STATUS: {}\n\n", status));
     }
 
+    if app.view == View::Peers {
+        // Snapshots are copies , rendering never holds on to live peer state
+        let peers = app.peer_snapshots();
+        let hint = [
+            Action::SortPeers,
+            Action::ReversePeers,
+            Action::FilterPeers,
+            Action::ToggleView,
+        ]
+        .map(|action| {
+            format!(
+                "{}: {}",
+                app.keymap.keys_label(action),
+                action.description()
+            )
+        })
+        .join("  ");
+        content.push_str(&app.peers_view.render(peers, &hint));
+
+        let text = Paragraph::new(content).wrap(ratatui::widgets::Wrap { trim: true });
+        frame.render_widget(text, frame.area());
+        render_help(frame, app);
+        return;
+    }
+
+    if app.view == View::Files {
+        let files = app
+            .block_manager
+            .as_ref()
+            .map(|manager| manager.file_progress())
+            .unwrap_or_default();
+        let hint = [
+            Action::Previous,
+            Action::Next,
+            Action::CyclePriority,
+            Action::ToggleView,
+        ]
+        .map(|action| {
+            format!(
+                "{}: {}",
+                app.keymap.keys_label(action),
+                action.description()
+            )
+        })
+        .join("  ");
+        content.push_str(&app.files_view.render(&files, &hint));
+
+        let text = Paragraph::new(content).wrap(ratatui::widgets::Wrap { trim: true });
+        frame.render_widget(text, frame.area());
+        render_help(frame, app);
+        return;
+    }
+
+    if app.view == View::Trackers {
+        let hint = format!(
+            "{}: {}",
+            app.keymap.keys_label(Action::ToggleView),
+            Action::ToggleView.description()
+        );
+        content.push_str(&trackers::render(app.open_torrent(), Instant::now(), &hint));
+
+        let text = Paragraph::new(content).wrap(ratatui::widgets::Wrap { trim: true });
+        frame.render_widget(text, frame.area());
+        render_help(frame, app);
+        return;
+    }
+
+    if app.view == View::Queue {
+        let hint = [
+            Action::Previous,
+            Action::Next,
+            Action::QueueUp,
+            Action::QueueDown,
+            Action::QueueTop,
+            Action::QueueBottom,
+            Action::ToggleView,
+        ]
+        .map(|action| {
+            format!(
+                "{}: {}",
+                app.keymap.keys_label(action),
+                action.description()
+            )
+        })
+        .join("  ");
+        content.push_str(&app.queue_view.render(&app.session, &hint));
+
+        let text = Paragraph::new(content).wrap(ratatui::widgets::Wrap { trim: true });
+        frame.render_widget(text, frame.area());
+        render_help(frame, app);
+        return;
+    }
+
+    if app.view == View::Diagnostics {
+        // No live connections in the TUI , so only what the engine tracks shows up
+        let peers = app
+            .block_manager
+            .as_ref()
+            .map(|manager| manager.peer_diagnostics())
+            .unwrap_or_default();
+        let hint = format!(
+            "{}: {}",
+            app.keymap.keys_label(Action::ToggleView),
+            Action::ToggleView.description()
+        );
+        content.push_str(&diagnostics::render(&peers, &hint));
+
+        let text = Paragraph::new(content).wrap(ratatui::widgets::Wrap { trim: true });
+        frame.render_widget(text, frame.area());
+        render_help(frame, app);
+        return;
+    }
+
+    if let Some(manager) = &app.block_manager {
+        content.push_str(&manager.swarm_health().header_line());
+        content.push_str("\n\n");
+    }
+
     // Show torrent info
     if let Some(torrent) = &app.torrent {
         content.push_str(&format!(
             "Torrent Info:\n\
             - Name: {}\n\
-            - Size: {} bytes\n\
+            - Size: {}\n\
             - Pieces: {}\n\
-            - Piece Length: {} bytes\n\n",
+            - Piece Length: {}\n\
+            - Version: {}\n",
             torrent.name,
-            torrent.length,
-            torrent.pieces.len(),
-            torrent.piece_length
+            humanize::bytes(torrent.length as u64),
+            torrent.piece_count(),
+            humanize::bytes(torrent.piece_length as u64),
+            torrent.version
+        ));
+        if let Some(v2) = torrent.info_hash_v2 {
+            content.push_str(&format!("- v2 Info Hash: {}\n", hex::encode(v2)));
+        }
+        content.push('\n');
+
+        let (_, tracker_status) = TrackerUrl::check(&torrent.announce);
+        content.push_str(&format!(
+            "Tracker: {} ({})\n\n",
+            torrent.announce, tracker_status
         ));
     }
 
-    // Show download progress
-    if let Some(manager) = &app.block_manager {
-        let stats = manager.get_stats();
+    // Show download progress , read from the published snapshot so drawing never waits on the manager
+    if let Some(stats) = &app.stats {
+        let stats = stats.borrow();
 
         content.push_str("Download Progress:\n");
 
@@ -318,11 +931,55 @@ fn render(frame: &mut Frame, app: &App) {
 
         content.push_str(&format!(
             "Pieces: {}/{}\n\
-            Bytes: {}/{}\n",
-            stats.verified_pieces, stats.total_pieces, stats.downloaded_bytes, stats.total_bytes
+            Bytes: {} / {}\n\
+            Speed: {} , ETA: {}\n",
+            stats.verified_pieces,
+            stats.total_pieces,
+            humanize::bytes(stats.downloaded_bytes as u64),
+            humanize::bytes(stats.total_bytes as u64),
+            humanize::rate(stats.download_speed_bps()),
+            humanize::eta(stats.eta_seconds())
         ));
+
+        // Only once something was served , a plain download has no use for it
+        if stats.uploaded_bytes > 0 || stats.slots.in_use > 0 {
+            content.push_str(&format!(
+                "\nSeeding:\n\
+                Uploaded: {}\n\
+                {}\n",
+                humanize::bytes(stats.uploaded_bytes),
+                stats.slots
+            ));
+        }
     }
 
     let text = Paragraph::new(content).wrap(ratatui::widgets::Wrap { trim: true });
     frame.render_widget(text, frame.area());
+    render_help(frame, app);
+}
+
+/// Keybindings listed in a box over the current pane
+fn render_help(frame: &mut Frame, app: &App) {
+    if !app.show_help {
+        return;
+    }
+
+    let text = app.keymap.help_text() + "\nPress any key to close";
+    let area = frame.area();
+    let width = 50.min(area.width);
+    let height = (text.lines().count() as u16 + 2).min(area.height);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+
+    let help = Paragraph::new(text).block(
+        ratatui::widgets::Block::default()
+            .borders(Borders::ALL)
+            .title(" Keys "),
+    );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(help, popup);
 }
@@ -0,0 +1,221 @@
+use mini_p2p_file_transfer_system::{
+    core::peer::{LastMessage, PeerSnapshot},
+    protocol::peer::PeerSource,
+    util::humanize,
+};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Pane shown by the TUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Torrent,
+    Peers,
+    Files,
+    Diagnostics,
+    /// Tiers of the open torrent with each tracker's stats
+    Trackers,
+    /// Every torrent of the session , in queue order
+    Queue,
+}
+
+/// Column the peers pane is sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerSort {
+    Rate,
+    Progress,
+    Client,
+}
+
+impl PeerSort {
+    pub fn next(self) -> Self {
+        match self {
+            PeerSort::Rate => PeerSort::Progress,
+            PeerSort::Progress => PeerSort::Client,
+            PeerSort::Client => PeerSort::Rate,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            PeerSort::Rate => "rate",
+            PeerSort::Progress => "progress",
+            PeerSort::Client => "client",
+        }
+    }
+}
+
+/// Which peers the pane shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerFilter {
+    All,
+    /// Only peers that unchoked us
+    Unchoked,
+    /// Only peers that have every piece
+    Seeding,
+}
+
+impl PeerFilter {
+    pub fn next(self) -> Self {
+        match self {
+            PeerFilter::All => PeerFilter::Unchoked,
+            PeerFilter::Unchoked => PeerFilter::Seeding,
+            PeerFilter::Seeding => PeerFilter::All,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            PeerFilter::All => "all",
+            PeerFilter::Unchoked => "unchoked",
+            PeerFilter::Seeding => "seeding",
+        }
+    }
+
+    fn matches(&self, peer: &PeerSnapshot) -> bool {
+        match self {
+            PeerFilter::All => true,
+            PeerFilter::Unchoked => !peer.peer_choking,
+            PeerFilter::Seeding => peer.is_seed,
+        }
+    }
+}
+
+/// Sort and filter settings of the peers pane
+#[derive(Debug, Clone)]
+pub struct PeersView {
+    pub sort: PeerSort,
+    pub descending: bool,
+    pub filter: PeerFilter,
+}
+
+impl Default for PeersView {
+    fn default() -> Self {
+        Self {
+            sort: PeerSort::Rate,
+            descending: true,
+            filter: PeerFilter::All,
+        }
+    }
+}
+
+impl PeersView {
+    /// Filters and sorts a batch of snapshots for display
+    pub fn apply(&self, mut peers: Vec<PeerSnapshot>) -> Vec<PeerSnapshot> {
+        peers.retain(|peer| self.filter.matches(peer));
+
+        peers.sort_by(|a, b| {
+            let ordering = match self.sort {
+                PeerSort::Rate => a
+                    .download_rate
+                    .partial_cmp(&b.download_rate)
+                    .unwrap_or(Ordering::Equal),
+                PeerSort::Progress => a
+                    .progress
+                    .partial_cmp(&b.progress)
+                    .unwrap_or(Ordering::Equal),
+                PeerSort::Client => a.client.cmp(&b.client),
+            };
+
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        peers
+    }
+
+    /// Text for the peers pane , `keys` is the line of key hints under the title
+    pub fn render(&self, peers: Vec<PeerSnapshot>, keys: &str) -> String {
+        let peers = self.apply(peers);
+        let mut content = format!(
+            "Peers ({}) - sort: {} {} | filter: {}\n",
+            peers.len(),
+            self.sort.label(),
+            if self.descending { "desc" } else { "asc" },
+            self.filter.label()
+        );
+        content.push_str(&format!("  {}\n", keys));
+        content.push_str(&format!("  Sources : {}\n\n", source_breakdown(&peers)));
+
+        // Only there when GeoIP lookups are set up
+        let located = peers.iter().any(|peer| peer.location.is_some());
+
+        for peer in peers {
+            let location = match (&peer.location, located) {
+                (_, false) => String::new(),
+                (Some(location), true) => format!("{:<12} ", location.label()),
+                (None, true) => format!("{:<12} ", "-"),
+            };
+            content.push_str(&format!(
+                "{:<22} {:<18} {}{:<14} {:>6.1}% {:>12} down {:>12} up | rx {} tx {}{}{}{}\n",
+                peer.addr,
+                peer.client,
+                location,
+                sources_label(&peer.sources),
+                peer.progress,
+                humanize::rate(peer.download_rate),
+                humanize::rate(peer.upload_rate),
+                last_message_label(peer.last_received, &peer),
+                last_message_label(peer.last_sent, &peer),
+                if peer.peer_choking { "" } else { " [unchoked]" },
+                if peer.snubbed { " [snubbed]" } else { "" },
+                if peer.protocol_violations > 0 {
+                    format!(" [{} violations]", peer.protocol_violations)
+                } else {
+                    String::new()
+                }
+            ));
+        }
+
+        content
+    }
+}
+
+/// e.g "piece 3s ago" , or "-" when nothing went that way yet
+fn last_message_label(last: Option<LastMessage>, peer: &PeerSnapshot) -> String {
+    match last {
+        Some(last) => format!(
+            "{} {} ago",
+            last.kind,
+            humanize::duration(last.age(peer.taken_at))
+        ),
+        None => String::from("-"),
+    }
+}
+
+/// Where a peer came from , e.g "tracker+DHT" , or "incoming" when it connected to us
+fn sources_label(sources: &BTreeSet<PeerSource>) -> String {
+    if sources.is_empty() {
+        return String::from("incoming");
+    }
+    sources
+        .iter()
+        .map(PeerSource::label)
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// Shown peers per source , a peer reported by two sources counts for both
+fn source_breakdown(peers: &[PeerSnapshot]) -> String {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for peer in peers {
+        if peer.sources.is_empty() {
+            *counts.entry("incoming").or_insert(0) += 1;
+        }
+        for source in &peer.sources {
+            *counts.entry(source.label()).or_insert(0) += 1;
+        }
+    }
+
+    if counts.is_empty() {
+        return String::from("none");
+    }
+    counts
+        .iter()
+        .map(|(source, count)| format!("{} {}", source, count))
+        .collect::<Vec<_>>()
+        .join(" , ")
+}
@@ -0,0 +1,59 @@
+use mini_p2p_file_transfer_system::{app::session::Session, util::humanize};
+
+/// Selection in the torrents pane
+#[derive(Debug, Clone, Default)]
+pub struct QueueView {
+    pub selected: usize,
+}
+
+impl QueueView {
+    /// Moves the selection , staying on the list
+    pub fn select_next(&mut self, torrents: usize) {
+        self.selected = (self.selected + 1).min(torrents.saturating_sub(1));
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Id of the selected torrent
+    pub fn selected_id(&self, session: &Session) -> Option<usize> {
+        session.torrents.get(self.selected).map(|t| t.id)
+    }
+
+    /// Keeps the selection on torrent `id` after the list was reordered
+    pub fn follow(&mut self, session: &Session, id: usize) {
+        if let Some(index) = session.torrents.iter().position(|t| t.id == id) {
+            self.selected = index;
+        }
+    }
+
+    /// Text for the torrents pane , `keys` is the line of key hints under the title
+    pub fn render(&self, session: &Session, keys: &str) -> String {
+        let queued = session.queue().len();
+        let mut content = format!(
+            "Torrents ({}) - {} queued\n",
+            session.torrents.len(),
+            queued
+        );
+        content.push_str(&format!("  {}\n\n", keys));
+
+        for (index, torrent) in session.torrents.iter().enumerate() {
+            let state = match session.queue_position(torrent.id) {
+                Some(position) => format!("#{}", position + 1),
+                None if torrent.error.is_some() => String::from("failed"),
+                None if torrent.paused => String::from("paused"),
+                None => String::from("active"),
+            };
+            content.push_str(&format!(
+                "{} {:<8} {:>10}  {}\n",
+                if index == self.selected { ">" } else { " " },
+                state,
+                humanize::bytes(torrent.torrent.length as u64),
+                torrent.torrent.name
+            ));
+        }
+
+        content
+    }
+}
@@ -0,0 +1,172 @@
+use clap::Args;
+use color_eyre::{Result, eyre::eyre};
+use mini_p2p_file_transfer_system::{
+    app::session::DEFAULT_LISTEN_PORT,
+    net::{
+        block_manager::BlockManager,
+        peer_candidates::PeerCandidates,
+        peer_manager::PeerManager,
+        tracker::{Tracker, TrackerEvent, TrackerRequest},
+    },
+    protocol::{handshake::Handshake, torrent::Torrent},
+    storage::{backend::SinkStorage, files::FileStorage},
+    util::humanize,
+};
+use std::{
+    env, fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, time::timeout};
+
+#[derive(Args, Debug, Clone)]
+pub struct SpeedtestArgs {
+    #[arg(value_name = "FILE", help = "A well seeded test torrent")]
+    pub torrent: PathBuf,
+    #[arg(
+        long,
+        default_value_t = 30,
+        help = "How long to download for , in seconds"
+    )]
+    pub seconds: u64,
+    #[arg(long, default_value_t = 20, help = "Peers to open test connections to")]
+    pub peers: usize,
+    #[arg(
+        long,
+        help = "Write to a temp dir instead of discarding , to include the disk"
+    )]
+    pub disk: bool,
+}
+
+/// How long a single test connection may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often connections are polled during the download
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Checks the setup against a test torrent : tracker reachability , how many peers accept a
+/// connection and how fast , then the download itself
+///
+/// Data goes to a sink (or a temp dir with `--disk`) that is removed afterwards
+pub fn run(args: SpeedtestArgs) -> Result<()> {
+    let bytes = fs::read(&args.torrent)?;
+    let torrent = Torrent::from_bytes(&bytes).map_err(|e| eyre!("{}", e))?;
+    println!(
+        "Speed test with {} ({} , {} pieces)",
+        torrent.name,
+        humanize::bytes(torrent.length as u64),
+        torrent.pieces.len()
+    );
+
+    let temp_dir = env::temp_dir().join(format!("sekiro-speedtest-{}", std::process::id()));
+    let mut manager = if args.disk {
+        let storage = FileStorage::from(torrent.clone(), temp_dir.clone());
+        BlockManager::from(torrent.clone(), storage)?
+    } else {
+        BlockManager::with_storage(torrent.clone(), Box::new(SinkStorage::new()))?
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let result = runtime.block_on(measure(&torrent, &mut manager, &args));
+
+    drop(manager);
+    if args.disk {
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+    result
+}
+
+async fn measure(
+    torrent: &Torrent,
+    manager: &mut BlockManager,
+    args: &SpeedtestArgs,
+) -> Result<()> {
+    let tracker = Tracker::new(torrent.announce.clone());
+    let request = TrackerRequest {
+        info_hash: torrent.info_hashes().primary(),
+        left: torrent.length as u64,
+        uploaded: 0,
+        downloaded: 0,
+        port: DEFAULT_LISTEN_PORT,
+        compact: true,
+        event: Some(TrackerEvent::Started),
+    };
+
+    let started = Instant::now();
+    let response = tracker
+        .announce(request)
+        .await
+        .map_err(|e| eyre!("Tracker announce failed : {}", e))?;
+    println!(
+        "Tracker : answered in {} ms , {} seeds , {} leechers , {} peers returned",
+        started.elapsed().as_millis(),
+        response.complete.unwrap_or(0),
+        response.incomplete.unwrap_or(0),
+        response.peers.len()
+    );
+
+    let mut reached = 0;
+    let mut latencies = Vec::new();
+    let addrs: Vec<_> = response
+        .peers
+        .iter()
+        .filter_map(|peer| peer.socket_addr())
+        .take(args.peers)
+        .collect();
+
+    let deadline = Instant::now() + Duration::from_secs(args.seconds);
+    for addr in &addrs {
+        if Instant::now() >= deadline {
+            break;
+        }
+        let started = Instant::now();
+        if let Ok(Ok(_)) = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            reached += 1;
+            latencies.push(started.elapsed());
+        }
+    }
+
+    let average = latencies
+        .iter()
+        .sum::<Duration>()
+        .checked_div(latencies.len() as u32)
+        .unwrap_or_default();
+    println!(
+        "Connections : {}/{} peers reachable , {} ms average connect time",
+        reached,
+        addrs.len(),
+        average.as_millis()
+    );
+
+    // The download gets its own `--seconds` , through the same engine a real download uses
+    let peer_id = Tracker::generate_peer_id();
+    let mut candidates = PeerCandidates::new(peer_id, DEFAULT_LISTEN_PORT);
+    candidates.extend(response.peers);
+    let handshake = Handshake::new(torrent.info_hashes().primary(), peer_id);
+    let mut peers =
+        PeerManager::new(handshake, DEFAULT_LISTEN_PORT).with_max_connections(args.peers);
+
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.seconds);
+    let mut most_peers = 0;
+    while Instant::now() < deadline && !manager.is_download_complete() {
+        peers.dial_candidates(&mut candidates);
+        peers.poll(manager);
+        most_peers = most_peers.max(peers.len());
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    let elapsed = started.elapsed();
+    let stats = manager.get_stats();
+    println!(
+        "Throughput : {} of {} in {} , {} from up to {} peers",
+        humanize::bytes(stats.downloaded_bytes as u64),
+        humanize::bytes(stats.total_bytes as u64),
+        humanize::duration(elapsed),
+        humanize::rate(stats.downloaded_bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)),
+        most_peers
+    );
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use clap::Args;
+use color_eyre::{Result, eyre::eyre};
+use mini_p2p_file_transfer_system::{
+    net::{availability::PieceAvailability, block_manager::BlockManager},
+    protocol::torrent::Torrent,
+    storage::files::FileStorage,
+};
+use std::{fs, path::PathBuf};
+
+#[derive(Args, Debug, Clone)]
+pub struct SwarmStatsArgs {
+    #[arg(value_name = "FILE", help = "Path to the .torrent file")]
+    pub torrent: PathBuf,
+    #[arg(
+        short,
+        long,
+        default_value = ".",
+        help = "Where the data is downloaded to"
+    )]
+    pub download_dir: PathBuf,
+    #[arg(long, help = "Print the raw availability as JSON")]
+    pub json: bool,
+}
+
+/// Width of the longest histogram bar in characters
+const BAR_WIDTH: usize = 40;
+
+/// Prints how pieces are spread over the swarm and what we have of them
+pub fn run(args: SwarmStatsArgs) -> Result<()> {
+    let bytes = fs::read(&args.torrent)?;
+    let torrent = Torrent::from_bytes(&bytes).map_err(|e| eyre!("{}", e))?;
+
+    let storage = FileStorage::from(torrent.clone(), args.download_dir);
+    let manager = BlockManager::from(torrent, storage)?;
+    let availability = manager.piece_availability();
+
+    if args.json {
+        println!("{}", availability.to_json());
+        return Ok(());
+    }
+
+    print_summary(&availability);
+    Ok(())
+}
+
+fn print_summary(availability: &PieceAvailability) {
+    println!(
+        "Pieces : {}/{} , peers : {}",
+        availability.pieces_we_have(),
+        availability.piece_count(),
+        availability.peers
+    );
+    println!("Our bitfield : {}", hex::encode(&availability.ours));
+
+    if availability.peers == 0 {
+        println!("No peers connected , nothing to count availability from");
+        return;
+    }
+
+    println!(
+        "Distributed copies : {:.3}",
+        availability.distributed_copies()
+    );
+
+    println!("\nPieces by number of peers that have them :");
+    print_histogram(&availability.histogram());
+
+    println!("\nMissing pieces by number of peers that have them :");
+    print_histogram(&availability.missing_histogram());
+}
+
+fn print_histogram(histogram: &[usize]) {
+    let highest = histogram.iter().copied().max().unwrap_or(0);
+
+    for (peers, &pieces) in histogram.iter().enumerate() {
+        let width = (pieces * BAR_WIDTH).checked_div(highest).unwrap_or(0);
+        println!(
+            "{:>4} | {:<BAR_WIDTH$} {}",
+            peers,
+            "#".repeat(width),
+            pieces
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use mini_p2p_file_transfer_system::{
+    app::manager::ManagedTorrent, net::tracker::Tracker, util::humanize,
+};
+use std::time::{Instant, SystemTime};
+
+/// Text for the trackers pane , `keys` is the line of key hints under the title
+pub fn render(torrent: Option<&ManagedTorrent>, now: Instant, keys: &str) -> String {
+    let Some(torrent) = torrent else {
+        return format!("Trackers\n  {}\n\nNo torrent loaded\n", keys);
+    };
+    let trackers = &torrent.trackers;
+    let next = match torrent.slot_filler.next_announce() {
+        Some(at) if at > now => format!("in {}", humanize::duration(at - now)),
+        _ => String::from("now"),
+    };
+    let mut content = format!(
+        "Trackers ({} in {} tiers) - next announce {}\n",
+        trackers.trackers().count(),
+        trackers.tiers().len(),
+        next
+    );
+    content.push_str(&format!("  {}\n\n", keys));
+
+    let current = trackers.current().announce_url();
+    for (index, tier) in trackers.tiers().iter().enumerate() {
+        content.push_str(&format!("Tier {}\n", index + 1));
+        for tracker in tier.trackers() {
+            let marker = if index == trackers.current_tier() && tracker.announce_url() == current {
+                ">"
+            } else {
+                " "
+            };
+            content.push_str(&format!(
+                "{} {:<10} {}\n",
+                marker,
+                state(tracker),
+                tracker.announce_url()
+            ));
+            content.push_str(&format!("    {}\n", stats_line(tracker)));
+        }
+    }
+
+    content
+}
+
+/// e.g "[working]" , trackers worth pruning stand out as dead
+fn state(tracker: &Tracker) -> &'static str {
+    let stats = tracker.stats();
+    if !tracker.status().is_ready() {
+        "[unusable]"
+    } else if stats.looks_dead() {
+        "[dead]"
+    } else if stats.consecutive_failures > 0 {
+        "[failing]"
+    } else if stats.successes > 0 {
+        "[working]"
+    } else {
+        "[unused]"
+    }
+}
+
+/// Counters , recent success rate , peers and last error of a tracker
+fn stats_line(tracker: &Tracker) -> String {
+    let stats = tracker.stats();
+    let mut line = format!("{} ok , {} failed", stats.successes, stats.failures);
+    if let Some(rate) = stats.success_rate() {
+        line.push_str(&format!(" ({:.0}% recently)", rate * 100.0));
+    }
+    if let Some(peers) = stats.last_peers {
+        line.push_str(&format!(" , {} peers", peers));
+    }
+    if let Some(when) = stats.last_success {
+        line.push_str(&format!(" , answered {} ago", ago(when)));
+    }
+    if !tracker.status().is_ready() {
+        line.push_str(&format!(" , {}", tracker.status()));
+    } else if let Some(error) = tracker.last_error() {
+        line.push_str(&format!(" , last error : {}", error));
+    }
+    line
+}
+
+fn ago(when: SystemTime) -> String {
+    humanize::duration(SystemTime::now().duration_since(when).unwrap_or_default())
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+/// Local address or interface all traffic has to leave through , e.g a VPN's `tun0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindTarget {
+    Addr(IpAddr),
+    /// Interface name , sockets are pinned to it with SO_BINDTODEVICE
+    Interface(String),
+}
+
+impl BindTarget {
+    /// An ip address binds to that address , anything else is taken as an interface name
+    pub fn parse(value: &str) -> Self {
+        match value.parse::<IpAddr>() {
+            Ok(ip) => BindTarget::Addr(ip),
+            Err(_) => BindTarget::Interface(value.to_string()),
+        }
+    }
+
+    /// Whether the address or interface is still there , the kill switch checks this
+    pub fn is_available(&self) -> bool {
+        match self {
+            // Binding fails once the address is gone from every interface
+            BindTarget::Addr(ip) => std::net::UdpSocket::bind(SocketAddr::new(*ip, 0)).is_ok(),
+            BindTarget::Interface(name) => interface_is_up(name),
+        }
+    }
+
+    /// Pins an outgoing TCP socket before it connects to `remote`
+    pub fn prepare_tcp(
+        &self,
+        socket: &tokio::net::TcpSocket,
+        remote: SocketAddr,
+    ) -> io::Result<()> {
+        match self {
+            BindTarget::Addr(ip) => {
+                if ip.is_ipv4() != remote.is_ipv4() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrNotAvailable,
+                        format!("{} can't reach {}", ip, remote),
+                    ));
+                }
+                socket.bind(SocketAddr::new(*ip, 0))
+            }
+            BindTarget::Interface(name) => bind_tcp_device(socket, name),
+        }
+    }
+
+    /// Address a listening socket on `port` should bind to
+    pub fn listen_addr(&self, port: u16) -> SocketAddr {
+        match self {
+            BindTarget::Addr(ip) => SocketAddr::new(*ip, port),
+            BindTarget::Interface(_) => SocketAddr::from(([0, 0, 0, 0], port)),
+        }
+    }
+
+    /// Pins a bound UDP socket to the interface , addresses are handled by `listen_addr`
+    pub fn prepare_udp(&self, socket: &tokio::net::UdpSocket) -> io::Result<()> {
+        match self {
+            BindTarget::Addr(_) => Ok(()),
+            BindTarget::Interface(name) => bind_udp_device(socket, name),
+        }
+    }
+
+    /// Pins a listening TCP socket to the interface before it's bound
+    pub fn prepare_listener(&self, socket: &tokio::net::TcpSocket) -> io::Result<()> {
+        match self {
+            BindTarget::Addr(_) => Ok(()),
+            BindTarget::Interface(name) => bind_tcp_device(socket, name),
+        }
+    }
+}
+
+impl fmt::Display for BindTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindTarget::Addr(ip) => write!(f, "{}", ip),
+            BindTarget::Interface(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn interface_is_up(name: &str) -> bool {
+    // tun devices report "unknown" while working , only "down" or a missing device count as gone
+    match std::fs::read_to_string(
+        std::path::Path::new("/sys/class/net")
+            .join(name)
+            .join("operstate"),
+    ) {
+        Ok(state) => state.trim() != "down",
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interface_is_up(_name: &str) -> bool {
+    // No cheap way to list interfaces without extra deps , trust the name
+    true
+}
+
+#[cfg(target_os = "linux")]
+fn bind_tcp_device(socket: &tokio::net::TcpSocket, name: &str) -> io::Result<()> {
+    socket.bind_device(Some(name.as_bytes()))
+}
+
+#[cfg(target_os = "linux")]
+fn bind_udp_device(socket: &tokio::net::UdpSocket, name: &str) -> io::Result<()> {
+    socket.bind_device(Some(name.as_bytes()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_tcp_device(_socket: &tokio::net::TcpSocket, name: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("binding to interface {} is only supported on Linux", name),
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_udp_device(_socket: &tokio::net::UdpSocket, name: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("binding to interface {} is only supported on Linux", name),
+    ))
+}
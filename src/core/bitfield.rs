@@ -0,0 +1,180 @@
+//! Which pieces of a torrent are held , one bit per piece
+//!
+//! Laid out like the Bitfield message so it goes on and off the wire as is : high bit of the first
+//! byte is piece 0 , and the spare bits after the last piece are always zero
+
+use crate::protocol::message::PeerMessage;
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+
+/// A set of pieces out of `len` , backed by the wire format bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Bitfield {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bitfield {
+    /// No pieces out of `len`
+    pub fn new(len: usize) -> Self {
+        Self {
+            bytes: vec![0; len.div_ceil(8)],
+            len,
+        }
+    }
+
+    /// Every piece out of `len`
+    pub fn full(len: usize) -> Self {
+        let mut bitfield = Self {
+            bytes: vec![0xff; len.div_ceil(8)],
+            len,
+        };
+        bitfield.clear_spare();
+        bitfield
+    }
+
+    /// Checks a wire payload against a torrent of `len` pieces , the size has to match exactly and
+    /// no spare bit may be set
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Result<Self> {
+        let expected = len.div_ceil(8);
+        if bytes.len() != expected {
+            return Err(anyhow!(
+                "bitfield is {} bytes , expected {}",
+                bytes.len(),
+                expected
+            ));
+        }
+
+        let bitfield = Self {
+            bytes: bytes.to_vec(),
+            len,
+        };
+        if bitfield.spare_bits() != 0 {
+            return Err(anyhow!("bitfield has spare bits set"));
+        }
+        Ok(bitfield)
+    }
+
+    /// Takes the payload of a Bitfield message , None for any other message
+    pub fn from_message(message: &PeerMessage, len: usize) -> Option<Result<Self>> {
+        match message {
+            PeerMessage::Bitfield(bytes) => Some(Self::from_bytes(bytes, len)),
+            _ => None,
+        }
+    }
+
+    /// Bitfield message carrying these pieces
+    pub fn to_message(&self) -> PeerMessage {
+        PeerMessage::Bitfield(Bytes::copy_from_slice(&self.bytes))
+    }
+
+    /// Wire format bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Pieces the bitfield covers , set or not
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether piece `index` is set , false past the end
+    pub fn has(&self, index: usize) -> bool {
+        index < self.len && self.bytes[index / 8] & mask(index) != 0
+    }
+
+    /// Sets piece `index` , returns true if it wasn't set before
+    ///
+    /// Panics past the end , the caller checks indices that came off the wire
+    pub fn set(&mut self, index: usize) -> bool {
+        assert!(
+            index < self.len,
+            "piece {} out of range , bitfield has {}",
+            index,
+            self.len
+        );
+        let was_set = self.has(index);
+        self.bytes[index / 8] |= mask(index);
+        !was_set
+    }
+
+    /// Clears piece `index` , returns true if it was set
+    pub fn unset(&mut self, index: usize) -> bool {
+        let was_set = self.has(index);
+        if was_set {
+            self.bytes[index / 8] &= !mask(index);
+        }
+        was_set
+    }
+
+    /// Pieces set
+    pub fn count_ones(&self) -> usize {
+        self.bytes
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    /// Whether every piece is set , a seed's bitfield
+    pub fn is_full(&self) -> bool {
+        self.count_ones() == self.len
+    }
+
+    /// Indices of the pieces set , in order
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|&index| self.has(index))
+    }
+
+    /// Indices of the pieces not set , in order
+    pub fn missing(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|&index| !self.has(index))
+    }
+
+    /// Bits after the last piece in the final byte , zero in a valid bitfield
+    fn spare_bits(&self) -> u8 {
+        let spare = self.bytes.len() * 8 - self.len;
+        match self.bytes.last() {
+            Some(last) if spare > 0 => last & ((1u8 << spare) - 1),
+            _ => 0,
+        }
+    }
+
+    fn clear_spare(&mut self) {
+        let spare = self.bytes.len() * 8 - self.len;
+        if spare > 0
+            && let Some(last) = self.bytes.last_mut()
+        {
+            *last &= !((1u8 << spare) - 1);
+        }
+    }
+}
+
+impl FromIterator<bool> for Bitfield {
+    /// One piece per flag , in order
+    fn from_iter<I: IntoIterator<Item = bool>>(flags: I) -> Self {
+        let mut bitfield = Self::default();
+        for flag in flags {
+            if bitfield.len.is_multiple_of(8) {
+                bitfield.bytes.push(0);
+            }
+            bitfield.len += 1;
+            if flag {
+                bitfield.set(bitfield.len - 1);
+            }
+        }
+        bitfield
+    }
+}
+
+/// Bit of `index` within its byte , high bit first
+fn mask(index: usize) -> u8 {
+    0x80 >> (index % 8)
+}
@@ -0,0 +1,107 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of the current time for the engine
+///
+/// Everything that deals with timeouts asks a clock instead of calling `Instant::now()` , so tests can swap in a
+/// MockClock and move time forward by hand
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The real clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Moves the clock forward , every clone of this clock sees the change
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Shared handle to a clock
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The clock used when nothing else is specified
+pub fn system_clock() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// A gap this long between two checks means the machine was asleep , not just busy
+pub const SUSPEND_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Notices when the system was suspended , by time jumping between two checks
+///
+/// Depending on the platform `Instant` either keeps counting through a suspend (and every timer
+/// based on it fires at once on resume) or stands still while the wall clock moves on , so both are
+/// compared with the last check. A wall clock set forward by hand looks the same and is treated the
+/// same , which costs no more than a round of keep-alives and an early announce
+#[derive(Debug, Clone)]
+pub struct SuspendDetector {
+    threshold: Duration,
+    last: Option<(Instant, SystemTime)>,
+}
+
+impl SuspendDetector {
+    pub fn new() -> Self {
+        Self {
+            threshold: SUSPEND_THRESHOLD,
+            last: None,
+        }
+    }
+
+    /// Gap that counts as a suspend , keep it well above the time between checks
+    pub fn with_threshold(mut self, threshold: Duration) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Call on every pass of the loop , returns how long we were away when time jumped
+    pub fn check(&mut self, now: Instant, wall: SystemTime) -> Option<Duration> {
+        let (last, last_wall) = self.last.replace((now, wall))?;
+        let elapsed = now.saturating_duration_since(last);
+        // A wall clock set back is no suspend
+        let wall_elapsed = wall.duration_since(last_wall).unwrap_or_default();
+        let gap = elapsed.max(wall_elapsed);
+        (gap >= self.threshold).then_some(gap)
+    }
+}
+
+impl Default for SuspendDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
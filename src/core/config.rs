@@ -0,0 +1,486 @@
+use crate::{
+    core::bind::BindTarget,
+    net::{
+        encryption::EncryptionPolicy, metadata_fetch::DEFAULT_METADATA_CHUNK_RATE,
+        port_policy::PortPolicy,
+    },
+    protocol::metadata::DEFAULT_MAX_METADATA_SIZE,
+};
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of our folder inside the platform config directory
+pub const CONFIG_DIR_NAME: &str = "sekiro";
+
+/// Where settings and caches live : `$XDG_CONFIG_HOME/sekiro` , falling back to `~/.config/sekiro`
+///
+/// None when neither variable is set (e.g some service accounts)
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(base.join(CONFIG_DIR_NAME))
+}
+
+/// Where torrents are downloaded when neither the config nor the add call says otherwise : `~/Downloads` ,
+/// or `Downloads` in the working directory without a home directory
+pub fn default_download_dir() -> PathBuf {
+    match env::var_os("HOME") {
+        Some(home) if !home.is_empty() => PathBuf::from(home).join("Downloads"),
+        _ => PathBuf::from("Downloads"),
+    }
+}
+
+/// Name of the settings file inside the config directory
+pub const CONFIG_FILE_NAME: &str = "config.json";
+
+/// Routers a fresh DHT node asks for its first nodes
+pub const DEFAULT_DHT_BOOTSTRAP_NODES: [&str; 3] = [
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+/// Settings read from `config.json` , anything missing keeps its default
+///
+/// ```json
+/// { "dht": { "enabled": true , "port": 6881 , "read_only": false ,
+///           "bootstrap_nodes": ["router.example.com:6881"] } ,
+///   "network": { "bind": "tun0" , "kill_switch": true , "encryption": "preferred" ,
+///                "allow_privileged_ports": false , "blocked_ports": [1900 , 6666 , 6667] ,
+///                "limit_lan_peers": false } ,
+///   "metadata": { "max_size_mib": 8 , "chunks_per_second": 64 } ,
+///   "labels": { "private": { "ratio": 2.0 , "seed_time_minutes": 4320 } , "public": { "ratio": 0.1 } } ,
+///   "idle_pause_days": 14 ,
+///   "download_dir": "/srv/torrents" ,
+///   "verify_writes": false ,
+///   "keymap": { "next": ["n" , "down"] , "previous": ["p" , "up"] , "quit": "q" } ,
+///   "geoip_databases": ["/usr/share/GeoIP/GeoLite2-Country.mmdb" , "/usr/share/GeoIP/GeoLite2-ASN.mmdb"] }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub dht: DhtConfig,
+    pub network: NetworkConfig,
+    /// Caps on fetching a magnet link's info dictionary from peers
+    pub metadata: MetadataConfig,
+    /// Seeding targets per label , applied to a torrent when it gets the label
+    pub labels: HashMap<String, SeedTarget>,
+    /// Torrents that haven't uploaded or downloaded anything for this long get paused , None never pauses
+    pub idle_pause: Option<Duration>,
+    /// Key names per TUI action , replacing that action's default keys. Checked by the TUI , which
+    /// knows the actions and key names
+    pub keymap: BTreeMap<String, Vec<String>>,
+    /// MaxMind format databases peers are looked up in , only used with the `geoip` feature
+    pub geoip_databases: Vec<PathBuf>,
+    /// Where torrents are downloaded unless they're added with their own directory , None for
+    /// `default_download_dir`
+    pub download_dir: Option<PathBuf>,
+    /// Read every piece back after writing it and hash it again , for disks that lose writes silently
+    pub verify_writes: bool,
+}
+
+/// When a torrent has seeded enough , whichever target is hit first. No targets seeds forever
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SeedTarget {
+    /// Uploaded bytes over the torrent's size
+    pub ratio: Option<f64>,
+    /// Time spent seeding since the download finished
+    pub seed_time: Option<Duration>,
+}
+
+impl SeedTarget {
+    pub fn is_set(&self) -> bool {
+        self.ratio.is_some() || self.seed_time.is_some()
+    }
+
+    /// Whether seeding can stop given the current ratio and time seeded so far
+    pub fn reached(&self, ratio: f64, seeded_for: Duration) -> bool {
+        let ratio_reached = self.ratio.is_some_and(|target| ratio >= target);
+        let time_reached = self.seed_time.is_some_and(|target| seeded_for >= target);
+        ratio_reached || time_reached
+    }
+
+    fn from_json(label: &str, value: &Value) -> Result<Self> {
+        let Some(section) = value.as_object() else {
+            return Err(anyhow!("labels.{} must be an object", label));
+        };
+        let mut target = SeedTarget::default();
+
+        if let Some(ratio) = section.get("ratio") {
+            target.ratio = match ratio {
+                Value::Null => None,
+                ratio => Some(
+                    ratio
+                        .as_f64()
+                        .filter(|r| r.is_finite() && *r >= 0.0)
+                        .ok_or_else(|| anyhow!("labels.{}.ratio must be a number >= 0", label))?,
+                ),
+            };
+        }
+
+        if let Some(minutes) = section.get("seed_time_minutes") {
+            target.seed_time = match minutes {
+                Value::Null => None,
+                minutes => Some(Duration::from_secs(
+                    minutes
+                        .as_u64()
+                        .ok_or_else(|| {
+                            anyhow!("labels.{}.seed_time_minutes must be whole minutes", label)
+                        })?
+                        .saturating_mul(60),
+                )),
+            };
+        }
+        Ok(target)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// Address or interface every socket and tracker request goes through , None uses the OS default route
+    pub bind: Option<BindTarget>,
+    /// Pause all networking while the bound address or interface is gone , so nothing leaks out another route
+    pub kill_switch: bool,
+    /// Whether peer connections use MSE , and which mode is tried first
+    pub encryption: EncryptionPolicy,
+    /// Peer ports we refuse to dial
+    pub ports: PortPolicy,
+    /// Apply rate limits to peers on the local network too , off lets LAN transfers run at wire speed
+    pub limit_lan_peers: bool,
+}
+
+impl NetworkConfig {
+    fn from_json(value: &Value) -> Result<Self> {
+        let mut config = NetworkConfig::default();
+        let Some(section) = value.as_object() else {
+            return Err(anyhow!("network must be an object"));
+        };
+
+        if let Some(bind) = section.get("bind") {
+            config.bind = match bind {
+                Value::Null => None,
+                Value::String(target) if !target.trim().is_empty() => {
+                    Some(BindTarget::parse(target.trim()))
+                }
+                _ => return Err(anyhow!("network.bind must be an address or interface name")),
+            };
+        }
+
+        if let Some(kill_switch) = section.get("kill_switch") {
+            config.kill_switch = kill_switch
+                .as_bool()
+                .ok_or_else(|| anyhow!("network.kill_switch must be true or false"))?;
+        }
+
+        if let Some(encryption) = section.get("encryption") {
+            let name = encryption.as_str().ok_or_else(|| {
+                anyhow!("network.encryption must be disabled , enabled , preferred or required")
+            })?;
+            config.encryption = EncryptionPolicy::parse(name)?;
+        }
+
+        if let Some(allow) = section.get("allow_privileged_ports") {
+            config.ports.allow_privileged = allow
+                .as_bool()
+                .ok_or_else(|| anyhow!("network.allow_privileged_ports must be true or false"))?;
+        }
+
+        if let Some(limit) = section.get("limit_lan_peers") {
+            config.limit_lan_peers = limit
+                .as_bool()
+                .ok_or_else(|| anyhow!("network.limit_lan_peers must be true or false"))?;
+        }
+
+        // Replaces the default list , [] turns port blocking off
+        if let Some(blocked) = section.get("blocked_ports") {
+            let ports = blocked
+                .as_array()
+                .ok_or_else(|| anyhow!("network.blocked_ports must be a list of ports"))?;
+            config.ports.blocked = ports
+                .iter()
+                .map(|port| {
+                    port.as_u64()
+                        .and_then(|port| u16::try_from(port).ok())
+                        .ok_or_else(|| {
+                            anyhow!("network.blocked_ports has an invalid port {}", port)
+                        })
+                })
+                .collect::<Result<_>>()?;
+        }
+
+        if config.kill_switch && config.bind.is_none() {
+            return Err(anyhow!("network.kill_switch needs network.bind to be set"));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhtConfig {
+    /// Run the DHT at all , when off we don't advertise it in handshakes either
+    pub enabled: bool,
+    /// UDP port for the DHT , None shares the peer listen port
+    pub port: Option<u16>,
+    /// Don't answer queries (BEP 43) , for networks that drop unsolicited inbound traffic
+    pub read_only: bool,
+    /// `host:port` entries used to join the DHT when the routing table is empty
+    pub bootstrap_nodes: Vec<String>,
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            port: None,
+            read_only: false,
+            bootstrap_nodes: DEFAULT_DHT_BOOTSTRAP_NODES
+                .iter()
+                .map(|node| node.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl DhtConfig {
+    /// UDP port the DHT binds to
+    pub fn listen_port(&self, peer_port: u16) -> u16 {
+        self.port.unwrap_or(peer_port)
+    }
+
+    /// Checks every field , so a bad setting is reported when it's set instead of when the DHT starts
+    pub fn validate(&self) -> Result<()> {
+        if self.port == Some(0) {
+            return Err(anyhow!("dht.port can't be 0"));
+        }
+
+        for node in &self.bootstrap_nodes {
+            let valid = node
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if !valid {
+                return Err(anyhow!(
+                    "dht.bootstrap_nodes entry '{}' is not host:port",
+                    node
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn from_json(value: &Value) -> Result<Self> {
+        let mut config = DhtConfig::default();
+        let Some(section) = value.as_object() else {
+            return Err(anyhow!("dht must be an object"));
+        };
+
+        if let Some(port) = section.get("port") {
+            config.port = match port {
+                Value::Null => None,
+                port => Some(
+                    port.as_u64()
+                        .and_then(|p| u16::try_from(p).ok())
+                        .ok_or_else(|| anyhow!("dht.port must be a port number"))?,
+                ),
+            };
+        }
+
+        if let Some(enabled) = section.get("enabled") {
+            config.enabled = enabled
+                .as_bool()
+                .ok_or_else(|| anyhow!("dht.enabled must be true or false"))?;
+        }
+
+        if let Some(read_only) = section.get("read_only") {
+            config.read_only = read_only
+                .as_bool()
+                .ok_or_else(|| anyhow!("dht.read_only must be true or false"))?;
+        }
+
+        if let Some(nodes) = section.get("bootstrap_nodes") {
+            config.bootstrap_nodes = nodes
+                .as_array()
+                .and_then(|nodes| {
+                    nodes
+                        .iter()
+                        .map(|node| node.as_str().map(String::from))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| anyhow!("dht.bootstrap_nodes must be a list of strings"))?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataConfig {
+    /// Largest info dictionary fetched for a magnet link , peers announcing more are dropped
+    pub max_size: usize,
+    /// Metadata pieces asked of one peer per second , None doesn't pace requests
+    pub chunk_rate: Option<u32>,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_METADATA_SIZE,
+            chunk_rate: Some(DEFAULT_METADATA_CHUNK_RATE),
+        }
+    }
+}
+
+impl MetadataConfig {
+    fn from_json(value: &Value) -> Result<Self> {
+        let mut config = MetadataConfig::default();
+        let Some(section) = value.as_object() else {
+            return Err(anyhow!("metadata must be an object"));
+        };
+
+        if let Some(size) = section.get("max_size_mib") {
+            let mib = size
+                .as_f64()
+                .filter(|&mib| mib > 0.0 && mib <= 1024.0)
+                .ok_or_else(|| {
+                    anyhow!("metadata.max_size_mib must be a size in MiB , 1024 at most")
+                })?;
+            config.max_size = (mib * 1024.0 * 1024.0) as usize;
+        }
+
+        if let Some(rate) = section.get("chunks_per_second") {
+            config.chunk_rate = match rate {
+                Value::Null => None,
+                rate => Some(
+                    rate.as_u64()
+                        .and_then(|rate| u32::try_from(rate).ok())
+                        .filter(|&rate| rate > 0)
+                        .ok_or_else(|| {
+                            anyhow!("metadata.chunks_per_second must be a whole number > 0 or null")
+                        })?,
+                ),
+            };
+        }
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// Default location of the settings file
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(text)?;
+        let mut config = Config::default();
+
+        if let Some(dht) = value.get("dht") {
+            config.dht = DhtConfig::from_json(dht)?;
+        }
+        if let Some(network) = value.get("network") {
+            config.network = NetworkConfig::from_json(network)?;
+        }
+        if let Some(metadata) = value.get("metadata") {
+            config.metadata = MetadataConfig::from_json(metadata)?;
+        }
+        if let Some(labels) = value.get("labels") {
+            let labels = labels
+                .as_object()
+                .ok_or_else(|| anyhow!("labels must be an object"))?;
+            for (label, target) in labels {
+                config
+                    .labels
+                    .insert(label.clone(), SeedTarget::from_json(label, target)?);
+            }
+        }
+        if let Some(keymap) = value.get("keymap") {
+            let keymap = keymap
+                .as_object()
+                .ok_or_else(|| anyhow!("keymap must be an object"))?;
+            for (action, keys) in keymap {
+                let keys = match keys {
+                    Value::String(key) => vec![key.clone()],
+                    Value::Array(keys) => keys
+                        .iter()
+                        .map(|key| key.as_str().map(String::from))
+                        .collect::<Option<_>>()
+                        .ok_or_else(|| anyhow!("keymap.{} must only list key names", action))?,
+                    _ => return Err(anyhow!("keymap.{} must be a key or a list of keys", action)),
+                };
+                config.keymap.insert(action.clone(), keys);
+            }
+        }
+        if let Some(databases) = value.get("geoip_databases") {
+            config.geoip_databases = databases
+                .as_array()
+                .and_then(|databases| {
+                    databases
+                        .iter()
+                        .map(|path| path.as_str().map(PathBuf::from))
+                        .collect::<Option<_>>()
+                })
+                .ok_or_else(|| anyhow!("geoip_databases must be a list of paths"))?;
+        }
+        if let Some(dir) = value.get("download_dir") {
+            config.download_dir = match dir {
+                Value::Null => None,
+                dir => Some(
+                    dir.as_str()
+                        .filter(|dir| !dir.is_empty())
+                        .map(PathBuf::from)
+                        .ok_or_else(|| anyhow!("download_dir must be a path"))?,
+                ),
+            };
+        }
+        if let Some(verify) = value.get("verify_writes") {
+            config.verify_writes = verify
+                .as_bool()
+                .ok_or_else(|| anyhow!("verify_writes must be true or false"))?;
+        }
+        if let Some(days) = value.get("idle_pause_days") {
+            config.idle_pause = match days {
+                Value::Null => None,
+                days => Some(Duration::from_secs(
+                    days.as_u64()
+                        .filter(|&days| days > 0)
+                        .ok_or_else(|| anyhow!("idle_pause_days must be whole days > 0"))?
+                        .saturating_mul(24 * 60 * 60),
+                )),
+            };
+        }
+        Ok(config)
+    }
+
+    /// Where torrents go unless they're added with their own directory
+    pub fn download_dir(&self) -> PathBuf {
+        self.download_dir
+            .clone()
+            .unwrap_or_else(default_download_dir)
+    }
+
+    /// Reads the settings file , a missing file just means defaults
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::from_json(&text)
+                .map_err(|e| anyhow!("Invalid config file {} : {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Settings from the default location , defaults when there is no config directory
+    pub fn load_default() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) => Self::load(&path),
+            None => Ok(Config::default()),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Picks up edits to the config file while the client runs
+//!
+//! There is no file notification crate , the watcher compares the file's modification time and size
+//! whenever `poll` is called. Settings that can change at runtime are taken over , the ones that
+//! need a restart (bound address , DHT port ...) keep their old value and are reported as rejected
+
+use crate::core::{config::Config, events::Event};
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Watches one config file , call `poll` regularly (e.g every second)
+#[derive(Debug, Clone)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    /// Modification time and size when the file was last read , None while it doesn't exist
+    stamp: Option<(SystemTime, u64)>,
+    current: Config,
+}
+
+impl ConfigWatcher {
+    /// Starts watching `path` , `current` is the config the client is running with
+    pub fn new(path: impl Into<PathBuf>, current: Config) -> Self {
+        let path = path.into();
+        Self {
+            stamp: file_stamp(&path),
+            path,
+            current,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Config in effect , restart-required settings still hold their values from startup
+    pub fn current(&self) -> &Config {
+        &self.current
+    }
+
+    /// Reads the file again if it changed since the last call
+    ///
+    /// None when nothing changed. A file that no longer parses is an error and the current config
+    /// stays , the next edit gets another try
+    pub fn poll(&mut self) -> Option<Result<ConfigReload>> {
+        let stamp = file_stamp(&self.path);
+        if stamp == self.stamp {
+            return None;
+        }
+        self.stamp = stamp;
+
+        Some(Config::load(&self.path).map(|new| {
+            let reload = ConfigReload::between(&self.current, &new);
+            self.current = reload.config.clone();
+            reload
+        }))
+    }
+}
+
+/// Outcome of a reload
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigReload {
+    /// What to run with now , the new file with restart-required settings put back
+    pub config: Config,
+    /// Settings that changed and took effect
+    pub applied: Vec<String>,
+    /// Settings that changed but only take effect after a restart
+    pub rejected: Vec<String>,
+}
+
+impl ConfigReload {
+    /// Compares two configs setting by setting
+    pub fn between(current: &Config, new: &Config) -> Self {
+        let mut config = new.clone();
+        let mut applied = Vec::new();
+        let mut rejected = Vec::new();
+        // Returns true when the change has to wait for a restart
+        let mut note = |name: &str, changed: bool, needs_restart: bool| {
+            if changed {
+                if needs_restart {
+                    rejected.push(name.to_string());
+                } else {
+                    applied.push(name.to_string());
+                }
+            }
+            changed && needs_restart
+        };
+
+        if note(
+            "network.bind",
+            current.network.bind != new.network.bind,
+            true,
+        ) {
+            config.network.bind = current.network.bind.clone();
+        }
+        if note(
+            "network.limit_lan_peers",
+            current.network.limit_lan_peers != new.network.limit_lan_peers,
+            true,
+        ) {
+            config.network.limit_lan_peers = current.network.limit_lan_peers;
+        }
+        if note(
+            "network.kill_switch",
+            current.network.kill_switch != new.network.kill_switch,
+            true,
+        ) {
+            config.network.kill_switch = current.network.kill_switch;
+        }
+        note(
+            "network.encryption",
+            current.network.encryption != new.network.encryption,
+            false,
+        );
+        note(
+            "network.allow_privileged_ports",
+            current.network.ports.allow_privileged != new.network.ports.allow_privileged,
+            false,
+        );
+        note(
+            "network.blocked_ports",
+            current.network.ports.blocked != new.network.ports.blocked,
+            false,
+        );
+
+        if note("dht.enabled", current.dht.enabled != new.dht.enabled, true) {
+            config.dht.enabled = current.dht.enabled;
+        }
+        if note("dht.port", current.dht.port != new.dht.port, true) {
+            config.dht.port = current.dht.port;
+        }
+        note(
+            "dht.read_only",
+            current.dht.read_only != new.dht.read_only,
+            false,
+        );
+        note(
+            "dht.bootstrap_nodes",
+            current.dht.bootstrap_nodes != new.dht.bootstrap_nodes,
+            false,
+        );
+
+        note(
+            "metadata.max_size_mib",
+            current.metadata.max_size != new.metadata.max_size,
+            false,
+        );
+        note(
+            "metadata.chunks_per_second",
+            current.metadata.chunk_rate != new.metadata.chunk_rate,
+            false,
+        );
+
+        note("labels", current.labels != new.labels, false);
+        note(
+            "idle_pause_days",
+            current.idle_pause != new.idle_pause,
+            false,
+        );
+        note("keymap", current.keymap != new.keymap, false);
+        note(
+            "download_dir",
+            current.download_dir != new.download_dir,
+            false,
+        );
+        note(
+            "verify_writes",
+            current.verify_writes != new.verify_writes,
+            false,
+        );
+        note(
+            "geoip_databases",
+            current.geoip_databases != new.geoip_databases,
+            false,
+        );
+
+        Self {
+            config,
+            applied,
+            rejected,
+        }
+    }
+
+    /// Keeps in `applied` only the settings a front end takes over while running , the other
+    /// changes are reported as needing a restart
+    pub fn applying(mut self, handled: &[&str]) -> Self {
+        let (applied, unhandled): (Vec<String>, Vec<String>) = self
+            .applied
+            .into_iter()
+            .partition(|name| handled.contains(&name.as_str()));
+        self.applied = applied;
+        self.rejected.extend(unhandled);
+        self
+    }
+
+    /// True when the file changed without touching any setting (e.g reformatted)
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.rejected.is_empty()
+    }
+
+    /// One line for the status bar or the log
+    pub fn summary(&self) -> String {
+        let list = |names: &[String]| {
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(" , ")
+            }
+        };
+        format!(
+            "Config reloaded , applied : {} | needs restart : {}",
+            list(&self.applied),
+            list(&self.rejected)
+        )
+    }
+
+    pub fn to_event(&self) -> Event {
+        Event::ConfigReloaded {
+            applied: self.applied.clone(),
+            rejected: self.rejected.clone(),
+        }
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// How many events a slow subscriber can fall behind before it starts missing them
+pub const EVENT_BUS_CAPACITY: usize = 1024;
+
+/// Things that happen in the engine that the UI (or anyone else) may want to show
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Torrent creation hashed another piece
+    HashProgress {
+        hashed_pieces: usize,
+        total_pieces: usize,
+        hashed_bytes: u64,
+        total_bytes: u64,
+    },
+    /// A piece we had no longer matches its hash (bit-rot , outside edits) , so it was never sent on.
+    /// The piece has been queued for download again
+    PieceCorrupted {
+        piece_index: usize,
+        reason: String,
+        found_by: CorruptionCheck,
+    },
+    /// Storage kept failing or timing out , the torrent stopped requesting data for `retry_in`
+    StoragePaused { reason: String, retry_in: Duration },
+    /// Storage works again after a pause , downloading carries on
+    StorageResumed,
+    /// A file of the torrent is skipped (writes kept failing , e.g path too long) , the rest carries on
+    FileQuarantined {
+        file_index: usize,
+        path: PathBuf,
+        reason: String,
+    },
+    /// Resume data (and its backup) couldn't be used , the torrent is rechecked from scratch
+    ResumeDataRejected { path: PathBuf, reason: String },
+    /// The config file changed on disk. `rejected` settings only take effect after a restart
+    ConfigReloaded {
+        applied: Vec<String>,
+        rejected: Vec<String>,
+    },
+}
+
+/// What read a corrupted piece back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionCheck {
+    /// The background scrub
+    Scrub,
+    /// Serving it to a peer , pieces not hashed since startup are checked before their first upload
+    Upload,
+}
+
+/// Fan out channel for engine events
+///
+/// Publishing never blocks and never fails , events sent while nobody is subscribed are dropped.
+/// A subscriber that falls more than EVENT_BUS_CAPACITY events behind gets `RecvError::Lagged` and skips ahead
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Self { tx }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(EVENT_BUS_CAPACITY)
+    }
+}
@@ -0,0 +1,51 @@
+//! Keeps a panic in one torrent's logic from taking the whole session down
+//!
+//! The torrent that panicked is stopped with the panic message as its error , every other torrent
+//! carries on. Its state may be half updated , so it isn't touched again until the user restarts it
+
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Runs `f` , a panic comes back as an error holding the panic message
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| panic_message(payload.as_ref()))
+}
+
+/// Text of a panic payload , `panic!` with a message gives a `&str` or a `String`
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("panicked without a message")
+    }
+}
+
+/// `catch_panic` for a task , resolves to an error when any poll of `future` panics
+pub fn isolated<F: Future>(future: F) -> Isolated<F> {
+    Isolated {
+        future: Box::pin(future),
+    }
+}
+
+/// Future returned by `isolated`
+pub struct Isolated<F: Future> {
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Isolated<F> {
+    type Output = Result<F::Output, String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.as_mut();
+        match catch_panic(move || future.poll(cx)) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(message) => Poll::Ready(Err(message)),
+        }
+    }
+}
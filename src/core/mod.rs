@@ -0,0 +1,13 @@
+pub mod bind;
+pub mod bitfield;
+pub mod clock;
+pub mod config;
+pub mod config_watch;
+pub mod events;
+pub mod isolate;
+pub mod peer;
+pub mod piece_math;
+pub mod piece_picker;
+pub mod priority;
+pub mod resources;
+pub mod runtime;
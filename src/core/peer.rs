@@ -0,0 +1,366 @@
+use crate::{
+    core::bitfield::Bitfield,
+    protocol::{
+        extension::{
+            EXTENDED_HANDSHAKE_ID, ExtendedHandshake, LOCAL_UPLOAD_ONLY_ID, UPLOAD_ONLY,
+            parse_upload_only,
+        },
+        message::PeerMessage,
+        metadata::UT_METADATA,
+        peer::PeerSource,
+    },
+};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// A keep-alive goes out when nothing else was sent to a peer for this long
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(120);
+
+/// A peer that unchoked us but sent no block for this long , while we want its pieces , is snubbing us
+pub const SNUB_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// When a message last went one way over a connection , and which one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastMessage {
+    pub at: Instant,
+    /// Message name , e.g "piece" or "keep-alive"
+    pub kind: &'static str,
+}
+
+impl LastMessage {
+    pub fn age(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.at)
+    }
+}
+
+/// Messages that went one way over a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageLog {
+    pub last: Option<LastMessage>,
+    pub messages: u64,
+    /// Part of `messages` that were keep-alives
+    pub keepalives: u64,
+}
+
+impl MessageLog {
+    pub fn record(&mut self, message: &PeerMessage, now: Instant) {
+        self.last = Some(LastMessage {
+            at: now,
+            kind: message.name(),
+        });
+        self.messages += 1;
+        if *message == PeerMessage::KeepAlive {
+            self.keepalives += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Live state of a peer we're connected to
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub peer_id: Option<[u8; 20]>,
+    /// Client name worked out from the peer id
+    pub client: String,
+    /// Where we heard about the peer , empty when it connected to us
+    pub sources: BTreeSet<PeerSource>,
+
+    // Choke / interest state , both directions
+    pub am_choking: bool,
+    pub am_interested: bool,
+    pub peer_choking: bool,
+    pub peer_interested: bool,
+
+    /// How many pieces the peer told us it has
+    pub pieces_have: usize,
+    /// Pieces the peer has , nothing until it sends a Bitfield or Have. Sized by `with_piece_count`
+    pub bitfield: Bitfield,
+    /// Peer said it won't download anything (BEP 21) , a seed or partial seed
+    pub upload_only: bool,
+    /// Id the peer wants upload_only messages under , None if it doesn't support them
+    pub upload_only_id: Option<u8>,
+    /// Id the peer wants ut_metadata messages under , None if it doesn't support them
+    pub ut_metadata_id: Option<u8>,
+    /// Malformed messages the peer sent , e.g a Bitfield of the wrong size
+    pub protocol_violations: u32,
+    /// Bytes per second we're receiving from the peer
+    pub download_rate: f64,
+    /// Bytes per second we're sending to the peer
+    pub upload_rate: f64,
+    pub downloaded: u64,
+    pub uploaded: u64,
+
+    pub connected_at: Instant,
+    /// Messages the peer sent us
+    pub received: MessageLog,
+    /// Messages we sent the peer
+    pub sent: MessageLog,
+    /// Last Piece message from the peer
+    pub last_block_at: Option<Instant>,
+    /// Last time the peer unchoked us
+    pub unchoked_at: Option<Instant>,
+}
+
+/// Copy of a peer's state for readers (UI , RPC) so they never touch the live peer
+#[derive(Debug, Clone)]
+pub struct PeerSnapshot {
+    pub addr: SocketAddr,
+    pub client: String,
+    /// Empty when the peer connected to us
+    pub sources: BTreeSet<PeerSource>,
+    /// 0.0 - 100.0
+    pub progress: f64,
+    pub download_rate: f64,
+    pub upload_rate: f64,
+    /// Whether the peer is choking us
+    pub peer_choking: bool,
+    pub is_seed: bool,
+    pub protocol_violations: u32,
+    /// Country and ASN , filled in by a GeoIP lookup when one is set up
+    pub location: Option<PeerLocation>,
+    pub last_received: Option<LastMessage>,
+    pub last_sent: Option<LastMessage>,
+    pub keepalives_received: u64,
+    pub keepalives_sent: u64,
+    /// Time since the peer sent anything , or since it connected
+    pub idle_for: Duration,
+    pub snubbed: bool,
+    /// When the snapshot was taken , ages of the last messages count from here
+    pub taken_at: Instant,
+}
+
+/// Where a peer's address is , from a local GeoIP database
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerLocation {
+    /// ISO 3166 code , e.g "DE"
+    pub country: Option<String>,
+    /// Autonomous system number of the network the address belongs to
+    pub asn: Option<u32>,
+    /// Organization running that network
+    pub as_org: Option<String>,
+}
+
+impl PeerLocation {
+    pub fn is_empty(&self) -> bool {
+        self.country.is_none() && self.asn.is_none()
+    }
+
+    /// Short form for a table column , e.g "DE AS3320"
+    pub fn label(&self) -> String {
+        let parts: Vec<String> = self
+            .country
+            .iter()
+            .cloned()
+            .chain(self.asn.map(|asn| format!("AS{}", asn)))
+            .collect();
+        if parts.is_empty() {
+            return String::from("-");
+        }
+        parts.join(" ")
+    }
+}
+
+impl Peer {
+    pub fn new(addr: SocketAddr, connected_at: Instant) -> Self {
+        Self {
+            addr,
+            peer_id: None,
+            client: String::from("Unknown"),
+            sources: BTreeSet::new(),
+            am_choking: true,
+            am_interested: false,
+            peer_choking: true,
+            peer_interested: false,
+            pieces_have: 0,
+            bitfield: Bitfield::default(),
+            upload_only: false,
+            upload_only_id: None,
+            ut_metadata_id: None,
+            protocol_violations: 0,
+            download_rate: 0.0,
+            upload_rate: 0.0,
+            downloaded: 0,
+            uploaded: 0,
+            connected_at,
+            received: MessageLog::default(),
+            sent: MessageLog::default(),
+            last_block_at: None,
+            unchoked_at: None,
+        }
+    }
+
+    /// Sizes the bitfield to a torrent of `piece_count` pieces , Haves past it are ignored
+    pub fn with_piece_count(mut self, piece_count: usize) -> Self {
+        self.bitfield = Bitfield::new(piece_count);
+        self.pieces_have = 0;
+        self
+    }
+
+    pub fn set_peer_id(&mut self, peer_id: [u8; 20]) {
+        self.client = client_name(&peer_id);
+        self.peer_id = Some(peer_id);
+    }
+
+    /// Takes the peer's Bitfield message
+    pub fn set_bitfield(&mut self, bitfield: Bitfield) {
+        self.pieces_have = bitfield.count_ones();
+        self.bitfield = bitfield;
+    }
+
+    /// Takes a Have message , returns true when the piece is new for the peer
+    pub fn set_have(&mut self, index: usize) -> bool {
+        let new = index < self.bitfield.len() && self.bitfield.set(index);
+        if new {
+            self.pieces_have += 1;
+        }
+        new
+    }
+
+    /// Notes a message the peer sent us
+    pub fn record_received(&mut self, message: &PeerMessage, now: Instant) {
+        self.received.record(message, now);
+        match message {
+            PeerMessage::Piece { .. } => self.last_block_at = Some(now),
+            PeerMessage::Unchoke => self.unchoked_at = Some(now),
+            _ => {}
+        }
+    }
+
+    /// Notes a message we sent the peer
+    pub fn record_sent(&mut self, message: &PeerMessage, now: Instant) {
+        self.sent.record(message, now);
+    }
+
+    /// Starts the snub timer over , the peer couldn't send us anything while we were suspended
+    pub fn resumed(&mut self, now: Instant) {
+        if !self.peer_choking {
+            self.unchoked_at = Some(now);
+        }
+    }
+
+    /// Time since the peer sent anything , or since it connected if it never did
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        let since = self.received.last.map_or(self.connected_at, |last| last.at);
+        now.saturating_duration_since(since)
+    }
+
+    /// Whether a keep-alive is due , nothing was sent to the peer for `KEEPALIVE_INTERVAL`
+    pub fn needs_keepalive(&self, now: Instant) -> bool {
+        let since = self.sent.last.map_or(self.connected_at, |last| last.at);
+        now.saturating_duration_since(since) >= KEEPALIVE_INTERVAL
+    }
+
+    /// Unchoked us and we're interested , yet no block came for `SNUB_TIMEOUT`
+    pub fn is_snubbed(&self, now: Instant) -> bool {
+        if !self.am_interested || self.peer_choking {
+            return false;
+        }
+        let since = [self.last_block_at, self.unchoked_at]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(self.connected_at);
+        now.saturating_duration_since(since) >= SNUB_TIMEOUT
+    }
+
+    pub fn has_piece(&self, index: usize) -> bool {
+        self.bitfield.has(index)
+    }
+
+    /// Whether the peer is on our local network , found by LSD or at a private address
+    pub fn is_lan(&self) -> bool {
+        self.sources.contains(&PeerSource::Lsd) || is_lan_address(self.addr.ip())
+    }
+
+    /// Takes what the peer told us in its extension handshake
+    pub fn apply_extended_handshake(&mut self, handshake: &ExtendedHandshake) {
+        self.upload_only = handshake.upload_only;
+        self.upload_only_id = handshake.message_id(UPLOAD_ONLY);
+        self.ut_metadata_id = handshake.message_id(UT_METADATA);
+    }
+
+    /// Handles an extension message sent to one of our local ids , returns false for ids we don't know
+    pub fn handle_extended(&mut self, id: u8, payload: &[u8]) -> bool {
+        match id {
+            EXTENDED_HANDSHAKE_ID => {
+                if let Ok(handshake) = ExtendedHandshake::decode(payload) {
+                    self.apply_extended_handshake(&handshake);
+                }
+                true
+            }
+            LOCAL_UPLOAD_ONLY_ID => {
+                if let Some(upload_only) = parse_upload_only(payload) {
+                    self.upload_only = upload_only;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Copy of the peer's state as of `now`
+    pub fn snapshot(&self, total_pieces: usize, now: Instant) -> PeerSnapshot {
+        let progress = if total_pieces == 0 {
+            0.0
+        } else {
+            (self.pieces_have as f64 / total_pieces as f64) * 100.0
+        };
+
+        PeerSnapshot {
+            addr: self.addr,
+            client: self.client.clone(),
+            sources: self.sources.clone(),
+            progress,
+            download_rate: self.download_rate,
+            upload_rate: self.upload_rate,
+            peer_choking: self.peer_choking,
+            is_seed: total_pieces > 0 && self.pieces_have >= total_pieces,
+            protocol_violations: self.protocol_violations,
+            location: None,
+            last_received: self.received.last,
+            last_sent: self.sent.last,
+            keepalives_received: self.received.keepalives,
+            keepalives_sent: self.sent.keepalives,
+            idle_for: self.idle_for(now),
+            snubbed: self.is_snubbed(now),
+            taken_at: now,
+        }
+    }
+}
+
+/// Works out the client from an Azureus style peer id (`-qB4630-...`)
+pub fn client_name(peer_id: &[u8; 20]) -> String {
+    if peer_id[0] != b'-' || peer_id[7] != b'-' {
+        return String::from("Unknown");
+    }
+
+    let code = String::from_utf8_lossy(&peer_id[1..3]).to_string();
+    let version: String = peer_id[3..7]
+        .iter()
+        .filter(|b| b.is_ascii_alphanumeric())
+        .map(|&b| b as char)
+        .collect();
+
+    let name = match code.as_str() {
+        "qB" => "qBittorrent",
+        "TR" => "Transmission",
+        "UT" => "uTorrent",
+        "LT" | "lt" => "libtorrent",
+        "DE" => "Deluge",
+        "AZ" => "Vuze",
+        "BI" => "BiglyBT",
+        "RS" => "Sekiro",
+        _ => return format!("{} {}", code, version),
+    };
+
+    format!("{} {}", name, version)
+}
+
+/// Private (RFC 1918 , unique local) , link-local and loopback addresses , never routed over the internet
+pub fn is_lan_address(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local() || ip.is_loopback(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unique_local() || ip.is_unicast_link_local(),
+    }
+}
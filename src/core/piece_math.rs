@@ -0,0 +1,141 @@
+//! Checked arithmetic for piece and byte offsets
+//!
+//! Offsets are worked out in u64 and only turned into usize at the very end , so malformed metadata
+//! (a huge piece length , an index past the end) is an error instead of a panic or a silent wraparound
+//! on 32-bit targets
+
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Index of a piece in a torrent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PieceIndex(pub u64);
+
+/// Position of a byte in the torrent's data , all files laid end to end
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteOffset(pub u64);
+
+impl From<usize> for PieceIndex {
+    fn from(index: usize) -> Self {
+        PieceIndex(index as u64)
+    }
+}
+
+impl From<u32> for PieceIndex {
+    fn from(index: u32) -> Self {
+        PieceIndex(index as u64)
+    }
+}
+
+impl fmt::Display for PieceIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<usize> for ByteOffset {
+    fn from(offset: usize) -> Self {
+        ByteOffset(offset as u64)
+    }
+}
+
+impl ByteOffset {
+    pub fn checked_add(self, bytes: u64) -> Result<ByteOffset> {
+        self.0
+            .checked_add(bytes)
+            .map(ByteOffset)
+            .ok_or_else(|| anyhow!("Offset {} + {} overflows", self.0, bytes))
+    }
+
+    /// The offset as an index into memory , fails where usize is narrower than the offset
+    pub fn to_usize(self) -> Result<usize> {
+        usize::try_from(self.0)
+            .map_err(|_| anyhow!("Offset {} doesn't fit in memory on this target", self.0))
+    }
+}
+
+/// How a torrent's bytes split into pieces , every piece is `piece_length` long except the last
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceLayout {
+    pub piece_length: u64,
+    pub total_length: u64,
+    pub piece_count: u64,
+}
+
+impl PieceLayout {
+    /// Checks the three numbers agree , the piece count has to be exactly what the lengths need
+    pub fn new(piece_length: usize, total_length: usize, piece_count: usize) -> Result<Self> {
+        let layout = Self {
+            piece_length: piece_length as u64,
+            total_length: total_length as u64,
+            piece_count: piece_count as u64,
+        };
+
+        if layout.piece_length == 0 {
+            return Err(anyhow!("Piece length is zero"));
+        }
+        let needed = layout.total_length.div_ceil(layout.piece_length);
+        if needed != layout.piece_count {
+            return Err(anyhow!(
+                "{} bytes in {} byte pieces needs {} pieces , got {}",
+                layout.total_length,
+                layout.piece_length,
+                needed,
+                layout.piece_count
+            ));
+        }
+        Ok(layout)
+    }
+
+    fn check_index(&self, index: PieceIndex) -> Result<()> {
+        if index.0 >= self.piece_count {
+            return Err(anyhow!(
+                "Piece {} out of range , torrent has {}",
+                index,
+                self.piece_count
+            ));
+        }
+        Ok(())
+    }
+
+    /// First byte of a piece
+    pub fn piece_start(&self, index: PieceIndex) -> Result<ByteOffset> {
+        self.check_index(index)?;
+        index
+            .0
+            .checked_mul(self.piece_length)
+            .map(ByteOffset)
+            .ok_or_else(|| anyhow!("Start of piece {} overflows", index))
+    }
+
+    /// Length of a piece , the last one gets whatever is left
+    pub fn piece_size(&self, index: PieceIndex) -> Result<u64> {
+        let start = self.piece_start(index)?;
+        let left = self
+            .total_length
+            .checked_sub(start.0)
+            .ok_or_else(|| anyhow!("Piece {} starts past the end of the torrent", index))?;
+        Ok(left.min(self.piece_length))
+    }
+
+    /// Where `length` bytes at `begin` inside a piece start , the block has to fit in the piece
+    pub fn block_start(&self, index: PieceIndex, begin: u64, length: u64) -> Result<ByteOffset> {
+        let size = self.piece_size(index)?;
+        let fits = begin.checked_add(length).is_some_and(|end| end <= size);
+        if !fits {
+            return Err(anyhow!(
+                "Block {}+{} is past the end of piece {} ({} bytes)",
+                begin,
+                length,
+                index,
+                size
+            ));
+        }
+        self.piece_start(index)?.checked_add(begin)
+    }
+
+    /// Piece holding a byte , None past the end
+    pub fn piece_at(&self, offset: ByteOffset) -> Option<PieceIndex> {
+        (offset.0 < self.total_length).then(|| PieceIndex(offset.0 / self.piece_length))
+    }
+}
@@ -0,0 +1,271 @@
+use crate::core::bitfield::Bitfield;
+use anyhow::{Result, anyhow};
+use std::collections::hash_map::RandomState;
+use std::fmt::{self, Debug};
+use std::hash::{BuildHasher, Hasher};
+
+/// What a picker gets to look at when choosing the next piece
+#[derive(Debug, Clone, Copy)]
+pub struct PickContext<'a> {
+    /// Pieces we want and don't have yet , most urgent first (re-downloads , stalled pieces)
+    pub candidates: &'a [usize],
+    /// Connected peers that have each piece , indexed by piece
+    pub availability: &'a [u32],
+}
+
+impl PickContext<'_> {
+    /// Copies of a piece in the swarm , 0 for pieces nobody told us about
+    pub fn availability_of(&self, index: usize) -> u32 {
+        self.availability.get(index).copied().unwrap_or(0)
+    }
+}
+
+/// Decides which piece to download next
+///
+/// The block manager asks for one piece at a time and only ever offers pieces that are still
+/// missing , so a strategy only has to rank the candidates. Implement it to plug in your own
+/// ordering (deadlines for streaming , file priorities ...) and hand it to `BlockManager::set_picker`
+pub trait PiecePickerStrategy: Debug + Send {
+    /// Short name for logs and the UI
+    fn name(&self) -> &str;
+
+    /// One of `ctx.candidates` , None to download nothing right now
+    fn pick(&mut self, ctx: &PickContext) -> Option<usize>;
+}
+
+/// Pieces in queue order , what streaming a file front to back wants
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sequential;
+
+impl PiecePickerStrategy for Sequential {
+    fn name(&self) -> &str {
+        "sequential"
+    }
+
+    fn pick(&mut self, ctx: &PickContext) -> Option<usize> {
+        ctx.candidates.first().copied()
+    }
+}
+
+/// Pieces the fewest peers have first , keeps rare pieces alive in the swarm
+///
+/// Ties are broken at random so peers starting together don't all go after the same piece
+#[derive(Debug, Clone, Default)]
+pub struct RarestFirst {
+    random: Random,
+}
+
+impl RarestFirst {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Same seed , same tie-breaks
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            random: Random::with_seed(seed),
+        }
+    }
+}
+
+impl PiecePickerStrategy for RarestFirst {
+    fn name(&self) -> &str {
+        "rarest-first"
+    }
+
+    fn pick(&mut self, ctx: &PickContext) -> Option<usize> {
+        let mut best: Option<(usize, u32)> = None;
+        let mut ties = 0u64;
+
+        for &index in ctx.candidates {
+            let count = ctx.availability_of(index);
+            match best {
+                Some((_, rarest)) if count > rarest => continue,
+                Some((_, rarest)) if count == rarest => {
+                    // Reservoir sampling , each tied piece ends up picked with the same odds
+                    ties += 1;
+                    if self.random.next().is_multiple_of(ties) {
+                        best = Some((index, count));
+                    }
+                }
+                _ => {
+                    best = Some((index, count));
+                    ties = 1;
+                }
+            }
+        }
+        best.map(|(index, _)| index)
+    }
+}
+
+/// Any missing piece , spreads downloads of many clients starting at once
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        // std has no rng , RandomState is seeded from the OS
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        Self::with_seed(hasher.finish())
+    }
+
+    /// Same seed , same picks
+    pub fn with_seed(seed: u64) -> Self {
+        // xorshift gets stuck on 0
+        Self { state: seed.max(1) }
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PiecePickerStrategy for Random {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn pick(&mut self, ctx: &PickContext) -> Option<usize> {
+        if ctx.candidates.is_empty() {
+            return None;
+        }
+        let slot = self.next() % ctx.candidates.len() as u64;
+        Some(ctx.candidates[slot as usize])
+    }
+}
+
+/// Piece availability in the swarm plus the strategy that picks from it
+///
+/// Counts follow peer Bitfield and Have messages as they arrive instead of being recounted from
+/// every peer on each pick. Picks can be limited to the pieces one peer has , so a request only
+/// ever goes to a peer that can answer it
+#[derive(Debug)]
+pub struct PiecePicker {
+    /// Connected peers that have each piece
+    availability: Vec<u32>,
+    strategy: Box<dyn PiecePickerStrategy>,
+}
+
+impl PiecePicker {
+    /// Rarest first over `piece_count` pieces nobody has yet
+    pub fn new(piece_count: usize) -> Self {
+        Self::with_strategy(piece_count, Box::new(RarestFirst::new()))
+    }
+
+    pub fn with_strategy(piece_count: usize, strategy: Box<dyn PiecePickerStrategy>) -> Self {
+        Self {
+            availability: vec![0; piece_count],
+            strategy,
+        }
+    }
+
+    pub fn set_strategy(&mut self, strategy: Box<dyn PiecePickerStrategy>) {
+        self.strategy = strategy;
+    }
+
+    pub fn strategy_name(&self) -> &str {
+        self.strategy.name()
+    }
+
+    /// Connected peers that have each piece , indexed by piece
+    pub fn availability(&self) -> &[u32] {
+        &self.availability
+    }
+
+    /// Counts the pieces in a peer's bitfield
+    pub fn add_bitfield(&mut self, bitfield: &Bitfield) {
+        for index in bitfield.ones() {
+            if let Some(count) = self.availability.get_mut(index) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Takes back what `add_bitfield` counted , for a peer that left or replaced its bitfield
+    pub fn remove_bitfield(&mut self, bitfield: &Bitfield) {
+        for index in bitfield.ones() {
+            if let Some(count) = self.availability.get_mut(index) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// A peer announced a piece it didn't have before
+    pub fn add_have(&mut self, index: usize) {
+        if let Some(count) = self.availability.get_mut(index) {
+            *count += 1;
+        }
+    }
+
+    /// One of `candidates` , only pieces set in `peer` when given
+    pub fn pick(&mut self, candidates: &[usize], peer: Option<&Bitfield>) -> Option<usize> {
+        let candidates: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&index| peer.is_none_or(|bitfield| bitfield.has(index)))
+            .collect();
+        let ctx = PickContext {
+            candidates: &candidates,
+            availability: &self.availability,
+        };
+
+        // A custom strategy could hand back anything , only offered pieces count
+        self.strategy
+            .pick(&ctx)
+            .filter(|index| candidates.contains(index))
+    }
+}
+
+/// Built in strategies , for choosing one from config or the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PickerKind {
+    Sequential,
+    #[default]
+    RarestFirst,
+    Random,
+}
+
+impl PickerKind {
+    /// Accepts what `Display` prints
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "sequential" => Ok(PickerKind::Sequential),
+            "rarest-first" | "rarest" => Ok(PickerKind::RarestFirst),
+            "random" => Ok(PickerKind::Random),
+            other => Err(anyhow!("Unknown piece picker : {}", other)),
+        }
+    }
+
+    pub fn build(self) -> Box<dyn PiecePickerStrategy> {
+        match self {
+            PickerKind::Sequential => Box::new(Sequential),
+            PickerKind::RarestFirst => Box::new(RarestFirst::new()),
+            PickerKind::Random => Box::new(Random::new()),
+        }
+    }
+}
+
+impl fmt::Display for PickerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PickerKind::Sequential => "sequential",
+            PickerKind::RarestFirst => "rarest-first",
+            PickerKind::Random => "random",
+        };
+        f.write_str(name)
+    }
+}
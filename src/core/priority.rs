@@ -0,0 +1,61 @@
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Share of a file's pieces that has to be verified before its remaining pieces go ahead of the
+/// rest of their priority level
+pub const DEFAULT_FINISH_BOOST: f64 = 0.9;
+
+/// How much the user wants a file of the torrent , pieces take the highest priority of the files
+/// they overlap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum FilePriority {
+    /// Not downloaded , pieces that only hold skipped files are never requested
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl FilePriority {
+    /// Levels pieces are picked in , most wanted first
+    pub const PICK_ORDER: [FilePriority; 3] =
+        [FilePriority::High, FilePriority::Normal, FilePriority::Low];
+
+    /// Next one up , wrapping from High back to Skip. What the TUI toggle steps through
+    pub fn next(self) -> Self {
+        match self {
+            FilePriority::Skip => FilePriority::Low,
+            FilePriority::Low => FilePriority::Normal,
+            FilePriority::Normal => FilePriority::High,
+            FilePriority::High => FilePriority::Skip,
+        }
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        *self == FilePriority::Skip
+    }
+
+    /// Accepts what `Display` prints
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "skip" | "off" => Ok(FilePriority::Skip),
+            "low" => Ok(FilePriority::Low),
+            "normal" => Ok(FilePriority::Normal),
+            "high" => Ok(FilePriority::High),
+            other => Err(anyhow!("Unknown file priority : {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for FilePriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FilePriority::Skip => "skip",
+            FilePriority::Low => "low",
+            FilePriority::Normal => "normal",
+            FilePriority::High => "high",
+        };
+        f.write_str(name)
+    }
+}
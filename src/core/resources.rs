@@ -0,0 +1,104 @@
+use serde_json::{Value, json};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How often `settled` looks at the counts again
+const SETTLE_POLL: Duration = Duration::from_millis(10);
+
+static TASKS: AtomicUsize = AtomicUsize::new(0);
+static SOCKETS: AtomicUsize = AtomicUsize::new(0);
+static FILE_HANDLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Kinds of resources the engine keeps count of , to spot leaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// A spawned background task
+    Task,
+    /// An open peer connection
+    Socket,
+    /// A file kept open between writes
+    FileHandle,
+}
+
+impl Resource {
+    fn counter(&self) -> &'static AtomicUsize {
+        match self {
+            Resource::Task => &TASKS,
+            Resource::Socket => &SOCKETS,
+            Resource::FileHandle => &FILE_HANDLES,
+        }
+    }
+}
+
+/// Counts one live resource for as long as it's held , drop it together with the resource
+#[derive(Debug)]
+pub struct Tracked(Resource);
+
+impl Tracked {
+    pub fn new(resource: Resource) -> Self {
+        resource.counter().fetch_add(1, Ordering::Relaxed);
+        Self(resource)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.counter().fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Live resources across the process at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub tasks: usize,
+    pub sockets: usize,
+    pub file_handles: usize,
+}
+
+impl ResourceUsage {
+    pub fn now() -> Self {
+        Self {
+            tasks: TASKS.load(Ordering::Relaxed),
+            sockets: SOCKETS.load(Ordering::Relaxed),
+            file_handles: FILE_HANDLES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Nothing alive , what's expected once every torrent is gone
+    pub fn is_idle(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Counts once aborted tasks had the chance to wind down , or as they are after `timeout`
+    ///
+    /// An aborted task keeps its count until the runtime drops it , so checking right after a
+    /// teardown would report tasks that are already on their way out
+    pub async fn settled(timeout: Duration) -> Self {
+        let _ = tokio::time::timeout(timeout, async {
+            while !Self::now().is_idle() {
+                tokio::time::sleep(SETTLE_POLL).await;
+            }
+        })
+        .await;
+        Self::now()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "tasks": self.tasks,
+            "sockets": self.sockets,
+            "file_handles": self.file_handles,
+        })
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} tasks , {} sockets , {} open files",
+            self.tasks, self.sockets, self.file_handles
+        )
+    }
+}
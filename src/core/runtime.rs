@@ -0,0 +1,210 @@
+use crate::core::{
+    bind::BindTarget,
+    clock::{SharedClock, system_clock},
+};
+use std::fmt::Debug;
+use std::future::{Future, poll_fn};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::{Pin, pin};
+use std::sync::Arc;
+use std::task::Poll;
+use std::thread;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A boxed future , the trait below has to stay object safe
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A connected byte stream to a peer
+pub trait PeerStream: AsyncRead + AsyncWrite + Debug + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Debug + Send + Unpin> PeerStream for T {}
+
+/// A bound UDP socket , used by the DHT
+pub trait DatagramSocket: Debug + Send + Sync {
+    fn send_to<'a>(&'a self, data: &'a [u8], addr: SocketAddr) -> BoxFuture<'a, io::Result<usize>>;
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8])
+    -> BoxFuture<'a, io::Result<(usize, SocketAddr)>>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// A task started with `Runtime::spawn` , dropping the handle leaves the task running
+pub trait TaskHandle: Debug + Send + Sync {
+    /// Stops the task at its next await , whatever it owns is dropped
+    fn abort(&self);
+    fn is_finished(&self) -> bool;
+}
+
+/// Everything the engine needs from an async runtime
+///
+/// The engine core (pieces , choking , picking) and what feeds it (dials , the disk thread , the DHT)
+/// only talk to this trait , so it can run on tokio , on another executor , or under a test harness
+/// driving a MockClock
+pub trait Runtime: Debug + Send + Sync {
+    /// Runs a task in the background
+    fn spawn(&self, task: BoxFuture<'static, ()>) -> Box<dyn TaskHandle>;
+
+    /// Runs blocking work (e.g file I/O) on a thread of its own , `name` shows up in debuggers
+    fn spawn_blocking(&self, name: &str, work: Box<dyn FnOnce() + Send>) -> io::Result<()>;
+
+    /// Completes after `duration` has passed
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Clock used for every timeout decision
+    fn clock(&self) -> SharedClock;
+
+    /// Connects to a peer , leaving through `bind` when set
+    fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        bind: Option<BindTarget>,
+    ) -> BoxFuture<'static, io::Result<Box<dyn PeerStream>>>;
+
+    /// Binds a UDP socket on `addr` , a wildcard address is narrowed down to `bind` when set
+    fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        bind: Option<BindTarget>,
+    ) -> BoxFuture<'static, io::Result<Box<dyn DatagramSocket>>>;
+}
+
+/// Shared handle to a runtime
+pub type SharedRuntime = Arc<dyn Runtime>;
+
+/// The runtime used when nothing else is specified
+pub fn tokio_runtime() -> SharedRuntime {
+    Arc::new(TokioRuntime::new())
+}
+
+/// Runs `future` until it completes or `duration` passes on the runtime's timer , None when time ran out
+pub async fn timeout<T>(
+    runtime: &dyn Runtime,
+    duration: Duration,
+    future: impl Future<Output = T>,
+) -> Option<T> {
+    let mut future = pin!(future);
+    let mut expired = runtime.sleep(duration);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        expired.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
+
+/// Runtime backed by tokio
+///
+/// Tasks go to whichever tokio runtime `spawn` is called in , so it panics outside of one , same as
+/// `tokio::spawn`. Blocking work gets a plain thread
+#[derive(Debug, Clone)]
+pub struct TokioRuntime {
+    clock: SharedClock,
+}
+
+impl TokioRuntime {
+    pub fn new() -> Self {
+        Self {
+            clock: system_clock(),
+        }
+    }
+
+    /// Swaps the clock , the sleeps still use tokio's timer
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Default for TokioRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) -> Box<dyn TaskHandle> {
+        Box::new(tokio::spawn(task))
+    }
+
+    fn spawn_blocking(&self, name: &str, work: Box<dyn FnOnce() + Send>) -> io::Result<()> {
+        thread::Builder::new()
+            .name(String::from(name))
+            .spawn(work)
+            .map(|_| ())
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        bind: Option<BindTarget>,
+    ) -> BoxFuture<'static, io::Result<Box<dyn PeerStream>>> {
+        Box::pin(async move {
+            let socket = match addr {
+                SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+                SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+            };
+            if let Some(bind) = &bind {
+                bind.prepare_tcp(&socket, addr)?;
+            }
+
+            let stream = socket.connect(addr).await?;
+            Ok(Box::new(stream) as Box<dyn PeerStream>)
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        bind: Option<BindTarget>,
+    ) -> BoxFuture<'static, io::Result<Box<dyn DatagramSocket>>> {
+        Box::pin(async move {
+            let addr = match &bind {
+                Some(bind) if addr.ip().is_unspecified() => bind.listen_addr(addr.port()),
+                _ => addr,
+            };
+
+            let socket = tokio::net::UdpSocket::bind(addr).await?;
+            if let Some(bind) = &bind {
+                bind.prepare_udp(&socket)?;
+            }
+            Ok(Box::new(socket) as Box<dyn DatagramSocket>)
+        })
+    }
+}
+
+impl TaskHandle for tokio::task::JoinHandle<()> {
+    fn abort(&self) {
+        tokio::task::JoinHandle::abort(self);
+    }
+
+    fn is_finished(&self) -> bool {
+        tokio::task::JoinHandle::is_finished(self)
+    }
+}
+
+impl DatagramSocket for tokio::net::UdpSocket {
+    fn send_to<'a>(&'a self, data: &'a [u8], addr: SocketAddr) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(tokio::net::UdpSocket::send_to(self, data, addr))
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        Box::pin(tokio::net::UdpSocket::recv_from(self, buf))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        tokio::net::UdpSocket::local_addr(self)
+    }
+}
@@ -1,6 +1,8 @@
+pub mod app;
 pub mod core;
 pub mod logging;
 pub mod net;
 pub mod pools;
 pub mod protocol;
 pub mod storage;
+pub mod util;
@@ -0,0 +1,26 @@
+//! Where the engine's log lines go , stdout unless a front end needs stdout for itself
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Sends engine log lines to stderr , e.g while stdout carries one JSON object per line
+pub fn log_to_stderr(enabled: bool) {
+    TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn logs_to_stderr() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// `println!` for engine log lines , goes to stderr after `log_to_stderr(true)`
+#[macro_export]
+macro_rules! log_line {
+    ($($arg:tt)*) => {
+        if $crate::logging::console::logs_to_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
+#[cfg(feature = "color")]
 use colored::Colorize;
 
 #[derive(Debug, Clone, Copy)]
@@ -23,6 +24,7 @@ impl LoggingEvent {
         }
     }
 
+    #[cfg(feature = "color")]
     pub fn colored_str(&mut self) -> String {
         match self {
             LoggingEvent::APPCRASHED => self.to_str().bright_red().to_string(),
@@ -32,6 +34,12 @@ impl LoggingEvent {
             LoggingEvent::APPSTARTED => self.to_str().bright_yellow().to_string(),
         }
     }
+
+    /// Plain text when built without the color feature
+    #[cfg(not(feature = "color"))]
+    pub fn colored_str(&mut self) -> String {
+        self.to_str().to_string()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -54,6 +62,7 @@ impl LogLevel {
         }
     }
 
+    #[cfg(feature = "color")]
     pub fn colored_str(&self) -> String {
         // Colors the strings , used the Colorized crate for this
         match self {
@@ -65,6 +74,12 @@ impl LogLevel {
         }
     }
 
+    /// Plain text when built without the color feature
+    #[cfg(not(feature = "color"))]
+    pub fn colored_str(&self) -> String {
+        self.to_str().to_string()
+    }
+
     pub fn priority(&self) -> u8 {
         match self {
             LogLevel::ERROR => 0,
@@ -0,0 +1,122 @@
+use crate::net::{
+    tracker::{Tracker, TrackerRequest, TrackerResponse},
+    tracker_url::TrackerUrl,
+};
+use anyhow::{Result, anyhow};
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    task::JoinSet,
+};
+
+/// Default number of announces allowed in flight at once
+pub const DEFAULT_ANNOUNCE_CONCURRENCY: usize = 16;
+
+/// A single announce waiting to be sent
+#[derive(Debug, Clone)]
+pub struct AnnounceJob {
+    /// Caller defined id , handed back with the result
+    pub id: usize,
+    pub tracker: Tracker,
+    pub request: TrackerRequest,
+}
+
+/// Runs announces for many torrents at once
+///
+/// At most `limit` announces are in flight , and announces to the same tracker host run one after the other
+/// so we don't hammer a single tracker while a slow one can't hold up the rest
+#[derive(Debug)]
+pub struct AnnouncePool {
+    limit: Arc<Semaphore>,
+}
+
+impl AnnouncePool {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: Arc::new(Semaphore::new(limit.max(1))),
+        }
+    }
+
+    /// Announces every job and returns the results in the same order as the jobs
+    ///
+    /// `on_result` sees each result as soon as its announce is done , so a slow tracker doesn't hold
+    /// back what the others answered
+    pub async fn announce_all(
+        &mut self,
+        jobs: Vec<AnnounceJob>,
+        mut on_result: impl FnMut(usize, &Result<TrackerResponse>),
+    ) -> Vec<(usize, Result<TrackerResponse>)> {
+        let mut set = JoinSet::new();
+        let mut task_jobs = HashMap::new();
+        // Only for this round , hosts seen once don't pile up over the session
+        let mut host_locks: HashMap<String, Arc<Mutex<()>>> = HashMap::new();
+
+        for (position, job) in jobs.into_iter().enumerate() {
+            let host = announce_host(job.tracker.announce_url());
+            let host_lock = host_locks
+                .entry(host)
+                .or_insert_with(|| Arc::new(Mutex::new(())))
+                .clone();
+            let limit = self.limit.clone();
+
+            let job_id = job.id;
+            let handle = set.spawn(async move {
+                // Take the host lock first so queued jobs for a busy host don't sit on permits
+                let _host = host_lock.lock().await;
+                let _permit = limit.acquire_owned().await;
+                let result = job.tracker.announce(job.request).await;
+                (position, job.id, result)
+            });
+            task_jobs.insert(handle.id(), (position, job_id));
+        }
+
+        let mut results = Vec::new();
+        while let Some(joined) = set.join_next().await {
+            let result = match joined {
+                Ok(result) => result,
+                Err(e) => {
+                    // A panicked announce still gets reported against its torrent
+                    let Some(&(position, job_id)) = task_jobs.get(&e.id()) else {
+                        continue;
+                    };
+                    (
+                        position,
+                        job_id,
+                        Err(anyhow!("Announce task failed : {}", e)),
+                    )
+                }
+            };
+            on_result(result.1, &result.2);
+            results.push(result);
+        }
+
+        results.sort_by_key(|(position, _, _)| *position);
+        results
+            .into_iter()
+            .map(|(_, id, result)| (id, result))
+            .collect()
+    }
+}
+
+impl Default for AnnouncePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_ANNOUNCE_CONCURRENCY)
+    }
+}
+
+/// Gets the host (with port) out of an announce url , eg `http://tracker.example.com:80/announce` -> `tracker.example.com:80`
+pub fn announce_host(url: &str) -> String {
+    // Parsed urls always carry a port , so `host` and `host:80` share a lock
+    if let Ok(parsed) = TrackerUrl::parse(url) {
+        return format!("{}:{}", parsed.host(), parsed.port());
+    }
+
+    let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let host = without_scheme
+        .split(['/', '?'])
+        .next()
+        .unwrap_or(without_scheme);
+
+    // Strip any user info
+    host.rsplit('@').next().unwrap_or(host).to_ascii_lowercase()
+}
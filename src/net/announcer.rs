@@ -0,0 +1,352 @@
+//! Keeps a torrent announced to its trackers for as long as it runs
+//!
+//! `AnnounceSchedule` decides when the next announce is due and which event it carries , the
+//! `Announcer` sends announces from a background task whenever the schedule says so and hands back
+//! what the trackers answered on the next `poll`
+
+use crate::{
+    core::clock::{SharedClock, system_clock},
+    net::{
+        tracker::{TrackerEvent, TrackerRequest, TrackerResponse},
+        tracker_manager::TrackerManager,
+    },
+};
+use anyhow::{Result, anyhow};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// Interval used when the tracker doesn't send one
+pub const DEFAULT_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Shortest wait between regular announces , whatever the tracker asks for
+pub const MIN_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Wait after the first failed announce , doubled for every failure after it up to the interval
+pub const ANNOUNCE_RETRY: Duration = Duration::from_secs(60);
+
+/// How long the `stopped` announce gets on shutdown
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When a torrent announces next and with which event
+///
+/// `started` goes out first and is retried until a tracker heard it , `completed` follows once the
+/// download finished (as soon as the tracker's `min interval` allows) , everything else is a
+/// regular announce every `interval`
+#[derive(Debug, Clone)]
+pub struct AnnounceSchedule {
+    started: bool,
+    /// The download finished while we were announced
+    completed: bool,
+    completion_sent: bool,
+    /// Has every wanted piece but not the whole torrent , regular announces carry `paused`
+    partial_seed: bool,
+    /// None announces on the next poll
+    next_at: Option<Instant>,
+    last_at: Option<Instant>,
+    interval: Duration,
+    min_interval: Duration,
+    failures: u32,
+}
+
+impl AnnounceSchedule {
+    /// Due right away with `started`
+    pub fn new() -> Self {
+        Self {
+            started: false,
+            completed: false,
+            completion_sent: false,
+            partial_seed: false,
+            next_at: None,
+            last_at: None,
+            interval: DEFAULT_ANNOUNCE_INTERVAL,
+            min_interval: MIN_ANNOUNCE_INTERVAL,
+            failures: 0,
+        }
+    }
+
+    /// Event the next announce carries
+    pub fn next_event(&self) -> Option<TrackerEvent> {
+        if !self.started {
+            Some(TrackerEvent::Started)
+        } else if self.completed && !self.completion_sent {
+            Some(TrackerEvent::Completed)
+        } else if self.partial_seed {
+            Some(TrackerEvent::Paused)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_at.is_none_or(|at| now >= at)
+    }
+
+    /// When the next announce goes out , None for right away
+    pub fn next_announce(&self) -> Option<Instant> {
+        self.next_at
+    }
+
+    /// Whether trackers were told we joined , and so need a `stopped` when we leave
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Interval the tracker asked for , or the default
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The download finished , `completed` goes out as soon as the tracker's `min interval` allows
+    ///
+    /// A torrent that was already complete when it started has nothing to report
+    pub fn completed(&mut self) {
+        if self.completed {
+            return;
+        }
+        self.completed = true;
+        if !self.started {
+            self.completion_sent = true;
+            return;
+        }
+        let allowed = self.last_at.map(|last| last + self.min_interval);
+        self.next_at = match (allowed, self.next_at) {
+            (Some(allowed), Some(next)) => Some(allowed.min(next)),
+            (allowed, _) => allowed,
+        };
+    }
+
+    /// Partial seeds announce `paused` (BEP 21) so private trackers don't count them as leechers ,
+    /// becoming one is announced right away
+    pub fn set_partial_seed(&mut self, partial_seed: bool) {
+        if partial_seed && !self.partial_seed && self.started {
+            self.next_at = None;
+        }
+        self.partial_seed = partial_seed;
+    }
+
+    /// Asks for an announce on the next poll , e.g after failing over to another tracker
+    pub fn announce_now(&mut self) {
+        self.next_at = None;
+    }
+
+    /// Another tracker took over , it hasn't heard `started` yet
+    ///
+    /// A download that already finished joins the new tracker as a seed , there's no `completed` to report
+    pub fn tracker_changed(&mut self) {
+        self.started = false;
+        self.completion_sent = self.completed;
+    }
+
+    /// Records how an announce carrying `event` went and schedules the next one
+    pub fn announced(
+        &mut self,
+        now: Instant,
+        event: Option<&TrackerEvent>,
+        result: &Result<TrackerResponse>,
+    ) {
+        self.last_at = Some(now);
+        let Ok(response) = result else {
+            self.failures += 1;
+            let backoff = ANNOUNCE_RETRY.saturating_mul(1u32 << (self.failures - 1).min(10));
+            self.next_at = Some(now + backoff.min(self.interval));
+            return;
+        };
+
+        self.failures = 0;
+        match event {
+            Some(TrackerEvent::Started) => self.started = true,
+            Some(TrackerEvent::Completed) => self.completion_sent = true,
+            _ => {}
+        }
+        if response.interval > 0 {
+            self.interval = Duration::from_secs(response.interval);
+        }
+        self.min_interval = response
+            .min_interval
+            .map(Duration::from_secs)
+            .unwrap_or_default()
+            .max(MIN_ANNOUNCE_INTERVAL);
+        self.interval = self.interval.max(self.min_interval);
+
+        // A download that finished while `started` was in flight reports it without waiting a whole interval
+        let wait = if matches!(
+            self.next_event(),
+            Some(TrackerEvent::Started | TrackerEvent::Completed)
+        ) {
+            self.min_interval
+        } else {
+            self.interval
+        };
+        self.next_at = Some(now + wait);
+    }
+}
+
+impl Default for AnnounceSchedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What came back from one announce
+#[derive(Debug, Clone)]
+pub enum AnnounceOutcome {
+    Announced {
+        tracker: String,
+        event: Option<TrackerEvent>,
+        response: TrackerResponse,
+    },
+    Failed {
+        tracker: String,
+        event: Option<TrackerEvent>,
+        error: String,
+    },
+}
+
+/// Announces one torrent to its trackers on the schedule , from a background task
+///
+/// Call `poll` regularly with a request holding the current transfer totals , it starts an announce
+/// when one is due and returns the answers that came in. `stop` sends `stopped` before the torrent
+/// goes away
+#[derive(Debug)]
+pub struct Announcer {
+    trackers: TrackerManager,
+    schedule: AnnounceSchedule,
+    in_flight: JoinSet<(Option<TrackerEvent>, Result<TrackerResponse>)>,
+    clock: SharedClock,
+}
+
+impl Announcer {
+    pub fn new(trackers: TrackerManager) -> Self {
+        Self {
+            trackers,
+            schedule: AnnounceSchedule::new(),
+            in_flight: JoinSet::new(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Replaces the clock the schedule runs on
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn trackers(&self) -> &TrackerManager {
+        &self.trackers
+    }
+
+    pub fn schedule(&self) -> &AnnounceSchedule {
+        &self.schedule
+    }
+
+    /// The download finished , see `AnnounceSchedule::completed`
+    pub fn completed(&mut self) {
+        self.schedule.completed();
+    }
+
+    /// See `AnnounceSchedule::set_partial_seed`
+    pub fn set_partial_seed(&mut self, partial_seed: bool) {
+        self.schedule.set_partial_seed(partial_seed);
+    }
+
+    /// Announces on the next poll , e.g when the swarm ran out of peers
+    pub fn announce_now(&mut self) {
+        self.schedule.announce_now();
+    }
+
+    /// Announces on the next poll after a system suspend , the trackers may have dropped us
+    ///
+    /// An announce sent before the suspend is given up on , its answer would be stale
+    pub fn resumed(&mut self) {
+        self.in_flight.abort_all();
+        self.schedule.announce_now();
+    }
+
+    /// Picks up finished announces and starts the next one when it's due
+    ///
+    /// `request` carries the transfer totals , its event is replaced with the schedule's. Must be
+    /// called inside a tokio runtime
+    pub fn poll(&mut self, request: &TrackerRequest) -> Vec<AnnounceOutcome> {
+        let mut outcomes = Vec::new();
+        while let Some(joined) = self.in_flight.try_join_next() {
+            // Aborted on purpose , see `resumed`
+            if joined.as_ref().is_err_and(|e| e.is_cancelled()) {
+                continue;
+            }
+            let (event, result) =
+                joined.unwrap_or_else(|e| (None, Err(anyhow!("Announce task failed : {}", e))));
+            outcomes.push(self.record(event, result));
+        }
+
+        if self.in_flight.is_empty() && self.schedule.is_due(self.clock.now()) {
+            let tracker = self.trackers.current().clone();
+            let event = self.schedule.next_event();
+            let request = TrackerRequest {
+                event: event.clone(),
+                ..request.clone()
+            };
+            self.in_flight
+                .spawn(async move { (event, tracker.announce(request).await) });
+        }
+        outcomes
+    }
+
+    /// Tells the trackers we're leaving , after `completed` if that was still owed
+    ///
+    /// Nothing is sent when no tracker heard `started`. An announce still in flight is dropped , the
+    /// `stopped` one gets `STOP_TIMEOUT`
+    pub async fn stop(&mut self, request: &TrackerRequest) -> Vec<AnnounceOutcome> {
+        self.in_flight.abort_all();
+        let mut outcomes = Vec::new();
+        if !self.schedule.is_started() {
+            return outcomes;
+        }
+
+        let mut events = vec![TrackerEvent::Stopped];
+        if let Some(TrackerEvent::Completed) = self.schedule.next_event() {
+            events.insert(0, TrackerEvent::Completed);
+        }
+        for event in events {
+            let tracker = self.trackers.current().clone();
+            let request = TrackerRequest {
+                event: Some(event.clone()),
+                ..request.clone()
+            };
+            let result = tokio::time::timeout(STOP_TIMEOUT, tracker.announce(request))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("No answer within {:?}", STOP_TIMEOUT)));
+            outcomes.push(self.record(Some(event), result));
+        }
+        outcomes
+    }
+
+    fn record(
+        &mut self,
+        event: Option<TrackerEvent>,
+        result: Result<TrackerResponse>,
+    ) -> AnnounceOutcome {
+        let tracker = self.trackers.current().announce_url().to_string();
+        self.schedule
+            .announced(self.clock.now(), event.as_ref(), &result);
+        // Failing over means the new tracker is asked right away , not after the retry wait
+        if self.trackers.record_result(&result) {
+            self.schedule.announce_now();
+        }
+        if self.trackers.current().announce_url() != tracker {
+            self.schedule.tracker_changed();
+        }
+
+        match result {
+            Ok(response) => AnnounceOutcome::Announced {
+                tracker,
+                event,
+                response,
+            },
+            Err(e) => AnnounceOutcome::Failed {
+                tracker,
+                event,
+                error: e.to_string(),
+            },
+        }
+    }
+}
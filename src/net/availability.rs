@@ -0,0 +1,82 @@
+use serde_json::{Value, json};
+
+/// How the pieces of a torrent are spread over the connected peers
+///
+/// Compact on purpose , one counter per piece and our own pieces as a wire format bitfield ,
+/// so it is cheap to export for analytics or to eyeball when the picker misbehaves
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PieceAvailability {
+    /// Connected peers that have each piece
+    pub counts: Vec<u32>,
+    /// Pieces we have , high bit first like the Bitfield message
+    pub ours: Vec<u8>,
+    /// Peers the counts were taken from
+    pub peers: usize,
+}
+
+impl PieceAvailability {
+    pub fn piece_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn we_have(&self, index: usize) -> bool {
+        self.ours
+            .get(index / 8)
+            .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    pub fn pieces_we_have(&self) -> usize {
+        (0..self.counts.len())
+            .filter(|&index| self.we_have(index))
+            .count()
+    }
+
+    /// Pieces per availability level , `histogram()[n]` is how many pieces exactly n peers have
+    pub fn histogram(&self) -> Vec<usize> {
+        let highest = self.counts.iter().copied().max().unwrap_or(0) as usize;
+        let mut histogram = vec![0; highest + 1];
+        for &count in &self.counts {
+            histogram[count as usize] += 1;
+        }
+        histogram
+    }
+
+    /// Same as `histogram` but only over pieces we're still missing , what the picker chooses from
+    pub fn missing_histogram(&self) -> Vec<usize> {
+        let mut histogram = vec![0; self.histogram().len()];
+        for (index, &count) in self.counts.iter().enumerate() {
+            if !self.we_have(index) {
+                histogram[count as usize] += 1;
+            }
+        }
+        histogram
+    }
+
+    /// Fewest copies of any piece in the swarm , 0 means some piece is nowhere to be found
+    pub fn rarest(&self) -> u32 {
+        self.counts.iter().copied().min().unwrap_or(0)
+    }
+
+    /// Full copies of the torrent among the peers (fractional , like most clients show it)
+    pub fn distributed_copies(&self) -> f64 {
+        if self.counts.is_empty() {
+            return 0.0;
+        }
+
+        let rarest = self.rarest();
+        let above = self.counts.iter().filter(|&&count| count > rarest).count();
+        rarest as f64 + above as f64 / self.counts.len() as f64
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "pieces": self.counts.len(),
+            "peers": self.peers,
+            "counts": self.counts,
+            "bitfield": hex::encode(&self.ours),
+            "histogram": self.histogram(),
+            "missing_histogram": self.missing_histogram(),
+            "distributed_copies": self.distributed_copies(),
+        })
+    }
+}
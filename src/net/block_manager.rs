@@ -1,24 +1,213 @@
 use crate::{
-    net::piece_manager::{BLOCK_SIZE, Block, BlockInfo, Piece, PieceState},
-    protocol::torrent::Torrent,
-    storage::files::FileStorage,
+    core::{
+        bitfield::Bitfield,
+        clock::{SharedClock, system_clock},
+        events::{CorruptionCheck, Event, EventBus},
+        peer::{Peer, PeerSnapshot},
+        piece_picker::{PiecePicker, PiecePickerStrategy},
+        priority::{DEFAULT_FINISH_BOOST, FilePriority},
+        runtime::SharedRuntime,
+    },
+    net::{
+        availability::PieceAvailability,
+        choker::{Choker, SlotStats},
+        diagnostics::{OutstandingRequest, PeerDiagnostics},
+        piece_manager::{
+            BLOCK_SIZE, Block, BlockInfo, MAX_BLOCK_SIZE, Piece, PieceState, clamp_block_size,
+        },
+        request_scheduler::{ENDGAME_MAX_PEERS_PER_BLOCK, RequestScheduler},
+        swarm_health::{SwarmHealth, TOP_PEERS, TopPeer},
+    },
+    protocol::{
+        extension::{ExtendedHandshake, upload_only_message},
+        handshake::Extensions,
+        merkle::MerklePiece,
+        message::PeerMessage,
+        metadata::{LOCAL_UT_METADATA_ID, MetadataMessage, serve_metadata},
+        torrent::Torrent,
+    },
+    storage::{
+        backend::{QuarantinedFile, SharedStorage, Storage},
+        breaker::{CircuitBreaker, DEFAULT_STORAGE_TIMEOUT},
+        disk_io::{DEFAULT_DISK_QUEUE_DEPTH, DiskIo, DiskReply},
+        files::FileStorage,
+        hash_worker::{
+            CheckJob, HashJob, HashOutcome, HashResult, HashWorker, RecvTimeoutError, verify_stored,
+        },
+        resume::{ResumeData, ResumeLoad},
+        scrub::ScrubSchedule,
+        spill::{SpillArea, SpilledPiece},
+        write_retry::{RetryPlan, WriteRetries},
+    },
+    util::humanize,
 };
 use anyhow::anyhow;
 use std::{
-    collections::VecDeque,
-    io::Error,
-    sync::{Arc, Mutex},
-    time::Instant,
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    io::{Error, ErrorKind},
+    iter, mem,
+    net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, TryLockError},
+    thread,
+    time::{Duration, Instant},
 };
+use tokio::sync::watch;
+
+/// First and longest pause between tries in `lock_storage`
+const LOCK_BACKOFF: Range<Duration> = Duration::from_millis(1)..Duration::from_millis(50);
+
+/// Locks storage , giving up after `timeout` instead of waiting on a hung disk forever
+///
+/// Blocks the calling thread while it waits , the event loop uses `try_lock_storage` for anything
+/// it does every turn
+fn lock_storage(
+    storage: &SharedStorage,
+    timeout: Duration,
+) -> Result<MutexGuard<'_, Box<dyn Storage>>, anyhow::Error> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = LOCK_BACKOFF.start;
+    loop {
+        match storage.try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(_)) => return Err(anyhow!("Storage lock is poisoned")),
+            Err(TryLockError::WouldBlock) if Instant::now() >= deadline => {
+                return Err(anyhow!(
+                    "Storage did not respond within {}",
+                    humanize::duration(timeout)
+                ));
+            }
+            Err(TryLockError::WouldBlock) => {
+                thread::sleep(backoff.min(deadline.saturating_duration_since(Instant::now())));
+                backoff = (backoff * 2).min(LOCK_BACKOFF.end);
+            }
+        }
+    }
+}
+
+/// Locks storage if nobody else holds it , None while the disk thread is busy with it
+fn try_lock_storage(
+    storage: &SharedStorage,
+) -> Result<Option<MutexGuard<'_, Box<dyn Storage>>>, anyhow::Error> {
+    match storage.try_lock() {
+        Ok(guard) => Ok(Some(guard)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Poisoned(_)) => Err(anyhow!("Storage lock is poisoned")),
+    }
+}
+
+/// Receiving end of a BlockManager's stats , `borrow()` it to read the latest snapshot without cloning
+pub type StatsReceiver = watch::Receiver<DownloadStats>;
 
 #[derive(Debug)]
 /// Handles blocks for a torrent
+///
+/// The manager owns every piece outright , other tasks never lock piece state and instead go through
+/// the manager (or read the snapshots it publishes)
 pub struct BlockManager {
     torrent: Torrent,
-    pieces: Vec<Arc<Mutex<Piece>>>,
-    storage: Arc<Mutex<FileStorage>>,
+    pieces: Vec<Piece>,
+    storage: SharedStorage,
+    /// Verifies and writes finished pieces off the download path
+    hash_worker: HashWorker,
+    /// Pieces handed to the hash worker that haven't come back yet
+    hashing: HashSet<usize>,
+    /// Reads blocks for uploads off the event loop
+    disk: DiskIo,
+    /// Requested blocks queued on `disk` , sent out by `finished_reads`
+    pending_reads: Vec<PendingRead>,
+    /// Requests (peer , index , begin , length) waiting for the hash worker to check their piece ,
+    /// see `check_before_upload`
+    awaiting_check: HashMap<usize, Vec<(SocketAddr, u32, u32, u32)>>,
+    /// Pieces the scrub handed to the hash worker , and those it found bad since the last `scrub_tick`
+    scrubbing: HashSet<usize>,
+    scrub_failures: Vec<usize>,
     download_queue: VecDeque<usize>,
+    /// Size of the blocks requested from peers for this torrent
+    block_size: usize,
     stats: DownloadStats,
+    /// Publishes a copy of `stats` whenever they change , readers never touch the download path
+    stats_tx: watch::Sender<DownloadStats>,
+    /// Peers connected for this torrent
+    peers: HashMap<SocketAddr, Peer>,
+    /// Every timeout and timestamp goes through this , tests swap in a MockClock
+    clock: SharedClock,
+    /// Upload-only state peers were last told about
+    advertised_upload_only: bool,
+    /// Pieces the user wants , unwanted pieces are never requested
+    wanted: Vec<bool>,
+    /// Background re-verification of pieces we have , None when switched off
+    scrub: Option<ScrubSchedule>,
+    events: Option<EventBus>,
+    /// Stops requesting data while storage keeps failing or hanging
+    breaker: CircuitBreaker,
+    /// Longest a disk operation may take before it counts against the breaker
+    storage_timeout: Duration,
+    /// Piece availability and the strategy choosing the next piece , rarest first unless changed
+    picker: PiecePicker,
+    /// Malformed messages per peer address , outlives the connection
+    protocol_violations: HashMap<SocketAddr, u32>,
+    /// Files storage is skipping , their pieces are no longer wanted
+    quarantined: HashSet<usize>,
+    /// Storage was busy when the quarantine was last synced , try again on the next results
+    quarantine_stale: bool,
+    /// Peers each outstanding block was requested from
+    requested_from: HashMap<BlockInfo, Vec<SocketAddr>>,
+    /// Cap on peers a block is requested from in endgame , 1 turns duplicates off
+    endgame_peers_per_block: usize,
+    /// Picks the peers we upload to
+    choker: Choker,
+    /// Pieces verified since the last `announcements` , each goes out as a Have
+    unannounced: Vec<usize>,
+    /// Set when what we want changed , every peer's interest is re-checked on the next `announcements`
+    interest_stale: bool,
+    /// Where `save_resume_data` writes , None keeps no resume data
+    resume_path: Option<PathBuf>,
+    /// Verified pieces whose writes failed , kept to be written again
+    write_retries: WriteRetries,
+    /// Where pieces that can't be written or held in memory wait , None drops them instead
+    spool: Option<SpillArea>,
+    /// Verified pieces hashed since the manager started. Pieces taken from resume data aren't , they
+    /// are hashed before they're first served so a corrupted file is never uploaded
+    hashed_this_run: Bitfield,
+    /// Priority of every file , one entry for a single file torrent
+    file_priorities: Vec<FilePriority>,
+    /// Highest priority among the files each piece overlaps , Skip when they're all skipped or quarantined
+    piece_priorities: Vec<FilePriority>,
+    /// Files at least this far along finish first , None picks without regard to files
+    finish_boost: Option<f64>,
+    /// Written pieces are read back and hashed again before they count as verified
+    verify_writes: bool,
+    /// Merkle hashes per piece for v2 torrents , checked instead of the SHA-1 piece hashes
+    merkle: Option<Vec<MerklePiece>>,
+}
+
+/// A block a peer asked for , being read by the disk thread
+#[derive(Debug)]
+struct PendingRead {
+    peer: SocketAddr,
+    index: u32,
+    begin: u32,
+    length: u32,
+    reply: DiskReply<Vec<u8>>,
+}
+
+/// One file of the torrent and how far along it is , for the files pane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileProgress {
+    /// Index in the torrent's file list
+    pub index: usize,
+    /// Path inside the torrent
+    pub path: PathBuf,
+    pub length: usize,
+    pub priority: FilePriority,
+    /// Storage gave up writing it (or was told to skip it)
+    pub quarantined: bool,
+    /// Verified pieces holding bytes of the file
+    pub pieces_done: usize,
+    pub pieces_total: usize,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -32,7 +221,20 @@ pub struct DownloadStats {
     pub total_bytes: usize,
     pub downloaded_bytes: usize,
     pub download_start: Option<Instant>,
+    /// Last block received , i.e the last download activity
     pub last_update: Option<Instant>,
+    /// Payload bytes received from peers , carried over between runs by the resume file
+    pub total_downloaded: u64,
+    /// Bytes served to peers , carried over between runs by the resume file
+    pub uploaded_bytes: u64,
+    /// Part of `uploaded_bytes` that went to peers on the local network
+    pub lan_uploaded_bytes: u64,
+    /// Bytes of blocks received from peers on the local network
+    pub lan_downloaded_bytes: u64,
+    /// Last block served to a peer
+    pub last_upload: Option<Instant>,
+    /// Upload slot usage , filled in whenever the stats are read or published
+    pub slots: SlotStats,
 }
 
 impl DownloadStats {
@@ -70,44 +272,346 @@ impl DownloadStats {
 // impl From<'a> for BlockManager {}
 impl BlockManager {
     pub fn from(torrent: Torrent, storage: FileStorage) -> Result<Self, Error> {
-        let mut pieces = Vec::new();
-        let piece_length = torrent.piece_length;
-        let total_length = torrent.length;
+        Self::with_storage(torrent, Box::new(storage))
+    }
 
-        for (index, &hash) in torrent.pieces.iter().enumerate() {
-            let length = if index == torrent.pieces.len() - 1 {
-                let piece_size = index * piece_length;
-                total_length - piece_size
-            } else {
-                piece_length
-            };
+    /// Manager writing to any storage backend , e.g MemoryStorage
+    pub fn with_storage(torrent: Torrent, storage: Box<dyn Storage>) -> Result<Self, Error> {
+        Self::open(torrent, storage, None, None)
+    }
+
+    /// Like `with_storage` , but starts from the resume file at `resume_path` instead of hashing
+    /// every piece. Pieces are only rechecked when the file is missing or unusable , or when any
+    /// file changed size or mtime since it was written
+    pub fn with_resume_file(
+        torrent: Torrent,
+        storage: Box<dyn Storage>,
+        resume_path: PathBuf,
+    ) -> Result<Self, Error> {
+        Self::open(torrent, storage, Some(resume_path), None)
+    }
 
-            pieces.push(Arc::new(Mutex::new(Piece::new(index, length, hash))));
+    /// `with_resume_file` publishing on `events` from the start , so a rejected resume file is
+    /// reported as `Event::ResumeDataRejected`
+    pub fn with_resume_file_and_events(
+        torrent: Torrent,
+        storage: Box<dyn Storage>,
+        resume_path: PathBuf,
+        events: EventBus,
+    ) -> Result<Self, Error> {
+        Self::open(torrent, storage, Some(resume_path), Some(events))
+    }
+
+    fn open(
+        torrent: Torrent,
+        storage: Box<dyn Storage>,
+        resume_path: Option<PathBuf>,
+        events: Option<EventBus>,
+    ) -> Result<Self, Error> {
+        let merkle = torrent
+            .merkle_pieces()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let layout = torrent
+            .layout()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let total_length = torrent.length;
+
+        // v2 only torrents have no SHA-1 hashes , their pieces are only ever checked by merkle hashes
+        let mut pieces = Vec::new();
+        for index in 0..torrent.piece_count() {
+            let hash = torrent.pieces.get(index).copied().unwrap_or_default();
+            let length = layout
+                .piece_size(index.into())
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+            // Never more than the piece length , which is a usize
+            pieces.push(Piece::new(index, length as usize, hash));
         }
 
+        let torrent_pieces = pieces.len();
+        let torrent_files = torrent.files.as_ref().map_or(1, |files| files.len());
         let stats = DownloadStats {
             total_pieces: pieces.len(),
             total_bytes: total_length,
             ..Default::default()
         };
 
+        let (stats_tx, _) = watch::channel(stats.clone());
+
+        let storage = Arc::new(Mutex::new(storage));
+
         let mut manager = Self {
             torrent,
             pieces,
-            storage: Arc::new(Mutex::new(storage)),
+            hash_worker: HashWorker::spawn(storage.clone()),
+            hashing: HashSet::new(),
+            disk: DiskIo::spawn(storage.clone(), DEFAULT_DISK_QUEUE_DEPTH),
+            pending_reads: Vec::new(),
+            awaiting_check: HashMap::new(),
+            scrubbing: HashSet::new(),
+            scrub_failures: Vec::new(),
+            storage,
             download_queue: VecDeque::new(),
+            block_size: BLOCK_SIZE,
             stats,
+            stats_tx,
+            peers: HashMap::new(),
+            clock: system_clock(),
+            advertised_upload_only: false,
+            wanted: vec![true; torrent_pieces],
+            scrub: None,
+            events,
+            breaker: CircuitBreaker::default(),
+            storage_timeout: DEFAULT_STORAGE_TIMEOUT,
+            picker: PiecePicker::new(torrent_pieces),
+            protocol_violations: HashMap::new(),
+            quarantined: HashSet::new(),
+            quarantine_stale: false,
+            requested_from: HashMap::new(),
+            endgame_peers_per_block: ENDGAME_MAX_PEERS_PER_BLOCK,
+            choker: Choker::default(),
+            unannounced: Vec::new(),
+            interest_stale: false,
+            resume_path,
+            hashed_this_run: Bitfield::new(torrent_pieces),
+            write_retries: WriteRetries::default(),
+            spool: None,
+            file_priorities: vec![FilePriority::default(); torrent_files],
+            piece_priorities: vec![FilePriority::default(); torrent_pieces],
+            finish_boost: Some(DEFAULT_FINISH_BOOST),
+            verify_writes: false,
+            merkle,
         };
 
-        // Initialize download queue with missing pieces
-        match manager.rebuild_download_queue() {
-            Ok(_) => println!("Download Queue rebuilt"),
-            Err(_) => println!("Error"),
+        // Initialize download queue with missing pieces , hashing them all unless the resume file can be trusted
+        let resumed = match manager.resume_path.clone() {
+            Some(path) => manager.resume(&path),
+            None => false,
         };
+        if !resumed {
+            match manager.rebuild_download_queue() {
+                Ok(_) => crate::log_line!("Download Queue rebuilt"),
+                Err(_) => crate::log_line!("Error"),
+            };
+        }
+
+        // Pick up blocks spilled by the last shutdown
+        match manager.restore_partial_pieces() {
+            Ok(0) => {}
+            Ok(restored) => crate::log_line!("Restored {} partially downloaded pieces", restored),
+            Err(e) => crate::log_line!("Could not restore partial pieces : {}", e),
+        }
 
         Ok(manager)
     }
 
+    /// Replaces the clock used for timeouts and stats
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.hash_worker.set_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    /// Moves the disk thread onto `runtime` and takes its clock
+    pub fn with_runtime(mut self, runtime: SharedRuntime) -> Self {
+        // Nothing is queued yet , the old thread ends as soon as its queue is dropped
+        self.disk = DiskIo::spawn_on(&*runtime, self.storage.clone(), DEFAULT_DISK_QUEUE_DEPTH);
+        self.with_clock(runtime.clock())
+    }
+
+    /// Handle to the storage backend , e.g to stream data out of a MemoryStorage
+    pub fn storage(&self) -> SharedStorage {
+        self.storage.clone()
+    }
+
+    /// Copies `length` bytes starting at `offset` in the torrent , fails if any piece in the range is missing.
+    /// Lets callers stream data straight out of the backend
+    pub fn read_at(&self, offset: usize, length: usize) -> Result<Vec<u8>, anyhow::Error> {
+        let end = offset
+            .checked_add(length)
+            .filter(|&end| end <= self.torrent.length)
+            .ok_or_else(|| anyhow!("Range {}+{} is past the end of the torrent", offset, length))?;
+
+        if self.breaker.is_open() {
+            return Err(anyhow!("Storage is paused after repeated failures"));
+        }
+
+        let piece_length = self.torrent.piece_length;
+        let storage = lock_storage(&self.storage, self.storage_timeout)?;
+        let mut out = Vec::with_capacity(length);
+        let mut position = offset;
+
+        while position < end {
+            let index = position / piece_length;
+            let start = position % piece_length;
+            let take = (self.pieces[index].length - start).min(end - position);
+
+            out.extend_from_slice(&storage.read_block(index, start, take)?);
+            position += take;
+        }
+
+        Ok(out)
+    }
+
+    /// Receives alerts such as PieceCorrupted
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Missing pieces of files that are at least `share` verified (0.0 to 1.0) are started before
+    /// the rest of their priority level , so nearly done files complete early. None turns it off
+    pub fn with_finish_boost(mut self, share: Option<f64>) -> Self {
+        self.finish_boost = share.map(|share| share.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Reads every piece back after writing it and checks the hash again , so a disk that drops
+    /// writes silently is caught at the cost of reading everything twice. Off by default
+    pub fn with_write_verification(mut self, verify: bool) -> Self {
+        self.verify_writes = verify;
+        self
+    }
+
+    /// Turns read-after-write checks on or off for pieces written from now on
+    pub fn set_write_verification(&mut self, verify: bool) {
+        self.verify_writes = verify;
+    }
+
+    /// Whether pieces are checked against merkle hashes (v2) rather than SHA-1
+    pub fn verifies_by_merkle(&self) -> bool {
+        self.merkle.is_some()
+    }
+
+    fn merkle_piece(&self, index: usize) -> Option<MerklePiece> {
+        self.merkle.as_ref()?.get(index).copied()
+    }
+
+    pub fn verifies_writes(&self) -> bool {
+        self.verify_writes
+    }
+
+    /// Caps the memory held by pieces waiting for a write retry , `bytes` for all of them together
+    pub fn with_retry_buffer(mut self, bytes: usize) -> Self {
+        self.write_retries = WriteRetries::new(bytes);
+        self
+    }
+
+    /// Verified pieces that can't be written keep their data under `dir` instead of being downloaded
+    /// again , and are written from there once storage works. Pieces left there by an earlier run are
+    /// picked up now
+    pub fn with_write_spool(mut self, dir: PathBuf) -> Self {
+        let spool = SpillArea::at(dir.join(hex::encode(self.torrent.info_hash)));
+        match self.restore_from(&spool) {
+            Ok(0) => {}
+            Ok(restored) => crate::log_line!("Picked up {} spooled pieces", restored),
+            Err(e) => crate::log_line!("Could not read spooled pieces : {}", e),
+        }
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Turns the background scrub on at `pieces_per_minute` , or off with None
+    pub fn set_scrub(&mut self, pieces_per_minute: Option<u32>) {
+        self.scrub = pieces_per_minute.map(ScrubSchedule::new);
+    }
+
+    pub fn scrub(&self) -> Option<&ScrubSchedule> {
+        self.scrub.as_ref()
+    }
+
+    /// Hands the pieces the scrub schedule says are due to the hash worker , call it regularly (e.g
+    /// every few seconds)
+    ///
+    /// Pieces that no longer match are dropped back to pending and queued again once their result is
+    /// in. Returns the indexes of those found since the last tick
+    pub fn scrub_tick(&mut self) -> Vec<usize> {
+        let now = self.clock.now();
+        let total = self.pieces.len();
+        if self.breaker.is_open() {
+            return Vec::new();
+        }
+        let Some(scrub) = &mut self.scrub else {
+            return Vec::new();
+        };
+
+        for index in scrub.due(now, total) {
+            // Only data we have can rot , pieces in flight are checked when they finish
+            if self.pieces[index].state != PieceState::Verified {
+                continue;
+            }
+            if self.submit_check(index).is_err() {
+                break;
+            }
+            self.scrubbing.insert(index);
+        }
+        mem::take(&mut self.scrub_failures)
+    }
+
+    /// Drops a piece that no longer hashes right , it's downloaded again and the user is told
+    fn piece_corrupted(&mut self, index: usize, reason: String, found_by: CorruptionCheck) {
+        let check = match found_by {
+            CorruptionCheck::Scrub => "Scrub",
+            CorruptionCheck::Upload => "Upload",
+        };
+        crate::log_line!(
+            "{} : piece {} is bad ({}) , downloading it again",
+            check,
+            index,
+            reason
+        );
+        self.invalidate_piece(index);
+        // Seeds start downloading again , peers with the piece become interesting
+        self.interest_stale = true;
+        if let Some(events) = &self.events {
+            events.publish(Event::PieceCorrupted {
+                piece_index: index,
+                reason,
+                found_by,
+            });
+        }
+        self.publish_stats();
+    }
+
+    /// Forgets a verified piece and puts it at the front of the queue
+    fn invalidate_piece(&mut self, index: usize) {
+        let piece = &mut self.pieces[index];
+        piece.reset();
+        self.hashed_this_run.unset(index);
+
+        self.stats.verified_pieces = self.stats.verified_pieces.saturating_sub(1);
+        self.stats.completed_pieces = self.stats.completed_pieces.saturating_sub(1);
+        self.stats.downloaded_bytes = self.stats.downloaded_bytes.saturating_sub(piece.length);
+        self.download_queue.push_front(index);
+    }
+
+    /// Changes the size of the blocks requested for this torrent
+    ///
+    /// The size is clamped to what peers accept. Pieces that haven't been started are split up again ,
+    /// pieces already in progress keep the layout they were requested with
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.set_block_size(block_size);
+        self
+    }
+
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.block_size = clamp_block_size(block_size);
+
+        for piece in &mut self.pieces {
+            let untouched = piece.state == PieceState::Pending
+                && piece.blocks.is_empty()
+                && piece.requested_blocks.is_empty();
+
+            if untouched && piece.block_size != self.block_size {
+                *piece =
+                    Piece::with_block_size(piece.index, piece.length, piece.hash, self.block_size);
+            }
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
     pub fn rebuild_download_queue(&mut self) -> Result<(), anyhow::Error> {
         self.download_queue.clear();
 
@@ -117,11 +621,12 @@ impl BlockManager {
         let storage = self.storage.lock().unwrap();
 
         // Finds
-        for (index, piece_arc) in self.pieces.iter().enumerate() {
-            let mut piece = piece_arc.lock().unwrap();
-
-            if storage.is_piece_complete(index).unwrap_or(false) {
+        for (index, piece) in self.pieces.iter_mut().enumerate() {
+            let merkle = self.merkle.as_deref().and_then(|pieces| pieces.get(index));
+            if verify_stored(&**storage, index, piece.length, &piece.hash, merkle).unwrap_or(false)
+            {
                 piece.state = PieceState::Verified;
+                self.hashed_this_run.set(index);
                 self.stats.verified_pieces += 1;
                 self.stats.downloaded_bytes += piece.length;
             } else {
@@ -129,133 +634,1785 @@ impl BlockManager {
             }
         }
 
+        self.publish_stats();
         Ok(())
     }
 
-    /// Simple sequential strategy to get the next piece
+    /// Takes verified pieces and counters from resume data , false when it can't be trusted and
+    /// every piece has to be hashed
+    fn resume(&mut self, path: &Path) -> bool {
+        let load = match &self.events {
+            Some(events) => ResumeData::load_or_warn(path, &self.torrent, events),
+            None => ResumeData::load(path, &self.torrent),
+        };
+        let data = match load {
+            ResumeLoad::Resumed(data) => data,
+            ResumeLoad::Recheck(reason) => {
+                if self.events.is_none() && path.exists() {
+                    crate::log_line!("{} , rechecking {}", reason, self.torrent.name);
+                }
+                return false;
+            }
+        };
+
+        let stamps = self.storage.lock().unwrap().file_stamps();
+        if stamps.is_none_or(|stamps| stamps != data.files) {
+            crate::log_line!(
+                "Files of {} changed since the resume data was written , rechecking",
+                self.torrent.name
+            );
+            return false;
+        }
+
+        self.download_queue.clear();
+        for (index, piece) in self.pieces.iter_mut().enumerate() {
+            if data.has_piece(index) {
+                piece.state = PieceState::Verified;
+                self.stats.verified_pieces += 1;
+                self.stats.downloaded_bytes += piece.length;
+            } else {
+                self.download_queue.push_back(index);
+            }
+        }
+        self.stats.uploaded_bytes = data.uploaded;
+        self.stats.total_downloaded = data.downloaded;
+
+        crate::log_line!(
+            "Resumed {} , {}/{} pieces without rechecking",
+            self.torrent.name,
+            self.stats.verified_pieces,
+            self.pieces.len()
+        );
+        self.publish_stats();
+        true
+    }
+
+    /// Verified pieces , file stamps and transfer counters as they are now
+    ///
+    /// Pieces still being hashed aren't in it , call `wait_for_verifications` first on shutdown
+    pub fn resume_data(&self) -> Result<ResumeData, anyhow::Error> {
+        let storage = lock_storage(&self.storage, self.storage_timeout)?;
+        // Stamps have to be taken after the last write reached the file
+        storage.flush()?;
+
+        let mut data = ResumeData::new(self.torrent.info_hash, self.pieces.len());
+        for piece in &self.pieces {
+            if piece.state == PieceState::Verified {
+                data.set_piece(piece.index);
+            }
+        }
+        data.files = storage.file_stamps().unwrap_or_default();
+        data.uploaded = self.stats.uploaded_bytes;
+        data.downloaded = self.stats.total_downloaded;
+        Ok(data)
+    }
+
+    /// Writes the resume file , false when the manager keeps none
+    pub fn save_resume_data(&self) -> Result<bool, anyhow::Error> {
+        let Some(path) = &self.resume_path else {
+            return Ok(false);
+        };
+        self.resume_data()?.save(path)?;
+        Ok(true)
+    }
+
+    /// Next piece to start , chosen by the piece picker among the queued pieces we want
     pub fn get_next_piece_to_download(&mut self) -> Option<usize> {
-        self.download_queue.pop_front()
+        self.next_piece(None)
     }
 
-    /// Gets the next block request , params are the blocks piece_index
-    pub fn get_next_block_request(&self, piece_index: usize) -> Option<BlockInfo> {
-        if piece_index >= self.pieces.len() {
-            return None;
+    /// Next piece to start with blocks from `addr` , only pieces the peer has are considered
+    pub fn get_next_piece_for_peer(&mut self, addr: &SocketAddr) -> Option<usize> {
+        let bitfield = self.peers.get(addr)?.bitfield.clone();
+        self.next_piece(Some(&bitfield))
+    }
+
+    /// Pieces of higher priority files go first , the picker chooses within a priority level. Inside
+    /// a level , pieces that finish a nearly done file come before the others
+    fn next_piece(&mut self, peer: Option<&Bitfield>) -> Option<usize> {
+        let candidates: Vec<usize> = self
+            .download_queue
+            .iter()
+            .copied()
+            .filter(|&index| self.is_wanted(index))
+            .collect();
+        let finishing = self.finishing_pieces();
+
+        for level in FilePriority::PICK_ORDER {
+            // Pieces wanted through `set_wanted_pieces` alone get the lowest level
+            let in_level: Vec<usize> = candidates
+                .iter()
+                .copied()
+                .filter(|&index| self.piece_priority(index).max(FilePriority::Low) == level)
+                .collect();
+            let boosted: Vec<usize> = in_level
+                .iter()
+                .copied()
+                .filter(|&index| finishing.contains(&index))
+                .collect();
+
+            for group in [boosted, in_level] {
+                if group.is_empty() {
+                    continue;
+                }
+                if let Some(index) = self.picker.pick(&group, peer) {
+                    let position = self.download_queue.iter().position(|&q| q == index)?;
+                    self.download_queue.remove(position);
+                    return Some(index);
+                }
+            }
+        }
+        None
+    }
+
+    /// Missing pieces of wanted files that are past the finish boost share
+    fn finishing_pieces(&self) -> HashSet<usize> {
+        let Some(share) = self.finish_boost else {
+            return HashSet::new();
+        };
+
+        let mut finishing = HashSet::new();
+        for (file_index, span) in self.file_spans().iter().enumerate() {
+            if self.file_priorities[file_index].is_skipped()
+                || self.quarantined.contains(&file_index)
+            {
+                continue;
+            }
+
+            let pieces = self.pieces_in(span);
+            let missing: Vec<usize> = pieces
+                .clone()
+                .filter(|&index| self.pieces[index].state != PieceState::Verified)
+                .collect();
+            let done = (pieces.len() - missing.len()) as f64 / pieces.len().max(1) as f64;
+            if !missing.is_empty() && done >= share {
+                finishing.extend(missing);
+            }
+        }
+        finishing
+    }
+
+    /// Swaps the piece picking strategy , takes effect from the next piece started
+    pub fn set_picker(&mut self, picker: Box<dyn PiecePickerStrategy>) {
+        self.picker.set_strategy(picker);
+    }
+
+    pub fn with_picker(mut self, picker: Box<dyn PiecePickerStrategy>) -> Self {
+        self.picker.set_strategy(picker);
+        self
+    }
+
+    pub fn picker_name(&self) -> &str {
+        self.picker.strategy_name()
+    }
+
+    /// Marks which pieces to download , one flag per piece. Pieces that are left out stay missing
+    ///
+    /// Changing a file's priority or quarantine works the flags out again from the files
+    pub fn set_wanted_pieces(&mut self, wanted: Vec<bool>) -> Result<(), anyhow::Error> {
+        if wanted.len() != self.pieces.len() {
+            return Err(anyhow!(
+                "Expected {} piece flags , got {}",
+                self.pieces.len(),
+                wanted.len()
+            ));
+        }
+        self.apply_wanted(wanted);
+        Ok(())
+    }
+
+    fn apply_wanted(&mut self, wanted: Vec<bool>) {
+        // Newly wanted pieces go to the back of the queue , unwanted ones leave it
+        for (index, &want) in wanted.iter().enumerate() {
+            let missing = self.pieces[index].state == PieceState::Pending;
+            if want && !self.wanted[index] && missing && !self.download_queue.contains(&index) {
+                self.download_queue.push_back(index);
+            }
         }
+        self.download_queue.retain(|&index| wanted[index]);
 
-        let mut piece = self.pieces[piece_index].lock().unwrap();
+        self.wanted = wanted;
+        self.interest_stale = true;
+    }
+
+    pub fn is_wanted(&self, piece_index: usize) -> bool {
+        self.wanted.get(piece_index).copied().unwrap_or(false)
+    }
+
+    /// Gets the next block request , params are the blocks piece_index
+    pub fn get_next_block_request(&mut self, piece_index: usize) -> Option<BlockInfo> {
+        let now = self.clock.now();
+        let piece = self.pieces.get_mut(piece_index)?;
 
         if piece.state == PieceState::Pending {
             piece.state = PieceState::InProgress;
         }
 
-        piece.get_next_block_request()
+        piece.get_next_block_request(now)
+    }
+
+    /// Hands out block requests to peers until every pipeline is full or nothing is left to request
+    ///
+    /// Peers are picked by the scheduler one block at a time , so a newly connected peer gets requests
+    /// straight away instead of waiting for the first peer's pipeline to fill up
+    pub fn assign_requests(
+        &mut self,
+        scheduler: &mut RequestScheduler,
+    ) -> Vec<(SocketAddr, BlockInfo)> {
+        let mut assigned = Vec::new();
+        let now = self.clock.now();
+        scheduler.expire_requests(now);
+        if self.is_upload_only() || !self.breaker.allow(now) {
+            return assigned;
+        }
+
+        let mut started: Vec<usize> = self
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, piece)| piece.state == PieceState::InProgress)
+            .map(|(index, _)| index)
+            .collect();
+        // Peers that have nothing left we want , skipped for the rest of this round
+        let mut exhausted = Vec::new();
+
+        while scheduler.has_capacity() {
+            let Some(peer) = scheduler.next_peer_except(&exhausted) else {
+                break;
+            };
+
+            match self.next_block_for_peer(&peer, &mut started) {
+                Some(block) => {
+                    scheduler.request_sent(&peer, block, now);
+                    self.requested_from.insert(block, vec![peer]);
+                    assigned.push((peer, block));
+                }
+                None => exhausted.push(peer),
+            }
+        }
+
+        if scheduler.has_capacity() && self.is_endgame() {
+            self.assign_endgame_duplicates(scheduler, &mut assigned);
+        }
+
+        assigned
+    }
+
+    /// Next block to request from `peer` , finishing pieces we already started before opening new ones
+    ///
+    /// Pieces in `started` with nothing left to request are dropped from it
+    fn next_block_for_peer(
+        &mut self,
+        peer: &SocketAddr,
+        started: &mut Vec<usize>,
+    ) -> Option<BlockInfo> {
+        let mut i = 0;
+        while i < started.len() {
+            let index = started[i];
+            if !self.peers.get(peer).is_some_and(|p| p.has_piece(index)) {
+                i += 1;
+                continue;
+            }
+            match self.get_next_block_request(index) {
+                Some(block) => return Some(block),
+                None => {
+                    started.remove(i);
+                }
+            }
+        }
+
+        loop {
+            let index = self.get_next_piece_for_peer(peer)?;
+            started.push(index);
+            if let Some(block) = self.get_next_block_request(index) {
+                return Some(block);
+            }
+        }
+    }
+
+    /// Whether every block we still want has been requested , only duplicates can speed things up now
+    pub fn is_endgame(&self) -> bool {
+        let mut outstanding = false;
+        for (piece, &wanted) in self.pieces.iter().zip(&self.wanted) {
+            if !wanted {
+                continue;
+            }
+            match piece.state {
+                PieceState::Pending | PieceState::Failed => return false,
+                PieceState::InProgress if !piece.missing_blocks.is_empty() => return false,
+                PieceState::InProgress => outstanding = true,
+                PieceState::Complete | PieceState::Verified => {}
+            }
+        }
+        outstanding
+    }
+
+    /// Changes how many peers a block may be requested from in endgame , at least 1
+    pub fn set_endgame_peers_per_block(&mut self, peers: usize) {
+        self.endgame_peers_per_block = peers.max(1);
+    }
+
+    /// Requests outstanding blocks again from the fastest peers that don't have them yet
+    ///
+    /// Each block goes to at most `endgame_peers_per_block` peers , blocks with the fewest holders
+    /// first so one slow block doesn't soak up every spare slot
+    fn assign_endgame_duplicates(
+        &mut self,
+        scheduler: &mut RequestScheduler,
+        assigned: &mut Vec<(SocketAddr, BlockInfo)>,
+    ) {
+        let mut outstanding: Vec<BlockInfo> = self
+            .pieces
+            .iter()
+            .filter(|piece| piece.state == PieceState::InProgress)
+            .flat_map(|piece| piece.requested_blocks.keys().copied())
+            .collect();
+        outstanding.sort_by_key(|block| {
+            let holders = self.requested_from.get(block).map_or(0, Vec::len);
+            (holders, block.piece_index, block.begin)
+        });
+
+        for block in outstanding {
+            if !scheduler.has_capacity() {
+                break;
+            }
+
+            let holders = self.requested_from.entry(block).or_default();
+            if holders.len() >= self.endgame_peers_per_block {
+                continue;
+            }
+            // Peers without the piece can't answer , leave them out along with the holders
+            let mut exclude = holders.clone();
+            exclude.extend(
+                self.peers
+                    .values()
+                    .filter(|peer| !peer.has_piece(block.piece_index))
+                    .map(|peer| peer.addr),
+            );
+            if let Some(peer) = scheduler.next_fastest_peer(&exclude) {
+                scheduler.request_sent(&peer, block, self.clock.now());
+                holders.push(peer);
+                assigned.push((peer, block));
+            }
+        }
+    }
+
+    /// Takes a block from `from` , returning the other peers it was requested from so they can be sent a Cancel
+    pub fn handle_block_from(
+        &mut self,
+        from: SocketAddr,
+        block: Block,
+    ) -> Result<Vec<SocketAddr>, anyhow::Error> {
+        let mut cancels = self
+            .requested_from
+            .get(&block.info)
+            .cloned()
+            .unwrap_or_default();
+        cancels.retain(|peer| peer != &from);
+
+        self.handle_block_received(block)?;
+        Ok(cancels)
     }
 
     pub fn handle_block_received(&mut self, block: Block) -> Result<(), anyhow::Error> {
+        // Pick up pieces the hash worker finished in the meantime
+        self.process_hash_results();
+
         // Gets the index of the block received
         let piece_index = block.info.piece_index;
 
         // Makes sure the blocks index is not greater than the len of pieces (i.e The Size of the piece)
-        if piece_index > self.pieces.len() {
+        if piece_index >= self.pieces.len() {
             return Err(anyhow!(
                 "Invalid piece index: {}/nExceeds the pieces length",
                 piece_index
             ));
         }
 
+        self.requested_from.remove(&block.info);
+
         // Find the piece in the Block Managers pieces
-        let piece_arc = self.pieces[piece_index].clone();
-        let mut piece = piece_arc.lock().unwrap();
+        let now = self.clock.now();
+        let piece = &mut self.pieces[piece_index];
+
+        // The losing copy of an endgame duplicate , the piece is already done
+        if matches!(piece.state, PieceState::Complete | PieceState::Verified) {
+            return Ok(());
+        }
 
         // Adds the block to its Parent Piece
-        piece.add_block(block)?;
+        piece.add_block(block, now)?;
 
         // Sets the size of the block
-        self.stats.downloaded_bytes = piece.blocks.len() * BLOCK_SIZE;
-        self.stats.last_update = Some(Instant::now());
+        self.stats.downloaded_bytes = piece.received_bytes();
+        let complete = piece.state == PieceState::Complete;
+        self.stats.download_start.get_or_insert(now);
+        self.stats.last_update = Some(now);
+        self.publish_stats();
 
-        if piece.state == PieceState::Complete {
-            drop(piece); // Release lock before verification
+        if complete {
             self.verify_and_write_piece(piece_index)?;
         }
 
         Ok(())
     }
 
-    /// Verifies and writes a piece to storage
+    /// Hands a complete piece to the hash worker , the result is picked up by `process_hash_results`
     pub fn verify_and_write_piece(&mut self, piece_index: usize) -> Result<(), anyhow::Error> {
         // Makes sure the pieces index is a part of the block managers pieces available
-        let piece_arc = self.pieces[piece_index].clone();
-        let mut piece = piece_arc.lock().unwrap();
+        let piece = self
+            .pieces
+            .get(piece_index)
+            .ok_or_else(|| anyhow!("Invalid piece index: {}", piece_index))?;
 
         // checks if the piece is complete
         if piece.state != PieceState::Complete {
             return Err(anyhow!("Piece {} is not complete", piece_index));
         }
 
+        if self.hashing.contains(&piece_index) {
+            return Ok(());
+        }
+
         // Assemble piece data
-        let piece_data = piece.assemble_piece()?;
+        let job = HashJob {
+            piece_index,
+            hash: piece.hash,
+            merkle: self.merkle_piece(piece_index),
+            data: piece.assemble_piece()?,
+            // Storage skips quarantined files , those bytes would never read back
+            read_back: self.verify_writes && !self.touches_quarantined(piece_index),
+        };
 
-        // Verify hash
-        if !piece.verify_hash(&piece_data) {
-            println!("Piece {} failed hash verification, resetting", piece_index);
-            piece.state = PieceState::Failed;
-            self.stats.failed_pieces += 1;
+        self.hash_worker.submit(job)?;
+        self.hashing.insert(piece_index);
 
-            // Reset piece for re-download
-            piece.reset();
-            self.download_queue.push_back(piece_index);
+        Ok(())
+    }
 
-            return Err(anyhow!(
-                "Hash verification failed for piece {}",
-                piece_index
-            ));
+    /// Applies every verification result that is ready without blocking , returns how many were applied
+    pub fn process_hash_results(&mut self) -> usize {
+        let results = self.hash_worker.try_results();
+        let count = results.len();
+
+        for result in results {
+            self.apply_hash_result(result);
+        }
+        self.retry_writes();
+        self.check_storage_stall();
+        if count > 0 || self.quarantine_stale {
+            self.sync_quarantine();
+        }
+
+        count
+    }
+
+    /// Blocks until every piece handed to the hash worker has been verified
+    ///
+    /// Gives up once storage doesn't answer within the storage timeout , pieces still queued are
+    /// picked up by a later `process_hash_results`
+    pub fn wait_for_verifications(&mut self) {
+        while !self.hashing.is_empty() {
+            match self.hash_worker.wait_result_timeout(self.storage_timeout) {
+                Ok(result) => self.apply_hash_result(result),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.check_storage_stall();
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
         }
+    }
 
-        // Write to disk
-        let mut storage = self.storage.lock().unwrap();
-        storage.write_piece(piece_index, &piece_data)?;
+    /// Changes how long a disk operation may take before it counts as a storage failure
+    pub fn set_storage_timeout(&mut self, timeout: Duration) {
+        self.storage_timeout = timeout;
+    }
 
-        // Update state
-        piece.state = PieceState::Verified;
-        self.stats.completed_pieces += 1;
-        self.stats.verified_pieces += 1;
+    pub fn storage_breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
 
-        println!(
-            "Piece {}/{} verified and written ({:.2}%)",
-            piece_index + 1,
-            self.pieces.len(),
-            self.stats.progress_percentage()
-        );
+    /// Whether downloading is paused because storage keeps failing
+    pub fn is_storage_paused(&self) -> bool {
+        self.breaker.is_open()
+    }
 
-        Ok(())
+    /// A write stuck longer than the storage timeout trips the breaker on its own
+    fn check_storage_stall(&mut self) {
+        let Some(busy) = self.hash_worker.busy_for() else {
+            return;
+        };
+
+        if busy >= self.storage_timeout && !self.breaker.is_open() {
+            let retry_in = self.breaker.trip(self.clock.now());
+            self.storage_paused(
+                format!("write stuck for {:.1}s", busy.as_secs_f64()),
+                retry_in,
+            );
+        }
     }
 
-    pub fn get_piece_state(&self, piece_index: usize) -> Option<PieceState> {
-        if piece_index >= self.pieces.len() {
-            return None;
+    fn storage_failed(&mut self, reason: String) {
+        if let Some(retry_in) = self.breaker.record_failure(self.clock.now()) {
+            self.storage_paused(reason, retry_in);
+        }
+    }
+
+    fn storage_paused(&self, reason: String, retry_in: Duration) {
+        crate::log_line!(
+            "Storage : {} , pausing downloads for {}",
+            reason,
+            humanize::duration(retry_in)
+        );
+        if let Some(events) = &self.events {
+            events.publish(Event::StoragePaused { reason, retry_in });
         }
+    }
 
-        let piece = self.pieces[piece_index].lock().unwrap();
-        Some(piece.state.clone())
+    fn storage_ok(&mut self) {
+        if self.breaker.record_success() {
+            crate::log_line!("Storage : responding again , resuming downloads");
+            if let Some(events) = &self.events {
+                events.publish(Event::StorageResumed);
+            }
+        }
     }
 
-    pub fn get_stats(&self) -> DownloadStats {
-        self.stats.clone()
+    /// Files storage gave up writing (or was told to skip) , for the files pane
+    pub fn quarantined_files(&self) -> Vec<QuarantinedFile> {
+        lock_storage(&self.storage, self.storage_timeout)
+            .map(|storage| storage.quarantined_files())
+            .unwrap_or_default()
     }
 
-    pub fn is_download_complete(&self) -> bool {
-        self.stats.verified_pieces == self.stats.total_pieces
+    /// Skips a file of the torrent , pieces that lie only in it stop being downloaded
+    pub fn quarantine_file(
+        &mut self,
+        file_index: usize,
+        reason: &str,
+    ) -> Result<(), anyhow::Error> {
+        lock_storage(&self.storage, self.storage_timeout)?.quarantine_file(file_index, reason)?;
+        self.sync_quarantine();
+        Ok(())
     }
 
-    pub fn has_piece(&self, piece_index: usize) -> bool {
-        if piece_index >= self.pieces.len() {
-            return false;
+    /// Downloads a quarantined file again
+    pub fn release_file(&mut self, file_index: usize) -> Result<(), anyhow::Error> {
+        lock_storage(&self.storage, self.storage_timeout)?.release_file(file_index)?;
+        if self.quarantined.remove(&file_index) {
+            self.refresh_wanted();
         }
+        Ok(())
+    }
 
-        let piece = self.pieces[piece_index].lock().unwrap();
-        piece.state == PieceState::Verified
+    /// Picks up files storage quarantined on its own and stops wanting their pieces
+    ///
+    /// Doesn't wait on storage , a busy lock leaves it for the next `process_hash_results`
+    fn sync_quarantine(&mut self) {
+        let files = match try_lock_storage(&self.storage) {
+            Ok(Some(storage)) => storage.quarantined_files(),
+            Ok(None) => {
+                self.quarantine_stale = true;
+                return;
+            }
+            Err(_) => return,
+        };
+        self.quarantine_stale = false;
+
+        let mut changed = false;
+        for file in files {
+            if self.quarantined.insert(file.index) {
+                changed = true;
+                crate::log_line!("Skipping {} : {}", file.path.display(), file.reason);
+                if let Some(events) = &self.events {
+                    events.publish(Event::FileQuarantined {
+                        file_index: file.index,
+                        path: file.path,
+                        reason: file.reason,
+                    });
+                }
+            }
+        }
+
+        if changed {
+            self.refresh_wanted();
+        }
+    }
+
+    /// Changes how much a file is wanted. Pieces that only hold skipped files are never requested ,
+    /// pieces of higher priority files are started first
+    pub fn set_file_priority(
+        &mut self,
+        file_index: usize,
+        priority: FilePriority,
+    ) -> Result<(), anyhow::Error> {
+        if file_index >= self.file_priorities.len() {
+            return Err(anyhow!(
+                "File index {} out of range , torrent has {} files",
+                file_index,
+                self.file_priorities.len()
+            ));
+        }
+
+        lock_storage(&self.storage, self.storage_timeout)?
+            .set_file_priority(file_index, priority)?;
+        if self.file_priorities[file_index] != priority {
+            self.file_priorities[file_index] = priority;
+            self.refresh_wanted();
+        }
+        Ok(())
+    }
+
+    pub fn file_priorities(&self) -> &[FilePriority] {
+        &self.file_priorities
+    }
+
+    /// Highest priority among the files a piece holds bytes of
+    pub fn piece_priority(&self, piece_index: usize) -> FilePriority {
+        self.piece_priorities
+            .get(piece_index)
+            .copied()
+            .unwrap_or(FilePriority::Skip)
+    }
+
+    /// Every file of the torrent with its priority and verified pieces , for the files pane
+    pub fn file_progress(&self) -> Vec<FileProgress> {
+        let paths: Vec<PathBuf> = match &self.torrent.files {
+            Some(files) => files
+                .iter()
+                .map(|file| file.path.iter().collect())
+                .collect(),
+            None => vec![PathBuf::from(&self.torrent.name)],
+        };
+
+        paths
+            .into_iter()
+            .zip(self.file_spans())
+            .enumerate()
+            .map(|(index, (path, span))| {
+                let pieces = self.pieces_in(&span);
+                FileProgress {
+                    index,
+                    path,
+                    length: span.len(),
+                    priority: self.file_priorities[index],
+                    quarantined: self.quarantined.contains(&index),
+                    pieces_done: pieces
+                        .clone()
+                        .filter(|&piece| self.pieces[piece].state == PieceState::Verified)
+                        .count(),
+                    pieces_total: pieces.len(),
+                }
+            })
+            .collect()
+    }
+
+    /// Works out piece priorities and wanted pieces again from the files
+    fn refresh_wanted(&mut self) {
+        let mut priorities = vec![FilePriority::Skip; self.pieces.len()];
+        let spans = self.file_spans();
+        for (file_index, &priority) in self.file_priorities.iter().enumerate() {
+            if self.quarantined.contains(&file_index) {
+                continue;
+            }
+            for index in spans
+                .get(file_index)
+                .map_or(0..0, |span| self.pieces_in(span))
+            {
+                priorities[index] = priorities[index].max(priority);
+            }
+        }
+
+        let wanted = priorities.iter().map(|p| !p.is_skipped()).collect();
+        self.piece_priorities = priorities;
+        self.apply_wanted(wanted);
+    }
+
+    /// Byte range of every file in the torrent , in file order
+    fn file_spans(&self) -> Vec<Range<usize>> {
+        match &self.torrent.files {
+            Some(files) => {
+                let mut start = 0;
+                files
+                    .iter()
+                    .map(|file| {
+                        let span = start..start + file.length;
+                        start = span.end;
+                        span
+                    })
+                    .collect()
+            }
+            None => iter::once(0..self.torrent.length).collect(),
+        }
+    }
+
+    /// Whether part of a piece lands in a quarantined file
+    fn touches_quarantined(&self, piece_index: usize) -> bool {
+        if self.quarantined.is_empty() {
+            return false;
+        }
+        let spans = self.file_spans();
+        self.quarantined.iter().any(|&file| {
+            spans
+                .get(file)
+                .is_some_and(|span| self.pieces_in(span).contains(&piece_index))
+        })
+    }
+
+    /// Pieces holding at least one byte of a file's span , pieces shared with a neighbour included
+    fn pieces_in(&self, span: &Range<usize>) -> Range<usize> {
+        let piece_length = self.torrent.piece_length;
+        if piece_length == 0 || span.is_empty() {
+            return 0..0;
+        }
+
+        let first = span.start / piece_length;
+        let last = (span.end - 1) / piece_length + 1;
+        first..last.min(self.pieces.len())
+    }
+
+    /// Whether pieces are still waiting on the hash worker
+    pub fn has_pending_verifications(&self) -> bool {
+        !self.hashing.is_empty()
+    }
+
+    fn apply_hash_result(&mut self, result: HashResult) {
+        let piece_index = result.piece_index;
+        match result.outcome {
+            HashOutcome::StoredIntact => return self.stored_checked(piece_index, None),
+            HashOutcome::StoredCorrupt(reason) => {
+                return self.stored_checked(piece_index, Some(reason));
+            }
+            _ => {}
+        }
+        self.hashing.remove(&piece_index);
+
+        let Some(piece) = self.pieces.get_mut(piece_index) else {
+            return;
+        };
+
+        match &result.outcome {
+            HashOutcome::Verified => {
+                // Update state
+                piece.state = PieceState::Verified;
+                piece.release_buffers();
+                self.hashed_this_run.set(piece_index);
+                self.stats.completed_pieces += 1;
+                self.stats.verified_pieces += 1;
+                self.unannounced.push(piece_index);
+                self.interest_stale = true;
+
+                crate::log_line!(
+                    "Piece {}/{} verified and written ({:.2}%)",
+                    piece_index + 1,
+                    self.stats.total_pieces,
+                    self.stats.progress_percentage()
+                );
+            }
+            HashOutcome::HashMismatch => {
+                crate::log_line!("Piece {} failed hash verification, resetting", piece_index);
+                piece.state = PieceState::Failed;
+                self.stats.failed_pieces += 1;
+
+                // Reset piece for re-download
+                piece.reset();
+                self.download_queue.push_back(piece_index);
+            }
+            // The data is good , see write_failed
+            HashOutcome::WriteFailed(_) => {}
+            // Upload checks , handled above
+            HashOutcome::StoredIntact | HashOutcome::StoredCorrupt(_) => {}
+        }
+
+        // Writes tell the breaker whether storage works , a hash mismatch says nothing about the disk
+        match result.outcome {
+            HashOutcome::Verified => {
+                self.storage_ok();
+                self.write_retried(piece_index);
+            }
+            HashOutcome::WriteFailed(e) => {
+                self.storage_failed(e.clone());
+                self.write_failed(piece_index, e);
+            }
+            HashOutcome::HashMismatch
+            | HashOutcome::StoredIntact
+            | HashOutcome::StoredCorrupt(_) => {}
+        }
+
+        self.publish_stats();
+    }
+
+    /// Keeps a verified piece that couldn't be written for a retry , in memory or in the spool. Only
+    /// when neither works is it downloaded again
+    fn write_failed(&mut self, index: usize, error: String) {
+        let now = self.clock.now();
+        let length = self.pieces[index].length;
+        let plan = self
+            .write_retries
+            .failed(index, length, now, self.spool.is_some());
+
+        let spooled = match (plan, &self.spool) {
+            (RetryPlan::Spool(_), Some(spool)) if spool.contains(index) => Ok(()),
+            (RetryPlan::Spool(_), Some(spool)) => {
+                let piece = &self.pieces[index];
+                spool.write(&SpilledPiece {
+                    index,
+                    hash: piece.hash,
+                    blocks: piece
+                        .blocks
+                        .values()
+                        .map(|block| (block.info.begin, block.data.clone()))
+                        .collect(),
+                })
+            }
+            _ => Ok(()),
+        };
+
+        match (plan, spooled) {
+            (RetryPlan::Buffer(at), _) => crate::log_line!(
+                "Piece {} could not be written : {} , retrying in {}",
+                index,
+                error,
+                humanize::duration(at.saturating_duration_since(now))
+            ),
+            (RetryPlan::Spool(at), Ok(())) => {
+                self.pieces[index].release_buffers();
+                crate::log_line!(
+                    "Piece {} could not be written : {} , spooled , retrying in {}",
+                    index,
+                    error,
+                    humanize::duration(at.saturating_duration_since(now))
+                );
+            }
+            (RetryPlan::Spool(_), Err(e)) | (RetryPlan::GiveUp, Err(e)) => {
+                crate::log_line!("Piece {} could not be spooled : {}", index, e);
+                self.drop_write_retry(index);
+            }
+            (RetryPlan::GiveUp, Ok(())) => {
+                crate::log_line!(
+                    "Piece {} could not be written : {} , resetting",
+                    index,
+                    error
+                );
+                self.drop_write_retry(index);
+            }
+        }
+    }
+
+    /// A piece that waited for a write retry made it to storage
+    fn write_retried(&mut self, index: usize) {
+        if self.write_retries.remove(index) == Some(true)
+            && let Some(spool) = &self.spool
+            && let Err(e) = spool.remove(index)
+        {
+            crate::log_line!("Could not remove spooled piece {} : {}", index, e);
+        }
+    }
+
+    /// Gives up on writing a piece , it's downloaded again
+    fn drop_write_retry(&mut self, index: usize) {
+        if self.write_retries.remove(index) == Some(true)
+            && let Some(spool) = &self.spool
+        {
+            let _ = spool.remove(index);
+        }
+        let piece = &mut self.pieces[index];
+        if piece.state == PieceState::Complete {
+            piece.reset();
+            self.download_queue.push_back(index);
+        }
+    }
+
+    /// Hands pieces whose write retry is due back to the hash worker , returns how many
+    ///
+    /// Nothing is retried while the storage breaker is open
+    pub fn retry_writes(&mut self) -> usize {
+        let now = self.clock.now();
+        let due = self.write_retries.due(now);
+        if due.is_empty() || !self.breaker.allow(now) {
+            return 0;
+        }
+
+        let mut retried = 0;
+        for (index, spooled) in due {
+            if self.hashing.contains(&index) {
+                continue;
+            }
+            if self.pieces[index].state != PieceState::Complete {
+                self.write_retries.remove(index);
+                continue;
+            }
+            if spooled && self.pieces[index].blocks.is_empty() && !self.unspool(index, now) {
+                continue;
+            }
+            match self.verify_and_write_piece(index) {
+                Ok(()) => retried += 1,
+                Err(e) => crate::log_line!("Could not retry writing piece {} : {}", index, e),
+            }
+        }
+        retried
+    }
+
+    /// Reads a spooled piece back into memory , false (after giving up on it) when that fails
+    fn unspool(&mut self, index: usize, now: Instant) -> bool {
+        let Some(spool) = &self.spool else {
+            return false;
+        };
+        let spilled = match spool.read(index) {
+            Ok(spilled) if spilled.hash == self.pieces[index].hash => spilled,
+            Ok(_) => {
+                crate::log_line!("Spooled piece {} belongs to another torrent", index);
+                self.drop_write_retry(index);
+                return false;
+            }
+            Err(e) => {
+                crate::log_line!("Could not read spooled piece {} : {}", index, e);
+                self.drop_write_retry(index);
+                return false;
+            }
+        };
+
+        let piece = &mut self.pieces[index];
+        for (begin, data) in spilled.blocks {
+            let info = BlockInfo::new(index, begin, data.len());
+            piece.blocks.insert(
+                begin,
+                Block {
+                    info,
+                    data,
+                    received_at: now,
+                },
+            );
+        }
+        self.write_retries.unspooled(index, piece.length);
+        true
+    }
+
+    /// Verified pieces waiting for their write to be retried , and how many of them are spooled
+    pub fn pending_writes(&self) -> (usize, usize) {
+        (self.write_retries.len(), self.write_retries.spooled())
+    }
+
+    /// Releases the blocks of pieces that have been in progress for too long and moves them to the front of the queue
+    ///
+    /// Returns the indexes of the pieces that were reassigned
+    pub fn reassign_stalled_pieces(&mut self) -> Vec<usize> {
+        let now = self.clock.now();
+        let mut stalled = Vec::new();
+
+        for piece in &mut self.pieces {
+            if piece.is_stalled(now) {
+                piece.release_requests();
+                stalled.push(piece.index);
+            }
+        }
+
+        // Boost them , stalled pieces go before anything else in the queue
+        for &index in stalled.iter().rev() {
+            self.download_queue.retain(|&queued| queued != index);
+            self.download_queue.push_front(index);
+        }
+
+        stalled
+    }
+
+    /// None for backends that keep nothing on disk
+    fn spill_area(&self) -> Option<SpillArea> {
+        let storage = self.storage.lock().unwrap();
+        let dir = storage.download_dir()?;
+        Some(SpillArea::new(dir, &self.torrent.info_hash))
+    }
+
+    /// Writes the blocks of partially downloaded pieces to the spill area so they survive a restart
+    ///
+    /// Meant to be called on shutdown , returns how many pieces were spilled
+    pub fn flush_partial_pieces(&self) -> Result<usize, anyhow::Error> {
+        let Some(spill) = self.spill_area() else {
+            return Ok(0);
+        };
+        let mut flushed = 0;
+
+        for piece in &self.pieces {
+            if piece.blocks.is_empty() || piece.state == PieceState::Verified {
+                continue;
+            }
+
+            let blocks = piece
+                .blocks
+                .values()
+                .map(|block| (block.info.begin, block.data.clone()))
+                .collect();
+
+            spill.write(&SpilledPiece {
+                index: piece.index,
+                hash: piece.hash,
+                blocks,
+            })?;
+            flushed += 1;
+        }
+
+        Ok(flushed)
+    }
+
+    /// Puts spilled blocks back into their pieces , pieces that become complete get verified straight away
+    pub fn restore_partial_pieces(&mut self) -> Result<usize, anyhow::Error> {
+        let Some(spill) = self.spill_area() else {
+            return Ok(0);
+        };
+        self.restore_from(&spill)
+    }
+
+    /// Takes back the blocks in a spill area and empties it
+    fn restore_from(&mut self, spill: &SpillArea) -> Result<usize, anyhow::Error> {
+        let now = self.clock.now();
+        let mut restored = 0;
+        let mut completed = Vec::new();
+
+        for spilled in spill.read_all()? {
+            let Some(piece) = self.pieces.get_mut(spilled.index) else {
+                continue;
+            };
+
+            // Spills from another version of the torrent or for pieces we already have are useless
+            if piece.hash != spilled.hash || piece.state == PieceState::Verified {
+                continue;
+            }
+
+            for (begin, data) in spilled.blocks {
+                let info = BlockInfo::new(piece.index, begin, data.len());
+
+                // Only take blocks that still line up with the piece's block layout
+                if !piece.missing_blocks.contains(&info) {
+                    continue;
+                }
+
+                piece.add_block(
+                    Block {
+                        info,
+                        data,
+                        received_at: now,
+                    },
+                    now,
+                )?;
+            }
+
+            if piece.state == PieceState::Pending && !piece.blocks.is_empty() {
+                piece.state = PieceState::InProgress;
+            }
+            if piece.state == PieceState::Complete {
+                completed.push(piece.index);
+            }
+            restored += 1;
+        }
+
+        spill.clear()?;
+
+        for index in completed {
+            self.download_queue.retain(|&queued| queued != index);
+            if let Err(e) = self.verify_and_write_piece(index) {
+                crate::log_line!("Restored piece {} could not be verified : {}", index, e);
+            }
+        }
+
+        Ok(restored)
+    }
+
+    pub fn get_piece_state(&self, piece_index: usize) -> Option<PieceState> {
+        self.pieces
+            .get(piece_index)
+            .map(|piece| piece.state.clone())
+    }
+
+    /// Starts tracking a newly connected peer
+    pub fn add_peer(&mut self, addr: SocketAddr) -> &mut Peer {
+        let now = self.clock.now();
+        let violations = self.protocol_violations.get(&addr).copied().unwrap_or(0);
+        let piece_count = self.pieces.len();
+        self.peers.entry(addr).or_insert_with(|| {
+            let mut peer = Peer::new(addr, now).with_piece_count(piece_count);
+            peer.protocol_violations = violations;
+            peer
+        })
+    }
+
+    /// Stops tracking a peer , its upload slot is handed out on the next `rechoke`
+    pub fn remove_peer(&mut self, addr: &SocketAddr) -> Option<Peer> {
+        self.choker.remove(addr);
+        for holders in self.requested_from.values_mut() {
+            holders.retain(|peer| peer != addr);
+        }
+        self.pending_reads.retain(|read| read.peer != *addr);
+        for waiting in self.awaiting_check.values_mut() {
+            waiting.retain(|request| request.0 != *addr);
+        }
+        let peer = self.peers.remove(addr)?;
+        self.picker.remove_bitfield(&peer.bitfield);
+        Some(peer)
+    }
+
+    pub fn peer_mut(&mut self, addr: &SocketAddr) -> Option<&mut Peer> {
+        self.peers.get_mut(addr)
+    }
+
+    /// Takes a peer's Bitfield message
+    ///
+    /// A payload that doesn't fit the torrent is a protocol violation , the peer is dropped and the
+    /// error tells the caller to close the connection
+    pub fn handle_bitfield(&mut self, addr: &SocketAddr, bits: &[u8]) -> Result<(), anyhow::Error> {
+        let bitfield = match Bitfield::from_bytes(bits, self.pieces.len()) {
+            Ok(bitfield) => bitfield,
+            Err(e) => return Err(self.protocol_violation(addr, e.to_string())),
+        };
+
+        if let Some(peer) = self.peers.get_mut(addr) {
+            self.picker.remove_bitfield(&peer.bitfield);
+            self.picker.add_bitfield(&bitfield);
+            peer.set_bitfield(bitfield);
+        }
+        Ok(())
+    }
+
+    /// Takes a peer's Have message , an index past the last piece is a protocol violation
+    pub fn handle_have(&mut self, addr: &SocketAddr, index: usize) -> Result<(), anyhow::Error> {
+        if index >= self.pieces.len() {
+            let reason = format!(
+                "have for piece {} , torrent has {}",
+                index,
+                self.pieces.len()
+            );
+            return Err(self.protocol_violation(addr, reason));
+        }
+
+        if let Some(peer) = self.peers.get_mut(addr)
+            && peer.set_have(index)
+        {
+            self.picker.add_have(index);
+        }
+        Ok(())
+    }
+
+    /// Applies a message read off a peer's connection , returns messages to send in reply
+    ///
+    /// Replies can go to other peers too (Cancels for endgame duplicates). An error means the peer
+    /// broke the protocol and the connection should be closed
+    pub fn handle_message(
+        &mut self,
+        from: SocketAddr,
+        message: PeerMessage,
+    ) -> Result<Vec<(SocketAddr, PeerMessage)>, anyhow::Error> {
+        let now = self.clock.now();
+        let mut replies = Vec::new();
+        if let Some(peer) = self.peers.get_mut(&from) {
+            peer.record_received(&message, now);
+        }
+
+        match message {
+            PeerMessage::KeepAlive | PeerMessage::Port(_) => {}
+            PeerMessage::Choke | PeerMessage::Unchoke => {
+                if let Some(peer) = self.peers.get_mut(&from) {
+                    peer.peer_choking = message == PeerMessage::Choke;
+                }
+            }
+            PeerMessage::Interested | PeerMessage::NotInterested => {
+                let interested = message == PeerMessage::Interested;
+                if let Some(peer) = self.peers.get_mut(&from) {
+                    peer.peer_interested = interested;
+                }
+                if interested {
+                    replies.extend(self.choker.interested(&mut self.peers, from));
+                } else {
+                    replies.extend(self.choker.not_interested(&mut self.peers, from));
+                }
+            }
+            PeerMessage::Have { index } => {
+                self.handle_have(&from, index as usize)?;
+                replies.extend(self.update_interest(&from).map(|message| (from, message)));
+            }
+            PeerMessage::Bitfield(bits) => {
+                self.handle_bitfield(&from, &bits)?;
+                replies.extend(self.update_interest(&from).map(|message| (from, message)));
+            }
+            PeerMessage::Request {
+                index,
+                begin,
+                length,
+            } => self.serve_request(&from, index, begin, length),
+            PeerMessage::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                self.stats.total_downloaded += block.len() as u64;
+                if let Some(peer) = self.peers.get_mut(&from) {
+                    peer.downloaded += block.len() as u64;
+                    if peer.is_lan() {
+                        self.stats.lan_downloaded_bytes += block.len() as u64;
+                    }
+                }
+                let info = BlockInfo::new(index as usize, begin as usize, block.len());
+                let block = Block {
+                    info,
+                    data: block.to_vec(),
+                    received_at: now,
+                };
+
+                for peer in self.handle_block_from(from, block)? {
+                    let cancel = PeerMessage::Cancel {
+                        index,
+                        begin,
+                        length: info.length as u32,
+                    };
+                    replies.push((peer, cancel));
+                }
+            }
+            // Only reads still queued on the disk thread (or waiting for a check) can be called off
+            PeerMessage::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                self.pending_reads.retain(|read| {
+                    (read.peer, read.index, read.begin, read.length) != (from, index, begin, length)
+                });
+                if let Some(waiting) = self.awaiting_check.get_mut(&(index as usize)) {
+                    waiting.retain(|request| *request != (from, index, begin, length));
+                }
+            }
+            PeerMessage::Extended {
+                id: LOCAL_UT_METADATA_ID,
+                payload,
+            } => {
+                // Peers on a magnet link asking for our info dictionary
+                let remote_id = self.peers.get(&from).and_then(|peer| peer.ut_metadata_id);
+                if let (Some(remote_id), Ok(MetadataMessage::Request { piece })) =
+                    (remote_id, MetadataMessage::decode(&payload))
+                {
+                    let reply = serve_metadata(&self.torrent.info_bytes, piece);
+                    replies.push((from, reply.to_message(remote_id)));
+                }
+            }
+            PeerMessage::Extended { id, payload } => {
+                if let Some(peer) = self.peers.get_mut(&from) {
+                    peer.handle_extended(id, &payload);
+                }
+            }
+        }
+
+        Ok(replies)
+    }
+
+    /// Whether `addr` has a piece we want and don't have yet
+    pub fn is_interesting(&self, addr: &SocketAddr) -> bool {
+        self.peers.get(addr).is_some_and(|peer| {
+            peer.bitfield.ones().any(|index| {
+                self.is_wanted(index) && self.pieces[index].state != PieceState::Verified
+            })
+        })
+    }
+
+    /// Interested or NotInterested for `addr` when our interest in it changed , None otherwise
+    pub fn update_interest(&mut self, addr: &SocketAddr) -> Option<PeerMessage> {
+        let interested = self.is_interesting(addr);
+        let peer = self.peers.get_mut(addr)?;
+        if peer.am_interested == interested {
+            return None;
+        }
+
+        peer.am_interested = interested;
+        Some(if interested {
+            PeerMessage::Interested
+        } else {
+            PeerMessage::NotInterested
+        })
+    }
+
+    /// Messages that keep peers up to date with us , call after `process_hash_results`
+    ///
+    /// Every piece verified since the last call goes out as a Have to each peer that doesn't have
+    /// it yet , and peers that no longer have anything we want are told we're not interested
+    pub fn announcements(&mut self) -> Vec<(SocketAddr, PeerMessage)> {
+        let mut messages = Vec::new();
+        let verified = std::mem::take(&mut self.unannounced);
+        for (addr, peer) in &self.peers {
+            for &index in &verified {
+                if !peer.has_piece(index) {
+                    messages.push((
+                        *addr,
+                        PeerMessage::Have {
+                            index: index as u32,
+                        },
+                    ));
+                }
+            }
+        }
+
+        if std::mem::take(&mut self.interest_stale) {
+            let addrs: Vec<SocketAddr> = self.peers.keys().copied().collect();
+            for addr in addrs {
+                if let Some(message) = self.update_interest(&addr) {
+                    messages.push((addr, message));
+                }
+            }
+        }
+        messages
+    }
+
+    /// Queues a requested block for an unchoked peer on the disk thread , `finished_reads` sends it
+    ///
+    /// Requests we won't serve are dropped , and so are requests while the disk queue is full
+    fn serve_request(&mut self, from: &SocketAddr, index: u32, begin: u32, length: u32) {
+        let Some(peer) = self.peers.get(from) else {
+            return;
+        };
+        let Some(piece) = self.pieces.get(index as usize) else {
+            return;
+        };
+        let fits = (begin as usize)
+            .checked_add(length as usize)
+            .is_some_and(|end| end <= piece.length);
+        if peer.am_choking
+            || piece.state != PieceState::Verified
+            || !fits
+            || length as usize > MAX_BLOCK_SIZE
+        {
+            return;
+        }
+        // Storage skipped the quarantined file's bytes , the piece verified in memory but never
+        // made it to disk whole
+        if self.touches_quarantined(index as usize) {
+            return;
+        }
+        if !self.hashed_this_run.has(index as usize) {
+            self.check_before_upload(*from, index, begin, length);
+            return;
+        }
+
+        let Ok(reply) = self
+            .disk
+            .try_read_piece(index as usize, begin as usize, length as usize)
+        else {
+            return;
+        };
+        self.pending_reads.push(PendingRead {
+            peer: *from,
+            index,
+            begin,
+            length,
+            reply,
+        });
+    }
+
+    /// Blocks the disk thread finished reading , as Piece messages for the peers that asked
+    ///
+    /// Peers that went away or got choked meanwhile are skipped , so are failed reads
+    pub fn finished_reads(&mut self) -> Vec<(SocketAddr, PeerMessage)> {
+        let mut messages = Vec::new();
+        let mut index = 0;
+        while index < self.pending_reads.len() {
+            let Some(result) = self.pending_reads[index].reply.try_take() else {
+                index += 1;
+                continue;
+            };
+            let read = self.pending_reads.remove(index);
+            let Ok(data) = result else {
+                continue;
+            };
+
+            let now = self.clock.now();
+            let Some(peer) = self.peers.get_mut(&read.peer) else {
+                continue;
+            };
+            if peer.am_choking {
+                continue;
+            }
+            peer.uploaded += data.len() as u64;
+            if peer.is_lan() {
+                self.stats.lan_uploaded_bytes += data.len() as u64;
+            }
+            self.stats.uploaded_bytes += data.len() as u64;
+            self.stats.last_upload = Some(now);
+            messages.push((
+                read.peer,
+                PeerMessage::Piece {
+                    index: read.index,
+                    begin: read.begin,
+                    block: data.into(),
+                },
+            ));
+        }
+
+        if !messages.is_empty() {
+            self.publish_stats();
+        }
+        messages
+    }
+
+    /// Notes a message on its way to a peer , for the peer's liveness stats and keep-alives
+    pub fn message_sent(&mut self, addr: &SocketAddr, message: &PeerMessage) {
+        let now = self.clock.now();
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.record_sent(message, now);
+        }
+    }
+
+    /// Keep-alives for peers we sent nothing to for `KEEPALIVE_INTERVAL`
+    pub fn keepalives(&self) -> Vec<(SocketAddr, PeerMessage)> {
+        let now = self.clock.now();
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.needs_keepalive(now))
+            .map(|(addr, _)| (*addr, PeerMessage::KeepAlive))
+            .collect()
+    }
+
+    /// Picks up after a system suspend , returns a keep-alive for every peer
+    ///
+    /// Request and snub timers start over instead of all running out at once , the keep-alives
+    /// find out which connections didn't survive the sleep
+    pub fn resumed(&mut self) -> Vec<(SocketAddr, PeerMessage)> {
+        let now = self.clock.now();
+        for piece in &mut self.pieces {
+            piece.restart_timers(now);
+        }
+        for peer in self.peers.values_mut() {
+            peer.resumed(now);
+        }
+        self.peers
+            .keys()
+            .map(|addr| (*addr, PeerMessage::KeepAlive))
+            .collect()
+    }
+
+    /// Reads queued on the disk thread for uploads
+    pub fn pending_reads(&self) -> usize {
+        self.pending_reads.len()
+    }
+
+    /// Holds a request for a piece taken on trust from resume data until the hash worker read it
+    /// back , the first request for the piece queues the check. See `upload_checked`
+    fn check_before_upload(&mut self, from: SocketAddr, index: u32, begin: u32, length: u32) {
+        let piece_index = index as usize;
+        if self.submit_check(piece_index).is_err() {
+            return;
+        }
+        self.awaiting_check
+            .entry(piece_index)
+            .or_default()
+            .push((from, index, begin, length));
+    }
+
+    /// Has the hash worker read a stored piece back , unless it's already on it for an upload or the
+    /// scrub. Fails once the worker has stopped
+    fn submit_check(&mut self, piece_index: usize) -> Result<(), anyhow::Error> {
+        if self.awaiting_check.contains_key(&piece_index) || self.scrubbing.contains(&piece_index) {
+            return Ok(());
+        }
+
+        let piece = &self.pieces[piece_index];
+        let job = CheckJob {
+            piece_index,
+            length: piece.length,
+            hash: piece.hash,
+            merkle: self
+                .merkle
+                .as_deref()
+                .and_then(|pieces| pieces.get(piece_index))
+                .cloned(),
+        };
+        self.hash_worker.check(job)
+    }
+
+    /// The hash worker read a piece back for the scrub or `check_before_upload` , the requests waiting
+    /// on it are served when it matched
+    fn stored_checked(&mut self, index: usize, corrupt: Option<String>) {
+        let scrubbed = self.scrubbing.remove(&index);
+        let waiting = self.awaiting_check.remove(&index).unwrap_or_default();
+        match corrupt {
+            None => {
+                self.hashed_this_run.set(index);
+                for (from, index, begin, length) in waiting {
+                    self.serve_request(&from, index, begin, length);
+                }
+            }
+            Some(reason) if scrubbed => {
+                self.scrub_failures.push(index);
+                self.piece_corrupted(index, reason, CorruptionCheck::Scrub);
+            }
+            Some(reason) => self.piece_corrupted(index, reason, CorruptionCheck::Upload),
+        }
+    }
+
+    /// Counts the violation against the peer and drops it
+    fn protocol_violation(&mut self, addr: &SocketAddr, reason: String) -> anyhow::Error {
+        let count = self.protocol_violations.entry(*addr).or_insert(0);
+        *count += 1;
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.protocol_violations = *count;
+        }
+
+        crate::log_line!(
+            "Peer {} violated the protocol ({}) , disconnecting",
+            addr,
+            reason
+        );
+        self.peers.remove(addr);
+        anyhow!("Peer {} violated the protocol : {}", addr, reason)
+    }
+
+    /// Protocol violations per peer address , kept after the peer is gone for diagnostics
+    pub fn protocol_violations(&self) -> &HashMap<SocketAddr, u32> {
+        &self.protocol_violations
+    }
+
+    /// Copies of every peer's state , safe to hold on to while rendering
+    pub fn peer_snapshots(&self) -> Vec<PeerSnapshot> {
+        self.peers
+            .values()
+            .map(|peer| peer.snapshot(self.pieces.len(), self.clock.now()))
+            .collect()
+    }
+
+    /// Outstanding requests and waiting uploads per peer , sorted by address
+    ///
+    /// Only what the engine knows , the connection fields are filled in by `PeerManager::diagnostics`
+    pub fn peer_diagnostics(&self) -> Vec<PeerDiagnostics> {
+        let now = self.clock.now();
+        let mut diagnostics: Vec<PeerDiagnostics> = self
+            .peers
+            .iter()
+            .map(|(addr, peer)| PeerDiagnostics {
+                peer_choking: peer.peer_choking,
+                snubbed: peer.is_snubbed(now),
+                ..PeerDiagnostics::new(*addr)
+            })
+            .collect();
+        diagnostics.sort_by_key(|peer| peer.addr);
+
+        for piece in &self.pieces {
+            for (block, sent) in &piece.requested_blocks {
+                let Some(holders) = self.requested_from.get(block) else {
+                    continue;
+                };
+                for holder in holders {
+                    if let Ok(at) = diagnostics.binary_search_by_key(holder, |peer| peer.addr) {
+                        diagnostics[at].outstanding.push(OutstandingRequest {
+                            piece_index: block.piece_index,
+                            begin: block.begin,
+                            length: block.length,
+                            age: now.saturating_duration_since(*sent),
+                        });
+                    }
+                }
+            }
+        }
+        for read in &self.pending_reads {
+            if let Ok(at) = diagnostics.binary_search_by_key(&read.peer, |peer| peer.addr) {
+                diagnostics[at].pending_uploads += 1;
+            }
+        }
+        for peer in &mut diagnostics {
+            peer.outstanding
+                .sort_by_key(|request| (Reverse(request.age), request.piece_index, request.begin));
+        }
+
+        diagnostics
+    }
+
+    /// Seeds , leechers and top uploaders among the connected peers
+    ///
+    /// Discovery counts live with the torrent , add them with `SwarmHealth::with_known_sources`
+    pub fn swarm_health(&self) -> SwarmHealth {
+        let total = self.pieces.len();
+        let mut health = SwarmHealth::default();
+        let mut progress = 0.0;
+
+        for peer in self.peers.values() {
+            let snapshot = peer.snapshot(total, self.clock.now());
+            if snapshot.is_seed || peer.upload_only {
+                health.seeds += 1;
+            } else {
+                health.leechers += 1;
+            }
+            progress += snapshot.progress;
+        }
+        if !self.peers.is_empty() {
+            health.average_progress = progress / self.peers.len() as f64;
+        }
+
+        let mut top: Vec<&Peer> = self
+            .peers
+            .values()
+            .filter(|peer| peer.downloaded > 0)
+            .collect();
+        top.sort_by(|a, b| b.downloaded.cmp(&a.downloaded).then(a.addr.cmp(&b.addr)));
+        health.top_peers = top
+            .into_iter()
+            .take(TOP_PEERS)
+            .map(|peer| TopPeer {
+                addr: peer.addr,
+                client: peer.client.clone(),
+                downloaded: peer.downloaded,
+                location: None,
+            })
+            .collect();
+
+        health
+    }
+
+    /// Bytes of blocks held in memory for pieces that aren't complete yet
+    pub fn buffered_bytes(&self) -> usize {
+        self.pieces.iter().map(Piece::received_bytes).sum()
+    }
+
+    /// Per piece counts of the connected peers that have it , plus our own pieces
+    pub fn piece_availability(&self) -> PieceAvailability {
+        PieceAvailability {
+            counts: self.picker.availability().to_vec(),
+            ours: self.our_bitfield().into_bytes(),
+            peers: self.peers.len(),
+        }
+    }
+
+    /// Pieces we have
+    pub fn our_bitfield(&self) -> Bitfield {
+        self.pieces
+            .iter()
+            .map(|piece| piece.state == PieceState::Verified)
+            .collect()
+    }
+
+    /// Whether we only upload now , i.e have every piece we want (BEP 21)
+    pub fn is_upload_only(&self) -> bool {
+        self.pieces
+            .iter()
+            .zip(&self.wanted)
+            .all(|(piece, &wanted)| !wanted || piece.state == PieceState::Verified)
+    }
+
+    /// Done with what we want but missing pieces we skipped , announced as a partial seed
+    pub fn is_partial_seed(&self) -> bool {
+        self.is_upload_only() && !self.is_download_complete()
+    }
+
+    /// Extension handshake for a new connection , carries our upload-only state
+    pub fn extended_handshake(&mut self, listen_port: u16) -> ExtendedHandshake {
+        self.advertised_upload_only = self.is_upload_only();
+        ExtendedHandshake::ours(self.advertised_upload_only, listen_port)
+            .with_metadata_size(self.torrent.info_bytes.len())
+    }
+
+    /// Messages that open a connection once the handshakes are done
+    ///
+    /// Our Bitfield goes first when we have any piece , so peers know what they can ask for.
+    /// The extension handshake goes out only when both sides set the extension protocol bit and the
+    /// Port message only when both set the DHT bit , strict clients drop peers that send messages
+    /// they never advertised. `dht_port` is None when our DHT isn't running
+    pub fn greeting(
+        &mut self,
+        shared: Extensions,
+        listen_port: u16,
+        dht_port: Option<u16>,
+    ) -> Vec<PeerMessage> {
+        let mut messages = Vec::new();
+        if self.stats.verified_pieces > 0 {
+            messages.push(self.our_bitfield().to_message());
+        }
+        if shared.extension_protocol {
+            messages.push(self.extended_handshake(listen_port).to_message());
+        }
+        if shared.dht
+            && let Some(port) = dht_port
+        {
+            messages.push(PeerMessage::Port(port));
+        }
+        messages
+    }
+
+    /// upload_only messages to send when our state changed since peers were last told , empty otherwise
+    pub fn upload_only_updates(&mut self) -> Vec<(SocketAddr, PeerMessage)> {
+        let upload_only = self.is_upload_only();
+        if upload_only == self.advertised_upload_only {
+            return Vec::new();
+        }
+        self.advertised_upload_only = upload_only;
+
+        self.peers
+            .values()
+            .filter_map(|peer| {
+                let id = peer.upload_only_id?;
+                Some((peer.addr, upload_only_message(id, upload_only)))
+            })
+            .collect()
+    }
+
+    /// Upload slots for this torrent , takes effect on the next `rechoke`
+    pub fn with_upload_slots(mut self, slots: usize) -> Self {
+        self.set_upload_slots(slots);
+        self
+    }
+
+    pub fn set_upload_slots(&mut self, slots: usize) {
+        self.choker.upload_slots = slots;
+    }
+
+    pub fn upload_slots(&self) -> usize {
+        self.choker.upload_slots
+    }
+
+    /// Slot usage as of the last rechoke
+    pub fn slot_stats(&self) -> SlotStats {
+        self.stats.slots
+    }
+
+    /// Hands out upload slots again and measures peer rates , run it every RECHOKE_INTERVAL
+    ///
+    /// Once we have everything we want the slots go to the peers we upload to fastest
+    pub fn rechoke(&mut self) -> Vec<(SocketAddr, PeerMessage)> {
+        let now = self.clock.now();
+        let seeding = self.is_upload_only();
+        let mut messages = self.choker.rechoke(&mut self.peers, seeding, now);
+        messages.extend(self.choker.fill(&mut self.peers));
+        self.stats.slots = self.choker.snapshot(&self.peers);
+        self.publish_stats();
+        messages
+    }
+
+    /// Peers we have nothing to trade with , they're upload-only and so are we
+    pub fn redundant_peers(&self) -> Vec<SocketAddr> {
+        if !self.is_upload_only() {
+            return Vec::new();
+        }
+
+        self.peers
+            .values()
+            .filter(|peer| peer.upload_only)
+            .map(|peer| peer.addr)
+            .collect()
+    }
+
+    pub fn get_stats(&self) -> DownloadStats {
+        self.stats.clone()
+    }
+
+    /// Subscribes to stats snapshots , meant for the UI and other readers that shouldn't block downloading
+    pub fn subscribe_stats(&self) -> StatsReceiver {
+        self.stats_tx.subscribe()
+    }
+
+    fn publish_stats(&self) {
+        // send_replace works even when nobody is subscribed
+        self.stats_tx.send_replace(self.stats.clone());
+    }
+
+    pub fn is_download_complete(&self) -> bool {
+        self.stats.verified_pieces == self.stats.total_pieces
+    }
+
+    pub fn has_piece(&self, piece_index: usize) -> bool {
+        self.pieces
+            .get(piece_index)
+            .is_some_and(|piece| piece.state == PieceState::Verified)
     }
 
     pub fn get_missing_piece_count(&self) -> usize {
@@ -0,0 +1,294 @@
+use crate::{core::peer::Peer, protocol::message::PeerMessage, util::humanize};
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Peers we upload to at once per torrent
+pub const DEFAULT_UPLOAD_SLOTS: usize = 4;
+
+/// How often `rechoke` should run , rates are measured over this window
+pub const RECHOKE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Rechokes an optimistic unchoke lasts before another peer gets the slot
+pub const OPTIMISTIC_UNCHOKE_ROUNDS: u32 = 3;
+
+/// How the upload slots are being used , for seeders tuning how many to open
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SlotStats {
+    pub upload_slots: usize,
+    /// Peers we upload to right now
+    pub in_use: usize,
+    /// Bytes served between the last two rechokes
+    pub round_uploaded: u64,
+    /// Peers unchoked during that round
+    pub round_slots: usize,
+    pub round_length: Duration,
+    /// Optimistic unchokes handed out
+    pub optimistic_unchokes: u64,
+    /// Optimistically unchoked peers that went on to earn a regular slot
+    pub optimistic_hits: u64,
+}
+
+impl SlotStats {
+    /// Bytes each open slot served last round , None before the first full round
+    pub fn bytes_per_slot(&self) -> Option<u64> {
+        if self.round_length.is_zero() {
+            return None;
+        }
+        Some(self.round_uploaded / self.round_slots.max(1) as u64)
+    }
+
+    /// Share of optimistic unchokes that turned into regular slots , None before the first one
+    pub fn optimistic_hit_rate(&self) -> Option<f64> {
+        (self.optimistic_unchokes > 0)
+            .then(|| self.optimistic_hits as f64 / self.optimistic_unchokes as f64)
+    }
+}
+
+impl fmt::Display for SlotStats {
+    /// e.g "3/4 slots in use , 1.2 MiB per slot last round , optimistic hits 2/5 (40%)"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} slots in use", self.in_use, self.upload_slots)?;
+        match self.bytes_per_slot() {
+            Some(bytes) => write!(f, " , {} per slot last round", humanize::bytes(bytes))?,
+            None => write!(f, " , no full round yet")?,
+        }
+        write!(
+            f,
+            " , optimistic hits {}/{}",
+            self.optimistic_hits, self.optimistic_unchokes
+        )?;
+        if let Some(rate) = self.optimistic_hit_rate() {
+            write!(f, " ({:.0}%)", rate * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decides which interested peers we upload to
+///
+/// Every rechoke the fastest peers keep their slots , measured by what they send us while we
+/// download and by what we send them while seeding. One slot goes to a random interested peer for a
+/// few rounds (optimistic unchoke) so newcomers with nothing to trade still get started
+#[derive(Debug, Clone)]
+pub struct Choker {
+    pub upload_slots: usize,
+    optimistic: Option<SocketAddr>,
+    optimistic_rounds: u32,
+    last_rechoke: Option<Instant>,
+    /// (downloaded , uploaded) per peer at the last rechoke , rates are worked out from the difference
+    counters: HashMap<SocketAddr, (u64, u64)>,
+    /// The current optimistic peer already counted as a hit
+    optimistic_scored: bool,
+    stats: SlotStats,
+}
+
+impl Default for Choker {
+    fn default() -> Self {
+        Self::new(DEFAULT_UPLOAD_SLOTS)
+    }
+}
+
+impl Choker {
+    pub fn new(upload_slots: usize) -> Self {
+        Self {
+            upload_slots,
+            optimistic: None,
+            optimistic_rounds: 0,
+            last_rechoke: None,
+            counters: HashMap::new(),
+            optimistic_scored: false,
+            stats: SlotStats::default(),
+        }
+    }
+
+    /// Peer we're optimistically unchoking , if any
+    pub fn optimistic(&self) -> Option<SocketAddr> {
+        self.optimistic
+    }
+
+    /// Takes down slot usage once a rechoke is done , readers get this copy instead of walking
+    /// the peers on every stats update
+    pub fn snapshot(&mut self, peers: &HashMap<SocketAddr, Peer>) -> SlotStats {
+        self.stats.upload_slots = self.upload_slots;
+        self.stats.in_use = Self::unchoked(peers);
+        self.stats
+    }
+
+    /// Peers we currently upload to
+    pub fn unchoked(peers: &HashMap<SocketAddr, Peer>) -> usize {
+        peers.values().filter(|peer| !peer.am_choking).count()
+    }
+
+    /// A peer became interested , it's unchoked straight away when a slot is free
+    pub fn interested(
+        &mut self,
+        peers: &mut HashMap<SocketAddr, Peer>,
+        addr: SocketAddr,
+    ) -> Vec<(SocketAddr, PeerMessage)> {
+        if Self::unchoked(peers) >= self.upload_slots {
+            return Vec::new();
+        }
+        set_choking(peers, addr, false).into_iter().collect()
+    }
+
+    /// A peer lost interest , its slot goes to the next interested peer
+    pub fn not_interested(
+        &mut self,
+        peers: &mut HashMap<SocketAddr, Peer>,
+        addr: SocketAddr,
+    ) -> Vec<(SocketAddr, PeerMessage)> {
+        let mut messages: Vec<_> = set_choking(peers, addr, true).into_iter().collect();
+        messages.extend(self.fill(peers));
+        messages
+    }
+
+    /// Forgets a disconnected peer , its slot is handed out on the next `fill` or rechoke
+    pub fn remove(&mut self, addr: &SocketAddr) {
+        self.counters.remove(addr);
+        if self.optimistic == Some(*addr) {
+            self.optimistic = None;
+        }
+    }
+
+    /// Unchokes interested peers while slots are free , fastest first
+    pub fn fill(
+        &mut self,
+        peers: &mut HashMap<SocketAddr, Peer>,
+    ) -> Vec<(SocketAddr, PeerMessage)> {
+        let free = self.upload_slots.saturating_sub(Self::unchoked(peers));
+        let waiting: Vec<SocketAddr> = ranked(peers, false)
+            .into_iter()
+            .filter(|addr| peers[addr].am_choking)
+            .take(free)
+            .collect();
+
+        waiting
+            .into_iter()
+            .filter_map(|addr| set_choking(peers, addr, false))
+            .collect()
+    }
+
+    /// Measures peer rates and hands out the upload slots again , meant to run every RECHOKE_INTERVAL
+    ///
+    /// `seeding` ranks peers by how fast we upload to them instead of how fast they upload to us.
+    /// Returns the Choke and Unchoke messages to send
+    pub fn rechoke(
+        &mut self,
+        peers: &mut HashMap<SocketAddr, Peer>,
+        seeding: bool,
+        now: Instant,
+    ) -> Vec<(SocketAddr, PeerMessage)> {
+        self.measure(peers, now);
+
+        let ranked = ranked(peers, seeding);
+        let regular = self.upload_slots.saturating_sub(1);
+        let mut chosen: Vec<SocketAddr> = ranked.iter().copied().take(regular).collect();
+
+        if let Some(addr) = self.optimistic
+            && !self.optimistic_scored
+            && chosen.contains(&addr)
+        {
+            self.stats.optimistic_hits += 1;
+            self.optimistic_scored = true;
+        }
+
+        self.optimistic_rounds += 1;
+        let keep_optimistic = self.optimistic.is_some_and(|addr| {
+            self.optimistic_rounds < OPTIMISTIC_UNCHOKE_ROUNDS
+                && !chosen.contains(&addr)
+                && peers.get(&addr).is_some_and(|peer| peer.peer_interested)
+        });
+        if !keep_optimistic {
+            let state = RandomState::new();
+            self.optimistic = ranked
+                .iter()
+                .filter(|addr| !chosen.contains(addr))
+                .min_by_key(|addr| state.hash_one(addr))
+                .copied();
+            self.optimistic_rounds = 0;
+            self.optimistic_scored = false;
+            if self.optimistic.is_some() {
+                self.stats.optimistic_unchokes += 1;
+            }
+        }
+        if self.upload_slots > 0
+            && let Some(addr) = self.optimistic
+        {
+            chosen.push(addr);
+        }
+
+        let addrs: Vec<SocketAddr> = peers.keys().copied().collect();
+        addrs
+            .into_iter()
+            .filter_map(|addr| set_choking(peers, addr, !chosen.contains(&addr)))
+            .collect()
+    }
+
+    /// Turns the byte counters into rates over the time since the last rechoke
+    fn measure(&mut self, peers: &mut HashMap<SocketAddr, Peer>, now: Instant) {
+        let elapsed = self
+            .last_rechoke
+            .map(|last| now.saturating_duration_since(last).as_secs_f64())
+            .unwrap_or(0.0);
+        self.last_rechoke = Some(now);
+
+        // Slots as they were through the round , before this rechoke hands them out again
+        let round_slots = Self::unchoked(peers);
+        let mut round_uploaded = 0;
+        for peer in peers.values_mut() {
+            let (downloaded, uploaded) = self
+                .counters
+                .insert(peer.addr, (peer.downloaded, peer.uploaded))
+                .unwrap_or((peer.downloaded, peer.uploaded));
+            round_uploaded += peer.uploaded.saturating_sub(uploaded);
+            if elapsed > 0.0 {
+                peer.download_rate = peer.downloaded.saturating_sub(downloaded) as f64 / elapsed;
+                peer.upload_rate = peer.uploaded.saturating_sub(uploaded) as f64 / elapsed;
+            }
+        }
+        self.counters.retain(|addr, _| peers.contains_key(addr));
+
+        if elapsed > 0.0 {
+            self.stats.round_uploaded = round_uploaded;
+            self.stats.round_slots = round_slots;
+            self.stats.round_length = Duration::from_secs_f64(elapsed);
+        }
+    }
+}
+
+/// Interested peers , fastest first
+fn ranked(peers: &HashMap<SocketAddr, Peer>, seeding: bool) -> Vec<SocketAddr> {
+    let mut interested: Vec<&Peer> = peers.values().filter(|peer| peer.peer_interested).collect();
+    let rate = |peer: &Peer| {
+        if seeding {
+            peer.upload_rate
+        } else {
+            peer.download_rate
+        }
+    };
+    interested.sort_by(|a, b| rate(b).total_cmp(&rate(a)).then(a.addr.cmp(&b.addr)));
+    interested.into_iter().map(|peer| peer.addr).collect()
+}
+
+/// Flips our choke state for a peer , the message to tell it or None if nothing changed
+fn set_choking(
+    peers: &mut HashMap<SocketAddr, Peer>,
+    addr: SocketAddr,
+    choking: bool,
+) -> Option<(SocketAddr, PeerMessage)> {
+    let peer = peers.get_mut(&addr)?;
+    if peer.am_choking == choking {
+        return None;
+    }
+    peer.am_choking = choking;
+    let message = if choking {
+        PeerMessage::Choke
+    } else {
+        PeerMessage::Unchoke
+    };
+    Some((addr, message))
+}
@@ -0,0 +1,491 @@
+use crate::{
+    core::{
+        bind::BindTarget,
+        runtime::{PeerStream, Runtime, TokioRuntime, timeout},
+    },
+    net::{
+        encryption::ConnectMode,
+        mse::{CRYPTO_RC4, EncryptedStream, KEY_LEN, KeyPair, MAX_PAD, Rc4, VC, hash, random_pad},
+    },
+    protocol::{
+        handshake::{HANDSHAKE_LEN, Handshake},
+        message::{read_exact, write_all},
+    },
+};
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+/// How long connecting plus both handshakes may take
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where connection setup is , errors say which step failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupStage {
+    Connecting,
+    /// MSE key exchange and negotiation , before the BitTorrent handshake
+    Encrypting,
+    SendingHandshake,
+    AwaitingHandshake,
+    /// Both handshakes went through , peer wire messages follow
+    Established,
+}
+
+impl fmt::Display for SetupStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SetupStage::Connecting => "connecting",
+            SetupStage::Encrypting => "negotiating encryption",
+            SetupStage::SendingHandshake => "sending handshake",
+            SetupStage::AwaitingHandshake => "waiting for handshake",
+            SetupStage::Established => "established",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A connection past the handshake , ready for peer wire messages
+#[derive(Debug)]
+pub struct Established {
+    pub stream: Box<dyn PeerStream>,
+    pub addr: SocketAddr,
+    /// What the peer sent , its id and the extensions it supports
+    pub remote: Handshake,
+    /// Whether the stream is encrypted
+    pub mode: ConnectMode,
+}
+
+/// Why connection setup failed , and at which stage
+#[derive(Debug, Clone)]
+pub struct SetupError {
+    pub addr: SocketAddr,
+    pub stage: SetupStage,
+    pub reason: String,
+}
+
+impl SetupError {
+    /// The peer was reached but the handshake failed , it may still take another connect mode
+    pub fn is_rejection(&self) -> bool {
+        self.stage != SetupStage::Connecting
+    }
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}) : {}", self.addr, self.stage, self.reason)
+    }
+}
+
+impl std::error::Error for SetupError {}
+
+/// How `connect_trying` went , with the modes the peer turned down on the way
+#[derive(Debug)]
+pub struct Attempts {
+    pub rejected: Vec<ConnectMode>,
+    pub result: Result<Established>,
+}
+
+/// Sets up one connection , outgoing or incoming
+///
+/// Outgoing connections send first and then check the peer answered for the same torrent. Incoming
+/// ones read first , since the info hash picks the torrent (and so the handshake) to answer with
+#[derive(Debug)]
+pub struct ConnectionSetup {
+    stage: SetupStage,
+    addr: SocketAddr,
+}
+
+impl ConnectionSetup {
+    /// Connects to `addr` and trades handshakes , `ours` carries the torrent's info hash.
+    /// With `bind` set the connection leaves through that address or interface
+    pub async fn connect(
+        addr: SocketAddr,
+        ours: &Handshake,
+        bind: Option<&BindTarget>,
+    ) -> Result<Established> {
+        Self::connect_on(&TokioRuntime::new(), addr, ours, bind).await
+    }
+
+    /// Same as `connect` , with the socket and the timeout coming from `runtime`
+    pub async fn connect_on(
+        runtime: &dyn Runtime,
+        addr: SocketAddr,
+        ours: &Handshake,
+        bind: Option<&BindTarget>,
+    ) -> Result<Established> {
+        Self::connect_in_mode(runtime, addr, ours, bind, ConnectMode::Plaintext).await
+    }
+
+    /// Tries `modes` in order , moving on to the next when the peer turns a handshake down.
+    /// A peer that can't be reached at all isn't tried again
+    pub async fn connect_trying(
+        runtime: &dyn Runtime,
+        addr: SocketAddr,
+        ours: &Handshake,
+        bind: Option<&BindTarget>,
+        modes: &[ConnectMode],
+    ) -> Attempts {
+        let mut rejected = Vec::new();
+        let mut result = Err(anyhow!("{} : no connect mode to try", addr));
+        for &mode in modes {
+            result = Self::connect_in_mode(runtime, addr, ours, bind, mode).await;
+            let Err(e) = &result else {
+                break;
+            };
+            if !e
+                .downcast_ref::<SetupError>()
+                .is_some_and(SetupError::is_rejection)
+            {
+                break;
+            }
+            rejected.push(mode);
+        }
+        Attempts { rejected, result }
+    }
+
+    /// Connects with the MSE handshake first when `mode` is Encrypted , the BitTorrent handshake
+    /// and everything after it then goes over RC4
+    pub async fn connect_in_mode(
+        runtime: &dyn Runtime,
+        addr: SocketAddr,
+        ours: &Handshake,
+        bind: Option<&BindTarget>,
+        mode: ConnectMode,
+    ) -> Result<Established> {
+        let mut setup = Self {
+            stage: SetupStage::Connecting,
+            addr,
+        };
+
+        let result = timeout(runtime, HANDSHAKE_TIMEOUT, async {
+            let mut stream = runtime.connect_tcp(addr, bind.cloned()).await?;
+            if mode == ConnectMode::Encrypted {
+                setup.stage = SetupStage::Encrypting;
+                stream = encrypt_outgoing(stream, &ours.info_hash).await?;
+            }
+            setup.send(&mut stream, ours).await?;
+            let remote = setup.receive(&mut stream).await?;
+            remote.check_info_hash(&ours.info_hash)?;
+            Ok::<_, anyhow::Error>((stream, remote))
+        })
+        .await;
+
+        setup.finish(result, mode)
+    }
+
+    /// Handshakes on an accepted connection , `answer` gives our handshake for the info hash the
+    /// peer asked for , None when we don't have that torrent
+    pub async fn accept(
+        stream: TcpStream,
+        answer: impl FnOnce(&[u8; 20]) -> Option<Handshake>,
+    ) -> Result<Established> {
+        let addr = stream.peer_addr()?;
+        Self::accept_stream(&TokioRuntime::new(), Box::new(stream), addr, answer).await
+    }
+
+    /// Same as `accept` for a stream from any runtime , `addr` is where it came from
+    pub async fn accept_stream(
+        runtime: &dyn Runtime,
+        stream: Box<dyn PeerStream>,
+        addr: SocketAddr,
+        answer: impl FnOnce(&[u8; 20]) -> Option<Handshake>,
+    ) -> Result<Established> {
+        Self::accept_in_mode(runtime, stream, addr, None, answer).await
+    }
+
+    /// Same as `accept_stream` for a peer that opens with the MSE handshake. `info_hashes` are
+    /// the torrents we serve , the peer picks one without naming it in the clear
+    pub async fn accept_encrypted(
+        runtime: &dyn Runtime,
+        stream: Box<dyn PeerStream>,
+        addr: SocketAddr,
+        info_hashes: &[[u8; 20]],
+        answer: impl FnOnce(&[u8; 20]) -> Option<Handshake>,
+    ) -> Result<Established> {
+        Self::accept_in_mode(runtime, stream, addr, Some(info_hashes), answer).await
+    }
+
+    async fn accept_in_mode(
+        runtime: &dyn Runtime,
+        mut stream: Box<dyn PeerStream>,
+        addr: SocketAddr,
+        encrypted: Option<&[[u8; 20]]>,
+        answer: impl FnOnce(&[u8; 20]) -> Option<Handshake>,
+    ) -> Result<Established> {
+        let mut setup = Self {
+            stage: SetupStage::AwaitingHandshake,
+            addr,
+        };
+
+        let result = timeout(runtime, HANDSHAKE_TIMEOUT, async {
+            if let Some(info_hashes) = encrypted {
+                setup.stage = SetupStage::Encrypting;
+                stream = encrypt_incoming(stream, info_hashes).await?;
+            }
+            let remote = setup.receive(&mut stream).await?;
+            let ours = answer(&remote.info_hash).ok_or_else(|| {
+                anyhow!(
+                    "Peer asked for unknown torrent {}",
+                    hex::encode(remote.info_hash)
+                )
+            })?;
+            setup.send(&mut stream, &ours).await?;
+            Ok::<_, anyhow::Error>((stream, remote))
+        })
+        .await;
+
+        let mode = match encrypted {
+            Some(_) => ConnectMode::Encrypted,
+            None => ConnectMode::Plaintext,
+        };
+        setup.finish(result, mode)
+    }
+
+    async fn send(&mut self, stream: &mut Box<dyn PeerStream>, ours: &Handshake) -> Result<()> {
+        self.stage = SetupStage::SendingHandshake;
+        write_all(stream, &ours.to_bytes()).await
+    }
+
+    async fn receive(&mut self, stream: &mut Box<dyn PeerStream>) -> Result<Handshake> {
+        self.stage = SetupStage::AwaitingHandshake;
+        let mut bytes = [0u8; HANDSHAKE_LEN];
+        read_exact(stream, &mut bytes).await?;
+        Handshake::parse(&bytes)
+    }
+
+    fn finish(
+        mut self,
+        result: Option<Result<(Box<dyn PeerStream>, Handshake)>>,
+        mode: ConnectMode,
+    ) -> Result<Established> {
+        let reason = match result {
+            Some(Ok((stream, remote))) => {
+                self.stage = SetupStage::Established;
+                return Ok(Established {
+                    stream,
+                    addr: self.addr,
+                    remote,
+                    mode,
+                });
+            }
+            Some(Err(e)) => e.to_string(),
+            None => format!("timed out after {}s", HANDSHAKE_TIMEOUT.as_secs()),
+        };
+        Err(SetupError {
+            addr: self.addr,
+            stage: self.stage,
+            reason,
+        }
+        .into())
+    }
+}
+
+/// MSE handshake from the side that dialed , `skey` is the torrent's info hash. Only RC4 is offered ,
+/// our BitTorrent handshake follows over it instead of riding along as initial payload
+async fn encrypt_outgoing(
+    mut stream: Box<dyn PeerStream>,
+    skey: &[u8; 20],
+) -> Result<Box<dyn PeerStream>> {
+    let keys = KeyPair::generate();
+    let mut hello = keys.public.to_vec();
+    hello.extend(random_pad());
+    write_all(&mut stream, &hello).await?;
+
+    let mut incoming = Incoming::default();
+    let secret = incoming.shared_secret(&mut stream, &keys).await?;
+    let mut write_cipher = Rc4::new(b"keyA", &secret, skey);
+    let mut read_cipher = Rc4::new(b"keyB", &secret, skey);
+
+    let mut request = hash(&[b"req1", &secret]).to_vec();
+    let req3 = hash(&[b"req3", &secret]);
+    request.extend(hash(&[b"req2", skey]).iter().zip(req3).map(|(a, b)| a ^ b));
+    let mut offer = VC.to_vec();
+    offer.extend(CRYPTO_RC4.to_be_bytes());
+    // No PadC , and no initial payload
+    offer.extend(0u16.to_be_bytes());
+    offer.extend(0u16.to_be_bytes());
+    write_cipher.apply(&mut offer);
+    request.extend(offer);
+    write_all(&mut stream, &request).await?;
+
+    // The answer starts after up to MAX_PAD bytes of the peer's padding , at its encrypted VC
+    let mut encrypted_vc = VC;
+    read_cipher.apply(&mut encrypted_vc);
+    incoming
+        .skip_past(&mut stream, &encrypted_vc, MAX_PAD + VC.len())
+        .await?;
+    let select = incoming.decrypt(&mut stream, &mut read_cipher, 4).await?;
+    let select = u32::from_be_bytes(select.try_into().unwrap());
+    if select != CRYPTO_RC4 {
+        return Err(anyhow!(
+            "Peer picked crypto {:#x} , we only offered RC4",
+            select
+        ));
+    }
+    let pad_len = incoming.decrypt(&mut stream, &mut read_cipher, 2).await?;
+    let pad_len = u16::from_be_bytes([pad_len[0], pad_len[1]]) as usize;
+    if pad_len > MAX_PAD {
+        return Err(anyhow!("Peer sent {} bytes of padding", pad_len));
+    }
+    incoming
+        .decrypt(&mut stream, &mut read_cipher, pad_len)
+        .await?;
+
+    let buffered = incoming.rest(&mut read_cipher);
+    Ok(Box::new(EncryptedStream::new(
+        stream,
+        read_cipher,
+        write_cipher,
+        buffered,
+    )))
+}
+
+/// MSE handshake from the side that was dialed , the peer picks one of `info_hashes` by hash
+async fn encrypt_incoming(
+    mut stream: Box<dyn PeerStream>,
+    info_hashes: &[[u8; 20]],
+) -> Result<Box<dyn PeerStream>> {
+    let keys = KeyPair::generate();
+    let mut incoming = Incoming::default();
+    let secret = incoming.shared_secret(&mut stream, &keys).await?;
+    let mut hello = keys.public.to_vec();
+    hello.extend(random_pad());
+    write_all(&mut stream, &hello).await?;
+
+    // The request starts after up to MAX_PAD bytes of the peer's padding
+    let req1 = hash(&[b"req1", &secret]);
+    incoming
+        .skip_past(&mut stream, &req1, MAX_PAD + req1.len())
+        .await?;
+    let mut skey_hash = incoming.take(&mut stream, 20).await?;
+    let req3 = hash(&[b"req3", &secret]);
+    skey_hash.iter_mut().zip(req3).for_each(|(a, b)| *a ^= b);
+    let skey = info_hashes
+        .iter()
+        .find(|info_hash| hash(&[b"req2", info_hash.as_slice()]) == skey_hash.as_slice())
+        .ok_or_else(|| anyhow!("Peer asked for a torrent we don't serve"))?;
+    let mut read_cipher = Rc4::new(b"keyA", &secret, skey);
+    let mut write_cipher = Rc4::new(b"keyB", &secret, skey);
+
+    let offer = incoming.decrypt(&mut stream, &mut read_cipher, 14).await?;
+    if offer[..8] != VC {
+        return Err(anyhow!("Peer's verification constant doesn't decrypt"));
+    }
+    let provide = u32::from_be_bytes(offer[8..12].try_into().unwrap());
+    if provide & CRYPTO_RC4 == 0 {
+        return Err(anyhow!(
+            "Peer offered crypto {:#x} , we only speak RC4",
+            provide
+        ));
+    }
+    let pad_len = u16::from_be_bytes([offer[12], offer[13]]) as usize;
+    if pad_len > MAX_PAD {
+        return Err(anyhow!("Peer sent {} bytes of padding", pad_len));
+    }
+    incoming
+        .decrypt(&mut stream, &mut read_cipher, pad_len)
+        .await?;
+    let payload_len = incoming.decrypt(&mut stream, &mut read_cipher, 2).await?;
+    let payload_len = u16::from_be_bytes([payload_len[0], payload_len[1]]) as usize;
+    // Often the peer's BitTorrent handshake , read as if it came after
+    let mut buffered = incoming
+        .decrypt(&mut stream, &mut read_cipher, payload_len)
+        .await?;
+
+    let mut answer = VC.to_vec();
+    answer.extend(CRYPTO_RC4.to_be_bytes());
+    answer.extend(0u16.to_be_bytes());
+    write_cipher.apply(&mut answer);
+    write_all(&mut stream, &answer).await?;
+
+    buffered.extend(incoming.rest(&mut read_cipher));
+    Ok(Box::new(EncryptedStream::new(
+        stream,
+        read_cipher,
+        write_cipher,
+        buffered,
+    )))
+}
+
+/// Bytes read off the socket during the MSE handshake that weren't used yet
+#[derive(Debug, Default)]
+struct Incoming {
+    buf: Vec<u8>,
+}
+
+impl Incoming {
+    /// Reads until at least `len` bytes are buffered
+    async fn fill(&mut self, stream: &mut Box<dyn PeerStream>, len: usize) -> Result<()> {
+        let mut chunk = [0u8; 1024];
+        while self.buf.len() < len {
+            match stream.read(&mut chunk).await? {
+                0 => return Err(anyhow!("Connection closed after {} bytes", self.buf.len())),
+                read => self.buf.extend_from_slice(&chunk[..read]),
+            }
+        }
+        Ok(())
+    }
+
+    async fn take(&mut self, stream: &mut Box<dyn PeerStream>, len: usize) -> Result<Vec<u8>> {
+        self.fill(stream, len).await?;
+        Ok(self.buf.drain(..len).collect())
+    }
+
+    async fn decrypt(
+        &mut self,
+        stream: &mut Box<dyn PeerStream>,
+        cipher: &mut Rc4,
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        let mut bytes = self.take(stream, len).await?;
+        cipher.apply(&mut bytes);
+        Ok(bytes)
+    }
+
+    /// Reads the peer's public key and works out the secret we share
+    async fn shared_secret(
+        &mut self,
+        stream: &mut Box<dyn PeerStream>,
+        keys: &KeyPair,
+    ) -> Result<[u8; KEY_LEN]> {
+        let public = self.take(stream, KEY_LEN).await?;
+        keys.shared_secret(&public.try_into().unwrap())
+            .ok_or_else(|| anyhow!("Peer sent an unusable public key"))
+    }
+
+    /// Drops everything up to and including `marker` , which has to end within `limit` bytes
+    async fn skip_past(
+        &mut self,
+        stream: &mut Box<dyn PeerStream>,
+        marker: &[u8],
+        limit: usize,
+    ) -> Result<()> {
+        loop {
+            if let Some(position) = self
+                .buf
+                .windows(marker.len())
+                .position(|window| window == marker)
+                .filter(|position| position + marker.len() <= limit)
+            {
+                self.buf.drain(..position + marker.len());
+                return Ok(());
+            }
+            if self.buf.len() >= limit {
+                return Err(anyhow!(
+                    "Peer's reply isn't MSE , no marker in {} bytes",
+                    limit
+                ));
+            }
+            self.fill(stream, self.buf.len() + 1).await?;
+        }
+    }
+
+    /// What's left , decrypted , it belongs to the stream after the handshake
+    fn rest(self, cipher: &mut Rc4) -> Vec<u8> {
+        let mut rest = self.buf;
+        cipher.apply(&mut rest);
+        rest
+    }
+}
@@ -0,0 +1,429 @@
+//! KRPC , the bencoded query / response messages DHT nodes trade over UDP (BEP 5)
+
+use crate::{
+    net::{dht::routing::NodeId, tracker::parse_compact_peers},
+    protocol::bencode::BencodeValue,
+};
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Error codes of BEP 5
+pub const ERROR_GENERIC: i64 = 201;
+pub const ERROR_SERVER: i64 = 202;
+pub const ERROR_PROTOCOL: i64 = 203;
+pub const ERROR_METHOD_UNKNOWN: i64 = 204;
+
+/// Size of a compact IPv4 node , id + ip + port
+pub const COMPACT_NODE_LEN: usize = 26;
+
+/// Size of a compact IPv6 node
+pub const COMPACT_NODE6_LEN: usize = 38;
+
+/// The queries we send and answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Ping,
+    FindNode {
+        target: NodeId,
+    },
+    GetPeers {
+        info_hash: [u8; 20],
+        /// Also ask for the node's seed and downloader bloom filters (BEP 33)
+        scrape: bool,
+    },
+    AnnouncePeer {
+        info_hash: [u8; 20],
+        port: u16,
+        /// Use the port the query came from instead of `port` , for peers behind NAT
+        implied_port: bool,
+        token: Bytes,
+    },
+    /// A stored item (BEP 44) , `seq` asks nodes to leave out items that aren't newer
+    Get {
+        target: [u8; 20],
+        seq: Option<i64>,
+    },
+}
+
+impl Query {
+    pub fn method(&self) -> &'static str {
+        match self {
+            Query::Ping => "ping",
+            Query::FindNode { .. } => "find_node",
+            Query::GetPeers { .. } => "get_peers",
+            Query::AnnouncePeer { .. } => "announce_peer",
+            Query::Get { .. } => "get",
+        }
+    }
+}
+
+/// The `r` dictionary of a reply , with the fields every method may carry already parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub id: NodeId,
+    /// `nodes` and `nodes6` , closer nodes for find_node and get_peers
+    pub nodes: Vec<(NodeId, SocketAddr)>,
+    /// `values` , peers of the torrent for get_peers
+    pub values: Vec<SocketAddr>,
+    /// Write token to hand back in announce_peer
+    pub token: Option<Bytes>,
+    /// The whole dictionary , for extensions like scrapes (BEP 33) and mutable items (BEP 44)
+    pub body: BencodeValue,
+}
+
+impl Response {
+    /// A reply carrying only our id , what ping and announce_peer get
+    pub fn id_only(id: NodeId) -> Self {
+        Self {
+            id,
+            nodes: Vec::new(),
+            values: Vec::new(),
+            token: None,
+            body: BencodeValue::dict(vec![(b"id", BencodeValue::bytes(&id.0))]),
+        }
+    }
+
+    /// A reply with nodes , values and a token , `body` is built from them
+    pub fn new(
+        id: NodeId,
+        nodes: Vec<(NodeId, SocketAddr)>,
+        values: Vec<SocketAddr>,
+        token: Option<Bytes>,
+    ) -> Self {
+        let mut entries = vec![(b"id".as_slice(), BencodeValue::bytes(&id.0))];
+        let (nodes4, nodes6) = encode_nodes(&nodes);
+        if !nodes4.is_empty() {
+            entries.push((b"nodes", BencodeValue::bytes(&nodes4)));
+        }
+        if !nodes6.is_empty() {
+            entries.push((b"nodes6", BencodeValue::bytes(&nodes6)));
+        }
+        if !values.is_empty() {
+            let values = values
+                .iter()
+                .map(|addr| BencodeValue::bytes(&compact_addr(addr)))
+                .collect();
+            entries.push((b"values", BencodeValue::List(values)));
+        }
+        if let Some(token) = &token {
+            entries.push((b"token", BencodeValue::Bytes(token.clone())));
+        }
+
+        Self {
+            id,
+            nodes,
+            values,
+            token,
+            body: BencodeValue::dict(entries),
+        }
+    }
+
+    fn parse(body: &BencodeValue) -> Result<Self> {
+        let id = node_id_field(body, b"id")?;
+
+        let mut nodes = Vec::new();
+        if let Some(compact) = body.get(b"nodes").and_then(|v| v.as_bytes()) {
+            nodes.extend(parse_nodes(compact, false));
+        }
+        if let Some(compact) = body.get(b"nodes6").and_then(|v| v.as_bytes()) {
+            nodes.extend(parse_nodes(compact, true));
+        }
+
+        let values = body
+            .get(b"values")
+            .and_then(|v| v.as_list())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_bytes())
+                    .flat_map(|compact| parse_compact_peers(compact, compact.len() == 18))
+                    .filter_map(|peer| peer.socket_addr())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            id,
+            nodes,
+            values,
+            token: body.get(b"token").and_then(|v| v.as_bytes()).cloned(),
+            body: body.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KrpcBody {
+    Query {
+        /// Id of the node asking
+        id: NodeId,
+        query: Query,
+        /// `ro` , the sender doesn't answer queries and shouldn't go in routing tables (BEP 43)
+        read_only: bool,
+    },
+    Response(Response),
+    Error {
+        code: i64,
+        message: String,
+    },
+}
+
+/// One KRPC datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KrpcMessage {
+    /// `t` , echoed back in the reply so it can be matched to the query
+    pub transaction: Bytes,
+    pub body: KrpcBody,
+}
+
+impl KrpcMessage {
+    pub fn query(transaction: Bytes, id: NodeId, query: Query, read_only: bool) -> Self {
+        Self {
+            transaction,
+            body: KrpcBody::Query {
+                id,
+                query,
+                read_only,
+            },
+        }
+    }
+
+    pub fn response(transaction: Bytes, response: Response) -> Self {
+        Self {
+            transaction,
+            body: KrpcBody::Response(response),
+        }
+    }
+
+    pub fn error(transaction: Bytes, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            transaction,
+            body: KrpcBody::Error {
+                code,
+                message: message.into(),
+            },
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut entries = vec![(
+            b"t".as_slice(),
+            BencodeValue::Bytes(self.transaction.clone()),
+        )];
+
+        match &self.body {
+            KrpcBody::Query {
+                id,
+                query,
+                read_only,
+            } => {
+                let mut args = vec![(b"id".as_slice(), BencodeValue::bytes(&id.0))];
+                match query {
+                    Query::Ping => {}
+                    Query::FindNode { target } => {
+                        args.push((b"target", BencodeValue::bytes(&target.0)));
+                    }
+                    Query::GetPeers { info_hash, scrape } => {
+                        args.push((b"info_hash", BencodeValue::bytes(info_hash)));
+                        if *scrape {
+                            args.push((b"scrape", BencodeValue::Integer(1)));
+                        }
+                    }
+                    Query::AnnouncePeer {
+                        info_hash,
+                        port,
+                        implied_port,
+                        token,
+                    } => {
+                        args.push((b"info_hash", BencodeValue::bytes(info_hash)));
+                        args.push((b"port", BencodeValue::Integer(*port as i64)));
+                        args.push((b"token", BencodeValue::Bytes(token.clone())));
+                        if *implied_port {
+                            args.push((b"implied_port", BencodeValue::Integer(1)));
+                        }
+                    }
+                    Query::Get { target, seq } => {
+                        args.push((b"target", BencodeValue::bytes(target)));
+                        if let Some(seq) = seq {
+                            args.push((b"seq", BencodeValue::Integer(*seq)));
+                        }
+                    }
+                }
+
+                entries.push((b"y", BencodeValue::bytes(b"q")));
+                entries.push((b"q", BencodeValue::bytes(query.method().as_bytes())));
+                entries.push((b"a", BencodeValue::dict(args)));
+                if *read_only {
+                    entries.push((b"ro", BencodeValue::Integer(1)));
+                }
+            }
+            KrpcBody::Response(response) => {
+                entries.push((b"y", BencodeValue::bytes(b"r")));
+                entries.push((b"r", response.body.clone()));
+            }
+            KrpcBody::Error { code, message } => {
+                entries.push((b"y", BencodeValue::bytes(b"e")));
+                entries.push((
+                    b"e",
+                    BencodeValue::List(vec![
+                        BencodeValue::Integer(*code),
+                        BencodeValue::bytes(message.as_bytes()),
+                    ]),
+                ));
+            }
+        }
+
+        BencodeValue::dict(entries).encode()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let message = BencodeValue::decode(bytes)?;
+        let transaction = message
+            .get(b"t")
+            .and_then(|v| v.as_bytes())
+            .cloned()
+            .ok_or_else(|| anyhow!("KRPC message has no transaction id"))?;
+
+        let kind = message.get(b"y").and_then(|v| v.as_bytes());
+        let body = match kind.map(|kind| kind.as_ref()) {
+            Some(b"q") => Self::decode_query(&message)?,
+            Some(b"r") => {
+                let reply = message
+                    .get(b"r")
+                    .ok_or_else(|| anyhow!("KRPC reply has no r dictionary"))?;
+                KrpcBody::Response(Response::parse(reply)?)
+            }
+            Some(b"e") => {
+                let error = message.get(b"e").and_then(|v| v.as_list());
+                let code = error
+                    .and_then(|e| e.first())
+                    .and_then(|v| v.as_integer())
+                    .unwrap_or(ERROR_GENERIC);
+                let text = error
+                    .and_then(|e| e.get(1))
+                    .and_then(|v| v.as_bytes())
+                    .map(|text| String::from_utf8_lossy(text).to_string())
+                    .unwrap_or_default();
+                KrpcBody::Error {
+                    code,
+                    message: text,
+                }
+            }
+            _ => return Err(anyhow!("KRPC message has no valid y field")),
+        };
+
+        Ok(Self { transaction, body })
+    }
+
+    fn decode_query(message: &BencodeValue) -> Result<KrpcBody> {
+        let method = message
+            .get(b"q")
+            .and_then(|v| v.as_bytes())
+            .ok_or_else(|| anyhow!("KRPC query has no method"))?;
+        let args = message
+            .get(b"a")
+            .ok_or_else(|| anyhow!("KRPC query has no arguments"))?;
+        let id = node_id_field(args, b"id")?;
+
+        let query = match method.as_ref() {
+            b"ping" => Query::Ping,
+            b"find_node" => Query::FindNode {
+                target: node_id_field(args, b"target")?,
+            },
+            b"get_peers" => Query::GetPeers {
+                info_hash: node_id_field(args, b"info_hash")?.0,
+                scrape: args.get(b"scrape").and_then(|v| v.as_integer()) == Some(1),
+            },
+            b"announce_peer" => Query::AnnouncePeer {
+                info_hash: node_id_field(args, b"info_hash")?.0,
+                port: args
+                    .get(b"port")
+                    .and_then(|v| v.as_integer())
+                    .and_then(|port| u16::try_from(port).ok())
+                    .ok_or_else(|| anyhow!("announce_peer has no valid port"))?,
+                implied_port: args.get(b"implied_port").and_then(|v| v.as_integer()) == Some(1),
+                token: args
+                    .get(b"token")
+                    .and_then(|v| v.as_bytes())
+                    .cloned()
+                    .ok_or_else(|| anyhow!("announce_peer has no token"))?,
+            },
+            b"get" => Query::Get {
+                target: node_id_field(args, b"target")?.0,
+                seq: args.get(b"seq").and_then(|v| v.as_integer()),
+            },
+            other => {
+                return Err(anyhow!(
+                    "Unknown KRPC method {}",
+                    String::from_utf8_lossy(other)
+                ));
+            }
+        };
+
+        Ok(KrpcBody::Query {
+            id,
+            query,
+            read_only: message.get(b"ro").and_then(|v| v.as_integer()) == Some(1),
+        })
+    }
+}
+
+fn node_id_field(dict: &BencodeValue, key: &[u8]) -> Result<NodeId> {
+    dict.get(key)
+        .and_then(|v| v.as_bytes())
+        .and_then(|id| <[u8; 20]>::try_from(id.as_ref()).ok())
+        .map(NodeId)
+        .ok_or_else(|| anyhow!("Missing or invalid {}", String::from_utf8_lossy(key)))
+}
+
+/// Reads compact node info , 26 bytes per IPv4 node or 38 per IPv6 node
+pub fn parse_nodes(data: &[u8], ipv6: bool) -> Vec<(NodeId, SocketAddr)> {
+    let entry = if ipv6 {
+        COMPACT_NODE6_LEN
+    } else {
+        COMPACT_NODE_LEN
+    };
+
+    data.chunks_exact(entry)
+        .map(|chunk| {
+            let id = NodeId(chunk[..20].try_into().unwrap());
+            let (ip, port) = chunk[20..].split_at(entry - 22);
+            let ip = match <[u8; 16]>::try_from(ip) {
+                Ok(octets) => IpAddr::V6(Ipv6Addr::from(octets)),
+                Err(_) => IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])),
+            };
+            (
+                id,
+                SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])),
+            )
+        })
+        .collect()
+}
+
+/// Writes nodes in compact form , IPv4 and IPv6 nodes go in separate strings (`nodes` , `nodes6`)
+pub fn encode_nodes(nodes: &[(NodeId, SocketAddr)]) -> (Vec<u8>, Vec<u8>) {
+    let mut nodes4 = Vec::new();
+    let mut nodes6 = Vec::new();
+
+    for (id, addr) in nodes {
+        let out = if addr.is_ipv4() {
+            &mut nodes4
+        } else {
+            &mut nodes6
+        };
+        out.extend_from_slice(&id.0);
+        out.extend_from_slice(&compact_addr(addr));
+    }
+    (nodes4, nodes6)
+}
+
+/// Ip followed by the port , 6 bytes for IPv4 and 18 for IPv6
+pub fn compact_addr(addr: &SocketAddr) -> Vec<u8> {
+    let mut out = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    out.extend_from_slice(&addr.port().to_be_bytes());
+    out
+}
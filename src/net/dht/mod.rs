@@ -0,0 +1,11 @@
+//! Mainline DHT (BEP 5)
+//!
+//! The routing table and its state file , KRPC messages , the node that sends and answers them and
+//! a background task that keeps it running
+pub mod krpc;
+pub mod mutable;
+pub mod node;
+pub mod routing;
+pub mod scrape;
+pub mod service;
+pub mod state;
@@ -0,0 +1,141 @@
+use crate::protocol::bencode::BencodeValue;
+use anyhow::{Result, anyhow};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha1::{Digest, Sha1};
+
+/// Largest salt BEP 44 allows
+pub const MAX_SALT_LEN: usize = 64;
+
+/// A torrent published as a DHT mutable item (BEP 46)
+///
+/// The publisher signs `{ ih : <info hash> }` with their key and bumps `seq` on every update ,
+/// so following the key always leads to the latest version of the content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutableTorrent {
+    pub public_key: [u8; 32],
+    pub salt: Vec<u8>,
+    /// Sequence number of the newest item we accepted
+    pub seq: Option<i64>,
+    /// Info hash that item points at
+    pub info_hash: Option<[u8; 20]>,
+}
+
+impl MutableTorrent {
+    pub fn new(public_key: [u8; 32], salt: Vec<u8>) -> Result<Self> {
+        if salt.len() > MAX_SALT_LEN {
+            return Err(anyhow!(
+                "Salt is {} bytes , at most {} allowed",
+                salt.len(),
+                MAX_SALT_LEN
+            ));
+        }
+        VerifyingKey::from_bytes(&public_key)
+            .map_err(|_| anyhow!("Not a valid ed25519 public key"))?;
+
+        Ok(Self {
+            public_key,
+            salt,
+            seq: None,
+            info_hash: None,
+        })
+    }
+
+    /// Reads `magnet:?xs=urn:btpk:<hex key>&s=<hex salt>`
+    pub fn from_magnet(uri: &str) -> Result<Self> {
+        let query = uri
+            .strip_prefix("magnet:?")
+            .ok_or_else(|| anyhow!("Not a magnet link"))?;
+
+        let mut public_key = None;
+        let mut salt = Vec::new();
+
+        for param in query.split('&') {
+            match param.split_once('=') {
+                Some(("xs", value)) => {
+                    if let Some(key) = value.strip_prefix("urn:btpk:") {
+                        let key = hex::decode(key)?;
+                        public_key = Some(
+                            key.try_into()
+                                .map_err(|_| anyhow!("Public key must be 32 bytes"))?,
+                        );
+                    }
+                }
+                Some(("s", value)) => salt = hex::decode(value)?,
+                _ => {}
+            }
+        }
+
+        let public_key = public_key.ok_or_else(|| anyhow!("Magnet link has no urn:btpk key"))?;
+        Self::new(public_key, salt)
+    }
+
+    /// DHT key the item is stored under , SHA-1 of the public key followed by the salt
+    pub fn target(&self) -> [u8; 20] {
+        let mut hasher = Sha1::new();
+        hasher.update(self.public_key);
+        hasher.update(&self.salt);
+        hasher.finalize().into()
+    }
+
+    /// Checks a `get` reply (the `r` dictionary) and takes it if it's newer than what we have
+    ///
+    /// Returns the new info hash when the publisher moved to another torrent , None if the item is
+    /// old news. Items with a bad signature or a foreign key are errors
+    pub fn apply_reply(&mut self, reply: &BencodeValue) -> Result<Option<[u8; 20]>> {
+        let key = reply
+            .get(b"k")
+            .and_then(|v| v.as_bytes())
+            .ok_or_else(|| anyhow!("Reply has no public key"))?;
+        if key.as_ref() != self.public_key {
+            return Err(anyhow!("Reply is signed by a different key"));
+        }
+
+        let seq = reply
+            .get(b"seq")
+            .and_then(|v| v.as_integer())
+            .ok_or_else(|| anyhow!("Reply has no sequence number"))?;
+        let value = reply
+            .get(b"v")
+            .ok_or_else(|| anyhow!("Reply has no value"))?;
+        let signature: [u8; 64] = reply
+            .get(b"sig")
+            .and_then(|v| v.as_bytes())
+            .and_then(|sig| sig.as_ref().try_into().ok())
+            .ok_or_else(|| anyhow!("Reply has no valid signature"))?;
+
+        if self.seq.is_some_and(|current| seq <= current) {
+            return Ok(None);
+        }
+
+        self.verify(seq, value, &signature)?;
+
+        let info_hash: [u8; 20] = value
+            .get(b"ih")
+            .and_then(|v| v.as_bytes())
+            .and_then(|ih| ih.as_ref().try_into().ok())
+            .ok_or_else(|| anyhow!("Item does not point at an info hash"))?;
+
+        self.seq = Some(seq);
+        if self.info_hash == Some(info_hash) {
+            return Ok(None);
+        }
+        self.info_hash = Some(info_hash);
+        Ok(Some(info_hash))
+    }
+
+    /// Signatures cover `4:salt<salt>3:seqi<seq>e1:v<value>` , the salt part only when there is one
+    fn verify(&self, seq: i64, value: &BencodeValue, signature: &[u8; 64]) -> Result<()> {
+        let mut message = Vec::new();
+        if !self.salt.is_empty() {
+            message.extend_from_slice(b"4:salt");
+            message.extend_from_slice(&BencodeValue::bytes(&self.salt).encode());
+        }
+        message.extend_from_slice(format!("3:seqi{}e1:v", seq).as_bytes());
+        message.extend_from_slice(&value.encode());
+
+        let key = VerifyingKey::from_bytes(&self.public_key)
+            .map_err(|_| anyhow!("Not a valid ed25519 public key"))?;
+        key.verify(&message, &Signature::from_bytes(signature))
+            .map_err(|_| anyhow!("Item signature does not match"))
+    }
+}
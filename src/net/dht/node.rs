@@ -0,0 +1,595 @@
+//! A DHT node , runs lookups and answers other nodes' queries over one UDP socket (BEP 5)
+//!
+//! The node has no task of its own , `DhtService` runs one for it. Replies to our queries and
+//! queries from other nodes come in on the same socket , queries get answered while a lookup waits
+//! for its replies or while `serve` runs.
+//! The routing table stays with the caller (the session saves it between runs) and is passed in
+
+use crate::{
+    core::{
+        bind::BindTarget,
+        clock::SharedClock,
+        config::DhtConfig,
+        runtime::{DatagramSocket, SharedRuntime, timeout, tokio_runtime},
+    },
+    net::dht::{
+        krpc::{ERROR_PROTOCOL, KrpcBody, KrpcMessage, Query, Response},
+        routing::{BUCKET_SIZE, NodeId, RoutingTable},
+        scrape::SwarmEstimate,
+    },
+    protocol::bencode::BencodeValue,
+};
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Queries a lookup has out at once (Kademlia's alpha)
+pub const LOOKUP_PARALLELISM: usize = 3;
+
+/// How long a batch of queries waits for replies
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Rounds of queries before a lookup settles for what it found
+pub const MAX_LOOKUP_ROUNDS: usize = 12;
+
+/// Write tokens are made from a secret that changes this often , the previous one is still accepted
+pub const TOKEN_ROTATION: Duration = Duration::from_secs(5 * 60);
+
+/// Announced peers are forgotten after this long without a new announce
+pub const PEER_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Peers kept per info hash , the oldest go first
+const MAX_STORED_PEERS: usize = 200;
+
+/// Peers per get_peers reply , keeps the datagram under the usual MTU
+const MAX_VALUES_PER_REPLY: usize = 50;
+
+const MAX_DATAGRAM: usize = 4096;
+
+/// What a lookup found
+#[derive(Debug, Clone, Default)]
+pub struct Lookup {
+    /// Peers from get_peers replies , without duplicates
+    pub peers: Vec<SocketAddr>,
+    /// Nodes closest to the target that answered , nearest first , with the token each handed out
+    pub closest: Vec<(NodeId, SocketAddr, Option<Bytes>)>,
+    /// Nodes that were sent a query
+    pub queried: usize,
+    /// Nodes that took our announce_peer
+    pub announced: usize,
+    /// Swarm size from the bloom filters in get_peers replies (BEP 33)
+    pub swarm: SwarmEstimate,
+    /// Replies to a `get` that carried the item (BEP 44) , unchecked , the caller verifies them
+    pub items: Vec<BencodeValue>,
+}
+
+/// Our node on the DHT
+#[derive(Debug)]
+pub struct Dht {
+    socket: Box<dyn DatagramSocket>,
+    own_id: NodeId,
+    /// Don't answer queries , and tell nodes to keep us out of their tables (BEP 43)
+    read_only: bool,
+    bootstrap_nodes: Vec<String>,
+    next_transaction: u16,
+    secret: [u8; 20],
+    previous_secret: [u8; 20],
+    secret_changed: Instant,
+    /// Peers that announced themselves to us , by info hash
+    stored_peers: HashMap<[u8; 20], Vec<(SocketAddr, Instant)>>,
+    clock: SharedClock,
+    /// Timers for replies and serving
+    runtime: SharedRuntime,
+}
+
+impl Dht {
+    /// Binds the node's socket on `port` , on the bound address or interface when there is one.
+    /// `own_id` should be the routing table's id
+    pub async fn bind(port: u16, own_id: NodeId, bind: Option<&BindTarget>) -> Result<Self> {
+        Self::bind_on(tokio_runtime(), port, own_id, bind).await
+    }
+
+    /// Same as `bind` , with the socket , timers and clock coming from `runtime`
+    pub async fn bind_on(
+        runtime: SharedRuntime,
+        port: u16,
+        own_id: NodeId,
+        bind: Option<&BindTarget>,
+    ) -> Result<Self> {
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        let socket = runtime
+            .bind_udp(addr, bind.cloned())
+            .await
+            .map_err(|e| match bind {
+                Some(bind) => anyhow!("Could not bind DHT socket to {} : {}", bind, e),
+                None => anyhow!("Could not bind DHT socket on {} : {}", addr, e),
+            })?;
+        let clock = runtime.clock();
+
+        Ok(Self {
+            socket,
+            own_id,
+            read_only: false,
+            bootstrap_nodes: DhtConfig::default().bootstrap_nodes,
+            next_transaction: 0,
+            secret: NodeId::random().0,
+            previous_secret: NodeId::random().0,
+            secret_changed: clock.now(),
+            stored_peers: HashMap::new(),
+            clock,
+            runtime,
+        })
+    }
+
+    /// Takes read-only mode and the bootstrap nodes from the config
+    pub fn with_config(mut self, config: &DhtConfig) -> Self {
+        self.set_config(config);
+        self
+    }
+
+    /// Same as `with_config` on a running node , the socket and port stay as they are
+    pub fn set_config(&mut self, config: &DhtConfig) {
+        self.read_only = config.read_only;
+        self.bootstrap_nodes = config.bootstrap_nodes.clone();
+    }
+
+    /// Replaces the clock used for tokens and stored peers
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.secret_changed = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn own_id(&self) -> NodeId {
+        self.own_id
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Joins the DHT by looking up our own id , through the bootstrap nodes when the table is empty.
+    /// Returns how many nodes the table holds afterwards
+    pub async fn bootstrap(&mut self, table: &mut RoutingTable) -> Result<usize> {
+        let lookup = self.find_node(table, self.own_id).await?;
+        if lookup.closest.is_empty() {
+            return Err(anyhow!(
+                "No DHT node answered , {} were asked",
+                lookup.queried
+            ));
+        }
+        Ok(table.len())
+    }
+
+    pub async fn ping(&mut self, table: &mut RoutingTable, addr: SocketAddr) -> Result<NodeId> {
+        let response = self.query(table, addr, Query::Ping).await?;
+        table.insert(response.id, addr);
+        Ok(response.id)
+    }
+
+    /// Nodes closest to `target`
+    pub async fn find_node(&mut self, table: &mut RoutingTable, target: NodeId) -> Result<Lookup> {
+        self.lookup(table, target, Query::FindNode { target }).await
+    }
+
+    /// Peers of a torrent , from the nodes closest to its info hash
+    pub async fn get_peers(
+        &mut self,
+        table: &mut RoutingTable,
+        info_hash: [u8; 20],
+    ) -> Result<Lookup> {
+        let query = Query::GetPeers {
+            info_hash,
+            scrape: true,
+        };
+        self.lookup(table, NodeId(info_hash), query).await
+    }
+
+    /// A mutable item (BEP 44) , from the nodes closest to `target`. Only items newer than `seq`
+    /// come back from nodes that honour it
+    pub async fn get_item(
+        &mut self,
+        table: &mut RoutingTable,
+        target: [u8; 20],
+        seq: Option<i64>,
+    ) -> Result<Lookup> {
+        self.lookup(table, NodeId(target), Query::Get { target, seq })
+            .await
+    }
+
+    /// Looks up a torrent's peers and tells the closest nodes we have it too
+    ///
+    /// `port` is the peer port , None lets the nodes use the port our queries come from
+    pub async fn announce_peer(
+        &mut self,
+        table: &mut RoutingTable,
+        info_hash: [u8; 20],
+        port: Option<u16>,
+    ) -> Result<Lookup> {
+        let mut lookup = self.get_peers(table, info_hash).await?;
+
+        let queries = lookup
+            .closest
+            .iter()
+            .filter_map(|(_, addr, token)| {
+                let query = Query::AnnouncePeer {
+                    info_hash,
+                    port: port.unwrap_or(0),
+                    implied_port: port.is_none(),
+                    token: token.clone()?,
+                };
+                Some((*addr, query))
+            })
+            .collect();
+        let results = self.round(table, queries).await;
+        lookup.announced = results.iter().filter(|(_, r)| r.is_ok()).count();
+        Ok(lookup)
+    }
+
+    /// Answers queries from other nodes for a while , between lookups
+    pub async fn serve(&mut self, table: &mut RoutingTable, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+
+        while let Some(received) = self.recv_until(deadline, &mut buf).await {
+            let Ok((len, from)) = received else {
+                continue;
+            };
+            if let Ok(message) = KrpcMessage::decode(&buf[..len])
+                && let KrpcBody::Query {
+                    id,
+                    query,
+                    read_only,
+                } = message.body
+            {
+                self.answer(table, from, message.transaction, id, query, read_only)
+                    .await;
+            }
+        }
+    }
+
+    /// Sends one query and waits for its reply
+    pub async fn query(
+        &mut self,
+        table: &mut RoutingTable,
+        addr: SocketAddr,
+        query: Query,
+    ) -> Result<Response> {
+        self.round(table, vec![(addr, query)])
+            .await
+            .pop()
+            .map(|(_, result)| result)
+            .unwrap_or_else(|| Err(anyhow!("{} did not answer", addr)))
+    }
+
+    /// Iterative lookup , each round asks the closest nodes not asked yet until no closer ones turn up
+    async fn lookup(
+        &mut self,
+        table: &mut RoutingTable,
+        target: NodeId,
+        query: Query,
+    ) -> Result<Lookup> {
+        // Bootstrap nodes have no id yet , None sorts them first
+        let mut candidates: Vec<(Option<NodeId>, SocketAddr)> = table
+            .closest(&target, BUCKET_SIZE)
+            .into_iter()
+            .map(|node| (Some(node.id), node.addr))
+            .collect();
+        if candidates.is_empty() {
+            candidates = self
+                .bootstrap_addrs()
+                .await
+                .into_iter()
+                .map(|addr| (None, addr))
+                .collect();
+        }
+        if candidates.is_empty() {
+            return Err(anyhow!("No DHT nodes to ask"));
+        }
+
+        let mut queried = HashSet::new();
+        let mut lookup = Lookup::default();
+
+        for _ in 0..MAX_LOOKUP_ROUNDS {
+            candidates.sort_by_key(|(id, _)| id.map(|id| id.distance(&target)));
+            let batch: Vec<(Option<NodeId>, SocketAddr)> = candidates
+                .iter()
+                .take(BUCKET_SIZE)
+                .filter(|(_, addr)| !queried.contains(addr))
+                .take(LOOKUP_PARALLELISM)
+                .copied()
+                .collect();
+            if batch.is_empty() {
+                break;
+            }
+
+            let queries = batch
+                .iter()
+                .map(|(_, addr)| (*addr, query.clone()))
+                .collect();
+            let results = self.round(table, queries).await;
+
+            for ((id, addr), (_, result)) in batch.into_iter().zip(results) {
+                queried.insert(addr);
+                let response = match result {
+                    Ok(response) => response,
+                    Err(_) => {
+                        // Known nodes that stop answering make room for ones that do
+                        if let Some(id) = id {
+                            table.remove(&id);
+                        }
+                        candidates.retain(|(_, a)| *a != addr);
+                        continue;
+                    }
+                };
+
+                table.insert(response.id, addr);
+                if let Some(candidate) = candidates.iter_mut().find(|(_, a)| *a == addr) {
+                    candidate.0 = Some(response.id);
+                }
+                lookup
+                    .closest
+                    .push((response.id, addr, response.token.clone()));
+                match query {
+                    Query::GetPeers { .. } => {
+                        lookup.swarm.add_response(&response.body);
+                    }
+                    Query::Get { .. } if response.body.get(b"v").is_some() => {
+                        lookup.items.push(response.body.clone());
+                    }
+                    _ => {}
+                }
+
+                for peer in response.values {
+                    if !lookup.peers.contains(&peer) {
+                        lookup.peers.push(peer);
+                    }
+                }
+                for (node_id, node_addr) in response.nodes {
+                    let known = candidates.iter().any(|(_, a)| *a == node_addr);
+                    if node_id != self.own_id && !known {
+                        candidates.push((Some(node_id), node_addr));
+                    }
+                }
+            }
+        }
+
+        lookup
+            .closest
+            .sort_by_key(|(id, _, _)| id.distance(&target));
+        lookup.closest.truncate(BUCKET_SIZE);
+        lookup.queried = queried.len();
+        Ok(lookup)
+    }
+
+    /// Sends every query , then collects replies until they're all in or QUERY_TIMEOUT passes.
+    /// Results come back in the order of `queries`
+    async fn round(
+        &mut self,
+        table: &mut RoutingTable,
+        queries: Vec<(SocketAddr, Query)>,
+    ) -> Vec<(SocketAddr, Result<Response>)> {
+        let mut results: Vec<(SocketAddr, Result<Response>)> = queries
+            .iter()
+            .map(|(addr, _)| (*addr, Err(anyhow!("{} did not answer", addr))))
+            .collect();
+        let mut pending: HashMap<Bytes, usize> = HashMap::new();
+
+        for (index, (addr, query)) in queries.into_iter().enumerate() {
+            let transaction = self.transaction();
+            let message =
+                KrpcMessage::query(transaction.clone(), self.own_id, query, self.read_only);
+            match self.socket.send_to(&message.encode(), addr).await {
+                Ok(_) => {
+                    pending.insert(transaction, index);
+                }
+                Err(e) => results[index].1 = Err(e.into()),
+            }
+        }
+
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        while !pending.is_empty() {
+            let Some(received) = self.recv_until(deadline, &mut buf).await else {
+                break;
+            };
+            // Some platforms report ICMP errors from earlier sends here
+            let Ok((len, from)) = received else {
+                continue;
+            };
+            let Ok(message) = KrpcMessage::decode(&buf[..len]) else {
+                continue;
+            };
+
+            let index = pending
+                .get(&message.transaction)
+                .copied()
+                .filter(|&index| results[index].0 == from);
+            match (message.body, index) {
+                (
+                    KrpcBody::Query {
+                        id,
+                        query,
+                        read_only,
+                    },
+                    _,
+                ) => {
+                    self.answer(table, from, message.transaction, id, query, read_only)
+                        .await;
+                }
+                (KrpcBody::Response(response), Some(index)) => {
+                    pending.remove(&message.transaction);
+                    results[index].1 = Ok(response);
+                }
+                (
+                    KrpcBody::Error {
+                        code,
+                        message: text,
+                    },
+                    Some(index),
+                ) => {
+                    pending.remove(&message.transaction);
+                    results[index].1 = Err(anyhow!("{} answered error {} : {}", from, code, text));
+                }
+                // Late replies to an earlier round , or spoofed ones
+                _ => {}
+            }
+        }
+        results
+    }
+
+    async fn answer(
+        &mut self,
+        table: &mut RoutingTable,
+        from: SocketAddr,
+        transaction: Bytes,
+        id: NodeId,
+        query: Query,
+        sender_read_only: bool,
+    ) {
+        if self.read_only {
+            return;
+        }
+        if !sender_read_only {
+            table.insert(id, from);
+        }
+
+        let message = match self.reply(table, from, query) {
+            Ok(response) => KrpcMessage::response(transaction, response),
+            Err((code, text)) => KrpcMessage::error(transaction, code, text),
+        };
+        let _ = self.socket.send_to(&message.encode(), from).await;
+    }
+
+    /// Our reply to a query , or a KRPC error code and message
+    fn reply(
+        &mut self,
+        table: &RoutingTable,
+        from: SocketAddr,
+        query: Query,
+    ) -> Result<Response, (i64, String)> {
+        let now = self.clock.now();
+        if now.duration_since(self.secret_changed) >= TOKEN_ROTATION {
+            self.previous_secret = self.secret;
+            self.secret = NodeId::random().0;
+            self.secret_changed = now;
+        }
+        let closest = |target: &NodeId| {
+            table
+                .closest(target, BUCKET_SIZE)
+                .into_iter()
+                .map(|node| (node.id, node.addr))
+                .collect::<Vec<_>>()
+        };
+
+        match query {
+            Query::Ping => Ok(Response::id_only(self.own_id)),
+            Query::FindNode { target } => Ok(Response::new(
+                self.own_id,
+                closest(&target),
+                Vec::new(),
+                None,
+            )),
+            Query::GetPeers { info_hash, .. } => {
+                let token = Some(token(&self.secret, from.ip()));
+                let peers = self.peers_for(&info_hash, now);
+                if peers.is_empty() {
+                    let nodes = closest(&NodeId(info_hash));
+                    return Ok(Response::new(self.own_id, nodes, Vec::new(), token));
+                }
+                Ok(Response::new(self.own_id, Vec::new(), peers, token))
+            }
+            Query::AnnouncePeer {
+                info_hash,
+                port,
+                implied_port,
+                token: given,
+            } => {
+                let valid = [self.secret, self.previous_secret]
+                    .iter()
+                    .any(|secret| token(secret, from.ip()) == given);
+                if !valid {
+                    return Err((ERROR_PROTOCOL, "Bad token".to_string()));
+                }
+
+                let port = if implied_port { from.port() } else { port };
+                self.store_peer(info_hash, SocketAddr::new(from.ip(), port), now);
+                Ok(Response::id_only(self.own_id))
+            }
+            // We don't store items , closer nodes are all we can offer
+            Query::Get { target, .. } => Ok(Response::new(
+                self.own_id,
+                closest(&NodeId(target)),
+                Vec::new(),
+                None,
+            )),
+        }
+    }
+
+    fn peers_for(&mut self, info_hash: &[u8; 20], now: Instant) -> Vec<SocketAddr> {
+        let Some(peers) = self.stored_peers.get_mut(info_hash) else {
+            return Vec::new();
+        };
+        peers.retain(|(_, announced)| now.duration_since(*announced) < PEER_TTL);
+        // Newest first , they're the likeliest to still be around
+        peers
+            .iter()
+            .rev()
+            .take(MAX_VALUES_PER_REPLY)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    fn store_peer(&mut self, info_hash: [u8; 20], addr: SocketAddr, now: Instant) {
+        let peers = self.stored_peers.entry(info_hash).or_default();
+        peers.retain(|(a, announced)| *a != addr && now.duration_since(*announced) < PEER_TTL);
+        peers.push((addr, now));
+        if peers.len() > MAX_STORED_PEERS {
+            peers.remove(0);
+        }
+    }
+
+    fn transaction(&mut self) -> Bytes {
+        self.next_transaction = self.next_transaction.wrapping_add(1);
+        Bytes::copy_from_slice(&self.next_transaction.to_be_bytes())
+    }
+
+    /// Next datagram , None once `deadline` has passed on the runtime's timer
+    async fn recv_until(
+        &self,
+        deadline: Instant,
+        buf: &mut [u8],
+    ) -> Option<io::Result<(usize, SocketAddr)>> {
+        let left = deadline.saturating_duration_since(Instant::now());
+        timeout(&*self.runtime, left, self.socket.recv_from(buf)).await
+    }
+
+    /// Bootstrap nodes resolved to addresses of the socket's family
+    async fn bootstrap_addrs(&self) -> Vec<SocketAddr> {
+        let ipv4 = self.socket.local_addr().map_or(true, |addr| addr.is_ipv4());
+        let mut addrs = Vec::new();
+
+        for node in &self.bootstrap_nodes {
+            match tokio::net::lookup_host(node.as_str()).await {
+                Ok(found) => addrs.extend(found.filter(|addr| addr.is_ipv4() == ipv4)),
+                Err(e) => crate::log_line!("Could not resolve DHT bootstrap node {} : {}", node, e),
+            }
+        }
+        addrs
+    }
+}
+
+/// Write token for an ip , the first 8 bytes of SHA-1 over the secret and the ip
+fn token(secret: &[u8; 20], ip: IpAddr) -> Bytes {
+    let mut hasher = Sha1::new();
+    hasher.update(secret);
+    match ip {
+        IpAddr::V4(ip) => hasher.update(ip.octets()),
+        IpAddr::V6(ip) => hasher.update(ip.octets()),
+    }
+    Bytes::copy_from_slice(&hasher.finalize()[..8])
+}
@@ -0,0 +1,208 @@
+use crate::core::clock::{SharedClock, system_clock};
+use sha1::{Digest, Sha1};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Nodes kept per bucket (the `k` of Kademlia)
+pub const BUCKET_SIZE: usize = 8;
+
+/// One bucket per bit of distance
+pub const BUCKET_COUNT: usize = 160;
+
+/// Buckets that haven't changed for this long get a lookup for a random id in their range (BEP 5)
+pub const BUCKET_REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// 160 bit id of a DHT node , also the space info hashes live in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub [u8; 20]);
+
+impl NodeId {
+    pub fn random() -> Self {
+        // std has no rng , RandomState is seeded from the OS and gives new keys every call
+        let mut hasher = Sha1::new();
+        for _ in 0..4 {
+            let mut state = RandomState::new().build_hasher();
+            state.write_u64(0);
+            hasher.update(state.finish().to_le_bytes());
+        }
+        NodeId(hasher.finalize().into())
+    }
+
+    pub fn distance(&self, other: &NodeId) -> [u8; 20] {
+        let mut distance = [0u8; 20];
+        for (i, byte) in distance.iter_mut().enumerate() {
+            *byte = self.0[i] ^ other.0[i];
+        }
+        distance
+    }
+
+    /// Which of our buckets `other` falls into , the number of leading bits it shares with us.
+    /// None for our own id
+    pub fn bucket_index(&self, other: &NodeId) -> Option<usize> {
+        let distance = self.distance(other);
+        let shared = distance
+            .iter()
+            .position(|&b| b != 0)
+            .map(|i| i * 8 + distance[i].leading_zeros() as usize)?;
+        Some(shared)
+    }
+
+    /// A random id that lands in bucket `index` of `self`
+    pub fn random_in_bucket(&self, index: usize) -> NodeId {
+        let mut id = NodeId::random().0;
+        let byte = index / 8;
+        let prefix_mask = !(0xffu8 >> (index % 8));
+        let bit = 0x80u8 >> (index % 8);
+
+        // Keep the shared prefix , the bit right after it has to differ from ours
+        id[..byte].copy_from_slice(&self.0[..byte]);
+        id[byte] = (self.0[byte] & prefix_mask) | (id[byte] & !prefix_mask);
+        id[byte] = (id[byte] & !bit) | (!self.0[byte] & bit);
+
+        NodeId(id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhtNode {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+    /// None for nodes loaded from disk that we haven't heard from this run
+    pub last_seen: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    nodes: Vec<DhtNode>,
+    /// When a node last joined or answered , None means never this run
+    last_changed: Option<Instant>,
+}
+
+/// Kademlia routing table , nodes sorted into buckets by how many leading bits they share with our id
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    own_id: NodeId,
+    buckets: Vec<Bucket>,
+    clock: SharedClock,
+}
+
+impl RoutingTable {
+    pub fn new(own_id: NodeId) -> Self {
+        Self {
+            own_id,
+            buckets: vec![Bucket::default(); BUCKET_COUNT],
+            clock: system_clock(),
+        }
+    }
+
+    /// Replaces the clock used for refresh timing
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn own_id(&self) -> NodeId {
+        self.own_id
+    }
+
+    /// Adds a node we just heard from , or refreshes it if we already know it.
+    /// Returns false when its bucket is full of other nodes
+    pub fn insert(&mut self, id: NodeId, addr: SocketAddr) -> bool {
+        self.add(id, addr, Some(self.clock.now()))
+    }
+
+    /// Adds a node from the state file , it only counts as seen once it answers
+    pub(crate) fn insert_unverified(&mut self, id: NodeId, addr: SocketAddr) -> bool {
+        self.add(id, addr, None)
+    }
+
+    fn add(&mut self, id: NodeId, addr: SocketAddr, seen: Option<Instant>) -> bool {
+        let Some(index) = self.own_id.bucket_index(&id) else {
+            return false;
+        };
+        let bucket = &mut self.buckets[index];
+
+        if let Some(node) = bucket.nodes.iter_mut().find(|n| n.id == id) {
+            node.addr = addr;
+            if seen.is_some() {
+                node.last_seen = seen;
+                bucket.last_changed = seen;
+            }
+            return true;
+        }
+
+        if bucket.nodes.len() >= BUCKET_SIZE {
+            // Evict a node we've never heard from before giving up on a live one
+            match bucket.nodes.iter().position(|n| n.last_seen.is_none()) {
+                Some(stale) if seen.is_some() => {
+                    bucket.nodes.remove(stale);
+                }
+                _ => return false,
+            }
+        }
+
+        bucket.nodes.push(DhtNode {
+            id,
+            addr,
+            last_seen: seen,
+        });
+        if seen.is_some() {
+            bucket.last_changed = seen;
+        }
+        true
+    }
+
+    pub fn remove(&mut self, id: &NodeId) -> Option<DhtNode> {
+        let index = self.own_id.bucket_index(id)?;
+        let bucket = &mut self.buckets[index];
+        let position = bucket.nodes.iter().position(|n| &n.id == id)?;
+        Some(bucket.nodes.remove(position))
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.nodes.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &DhtNode> {
+        self.buckets.iter().flat_map(|b| b.nodes.iter())
+    }
+
+    /// Up to `count` known nodes closest to `target` , nearest first
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<DhtNode> {
+        let mut nodes: Vec<DhtNode> = self.nodes().cloned().collect();
+        nodes.sort_by_key(|n| n.id.distance(target));
+        nodes.truncate(count);
+        nodes
+    }
+
+    /// Lookup targets for buckets that are due a refresh , and marks those buckets as refreshed
+    ///
+    /// Only buckets up to the deepest one holding nodes are worth refreshing , the ones past it
+    /// cover ranges too close to our id for anyone to be in them
+    pub fn due_refreshes(&mut self) -> Vec<NodeId> {
+        let now = self.clock.now();
+        let Some(deepest) = self.buckets.iter().rposition(|b| !b.nodes.is_empty()) else {
+            return Vec::new();
+        };
+
+        let mut targets = Vec::new();
+        for (index, bucket) in self.buckets.iter_mut().enumerate().take(deepest + 1) {
+            let due = match bucket.last_changed {
+                Some(changed) => now.duration_since(changed) >= BUCKET_REFRESH_INTERVAL,
+                None => true,
+            };
+
+            if due {
+                targets.push(self.own_id.random_in_bucket(index));
+                bucket.last_changed = Some(now);
+            }
+        }
+        targets
+    }
+}
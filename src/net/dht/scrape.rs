@@ -0,0 +1,125 @@
+use crate::protocol::bencode::BencodeValue;
+use sha1::{Digest, Sha1};
+use std::net::IpAddr;
+
+/// Bits in a BEP 33 bloom filter
+pub const BLOOM_BITS: usize = 2048;
+
+/// Size of a bloom filter on the wire
+pub const BLOOM_BYTES: usize = BLOOM_BITS / 8;
+
+/// Bloom filter of peer ips from a DHT scrape (BEP 33)
+///
+/// Nodes answer `get_peers` with `scrape=1` by sending a filter of the seeds (`BFsd`) and one of the
+/// downloaders (`BFpe`) they know about. Filters from different nodes are OR'ed together and the
+/// number of set bits gives an estimate of how many distinct ips went in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: [u8; BLOOM_BYTES],
+}
+
+impl BloomFilter {
+    pub fn new() -> Self {
+        Self {
+            bits: [0u8; BLOOM_BYTES],
+        }
+    }
+
+    /// Filter as sent by a node , None if it's the wrong size
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            bits: bytes.try_into().ok()?,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    pub fn insert(&mut self, ip: IpAddr) {
+        let hash = match ip {
+            IpAddr::V4(ip) => Sha1::digest(ip.octets()),
+            IpAddr::V6(ip) => Sha1::digest(ip.octets()),
+        };
+
+        // Two 11 bit indexes taken from the start of the hash
+        let first = (hash[0] as usize | (hash[1] as usize) << 8) % BLOOM_BITS;
+        let second = (hash[2] as usize | (hash[3] as usize) << 8) % BLOOM_BITS;
+        self.set(first);
+        self.set(second);
+    }
+
+    fn set(&mut self, index: usize) {
+        self.bits[index / 8] |= 1 << (index % 8);
+    }
+
+    /// Adds every ip another filter has seen
+    pub fn merge(&mut self, other: &BloomFilter) {
+        for (ours, theirs) in self.bits.iter_mut().zip(other.bits.iter()) {
+            *ours |= theirs;
+        }
+    }
+
+    /// Estimated number of distinct ips in the filter
+    pub fn estimate(&self) -> u64 {
+        let set: usize = self.bits.iter().map(|b| b.count_ones() as usize).sum();
+        // A full filter would give ln(0) , cap it one bit short
+        let zero = (BLOOM_BITS - set).max(1) as f64;
+        let m = BLOOM_BITS as f64;
+
+        let size = (zero / m).ln() / (2.0 * (1.0 - 1.0 / m).ln());
+        size.round() as u64
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Approximate swarm size of a torrent , built up from DHT scrape replies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SwarmEstimate {
+    seeds: BloomFilter,
+    peers: BloomFilter,
+    /// How many nodes contributed
+    pub responses: usize,
+}
+
+impl SwarmEstimate {
+    /// Folds in the `BFsd` / `BFpe` filters of a `get_peers` reply (the `r` dictionary).
+    /// Returns false if the node didn't send any usable filter
+    pub fn add_response(&mut self, reply: &BencodeValue) -> bool {
+        let filter = |key: &[u8]| {
+            reply
+                .get(key)
+                .and_then(|v| v.as_bytes())
+                .and_then(|bytes| BloomFilter::from_bytes(bytes))
+        };
+        let seeds = filter(b"BFsd");
+        let peers = filter(b"BFpe");
+
+        if seeds.is_none() && peers.is_none() {
+            return false;
+        }
+
+        if let Some(seeds) = seeds {
+            self.seeds.merge(&seeds);
+        }
+        if let Some(peers) = peers {
+            self.peers.merge(&peers);
+        }
+        self.responses += 1;
+        true
+    }
+
+    pub fn seeds(&self) -> u64 {
+        self.seeds.estimate()
+    }
+
+    /// Downloaders , peers that aren't seeding
+    pub fn peers(&self) -> u64 {
+        self.peers.estimate()
+    }
+}
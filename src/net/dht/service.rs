@@ -0,0 +1,164 @@
+//! Our DHT node in a background task , for front ends that download one torrent
+//!
+//! The task owns the node and the routing table. It joins the DHT , then answers other nodes'
+//! queries between lookups of the torrent , announcing us to the nodes closest to it every
+//! `LOOKUP_INTERVAL`. Peers it finds are picked up with `poll` , the swarm size the nodes report
+//! with `take_estimate`
+
+use crate::{
+    core::{
+        bind::BindTarget,
+        clock::SharedClock,
+        config::DhtConfig,
+        runtime::{SharedRuntime, TaskHandle},
+    },
+    net::dht::{
+        node::{Dht, Lookup},
+        routing::RoutingTable,
+        scrape::SwarmEstimate,
+    },
+    protocol::peer::{DiscoveredPeer, PeerHost, PeerSource},
+};
+use anyhow::Result;
+use std::time::Duration;
+use tokio::sync::{
+    mpsc::{self, error::TryRecvError},
+    oneshot,
+};
+
+/// How often the torrent is looked up again , which also renews our announce on the nodes
+pub const LOOKUP_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// How long the node answers queries before checking whether a lookup or bucket refresh is due
+const SERVE_SLICE: Duration = Duration::from_secs(1);
+
+/// Handle to the background node , see the module docs
+#[derive(Debug)]
+pub struct DhtService {
+    port: u16,
+    /// Asks the task for a lookup right away , closing it stops the task
+    lookups: mpsc::UnboundedSender<()>,
+    found: mpsc::UnboundedReceiver<Lookup>,
+    /// Newest scrape not handed out yet
+    estimate: Option<SwarmEstimate>,
+    /// The routing table , handed back when the task ends
+    table: oneshot::Receiver<RoutingTable>,
+    _task: Box<dyn TaskHandle>,
+}
+
+impl DhtService {
+    /// Binds the node on the configured port (and `bind` , when set) and starts it , `peer_port`
+    /// goes out in our announces. The node's socket , its task and its timers come from `runtime`
+    pub async fn start(
+        config: &DhtConfig,
+        table: RoutingTable,
+        info_hash: [u8; 20],
+        peer_port: u16,
+        bind: Option<&BindTarget>,
+        runtime: SharedRuntime,
+    ) -> Result<Self> {
+        let port = config.listen_port(peer_port);
+        let clock = runtime.clock();
+        let node = Dht::bind_on(runtime.clone(), port, table.own_id(), bind)
+            .await?
+            .with_config(config);
+
+        let (lookups, requests) = mpsc::unbounded_channel();
+        let (found_tx, found) = mpsc::unbounded_channel();
+        let (table_tx, table_rx) = oneshot::channel();
+        let task = runtime.spawn(Box::pin(async move {
+            let table = run(node, table, info_hash, peer_port, requests, found_tx, clock).await;
+            let _ = table_tx.send(table);
+        }));
+        Ok(Self {
+            port,
+            lookups,
+            found,
+            estimate: None,
+            table: table_rx,
+            _task: task,
+        })
+    }
+
+    /// UDP port the node listens on , what goes out in Port messages
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Looks the torrent up on the next turn instead of waiting for `LOOKUP_INTERVAL` , e.g when
+    /// the swarm ran out of peers
+    pub fn lookup_now(&self) {
+        let _ = self.lookups.send(());
+    }
+
+    /// Peers found since the last poll
+    pub fn poll(&mut self) -> Vec<DiscoveredPeer> {
+        let mut peers = Vec::new();
+        while let Ok(lookup) = self.found.try_recv() {
+            if lookup.swarm.responses > 0 {
+                self.estimate = Some(lookup.swarm);
+            }
+            peers.extend(lookup.peers.into_iter().map(|addr| {
+                DiscoveredPeer::new(PeerHost::Ip(addr.ip()), addr.port())
+                    .with_source(PeerSource::Dht)
+            }));
+        }
+        peers
+    }
+
+    /// Swarm size from the last lookup's scrape , once per lookup and after `poll`.
+    /// None when no node sent bloom filters
+    pub fn take_estimate(&mut self) -> Option<SwarmEstimate> {
+        self.estimate.take()
+    }
+
+    /// Stops the node once it's done with what it's doing , handing back the routing table to save.
+    /// None when the task panicked
+    pub async fn stop(self) -> Option<RoutingTable> {
+        drop(self.lookups);
+        self.table.await.ok()
+    }
+}
+
+async fn run(
+    mut node: Dht,
+    mut table: RoutingTable,
+    info_hash: [u8; 20],
+    peer_port: u16,
+    mut requests: mpsc::UnboundedReceiver<()>,
+    found: mpsc::UnboundedSender<Lookup>,
+    clock: SharedClock,
+) -> RoutingTable {
+    if let Err(e) = node.bootstrap(&mut table).await {
+        crate::log_line!("Could not join the DHT : {}", e);
+    }
+
+    let mut next_lookup = clock.now();
+    loop {
+        loop {
+            match requests.try_recv() {
+                Ok(()) => next_lookup = clock.now(),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return table,
+            }
+        }
+
+        if clock.now() >= next_lookup {
+            next_lookup = clock.now() + LOOKUP_INTERVAL;
+            match node
+                .announce_peer(&mut table, info_hash, Some(peer_port))
+                .await
+            {
+                Ok(lookup) => {
+                    let _ = found.send(lookup);
+                }
+                Err(e) => crate::log_line!("DHT lookup failed : {}", e),
+            }
+        }
+
+        for target in table.due_refreshes() {
+            let _ = node.find_node(&mut table, target).await;
+        }
+        node.serve(&mut table, SERVE_SLICE).await;
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{
+    core::config::config_dir,
+    net::dht::{
+        krpc::{encode_nodes, parse_nodes},
+        routing::{NodeId, RoutingTable},
+    },
+    protocol::bencode::BencodeValue,
+    storage::resume::{Envelope, read_with_backup, write_atomic},
+};
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+
+/// Format tag of the DHT state file
+pub const DHT_STATE_FORMAT: &str = "sekiro-dht";
+
+pub const DHT_STATE_VERSION: i64 = 1;
+
+/// Where the routing table is kept between runs , None if there is no config directory
+pub fn default_state_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("dht.dat"))
+}
+
+impl RoutingTable {
+    /// Writes our node id and every known node , in the compact node format of BEP 5
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let known: Vec<_> = self.nodes().map(|node| (node.id, node.addr)).collect();
+        let (nodes, nodes6) = encode_nodes(&known);
+
+        let chunks = BencodeValue::dict(vec![
+            (b"id", BencodeValue::bytes(&self.own_id().0)),
+            (b"nodes", BencodeValue::bytes(&nodes)),
+            (b"nodes6", BencodeValue::bytes(&nodes6)),
+        ]);
+        let envelope = Envelope::new(DHT_STATE_FORMAT, DHT_STATE_VERSION, chunks);
+
+        // A crash mid write keeps the previous table , which also stays around as backup
+        write_atomic(path, &envelope.encode())
+    }
+
+    /// Reads a table saved by `save` , or its backup when that is corrupt. Loaded nodes count as
+    /// unverified until they answer us
+    pub fn load(path: &Path) -> Result<Self> {
+        read_with_backup(path, Self::decode).map(|read| read.value)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let envelope = Envelope::decode(bytes, DHT_STATE_FORMAT)?;
+
+        if envelope.version != DHT_STATE_VERSION {
+            return Err(anyhow!(
+                "Unsupported DHT state version {}",
+                envelope.version
+            ));
+        }
+
+        let id: [u8; 20] = envelope
+            .chunks
+            .get(b"id")
+            .and_then(|v| v.as_bytes())
+            .and_then(|id| id.as_ref().try_into().ok())
+            .ok_or_else(|| anyhow!("DHT state has no node id"))?;
+
+        let mut table = RoutingTable::new(NodeId(id));
+
+        for (key, ipv6) in [(b"nodes".as_slice(), false), (b"nodes6", true)] {
+            if let Some(nodes) = envelope.chunks.get(key).and_then(|v| v.as_bytes()) {
+                for (id, addr) in parse_nodes(nodes, ipv6) {
+                    table.insert_unverified(id, addr);
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Loads the saved table , or starts a fresh one with a new id when there is none or it's unreadable
+    pub fn load_or_new(path: Option<&Path>) -> Self {
+        let Some(path) = path else {
+            return RoutingTable::new(NodeId::random());
+        };
+
+        match RoutingTable::load(path) {
+            Ok(table) => table,
+            Err(e) => {
+                if path.exists() {
+                    crate::log_line!("Could not load DHT state from {} : {}", path.display(), e);
+                }
+                RoutingTable::new(NodeId::random())
+            }
+        }
+    }
+}
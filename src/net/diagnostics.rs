@@ -0,0 +1,87 @@
+use crate::util::humanize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// A block request sent to a peer and not answered yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutstandingRequest {
+    pub piece_index: usize,
+    pub begin: usize,
+    pub length: usize,
+    /// Time since the request went out
+    pub age: Duration,
+}
+
+/// What one connection is waiting on , for debugging stalls in request pipelining and timeouts
+///
+/// The BlockManager fills in what the engine knows (`BlockManager::peer_diagnostics`) ,
+/// `PeerManager::diagnostics` adds the connection and pipeline side
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerDiagnostics {
+    pub addr: SocketAddr,
+    /// Requests to the peer , oldest first
+    pub outstanding: Vec<OutstandingRequest>,
+    /// Blocks the peer asked for that wait on the disk thread
+    pub pending_uploads: usize,
+    /// Messages waiting in the connection's send queue , None without a connection
+    pub queued_messages: Option<usize>,
+    /// Smoothed time from request to block , None until a block arrived since the peer unchoked us
+    pub rtt: Option<Duration>,
+    /// Requests the pipeline aims to keep outstanding , None while the peer chokes us
+    pub pipeline_depth: Option<usize>,
+    pub peer_choking: bool,
+    pub snubbed: bool,
+}
+
+impl PeerDiagnostics {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            outstanding: Vec::new(),
+            pending_uploads: 0,
+            queued_messages: None,
+            rtt: None,
+            pipeline_depth: None,
+            peer_choking: true,
+            snubbed: false,
+        }
+    }
+
+    /// Age of the longest waiting request
+    pub fn oldest_request(&self) -> Option<Duration> {
+        self.outstanding.first().map(|request| request.age)
+    }
+}
+
+impl fmt::Display for PeerDiagnostics {
+    /// e.g "1.2.3.4:6881 : 3/8 requests (oldest 12s) , 0 queued , rtt 180ms , 1 upload waiting"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} : {}", self.addr, self.outstanding.len())?;
+        if let Some(depth) = self.pipeline_depth {
+            write!(f, "/{}", depth)?;
+        }
+        write!(f, " requests")?;
+        if let Some(oldest) = self.oldest_request() {
+            write!(f, " (oldest {})", humanize::duration(oldest))?;
+        }
+        match self.queued_messages {
+            Some(queued) => write!(f, " , {} queued", queued)?,
+            None => write!(f, " , queue -")?,
+        }
+        match self.rtt {
+            Some(rtt) => write!(f, " , rtt {}ms", rtt.as_millis())?,
+            None => write!(f, " , rtt -")?,
+        }
+        if self.pending_uploads > 0 {
+            write!(f, " , {} uploads waiting", self.pending_uploads)?;
+        }
+        if self.peer_choking {
+            write!(f, " [choked]")?;
+        }
+        if self.snubbed {
+            write!(f, " [snubbed]")?;
+        }
+        Ok(())
+    }
+}
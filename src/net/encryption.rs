@@ -0,0 +1,125 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+/// How a connection to a peer is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectMode {
+    /// Plain BitTorrent handshake
+    Plaintext,
+    /// Message stream encryption handshake first (MSE / PE)
+    Encrypted,
+}
+
+/// Which connect modes we're willing to use , and in which order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionPolicy {
+    /// Plaintext only
+    Disabled,
+    /// Plaintext first , encrypted if the peer drops us
+    #[default]
+    Enabled,
+    /// Encrypted first , plaintext if the peer doesn't speak MSE
+    Preferred,
+    /// Encrypted only , peers that refuse are never connected
+    Required,
+}
+
+impl EncryptionPolicy {
+    /// Accepts what `Display` prints
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "disabled" => Ok(EncryptionPolicy::Disabled),
+            "enabled" => Ok(EncryptionPolicy::Enabled),
+            "preferred" => Ok(EncryptionPolicy::Preferred),
+            "required" => Ok(EncryptionPolicy::Required),
+            other => Err(anyhow!("Unknown encryption policy : {}", other)),
+        }
+    }
+
+    /// Modes to try with a peer we know nothing about , in order
+    pub fn modes(&self) -> &'static [ConnectMode] {
+        match self {
+            EncryptionPolicy::Disabled => &[ConnectMode::Plaintext],
+            EncryptionPolicy::Enabled => &[ConnectMode::Plaintext, ConnectMode::Encrypted],
+            EncryptionPolicy::Preferred => &[ConnectMode::Encrypted, ConnectMode::Plaintext],
+            EncryptionPolicy::Required => &[ConnectMode::Encrypted],
+        }
+    }
+
+    pub fn allows(&self, mode: ConnectMode) -> bool {
+        self.modes().contains(&mode)
+    }
+}
+
+impl fmt::Display for EncryptionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EncryptionPolicy::Disabled => "disabled",
+            EncryptionPolicy::Enabled => "enabled",
+            EncryptionPolicy::Preferred => "preferred",
+            EncryptionPolicy::Required => "required",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Remembers which mode each peer accepted
+///
+/// A peer that dropped our plaintext handshake gets retried with MSE straight away (or the other
+/// way round , depending on the policy) , and the mode that worked is tried first next time so
+/// reconnects don't pay for the failed attempt again
+#[derive(Debug, Clone, Default)]
+pub struct ConnectModes {
+    policy: EncryptionPolicy,
+    accepted: HashMap<SocketAddr, ConnectMode>,
+}
+
+impl ConnectModes {
+    pub fn new(policy: EncryptionPolicy) -> Self {
+        Self {
+            policy,
+            accepted: HashMap::new(),
+        }
+    }
+
+    pub fn policy(&self) -> EncryptionPolicy {
+        self.policy
+    }
+
+    /// Changes the policy , remembered modes it no longer allows are dropped
+    pub fn set_policy(&mut self, policy: EncryptionPolicy) {
+        self.policy = policy;
+        self.accepted.retain(|_, mode| policy.allows(*mode));
+    }
+
+    /// Modes to try with `addr` , in order. The next one is tried when a handshake is rejected
+    pub fn attempts(&self, addr: &SocketAddr) -> Vec<ConnectMode> {
+        let mut modes = self.policy.modes().to_vec();
+        if let Some(known) = self.accepted.get(addr)
+            && let Some(position) = modes.iter().position(|mode| mode == known)
+        {
+            let known = modes.remove(position);
+            modes.insert(0, known);
+        }
+        modes
+    }
+
+    /// The peer completed a handshake in `mode`
+    pub fn accepted(&mut self, addr: SocketAddr, mode: ConnectMode) {
+        self.accepted.insert(addr, mode);
+    }
+
+    /// The peer dropped a handshake in `mode` , forget it if that's the mode we remembered
+    pub fn rejected(&mut self, addr: &SocketAddr, mode: ConnectMode) {
+        if self.accepted.get(addr) == Some(&mode) {
+            self.accepted.remove(addr);
+        }
+    }
+
+    /// Mode that worked with `addr` last time
+    pub fn known_mode(&self, addr: &SocketAddr) -> Option<ConnectMode> {
+        self.accepted.get(addr).copied()
+    }
+}
@@ -0,0 +1,113 @@
+//! Country and ASN of peer addresses from local MaxMind format databases (GeoLite2 , GeoIP2 , DB-IP)
+//!
+//! Only files on disk are read , addresses are never sent anywhere for a lookup
+
+use crate::{
+    core::peer::{PeerLocation, PeerSnapshot},
+    net::swarm_health::SwarmHealth,
+};
+use anyhow::{Result, anyhow};
+use maxminddb::{Reader, geoip2};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Country and ASN databases loaded into memory , either can be missing
+#[derive(Default)]
+pub struct GeoIp {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl fmt::Debug for GeoIp {
+    // The readers hold the whole database , only their types are worth printing
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = |reader: &Option<Reader<Vec<u8>>>| {
+            reader
+                .as_ref()
+                .map(|reader| reader.metadata.database_type.clone())
+        };
+        f.debug_struct("GeoIp")
+            .field("country", &kind(&self.country))
+            .field("asn", &kind(&self.asn))
+            .finish()
+    }
+}
+
+impl GeoIp {
+    /// Loads every database in `paths` , see `add`
+    pub fn open(paths: &[impl AsRef<Path>]) -> Result<Self> {
+        let mut geoip = Self::default();
+        for path in paths {
+            geoip.add(path.as_ref())?;
+        }
+        Ok(geoip)
+    }
+
+    /// Loads a database , what it holds is read from its metadata
+    ///
+    /// ASN and ISP databases give the ASN , country and city databases the country. A second
+    /// database of the same kind replaces the first
+    pub fn add(&mut self, path: &Path) -> Result<()> {
+        let reader = Reader::open_readfile(path)
+            .map_err(|e| anyhow!("Could not read GeoIP database {} : {}", path.display(), e))?;
+        let kind = reader.metadata.database_type.clone();
+
+        if kind.contains("ASN") || kind.contains("ISP") {
+            self.asn = Some(reader);
+        } else if kind.contains("Country") || kind.contains("City") {
+            self.country = Some(reader);
+        } else {
+            return Err(anyhow!(
+                "{} is a {} database , expected a country , city or ASN one",
+                path.display(),
+                kind
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.country.is_none() && self.asn.is_none()
+    }
+
+    /// Where an address is , None when no database knows it
+    pub fn lookup(&self, ip: IpAddr) -> Option<PeerLocation> {
+        // Peers on a dual stack socket show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+
+        let country = self
+            .country
+            .as_ref()
+            .and_then(|reader| reader.lookup::<geoip2::Country>(ip).ok())
+            .and_then(|record| record.country?.iso_code.map(String::from));
+        let asn = self
+            .asn
+            .as_ref()
+            .and_then(|reader| reader.lookup::<geoip2::Asn>(ip).ok());
+
+        let location = PeerLocation {
+            country,
+            asn: asn.as_ref().and_then(|asn| asn.autonomous_system_number),
+            as_org: asn
+                .as_ref()
+                .and_then(|asn| asn.autonomous_system_organization)
+                .map(String::from),
+        };
+        (!location.is_empty()).then_some(location)
+    }
+
+    /// Fills in the location of every snapshot
+    pub fn annotate(&self, peers: &mut [PeerSnapshot]) {
+        for peer in peers {
+            peer.location = self.lookup(peer.addr.ip());
+        }
+    }
+
+    /// Fills in the location of the top peers
+    pub fn annotate_health(&self, health: &mut SwarmHealth) {
+        for peer in &mut health.top_peers {
+            peer.location = self.lookup(peer.addr.ip());
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Takes incoming peer connections on our listen port
+//!
+//! A background task accepts sockets , the handshakes run as tasks owned by the listener so a
+//! peer that never sends one doesn't hold up the rest. Connections that got through are picked up
+//! with `poll` and handed to `PeerManager::add_incoming`
+
+use crate::{
+    core::{
+        bind::BindTarget,
+        resources::{Resource, Tracked},
+    },
+    net::{connect::ConnectionSetup, peer_connection::PeerConnection},
+    protocol::handshake::Handshake,
+};
+use anyhow::Result;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::mpsc,
+    task::{JoinHandle, JoinSet},
+};
+
+/// Connections the OS queues for us before they're accepted
+const LISTEN_BACKLOG: u32 = 128;
+
+/// Handshakes running at once , sockets past that are closed right away
+pub const MAX_PENDING_HANDSHAKES: usize = 32;
+
+/// Pause after a failed accept , e.g while we're out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Our handshake for the info hash a peer asked for , None for torrents we don't serve
+pub type Answer = Arc<dyn Fn(&[u8; 20]) -> Option<Handshake> + Send + Sync>;
+
+/// Listening socket for peer connections , see the module docs. Dropping it stops listening
+pub struct PeerListener {
+    addr: SocketAddr,
+    answer: Answer,
+    sockets: mpsc::UnboundedReceiver<TcpStream>,
+    handshakes: JoinSet<Result<PeerConnection>>,
+    task: JoinHandle<()>,
+}
+
+impl PeerListener {
+    /// Listens on `port` , on the bound address or interface when there is one.
+    /// Must be called inside a tokio runtime
+    pub fn bind(port: u16, bind: Option<&BindTarget>, answer: Answer) -> Result<Self> {
+        let addr = bind.map_or_else(
+            || SocketAddr::from(([0, 0, 0, 0], port)),
+            |bind| bind.listen_addr(port),
+        );
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(bind) = bind {
+            bind.prepare_listener(&socket)?;
+        }
+        socket.set_reuseaddr(true)?;
+        socket.bind(addr)?;
+        let listener = socket.listen(LISTEN_BACKLOG)?;
+        let addr = listener.local_addr()?;
+
+        let (found, sockets) = mpsc::unbounded_channel();
+        let task = Tracked::new(Resource::Task);
+        let task = tokio::spawn(async move {
+            let _task = task;
+            accept_loop(listener, found).await
+        });
+        Ok(Self {
+            addr,
+            answer,
+            sockets,
+            handshakes: JoinSet::new(),
+            task,
+        })
+    }
+
+    /// Address we listen on , with the port the OS picked when asked for 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connections that got through the handshake since the last poll , failed ones are dropped
+    pub fn poll(&mut self) -> Vec<PeerConnection> {
+        while let Ok(stream) = self.sockets.try_recv() {
+            if self.handshakes.len() >= MAX_PENDING_HANDSHAKES {
+                continue;
+            }
+            let answer = self.answer.clone();
+            self.handshakes.spawn(async move {
+                let established =
+                    ConnectionSetup::accept(stream, |info_hash| answer(info_hash)).await?;
+                Ok(PeerConnection::start(established))
+            });
+        }
+
+        let mut accepted = Vec::new();
+        while let Some(joined) = self.handshakes.try_join_next() {
+            if let Ok(Ok(connection)) = joined {
+                accepted.push(connection);
+            }
+        }
+        accepted
+    }
+}
+
+impl fmt::Debug for PeerListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerListener")
+            .field("addr", &self.addr)
+            .field("handshakes", &self.handshakes.len())
+            .finish()
+    }
+}
+
+impl Drop for PeerListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept_loop(listener: TcpListener, found: mpsc::UnboundedSender<TcpStream>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                if found.send(stream).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                crate::log_line!("Could not accept a peer connection : {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Fetches the info dictionary of a magnet link from peers (BEP 9)
+//!
+//! Each peer is dialed with the magnet's info hash and asked for the metadata over `ut_metadata`.
+//! A few pieces are kept in flight per peer , a peer that refuses , lies about the size , sends
+//! pieces it wasn't asked for or a dictionary that doesn't hash to the info hash is dropped and the
+//! next one is tried. Requests to a peer are paced to `chunk_rate` pieces per second
+
+use crate::{
+    core::{
+        bind::BindTarget,
+        clock::{SharedClock, system_clock},
+        config::MetadataConfig,
+        peer::Peer,
+    },
+    net::{peer_connection::PeerConnection, rate_limit::RateLimiter},
+    protocol::{
+        extension::{EXTENDED_HANDSHAKE_ID, ExtendedHandshake},
+        handshake::{Extensions, Handshake},
+        magnet::MagnetUri,
+        message::PeerMessage,
+        metadata::{
+            DEFAULT_MAX_METADATA_SIZE, LOCAL_UT_METADATA_ID, MetadataDownload, MetadataMessage,
+            UT_METADATA,
+        },
+        torrent::Torrent,
+    },
+};
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How long one peer gets to hand over the whole dictionary
+pub const METADATA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Metadata pieces requested from a peer at once
+const METADATA_REQUESTS_IN_FLIGHT: usize = 4;
+
+/// Metadata pieces requested per second from one peer , 1 MiB/s of 16 KiB pieces
+pub const DEFAULT_METADATA_CHUNK_RATE: u32 = 64;
+
+/// A metadata fetch for a magnet link , configured with the `with_*` methods and run with `fetch`
+#[derive(Debug, Clone)]
+pub struct MetadataFetch {
+    pub magnet: MagnetUri,
+    peer_id: [u8; 20],
+    max_size: usize,
+    /// Pieces requested per second from each peer , None doesn't pace requests
+    chunk_rate: Option<u32>,
+    listen_port: u16,
+    /// Address or interface the connections to peers leave through
+    bind: Option<BindTarget>,
+    clock: SharedClock,
+}
+
+impl MetadataFetch {
+    pub fn new(magnet: MagnetUri, peer_id: [u8; 20]) -> Self {
+        Self {
+            magnet,
+            peer_id,
+            max_size: DEFAULT_MAX_METADATA_SIZE,
+            chunk_rate: Some(DEFAULT_METADATA_CHUNK_RATE),
+            listen_port: 0,
+            bind: None,
+            clock: system_clock(),
+        }
+    }
+
+    /// Largest info dictionary accepted , bigger announced sizes are refused before any request
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Pieces asked of one peer per second , None or 0 asks as fast as the peer answers
+    pub fn with_chunk_rate(mut self, chunk_rate: Option<u32>) -> Self {
+        self.chunk_rate = chunk_rate.filter(|&rate| rate > 0);
+        self
+    }
+
+    /// Size and rate caps from the config
+    pub fn with_config(self, config: &MetadataConfig) -> Self {
+        self.with_max_size(config.max_size)
+            .with_chunk_rate(config.chunk_rate)
+    }
+
+    /// Port put in our extension handshake
+    pub fn with_listen_port(mut self, port: u16) -> Self {
+        self.listen_port = port;
+        self
+    }
+
+    /// Connects to peers through the configured address or interface , like downloads do
+    pub fn with_bind(mut self, bind: Option<BindTarget>) -> Self {
+        self.bind = bind;
+        self
+    }
+
+    /// Replaces the clock request pacing runs on
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Asks each peer in turn until one hands over metadata that matches the info hash
+    ///
+    /// The torrent gets the magnet's trackers , one tier each
+    pub async fn fetch(&self, peers: &[SocketAddr]) -> Result<Torrent> {
+        if peers.is_empty() {
+            return Err(anyhow!("No peers to fetch the metadata from"));
+        }
+
+        for &addr in peers {
+            match self.from_peer(addr).await {
+                Ok(info) => return Torrent::from_info_bytes(&info, &self.magnet.trackers),
+                Err(e) => crate::log_line!("Metadata from {} failed : {}", addr, e),
+            }
+        }
+        Err(anyhow!(
+            "None of {} peers sent the metadata for {}",
+            peers.len(),
+            self.magnet.name()
+        ))
+    }
+
+    /// The raw info dictionary from one peer , checked against the info hash
+    pub async fn from_peer(&self, addr: SocketAddr) -> Result<Bytes> {
+        tokio::time::timeout(METADATA_TIMEOUT, self.exchange(addr))
+            .await
+            .map_err(|_| anyhow!("{} took longer than {:?}", addr, METADATA_TIMEOUT))?
+    }
+
+    async fn exchange(&self, addr: SocketAddr) -> Result<Bytes> {
+        // Only here for the metadata , the DHT bit would promise a Port message
+        let ours =
+            Handshake::new(self.magnet.info_hash, self.peer_id).with_extensions(Extensions {
+                extension_protocol: true,
+                ..Extensions::default()
+            });
+        let mut connection = PeerConnection::dial(
+            &Peer::new(addr, self.clock.now()),
+            &ours,
+            self.bind.as_ref(),
+        )
+        .await?;
+        if !connection.remote().extensions().extension_protocol {
+            return Err(anyhow!("{} doesn't support the extension protocol", addr));
+        }
+        connection
+            .send(ExtendedHandshake::ours(false, self.listen_port).to_message())
+            .await?;
+
+        let mut download: Option<(u8, MetadataDownload)> = None;
+        let mut in_flight = 0usize;
+        let pace = match self.chunk_rate {
+            Some(rate) => RateLimiter::new(rate as u64),
+            None => RateLimiter::unlimited(),
+        }
+        .with_clock(self.clock.clone());
+        loop {
+            let message = connection
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("{} closed the connection", addr))?;
+            let PeerMessage::Extended { id, payload } = message else {
+                continue;
+            };
+
+            match id {
+                EXTENDED_HANDSHAKE_ID => {
+                    let handshake = ExtendedHandshake::decode(&payload)?;
+                    let remote_id = handshake
+                        .message_id(UT_METADATA)
+                        .ok_or_else(|| anyhow!("{} doesn't support ut_metadata", addr))?;
+                    let size = handshake
+                        .metadata_size
+                        .ok_or_else(|| anyhow!("{} didn't say how big the metadata is", addr))?;
+                    let metadata =
+                        MetadataDownload::new(self.magnet.info_hash, size, self.max_size)?;
+                    download = Some((remote_id, metadata));
+                }
+                LOCAL_UT_METADATA_ID => {
+                    let Some((remote_id, metadata)) = download.as_mut() else {
+                        continue;
+                    };
+                    match MetadataMessage::decode(&payload)? {
+                        MetadataMessage::Data {
+                            piece,
+                            total_size,
+                            data,
+                        } => {
+                            in_flight = in_flight.saturating_sub(1);
+                            metadata.receive(piece, total_size, data)?;
+                            if metadata.is_complete() {
+                                return metadata.finish();
+                            }
+                        }
+                        MetadataMessage::Reject { piece } => {
+                            return Err(anyhow!("{} refused metadata piece {}", addr, piece));
+                        }
+                        // We have nothing to give yet
+                        MetadataMessage::Request { piece } => {
+                            let reject = MetadataMessage::Reject { piece };
+                            connection.send(reject.to_message(*remote_id)).await?;
+                        }
+                    }
+                }
+                _ => continue,
+            }
+
+            if let Some((remote_id, metadata)) = download.as_mut() {
+                while in_flight < METADATA_REQUESTS_IN_FLIGHT
+                    && let Some(piece) = metadata.next_request()
+                {
+                    let request = MetadataMessage::Request { piece };
+                    pace.acquire(1).await;
+                    connection.send(request.to_message(*remote_id)).await?;
+                    in_flight += 1;
+                }
+            }
+        }
+    }
+}
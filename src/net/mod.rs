@@ -1,3 +1,34 @@
+#[cfg(feature = "http-tracker")]
+pub mod announce_pool;
+#[cfg(feature = "http-tracker")]
+pub mod announcer;
+pub mod availability;
 pub mod block_manager;
+pub mod choker;
+pub mod connect;
+#[cfg(feature = "dht")]
+pub mod dht;
+pub mod diagnostics;
+pub mod encryption;
+#[cfg(feature = "geoip")]
+pub mod geoip;
+pub mod listener;
+pub mod metadata_fetch;
+pub mod mse;
+pub mod peer_candidates;
+pub mod peer_connection;
+pub mod peer_manager;
 pub mod piece_manager;
+pub mod port_policy;
+pub mod rate_limit;
+pub mod request_pipeline;
+pub mod request_scheduler;
+pub mod swarm_health;
+#[cfg(feature = "http-tracker")]
+pub mod torrent_fetch;
 pub mod tracker;
+pub mod tracker_manager;
+pub mod tracker_tier;
+pub mod tracker_url;
+pub mod udp_tracker;
+pub mod wire_dump;
@@ -0,0 +1,407 @@
+//! Building blocks of message stream encryption (MSE / PE)
+//!
+//! A Diffie-Hellman exchange over a fixed 768 bit prime gives both sides a shared secret , which
+//! keys one RC4 cipher per direction. The handshake itself runs in `connect` , this module only has
+//! the arithmetic , the ciphers and the stream wrapper the connection runs over afterwards
+
+use crate::core::runtime::PeerStream;
+use sha1::{Digest, Sha1};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes of a public key or the shared secret
+pub const KEY_LEN: usize = 96;
+
+/// Longest padding either side may send
+pub const MAX_PAD: usize = 512;
+
+/// Verification constant , 8 zero bytes sent encrypted so the other side can find where the cipher starts
+pub const VC: [u8; 8] = [0; 8];
+
+/// Bits of crypto_provide / crypto_select
+pub const CRYPTO_PLAINTEXT: u32 = 0x01;
+pub const CRYPTO_RC4: u32 = 0x02;
+
+/// Keystream thrown away before the first byte is encrypted , the start of RC4's is weak
+const RC4_DISCARD: usize = 1024;
+
+/// The prime all MSE peers use , big endian
+const PRIME: [u8; KEY_LEN] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xC9, 0x0F, 0xDA, 0xA2, 0x21, 0x68, 0xC2, 0x34,
+    0xC4, 0xC6, 0x62, 0x8B, 0x80, 0xDC, 0x1C, 0xD1, 0x29, 0x02, 0x4E, 0x08, 0x8A, 0x67, 0xCC, 0x74,
+    0x02, 0x0B, 0xBE, 0xA6, 0x3B, 0x13, 0x9B, 0x22, 0x51, 0x4A, 0x08, 0x79, 0x8E, 0x34, 0x04, 0xDD,
+    0xEF, 0x95, 0x19, 0xB3, 0xCD, 0x3A, 0x43, 0x1B, 0x30, 0x2B, 0x0A, 0x6D, 0xF2, 0x5F, 0x14, 0x37,
+    0x4F, 0xE1, 0x35, 0x6D, 0x6D, 0x51, 0xC2, 0x45, 0xE4, 0x85, 0xB5, 0x76, 0x62, 0x5E, 0x7E, 0xC6,
+    0xF4, 0x4C, 0x42, 0xE9, 0xA6, 0x3A, 0x36, 0x20, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+const GENERATOR: u64 = 2;
+
+/// Limbs of a number below the prime , least significant first
+const LIMBS: usize = KEY_LEN / 8;
+
+type Limbs = [u64; LIMBS];
+
+/// Our half of the key exchange
+pub struct KeyPair {
+    private: Limbs,
+    /// What goes to the peer , big endian
+    pub public: [u8; KEY_LEN],
+}
+
+impl KeyPair {
+    /// A fresh 160 bit private key , as the spec recommends
+    pub fn generate() -> Self {
+        let mut private = [0u64; LIMBS];
+        let bytes = random_bytes(20);
+        private[..3].copy_from_slice(&limbs_from_be(&bytes)[..3]);
+        let public = to_be(&mod_pow(&generator(), &private));
+        Self { private, public }
+    }
+
+    /// The secret both sides end up with , None when the peer's key is one no honest peer sends
+    pub fn shared_secret(&self, peer_public: &[u8; KEY_LEN]) -> Option<[u8; KEY_LEN]> {
+        let peer = limbs_from_be(peer_public);
+        let one = one();
+        let prime = prime();
+        // 0 , 1 and p - 1 or above would pin the secret to a value an eavesdropper can guess
+        if !less(&one, &peer) || !less(&peer, &sub(&prime, &one)) {
+            return None;
+        }
+        Some(to_be(&mod_pow(&peer, &self.private)))
+    }
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPair").finish_non_exhaustive()
+    }
+}
+
+/// SHA-1 over the parts in order , what the spec calls HASH()
+pub fn hash(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// RC4 keyed the MSE way , with the first 1024 bytes of keystream already thrown away
+#[derive(Clone)]
+pub struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    /// Cipher for one direction , `label` is "keyA" for what the initiator sends and "keyB" for
+    /// what it receives. `skey` is the torrent's info hash
+    pub fn new(label: &[u8], secret: &[u8; KEY_LEN], skey: &[u8; 20]) -> Self {
+        let key = hash(&[label, secret, skey]);
+        let mut state = [0u8; 256];
+        for (index, byte) in state.iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+
+        let mut cipher = Self { state, i: 0, j: 0 };
+        cipher.apply(&mut [0u8; RC4_DISCARD]);
+        cipher
+    }
+
+    /// Encrypts or decrypts `data` in place , the same operation either way
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let index = self.state[self.i as usize].wrapping_add(self.state[self.j as usize]);
+            *byte ^= self.state[index as usize];
+        }
+    }
+}
+
+impl fmt::Debug for Rc4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rc4").finish_non_exhaustive()
+    }
+}
+
+/// A peer connection past the MSE handshake , everything read is decrypted and everything written
+/// encrypted
+pub struct EncryptedStream {
+    inner: Box<dyn PeerStream>,
+    read_cipher: Rc4,
+    write_cipher: Rc4,
+    /// Decrypted bytes that came in with the end of the handshake , handed out before reading more
+    buffered: Vec<u8>,
+    /// Encrypted bytes the socket hasn't taken yet , they go out before anything new is encrypted
+    unsent: Vec<u8>,
+}
+
+impl EncryptedStream {
+    /// `buffered` is plaintext already read off `inner` past the handshake
+    pub fn new(
+        inner: Box<dyn PeerStream>,
+        read_cipher: Rc4,
+        write_cipher: Rc4,
+        buffered: Vec<u8>,
+    ) -> Self {
+        Self {
+            inner,
+            read_cipher,
+            write_cipher,
+            buffered,
+            unsent: Vec::new(),
+        }
+    }
+
+    /// Writes out what's left of `unsent` , Ready once it's all gone
+    fn poll_unsent(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.unsent.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.unsent))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.unsent.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl fmt::Debug for EncryptedStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedStream")
+            .field("inner", &self.inner)
+            .field("buffered", &self.buffered.len())
+            .field("unsent", &self.unsent.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for EncryptedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.buffered.is_empty() {
+            let len = this.buffered.len().min(buf.remaining());
+            buf.put_slice(&this.buffered[..len]);
+            this.buffered.drain(..len);
+            return Poll::Ready(Ok(()));
+        }
+
+        let start = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.read_cipher.apply(&mut buf.filled_mut()[start..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for EncryptedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // The cipher moves on as bytes are encrypted , so nothing new is taken until the last
+        // write is out
+        ready!(this.poll_unsent(cx))?;
+        this.unsent.extend_from_slice(buf);
+        this.write_cipher.apply(&mut this.unsent);
+        // Taken either way , a flush finishes what the socket didn't want yet
+        let _ = this.poll_unsent(cx)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_unsent(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_unsent(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Random padding of up to `MAX_PAD` bytes
+pub fn random_pad() -> Vec<u8> {
+    let len = random_bytes(2);
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize % (MAX_PAD + 1);
+    random_bytes(len)
+}
+
+/// `len` random bytes
+///
+/// std has no rng , RandomState is seeded from the OS and gives new keys every call. Hashing a few
+/// of them spreads that over the output
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len + 20);
+    while bytes.len() < len {
+        let mut hasher = Sha1::new();
+        for _ in 0..4 {
+            let mut state = RandomState::new().build_hasher();
+            state.write_usize(bytes.len());
+            hasher.update(state.finish().to_le_bytes());
+        }
+        bytes.extend_from_slice(&hasher.finalize());
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+fn prime() -> Limbs {
+    limbs_from_be(&PRIME)
+}
+
+fn generator() -> Limbs {
+    let mut limbs = [0u64; LIMBS];
+    limbs[0] = GENERATOR;
+    limbs
+}
+
+fn one() -> Limbs {
+    let mut limbs = [0u64; LIMBS];
+    limbs[0] = 1;
+    limbs
+}
+
+/// Big endian bytes (up to `KEY_LEN` of them) to limbs
+fn limbs_from_be(bytes: &[u8]) -> Limbs {
+    let mut padded = [0u8; KEY_LEN];
+    padded[KEY_LEN - bytes.len()..].copy_from_slice(bytes);
+    let mut limbs = [0u64; LIMBS];
+    for (index, chunk) in padded.rchunks(8).enumerate() {
+        limbs[index] = u64::from_be_bytes(chunk.try_into().unwrap());
+    }
+    limbs
+}
+
+fn to_be(limbs: &Limbs) -> [u8; KEY_LEN] {
+    let mut bytes = [0u8; KEY_LEN];
+    for (index, chunk) in bytes.rchunks_mut(8).enumerate() {
+        chunk.copy_from_slice(&limbs[index].to_be_bytes());
+    }
+    bytes
+}
+
+fn less(a: &Limbs, b: &Limbs) -> bool {
+    a.iter().rev().lt(b.iter().rev())
+}
+
+/// a - b , wrapping below zero
+fn sub(a: &Limbs, b: &Limbs) -> Limbs {
+    let mut result = [0u64; LIMBS];
+    let mut borrow = false;
+    for index in 0..LIMBS {
+        let (diff, under) = a[index].overflowing_sub(b[index]);
+        let (diff, under_again) = diff.overflowing_sub(borrow as u64);
+        result[index] = diff;
+        borrow = under || under_again;
+    }
+    result
+}
+
+/// a + b mod p , both already below p
+fn add_mod(a: &Limbs, b: &Limbs, prime: &Limbs) -> Limbs {
+    let mut sum = [0u64; LIMBS];
+    let mut carry = false;
+    for index in 0..LIMBS {
+        let (total, over) = a[index].overflowing_add(b[index]);
+        let (total, over_again) = total.overflowing_add(carry as u64);
+        sum[index] = total;
+        carry = over || over_again;
+    }
+    // Below 2p , one subtraction is enough. The bit carried out is dropped by the wrap
+    if carry || !less(&sum, prime) {
+        sub(&sum, prime)
+    } else {
+        sum
+    }
+}
+
+/// a * b mod p , shifting and adding one bit of b at a time. Slow , only used to get into Montgomery form
+fn mul_mod(a: &Limbs, b: &Limbs, prime: &Limbs) -> Limbs {
+    let mut result = [0u64; LIMBS];
+    for index in (0..LIMBS).rev() {
+        for bit in (0..64).rev() {
+            result = add_mod(&result, &result, prime);
+            if b[index] >> bit & 1 == 1 {
+                result = add_mod(&result, a, prime);
+            }
+        }
+    }
+    result
+}
+
+/// a * b / 2^768 mod p (Montgomery multiplication) , both in Montgomery form
+///
+/// The prime's lowest limb is all ones , so -p^-1 mod 2^64 is 1 and each round's factor is just the
+/// lowest limb
+fn mont_mul(a: &Limbs, b: &Limbs, prime: &Limbs) -> Limbs {
+    let mut t = [0u64; LIMBS + 2];
+    for &word in b {
+        let mut carry = 0u128;
+        for index in 0..LIMBS {
+            let sum = t[index] as u128 + a[index] as u128 * word as u128 + carry;
+            t[index] = sum as u64;
+            carry = sum >> 64;
+        }
+        let sum = t[LIMBS] as u128 + carry;
+        t[LIMBS] = sum as u64;
+        t[LIMBS + 1] = (sum >> 64) as u64;
+
+        // Adding m * p clears the lowest limb , which is then shifted out
+        let m = t[0];
+        let mut carry = (t[0] as u128 + m as u128 * prime[0] as u128) >> 64;
+        for index in 1..LIMBS {
+            let sum = t[index] as u128 + m as u128 * prime[index] as u128 + carry;
+            t[index - 1] = sum as u64;
+            carry = sum >> 64;
+        }
+        let sum = t[LIMBS] as u128 + carry;
+        t[LIMBS - 1] = sum as u64;
+        t[LIMBS] = t[LIMBS + 1] + (sum >> 64) as u64;
+    }
+
+    let mut result = [0u64; LIMBS];
+    result.copy_from_slice(&t[..LIMBS]);
+    if t[LIMBS] != 0 || !less(&result, prime) {
+        result = sub(&result, prime);
+    }
+    result
+}
+
+fn mod_pow(base: &Limbs, exponent: &Limbs) -> Limbs {
+    let prime = prime();
+    // 2^768 mod p , the Montgomery form of 1
+    let r = sub(&[0u64; LIMBS], &prime);
+    let base = mul_mod(base, &r, &prime);
+    let mut result = r;
+    for index in (0..LIMBS).rev() {
+        for bit in (0..64).rev() {
+            result = mont_mul(&result, &result, &prime);
+            if exponent[index] >> bit & 1 == 1 {
+                result = mont_mul(&result, &base, &prime);
+            }
+        }
+    }
+    mont_mul(&result, &one(), &prime)
+}
@@ -0,0 +1,173 @@
+use crate::{
+    net::port_policy::PortPolicy,
+    protocol::peer::{DiscoveredPeer, PeerHost, PeerSource},
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+
+/// Peers we heard about (tracker , DHT , PEX) and haven't tried yet
+///
+/// Every source feeds into the same queue so a peer reported by several of them is only tried once ,
+/// and anything that turns out to be ourselves never makes it in. Each peer remembers every source
+/// that reported it
+#[derive(Debug, Clone)]
+pub struct PeerCandidates {
+    our_peer_id: [u8; 20],
+    listen_port: u16,
+    /// Addresses we know other peers reach us on
+    own_addrs: HashSet<SocketAddr>,
+    /// Every (host , port) that has been queued , kept after the peer is taken so it isn't queued again
+    seen: HashSet<(PeerHost, u16)>,
+    queue: VecDeque<DiscoveredPeer>,
+    /// Every source that reported a (host , port) , kept like `seen`
+    sources: HashMap<(PeerHost, u16), BTreeSet<PeerSource>>,
+    /// Distinct peers reported per source , a peer found by two sources counts for both
+    by_source: BTreeMap<PeerSource, usize>,
+    /// Ports we won't dial , peers advertising them are never queued
+    port_policy: PortPolicy,
+    /// Peers turned away by `port_policy`
+    blocked: usize,
+}
+
+impl PeerCandidates {
+    pub fn new(our_peer_id: [u8; 20], listen_port: u16) -> Self {
+        Self {
+            our_peer_id,
+            listen_port,
+            own_addrs: HashSet::new(),
+            seen: HashSet::new(),
+            queue: VecDeque::new(),
+            sources: HashMap::new(),
+            by_source: BTreeMap::new(),
+            port_policy: PortPolicy::default(),
+            blocked: 0,
+        }
+    }
+
+    /// Port we're listening on changed , connections back to the old port are no longer to ourselves
+    pub fn set_listen_port(&mut self, listen_port: u16) {
+        self.listen_port = listen_port;
+    }
+
+    /// Changes which ports may be dialed , peers already queued on a now blocked port are dropped
+    pub fn set_port_policy(&mut self, policy: PortPolicy) {
+        let before = self.queue.len();
+        self.queue.retain(|peer| policy.allows(peer.port));
+        self.blocked += before - self.queue.len();
+        self.port_policy = policy;
+    }
+
+    /// Peers skipped because of their port
+    pub fn blocked(&self) -> usize {
+        self.blocked
+    }
+
+    /// Registers an address we're reachable on (e.g the external ip a tracker reported)
+    pub fn add_own_addr(&mut self, addr: SocketAddr) {
+        self.own_addrs.insert(addr);
+    }
+
+    /// Whether connecting to `addr` would just connect back to us
+    pub fn is_own_addr(&self, addr: &SocketAddr) -> bool {
+        if self.own_addrs.contains(addr) {
+            return true;
+        }
+
+        let ip = addr.ip();
+        addr.port() == self.listen_port && (ip.is_loopback() || ip.is_unspecified())
+    }
+
+    /// Whether a handshake came from our own peer id , such connections must be closed straight away
+    pub fn is_self_handshake(&self, remote_peer_id: &[u8; 20]) -> bool {
+        remote_peer_id == &self.our_peer_id
+    }
+
+    /// Queues a peer , returns false when it is a duplicate , ourselves or on a blocked port
+    ///
+    /// A duplicate still adds its source to the peer , and its peer id if the queued entry had none
+    pub fn insert(&mut self, peer: DiscoveredPeer) -> bool {
+        if peer.peer_id.as_ref() == Some(&self.our_peer_id) {
+            return false;
+        }
+
+        if !self.port_policy.allows(peer.port) {
+            self.blocked += 1;
+            return false;
+        }
+
+        if let Some(addr) = peer.socket_addr()
+            && self.is_own_addr(&addr)
+        {
+            return false;
+        }
+
+        let key = (peer.host.clone(), peer.port);
+        if self
+            .sources
+            .entry(key.clone())
+            .or_default()
+            .insert(peer.source)
+        {
+            *self.by_source.entry(peer.source).or_insert(0) += 1;
+        }
+
+        if !self.seen.insert(key) {
+            if let Some(peer_id) = peer.peer_id
+                && let Some(queued) = self
+                    .queue
+                    .iter_mut()
+                    .find(|queued| queued.host == peer.host && queued.port == peer.port)
+            {
+                queued.peer_id.get_or_insert(peer_id);
+            }
+            return false;
+        }
+
+        self.queue.push_back(peer);
+        true
+    }
+
+    /// Queues a batch of peers , returns how many were new
+    pub fn extend(&mut self, peers: impl IntoIterator<Item = DiscoveredPeer>) -> usize {
+        peers
+            .into_iter()
+            .filter(|peer| self.insert(peer.clone()))
+            .count()
+    }
+
+    /// Next peer to try connecting to
+    pub fn next_candidate(&mut self) -> Option<DiscoveredPeer> {
+        self.queue.pop_front()
+    }
+
+    /// Lets a peer be queued again , call it once a connection to it has ended
+    pub fn forget(&mut self, host: &PeerHost, port: u16) {
+        self.seen.remove(&(host.clone(), port));
+    }
+
+    /// Every source that reported a peer , empty for peers we never heard about
+    pub fn sources(&self, host: &PeerHost, port: u16) -> BTreeSet<PeerSource> {
+        self.sources
+            .get(&(host.clone(), port))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Distinct peers heard about , whatever the source
+    pub fn known(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// How many distinct peers each source turned up
+    pub fn known_by_source(&self) -> &BTreeMap<PeerSource, usize> {
+        &self.by_source
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
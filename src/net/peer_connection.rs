@@ -0,0 +1,302 @@
+use crate::{
+    core::{
+        bind::BindTarget,
+        peer::Peer,
+        resources::{Resource, Tracked},
+        runtime::{PeerStream, Runtime, TaskHandle, TokioRuntime},
+    },
+    net::{
+        connect::{ConnectionSetup, Established},
+        encryption::ConnectMode,
+        rate_limit::{RateLimiter, Rates},
+    },
+    protocol::{
+        handshake::Handshake,
+        message::{MessageDecoder, PeerMessage, write_all},
+    },
+};
+use anyhow::{Result, anyhow};
+use bytes::BytesMut;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::{
+    io::{ReadHalf, WriteHalf},
+    sync::mpsc,
+};
+
+/// Messages buffered per direction before `send` waits (outgoing) or reading pauses (incoming)
+pub const PEER_CHANNEL_CAPACITY: usize = 64;
+
+/// A live connection to one peer
+///
+/// A reader task decodes messages off the socket and a writer task encodes what `send` queues , so
+/// whoever drives the connection (usually a loop feeding the BlockManager) only deals in messages.
+/// Dropping the connection stops both tasks and closes the socket
+#[derive(Debug)]
+pub struct PeerConnection {
+    addr: SocketAddr,
+    remote: Handshake,
+    mode: ConnectMode,
+    outgoing: mpsc::Sender<PeerMessage>,
+    incoming: mpsc::Receiver<PeerMessage>,
+    /// Why the connection ended , set by whichever task noticed first
+    closed: Arc<Mutex<Option<String>>>,
+    /// Budget the writer takes outgoing bytes from , unlimited until one is set
+    upload_limit: Arc<Mutex<RateLimiter>>,
+    /// Budget the reader takes incoming bytes from , unlimited until one is set
+    download_limit: Arc<Mutex<RateLimiter>>,
+    /// This connection's own caps , on top of the shared budgets
+    peer_upload: RateLimiter,
+    peer_download: RateLimiter,
+    reader: Box<dyn TaskHandle>,
+    writer: Box<dyn TaskHandle>,
+    _socket: Tracked,
+}
+
+impl PeerConnection {
+    /// Connects to the peer and trades handshakes , `ours` picks the torrent. `bind` is the
+    /// configured address or interface connections have to leave through
+    pub async fn dial(peer: &Peer, ours: &Handshake, bind: Option<&BindTarget>) -> Result<Self> {
+        Self::dial_on(&TokioRuntime::new(), peer, ours, bind).await
+    }
+
+    /// Same as `dial` , the socket and both tasks come from `runtime`
+    pub async fn dial_on(
+        runtime: &dyn Runtime,
+        peer: &Peer,
+        ours: &Handshake,
+        bind: Option<&BindTarget>,
+    ) -> Result<Self> {
+        let established = ConnectionSetup::connect_on(runtime, peer.addr, ours, bind).await?;
+        Ok(Self::start_on(runtime, established))
+    }
+
+    /// Starts the read and write tasks on a connection that got through the handshake
+    ///
+    /// Must be called inside a tokio runtime
+    pub fn start(established: Established) -> Self {
+        Self::start_on(&TokioRuntime::new(), established)
+    }
+
+    /// Same as `start` , with the tasks spawned on `runtime`
+    pub fn start_on(runtime: &dyn Runtime, established: Established) -> Self {
+        let Established {
+            stream,
+            addr,
+            remote,
+            mode,
+        } = established;
+        let (stream_for_reader, stream) = tokio::io::split(stream);
+        let closed = Arc::new(Mutex::new(None));
+        let closed_for_reader = closed.clone();
+        let closed_for_writer = closed.clone();
+        let upload_limit = Arc::new(Mutex::new(RateLimiter::unlimited()));
+        let upload_limit_for_writer = upload_limit.clone();
+        let download_limit = Arc::new(Mutex::new(RateLimiter::unlimited()));
+        let download_limit_for_reader = download_limit.clone();
+        let peer_upload = RateLimiter::unlimited();
+        let peer_download = RateLimiter::unlimited();
+        let peer_upload_for_writer = peer_upload.clone();
+        let peer_download_for_reader = peer_download.clone();
+
+        let (outgoing, outgoing_rx) = mpsc::channel(PEER_CHANNEL_CAPACITY);
+        let (incoming_tx, incoming) = mpsc::channel(PEER_CHANNEL_CAPACITY);
+
+        // Counted inside the tasks , an aborted task drops its count with it
+        let reader_task = Tracked::new(Resource::Task);
+        let writer_task = Tracked::new(Resource::Task);
+        let reader = runtime.spawn(Box::pin(async move {
+            let _task = reader_task;
+            read_loop(
+                stream_for_reader,
+                incoming_tx,
+                closed_for_reader,
+                download_limit_for_reader,
+                peer_download_for_reader,
+            )
+            .await
+        }));
+        let writer = runtime.spawn(Box::pin(async move {
+            let _task = writer_task;
+            write_loop(
+                stream,
+                outgoing_rx,
+                closed_for_writer,
+                upload_limit_for_writer,
+                peer_upload_for_writer,
+            )
+            .await
+        }));
+
+        Self {
+            addr,
+            remote,
+            mode,
+            outgoing,
+            incoming,
+            closed,
+            upload_limit,
+            download_limit,
+            peer_upload,
+            peer_download,
+            reader,
+            writer,
+            _socket: Tracked::new(Resource::Socket),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Whether the connection is encrypted
+    pub fn mode(&self) -> ConnectMode {
+        self.mode
+    }
+
+    /// The peer's handshake , its peer id and advertised extensions
+    pub fn remote(&self) -> &Handshake {
+        &self.remote
+    }
+
+    /// Queues a message , waits while the writer is PEER_CHANNEL_CAPACITY messages behind
+    pub async fn send(&self, message: PeerMessage) -> Result<()> {
+        self.outgoing
+            .send(message)
+            .await
+            .map_err(|_| self.closed_error())
+    }
+
+    /// Queues a message without waiting , fails when the writer is full or gone
+    pub fn try_send(&self, message: PeerMessage) -> Result<()> {
+        self.outgoing.try_send(message).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => anyhow!("Send queue to {} is full", self.addr),
+            mpsc::error::TrySendError::Closed(_) => self.closed_error(),
+        })
+    }
+
+    /// Queues a message without waiting , handing it back when the queue is full
+    ///
+    /// Only a closed connection is an error , a full queue just means the writer is behind
+    pub fn offer(&self, message: PeerMessage) -> Result<Option<PeerMessage>> {
+        match self.outgoing.try_send(message) {
+            Ok(()) => Ok(None),
+            Err(mpsc::error::TrySendError::Full(message)) => Ok(Some(message)),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(self.closed_error()),
+        }
+    }
+
+    /// Shares `limiter`'s budget for everything sent from now on
+    pub fn set_upload_limiter(&self, limiter: RateLimiter) {
+        *self.upload_limit.lock().unwrap() = limiter;
+    }
+
+    /// Shares `limiter`'s budget for everything read from now on
+    pub fn set_download_limiter(&self, limiter: RateLimiter) {
+        *self.download_limit.lock().unwrap() = limiter;
+    }
+
+    /// Caps this connection alone , on top of any shared limiter. Takes effect right away
+    pub fn set_peer_rates(&self, rates: Rates) {
+        self.peer_download.set_rate(rates.download);
+        self.peer_upload.set_rate(rates.upload);
+    }
+
+    pub fn peer_rates(&self) -> Rates {
+        Rates::new(self.peer_download.rate(), self.peer_upload.rate())
+    }
+
+    /// Messages queued by `send` that the writer hasn't taken yet
+    pub fn queued_messages(&self) -> usize {
+        self.outgoing.max_capacity() - self.outgoing.capacity()
+    }
+
+    /// Next message from the peer , None once the connection is closed
+    pub async fn recv(&mut self) -> Option<PeerMessage> {
+        self.incoming.recv().await
+    }
+
+    /// A message that already arrived , without waiting
+    pub fn try_recv(&mut self) -> Option<PeerMessage> {
+        self.incoming.try_recv().ok()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.reader.is_finished() || self.writer.is_finished()
+    }
+
+    /// Why the connection ended , None while it's open
+    pub fn close_reason(&self) -> Option<String> {
+        self.closed.lock().unwrap().clone()
+    }
+
+    fn closed_error(&self) -> anyhow::Error {
+        match self.close_reason() {
+            Some(reason) => anyhow!("Connection to {} closed : {}", self.addr, reason),
+            None => anyhow!("Connection to {} closed", self.addr),
+        }
+    }
+}
+
+impl Drop for PeerConnection {
+    fn drop(&mut self) {
+        // Both tasks hold the socket , it only closes once they're gone
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+fn close(closed: &Mutex<Option<String>>, reason: String) {
+    closed.lock().unwrap().get_or_insert(reason);
+}
+
+async fn read_loop(
+    mut stream: ReadHalf<Box<dyn PeerStream>>,
+    incoming: mpsc::Sender<PeerMessage>,
+    closed: Arc<Mutex<Option<String>>>,
+    download_limit: Arc<Mutex<RateLimiter>>,
+    peer_download: RateLimiter,
+) {
+    let mut decoder = MessageDecoder::new();
+    loop {
+        match decoder.read_message(&mut stream).await {
+            Ok(Some(message)) => {
+                // Holding off the next read lets TCP slow the peer down
+                let bytes = 4 + message.wire_length();
+                let limiter = download_limit.lock().unwrap().clone();
+                limiter.acquire(bytes).await;
+                peer_download.acquire(bytes).await;
+                if incoming.send(message).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return close(&closed, String::from("peer closed the connection")),
+            Err(e) => return close(&closed, e.to_string()),
+        }
+    }
+}
+
+async fn write_loop(
+    mut stream: WriteHalf<Box<dyn PeerStream>>,
+    mut outgoing: mpsc::Receiver<PeerMessage>,
+    closed: Arc<Mutex<Option<String>>>,
+    upload_limit: Arc<Mutex<RateLimiter>>,
+    peer_upload: RateLimiter,
+) {
+    let mut buf = BytesMut::new();
+    while let Some(message) = outgoing.recv().await {
+        // Whatever else is queued goes out in the same write
+        message.encode_into(&mut buf);
+        while let Ok(message) = outgoing.try_recv() {
+            message.encode_into(&mut buf);
+        }
+
+        let limiter = upload_limit.lock().unwrap().clone();
+        limiter.acquire(buf.len()).await;
+        peer_upload.acquire(buf.len()).await;
+        if let Err(e) = write_all(&mut stream, &buf).await {
+            return close(&closed, e.to_string());
+        }
+        buf.clear();
+    }
+}
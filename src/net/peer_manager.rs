@@ -0,0 +1,709 @@
+use crate::{
+    core::{
+        bind::BindTarget,
+        clock::{SharedClock, system_clock},
+        runtime::{SharedRuntime, TaskHandle, tokio_runtime},
+    },
+    net::{
+        block_manager::BlockManager,
+        choker::RECHOKE_INTERVAL,
+        connect::ConnectionSetup,
+        diagnostics::PeerDiagnostics,
+        encryption::{ConnectMode, ConnectModes, EncryptionPolicy},
+        peer_candidates::PeerCandidates,
+        peer_connection::PeerConnection,
+        piece_manager::BlockInfo,
+        port_policy::PortPolicy,
+        rate_limit::{RateLimiter, RateLimits, Rates},
+        request_scheduler::RequestScheduler,
+        wire_dump::{Direction, WireDump},
+    },
+    protocol::{handshake::Handshake, message::PeerMessage, peer::PeerSource},
+};
+use anyhow::{Result, anyhow};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+/// Connections per torrent unless configured otherwise
+pub const DEFAULT_MAX_CONNECTIONS: usize = 50;
+
+/// Messages held back for a connection whose send queue is full , a peer further behind is dropped
+pub const MAX_SEND_BACKLOG: usize = 4096;
+
+/// What a dial task hands back , tagged with the dial's id. The modes are the ones the peer turned
+/// down before the last attempt
+type DialOutcome = (u64, Vec<ConnectMode>, Result<PeerConnection>);
+
+/// Something that happened to a connection during `poll`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerEvent {
+    Connected(SocketAddr),
+    /// Dial failed , the peer closed the connection , or it broke the protocol
+    Disconnected {
+        addr: SocketAddr,
+        reason: String,
+    },
+}
+
+/// Keeps a torrent's pool of peer connections
+///
+/// Dials candidates until `max_connections` are open (or being opened) , feeds every message read
+/// to the BlockManager and sends out the requests it hands back. Nothing here blocks , call `poll`
+/// from the torrent's loop. Dials run as tasks on the runtime (tokio unless `with_runtime` says otherwise)
+#[derive(Debug)]
+pub struct PeerManager {
+    handshake: Handshake,
+    max_connections: usize,
+    port_policy: PortPolicy,
+    connections: HashMap<SocketAddr, PeerConnection>,
+    /// Dial tasks in flight by id , with the address each is for
+    dials: HashMap<u64, (SocketAddr, Box<dyn TaskHandle>)>,
+    next_dial: u64,
+    /// Every dial task reports here , also when it panicked (see DialReport)
+    dial_outcomes: mpsc::UnboundedSender<DialOutcome>,
+    dialed: mpsc::UnboundedReceiver<DialOutcome>,
+    /// Addresses with a dial in flight , so a peer is never dialed twice at once
+    pending: HashSet<SocketAddr>,
+    /// Where each dialed peer was heard about , handed to the BlockManager once connected
+    sources: HashMap<SocketAddr, BTreeSet<PeerSource>>,
+    scheduler: RequestScheduler,
+    /// Shared by every connection , except LAN peers unless `limit_lan_peers`
+    limits: RateLimits,
+    /// Per peer caps last handed to the connections , compared with `limits` on every poll
+    peer_rates: Rates,
+    /// Peers given their own caps with `set_peer_rates` , the per peer default leaves them alone
+    rate_overrides: HashMap<SocketAddr, Rates>,
+    /// Connections the limits apply to , every one but LAN peers unless `limit_lan_peers`
+    limited: HashSet<SocketAddr>,
+    limit_lan_peers: bool,
+    /// Messages that didn't fit in a connection's send queue , sent in order as it drains
+    backlog: HashMap<SocketAddr, VecDeque<PeerMessage>>,
+    /// Advertised in the extension handshake of every new connection
+    listen_port: u16,
+    /// Sent in Port messages , None when our DHT isn't running
+    dht_port: Option<u16>,
+    /// When the upload slots are handed out again , see RECHOKE_INTERVAL
+    next_rechoke: Option<Instant>,
+    /// Debug option , every new connection records its messages here (see WireDump)
+    wire_dump_dir: Option<PathBuf>,
+    dumps: HashMap<SocketAddr, WireDump>,
+    /// Address or interface dials leave through , see `NetworkConfig::bind`
+    bind: Option<BindTarget>,
+    /// Set while the kill switch has networking paused , no new dials start
+    network_paused: bool,
+    /// Plaintext or MSE , in the order the policy says , unless a peer took one before
+    connect_modes: ConnectModes,
+    clock: SharedClock,
+    runtime: SharedRuntime,
+}
+
+impl PeerManager {
+    /// `handshake` is ours for the torrent , from `Session::handshake_for`. `listen_port` is where
+    /// peers can reach us , advertised in the extension handshake
+    pub fn new(handshake: Handshake, listen_port: u16) -> Self {
+        let (dial_outcomes, dialed) = mpsc::unbounded_channel();
+        Self {
+            handshake,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            port_policy: PortPolicy::default(),
+            connections: HashMap::new(),
+            dials: HashMap::new(),
+            next_dial: 0,
+            dial_outcomes,
+            dialed,
+            pending: HashSet::new(),
+            sources: HashMap::new(),
+            scheduler: RequestScheduler::new(),
+            limits: RateLimits::unlimited(),
+            peer_rates: Rates::default(),
+            rate_overrides: HashMap::new(),
+            limited: HashSet::new(),
+            limit_lan_peers: false,
+            backlog: HashMap::new(),
+            listen_port,
+            dht_port: None,
+            next_rechoke: None,
+            wire_dump_dir: None,
+            dumps: HashMap::new(),
+            bind: None,
+            network_paused: false,
+            connect_modes: ConnectModes::default(),
+            clock: system_clock(),
+            runtime: tokio_runtime(),
+        }
+    }
+
+    /// Runs dials and connections on `runtime` , its clock replaces ours
+    pub fn with_runtime(mut self, runtime: SharedRuntime) -> Self {
+        self.clock = runtime.clock();
+        self.runtime = runtime;
+        self
+    }
+
+    /// Replaces the clock used for request timing , rechokes and wire dumps
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Port of our running DHT node , peers that set the DHT bit get it in a Port message
+    pub fn with_dht_port(mut self, port: Option<u16>) -> Self {
+        self.dht_port = port;
+        self
+    }
+
+    /// Dials peers through the configured address or interface
+    pub fn with_bind(mut self, bind: Option<BindTarget>) -> Self {
+        self.bind = bind;
+        self
+    }
+
+    /// Which handshakes dials try , a peer that drops one is retried with the next straight away
+    pub fn with_encryption(mut self, policy: EncryptionPolicy) -> Self {
+        self.connect_modes.set_policy(policy);
+        self
+    }
+
+    /// Stops or resumes dialing when the kill switch fires , pausing drops the dials in flight.
+    /// Connections already open are the caller's to drop
+    pub fn set_network_paused(&mut self, paused: bool) {
+        self.network_paused = paused;
+        if paused {
+            for (_, (_, task)) in self.dials.drain() {
+                task.abort();
+            }
+            for addr in self.pending.drain() {
+                self.sources.remove(&addr);
+            }
+        }
+    }
+
+    /// Dumps the messages of every connection to a JSONL file in `dir` , None turns it off
+    pub fn with_wire_dump(mut self, dir: Option<PathBuf>) -> Self {
+        self.wire_dump_dir = dir;
+        self
+    }
+
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    pub fn with_port_policy(mut self, policy: PortPolicy) -> Self {
+        self.port_policy = policy;
+        self
+    }
+
+    /// Upload budget shared by the torrent's connections , e.g one limiter for the whole session
+    pub fn with_upload_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limits.upload = limiter;
+        self
+    }
+
+    /// Download budget shared by the torrent's connections
+    pub fn with_download_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limits.download = limiter;
+        self
+    }
+
+    /// Both budgets and the per peer caps , e.g `Session::rate_limits` so the session can change them
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Gives one peer its own caps instead of the per peer default , None goes back to the default.
+    /// False when there's no connection to it
+    pub fn set_peer_rates(&mut self, addr: &SocketAddr, rates: Option<Rates>) -> bool {
+        let Some(connection) = self.connections.get(addr) else {
+            return false;
+        };
+        match rates {
+            Some(rates) => {
+                self.rate_overrides.insert(*addr, rates);
+                connection.set_peer_rates(rates);
+            }
+            None => {
+                self.rate_overrides.remove(addr);
+                connection.set_peer_rates(self.default_rates(addr));
+            }
+        }
+        true
+    }
+
+    /// Caps of one connection , None when there's no connection to it
+    pub fn peer_rates(&self, addr: &SocketAddr) -> Option<Rates> {
+        self.connections.get(addr).map(PeerConnection::peer_rates)
+    }
+
+    /// Whether peers on the local network count against the rate limits , see NetworkConfig
+    pub fn with_limit_lan_peers(mut self, limit: bool) -> Self {
+        self.limit_lan_peers = limit;
+        self
+    }
+
+    /// Changes the cap , open connections over it are left alone and just not replaced
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Open connections
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Open connections plus dials in flight
+    fn slots_used(&self) -> usize {
+        self.connections.len() + self.pending.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.slots_used() >= self.max_connections
+    }
+
+    pub fn addrs(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.connections.keys()
+    }
+
+    /// Starts dials to queued candidates until the cap is reached , returns how many were started
+    pub fn dial_candidates(&mut self, candidates: &mut PeerCandidates) -> usize {
+        let mut started = 0;
+        while !self.network_paused && !self.is_full() {
+            let Some(candidate) = candidates.next_candidate() else {
+                break;
+            };
+            // Host names need resolving first , the dial path only takes addresses
+            let Some(addr) = candidate.socket_addr() else {
+                continue;
+            };
+            if self.port_policy.check(&addr).is_err()
+                || self.connections.contains_key(&addr)
+                || !self.pending.insert(addr)
+            {
+                continue;
+            }
+            self.sources
+                .insert(addr, candidates.sources(&candidate.host, candidate.port));
+
+            let handshake = self.handshake;
+            let modes = self.connect_modes.attempts(&addr);
+            let bind = self.bind.clone();
+            let runtime = self.runtime.clone();
+            let id = self.next_dial;
+            self.next_dial += 1;
+            let report = DialReport {
+                id,
+                outcomes: Some(self.dial_outcomes.clone()),
+            };
+            let task = self.runtime.spawn(Box::pin(async move {
+                let attempts = ConnectionSetup::connect_trying(
+                    &*runtime,
+                    addr,
+                    &handshake,
+                    bind.as_ref(),
+                    &modes,
+                )
+                .await;
+                let dialed = attempts
+                    .result
+                    .map(|established| PeerConnection::start_on(&*runtime, established));
+                report.send(attempts.rejected, dialed);
+            }));
+            self.dials.insert(id, (addr, task));
+            started += 1;
+        }
+        started
+    }
+
+    /// Takes a connection a peer opened to us , false (and the connection is dropped) when full
+    pub fn add_incoming(&mut self, connection: PeerConnection, engine: &mut BlockManager) -> bool {
+        let addr = connection.addr();
+        if self.is_full() || self.connections.contains_key(&addr) {
+            return false;
+        }
+        self.attach(connection, engine);
+        true
+    }
+
+    /// Picks up finished dials , applies every message that arrived and sends out new requests
+    ///
+    /// Dead connections and peers that broke the protocol are dropped and removed from `engine`
+    pub fn poll(&mut self, engine: &mut BlockManager) -> Vec<PeerEvent> {
+        let mut events = Vec::new();
+
+        // Per peer caps changed through the shared limits
+        let per_peer = self.limits.per_peer();
+        if per_peer != self.peer_rates {
+            self.peer_rates = per_peer;
+            for (addr, connection) in &self.connections {
+                if !self.rate_overrides.contains_key(addr) {
+                    connection.set_peer_rates(self.default_rates(addr));
+                }
+            }
+        }
+
+        while let Ok((id, rejected, result)) = self.dialed.try_recv() {
+            // Dials aborted on purpose (e.g by the kill switch) are already forgotten
+            let Some((addr, _)) = self.dials.remove(&id) else {
+                continue;
+            };
+            self.pending.remove(&addr);
+            for mode in rejected {
+                self.connect_modes.rejected(&addr, mode);
+            }
+            match result {
+                Ok(connection) => {
+                    self.connect_modes.accepted(addr, connection.mode());
+                    self.attach(connection, engine);
+                    events.push(PeerEvent::Connected(addr));
+                }
+                Err(e) => {
+                    self.sources.remove(&addr);
+                    events.push(PeerEvent::Disconnected {
+                        addr,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        // What was held back last time goes first , the writer had a poll interval to catch up
+        let mut dead = self.flush_backlogs();
+        let mut outgoing = Vec::new();
+        for (addr, connection) in &mut self.connections {
+            while let Some(message) = connection.try_recv() {
+                let block = match &message {
+                    PeerMessage::Piece {
+                        index,
+                        begin,
+                        block,
+                    } => Some(BlockInfo::new(
+                        *index as usize,
+                        *begin as usize,
+                        block.len(),
+                    )),
+                    _ => None,
+                };
+                let unchoke = match message {
+                    PeerMessage::Unchoke => Some(true),
+                    PeerMessage::Choke => Some(false),
+                    _ => None,
+                };
+                if let Some(dump) = self.dumps.get_mut(addr) {
+                    let _ = dump.record(Direction::Received, &message);
+                }
+
+                match engine.handle_message(*addr, message) {
+                    Ok(replies) => outgoing.extend(replies),
+                    Err(e) => {
+                        dead.push((*addr, e.to_string()));
+                        break;
+                    }
+                }
+
+                // Only peers that unchoked us get requests
+                match unchoke {
+                    Some(true) => self.scheduler.add_peer(*addr),
+                    Some(false) => self.scheduler.remove_peer(addr),
+                    None => {}
+                }
+                // Room in the pipeline is refilled by the assign_requests below
+                if let Some(block) = block {
+                    self.scheduler
+                        .block_received(addr, &block, self.clock.now());
+                }
+            }
+
+            if connection.is_closed() {
+                let reason = connection
+                    .close_reason()
+                    .unwrap_or_else(|| String::from("connection closed"));
+                dead.push((*addr, reason));
+            }
+        }
+
+        // Haves for pieces verified since the last poll , and interest that changed with them
+        engine.process_hash_results();
+        outgoing.extend(engine.announcements());
+        // Blocks the disk thread read for peers since the last poll
+        outgoing.extend(engine.finished_reads());
+
+        // Cancels for endgame duplicates free their slots before new requests are handed out
+        for (addr, message) in &outgoing {
+            if let PeerMessage::Cancel {
+                index,
+                begin,
+                length,
+            } = message
+            {
+                let block = BlockInfo::new(*index as usize, *begin as usize, *length as usize);
+                self.scheduler.request_cancelled(addr, &block);
+            }
+        }
+
+        // Pieces stuck on slow or vanished peers go back up for grabs before new requests go out
+        engine.reassign_stalled_pieces();
+        for (addr, block) in engine.assign_requests(&mut self.scheduler) {
+            outgoing.push((
+                addr,
+                PeerMessage::Request {
+                    index: block.piece_index as u32,
+                    begin: block.begin as u32,
+                    length: block.length as u32,
+                },
+            ));
+        }
+
+        // Upload slots are handed out again every RECHOKE_INTERVAL
+        let now = self.clock.now();
+        if self.next_rechoke.is_none_or(|due| now >= due) {
+            self.next_rechoke = Some(now + RECHOKE_INTERVAL);
+            outgoing.extend(engine.rechoke());
+        }
+
+        // Connections that went quiet on our side
+        outgoing.extend(engine.keepalives());
+
+        for (addr, message) in outgoing {
+            if !self.connections.contains_key(&addr) {
+                continue;
+            }
+            engine.message_sent(&addr, &message);
+            if let Err(e) = self.send(addr, message) {
+                dead.push((addr, e.to_string()));
+            }
+        }
+
+        for dump in self.dumps.values_mut() {
+            let _ = dump.flush();
+        }
+
+        for (addr, reason) in dead {
+            if self.connections.remove(&addr).is_some() {
+                self.backlog.remove(&addr);
+                self.dumps.remove(&addr);
+                self.forget_rates(&addr);
+                self.scheduler.remove_peer(&addr);
+                engine.remove_peer(&addr);
+                events.push(PeerEvent::Disconnected { addr, reason });
+            }
+        }
+
+        events
+    }
+
+    /// Outstanding requests , send queue and round trip time of every peer `engine` knows
+    pub fn diagnostics(&self, engine: &BlockManager) -> Vec<PeerDiagnostics> {
+        let mut diagnostics = engine.peer_diagnostics();
+        for peer in &mut diagnostics {
+            let backlog = self.backlog.get(&peer.addr).map_or(0, VecDeque::len);
+            peer.queued_messages = self
+                .connections
+                .get(&peer.addr)
+                .map(|connection| connection.queued_messages() + backlog);
+            if let Some(slot) = self
+                .scheduler
+                .slots()
+                .iter()
+                .find(|slot| slot.addr == peer.addr)
+            {
+                peer.rtt = slot.pipeline.rtt();
+                peer.pipeline_depth = Some(slot.pipeline.depth());
+            }
+        }
+        diagnostics
+    }
+
+    /// Picks up after a system suspend , see `BlockManager::resumed`
+    ///
+    /// Every connection gets a keep-alive , the ones that can't take it are closed. Connections
+    /// the peer dropped meanwhile show up as closed on the next `poll`
+    pub fn resumed(&mut self, engine: &mut BlockManager) -> Vec<PeerEvent> {
+        self.scheduler.restart_timers(self.clock.now());
+
+        let mut events = Vec::new();
+        for (addr, message) in engine.resumed() {
+            if !self.connections.contains_key(&addr) {
+                continue;
+            }
+            engine.message_sent(&addr, &message);
+            if let Err(e) = self.send(addr, message) {
+                self.disconnect(&addr, engine);
+                events.push(PeerEvent::Disconnected {
+                    addr,
+                    reason: e.to_string(),
+                });
+            }
+        }
+        events
+    }
+
+    /// Closes a connection , e.g for a peer the user banned
+    pub fn disconnect(&mut self, addr: &SocketAddr, engine: &mut BlockManager) -> bool {
+        self.backlog.remove(addr);
+        self.dumps.remove(addr);
+        self.scheduler.remove_peer(addr);
+        engine.remove_peer(addr);
+        self.forget_rates(addr);
+        self.connections.remove(addr).is_some()
+    }
+
+    /// Queues a message for a peer , holding it back while the connection's send queue is full
+    ///
+    /// Fails when the connection is closed or the peer fell MAX_SEND_BACKLOG messages behind
+    fn send(&mut self, addr: SocketAddr, message: PeerMessage) -> Result<()> {
+        let Some(connection) = self.connections.get(&addr) else {
+            return Ok(());
+        };
+        if let Some(dump) = self.dumps.get_mut(&addr) {
+            let _ = dump.record(Direction::Sent, &message);
+        }
+        let backlog = self.backlog.entry(addr).or_default();
+        // Behind the backlog , so messages still go out in order
+        let held = match backlog.is_empty() {
+            true => connection.offer(message)?,
+            false => Some(message),
+        };
+        if let Some(message) = held {
+            backlog.push_back(message);
+        }
+        if backlog.is_empty() {
+            self.backlog.remove(&addr);
+        } else if backlog.len() > MAX_SEND_BACKLOG {
+            return Err(anyhow!(
+                "{} is over {} messages behind",
+                addr,
+                MAX_SEND_BACKLOG
+            ));
+        }
+        Ok(())
+    }
+
+    /// Moves held back messages into send queues that have room again , returns broken connections
+    fn flush_backlogs(&mut self) -> Vec<(SocketAddr, String)> {
+        let mut dead = Vec::new();
+        for (addr, backlog) in &mut self.backlog {
+            let Some(connection) = self.connections.get(addr) else {
+                backlog.clear();
+                continue;
+            };
+            while let Some(message) = backlog.pop_front() {
+                match connection.offer(message) {
+                    Ok(None) => {}
+                    Ok(Some(message)) => {
+                        backlog.push_front(message);
+                        break;
+                    }
+                    Err(e) => {
+                        dead.push((*addr, e.to_string()));
+                        break;
+                    }
+                }
+            }
+        }
+        self.backlog.retain(|_, backlog| !backlog.is_empty());
+        dead
+    }
+
+    /// Per peer caps a connection gets without an override , none for unlimited LAN peers
+    fn default_rates(&self, addr: &SocketAddr) -> Rates {
+        if self.limited.contains(addr) {
+            self.peer_rates
+        } else {
+            Rates::default()
+        }
+    }
+
+    fn forget_rates(&mut self, addr: &SocketAddr) {
+        self.limited.remove(addr);
+        self.rate_overrides.remove(addr);
+    }
+
+    fn attach(&mut self, connection: PeerConnection, engine: &mut BlockManager) {
+        let addr = connection.addr();
+        let peer = engine.add_peer(addr);
+        peer.set_peer_id(connection.remote().peer_id);
+        peer.sources = self.sources.remove(&addr).unwrap_or_default();
+
+        if self.limit_lan_peers || !peer.is_lan() {
+            connection.set_upload_limiter(self.limits.upload.clone());
+            connection.set_download_limiter(self.limits.download.clone());
+            connection.set_peer_rates(self.peer_rates);
+            self.limited.insert(addr);
+        }
+
+        // Bitfield , extension handshake and Port go out first , what follows depends on them.
+        // Interest follows the peer's Bitfield and Haves , see BlockManager::update_interest
+        let shared = self
+            .handshake
+            .extensions()
+            .shared(&connection.remote().extensions());
+        self.connections.insert(addr, connection);
+        if let Some(dump) = self
+            .wire_dump_dir
+            .as_deref()
+            .and_then(|dir| WireDump::open(dir, addr))
+            .map(|dump| dump.with_clock(self.clock.clone()))
+        {
+            self.dumps.insert(addr, dump);
+        }
+        for message in engine.greeting(shared, self.listen_port, self.dht_port) {
+            engine.message_sent(&addr, &message);
+            // A connection that closed already shows up as dead on the next poll
+            if self.send(addr, message).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for PeerManager {
+    fn drop(&mut self) {
+        for (_, (_, task)) in self.dials.drain() {
+            task.abort();
+        }
+    }
+}
+
+/// Hands a dial's result back to the manager , a task that dies without one (it panicked , or the
+/// runtime dropped it) reports that instead , so its slot is still freed
+struct DialReport {
+    id: u64,
+    outcomes: Option<mpsc::UnboundedSender<DialOutcome>>,
+}
+
+impl DialReport {
+    fn send(mut self, rejected: Vec<ConnectMode>, result: Result<PeerConnection>) {
+        if let Some(outcomes) = self.outcomes.take() {
+            let _ = outcomes.send((self.id, rejected, result));
+        }
+    }
+}
+
+impl Drop for DialReport {
+    fn drop(&mut self) {
+        if let Some(outcomes) = self.outcomes.take() {
+            let reason = if std::thread::panicking() {
+                "Dial task panicked"
+            } else {
+                "Dial task was dropped"
+            };
+            let _ = outcomes.send((self.id, Vec::new(), Err(anyhow!(reason))));
+        }
+    }
+}
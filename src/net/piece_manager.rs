@@ -6,12 +6,29 @@ use std::time::{Duration, Instant};
 /// Standard BitTorrent block size (16KB)
 pub const BLOCK_SIZE: usize = 16 * 1024;
 
+/// Smallest block size we'll request , anything smaller just multiplies request overhead
+pub const MIN_BLOCK_SIZE: usize = 1024;
+
+/// Largest block size we'll request , BEP 3 lets peers drop connections asking for more than 128KB
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Clamps a configured block size into what peers accept , rounded down to a power of two
+pub fn clamp_block_size(size: usize) -> usize {
+    let size = size.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE);
+    1 << size.ilog2()
+}
+
 /// Maximum number of pending requests per peer
 pub const MAX_PENDING_REQUESTS: usize = 10;
 
 /// Request timeout duration
 pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// How long a piece may stay in progress before its blocks are released and it gets reassigned
+///
+/// Complements REQUEST_TIMEOUT , a piece whose blocks keep getting re-requested from dead peers would never time out on its own
+pub const PIECE_TIMEOUT: Duration = Duration::from_secs(120);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 
 // Information that is usually
@@ -68,6 +85,8 @@ pub struct Piece {
     pub hash: [u8; 20],
     // The pieces state
     pub state: PieceState,
+    /// Size of the blocks this piece is split into , every block but the last has this size
+    pub block_size: usize,
 
     //Block tracking
     pub blocks: HashMap<usize, Block>,
@@ -81,27 +100,21 @@ pub struct Piece {
 
 impl Piece {
     pub fn new(index: usize, length: usize, hash: [u8; 20]) -> Self {
-        let num_blocks = (length + BLOCK_SIZE - 1) / BLOCK_SIZE;
-        let mut missing_blocks = HashSet::new();
+        Self::with_block_size(index, length, hash, BLOCK_SIZE)
+    }
 
-        for i in 0..num_blocks {
-            let begin = i * BLOCK_SIZE;
-            let block_length = if i == num_blocks - 1 {
-                length - begin
-            } else {
-                BLOCK_SIZE
-            };
-
-            missing_blocks.insert(BlockInfo::new(index, begin, block_length));
-        }
+    /// Creates a piece split into blocks of `block_size` (clamped by `clamp_block_size`)
+    pub fn with_block_size(index: usize, length: usize, hash: [u8; 20], block_size: usize) -> Self {
+        let block_size = clamp_block_size(block_size);
 
         Self {
             index,
             length,
             hash,
             state: PieceState::Pending,
+            block_size,
             blocks: HashMap::new(),
-            missing_blocks,
+            missing_blocks: block_layout(index, length, block_size),
             requested_blocks: HashMap::new(),
             download_start: None,
             download_complete: None,
@@ -109,13 +122,17 @@ impl Piece {
     }
 
     pub fn is_complete(&self) -> bool {
-        self.missing_blocks.is_empty() && self.blocks.len() * BLOCK_SIZE >= self.length
+        self.missing_blocks.is_empty() && self.received_bytes() >= self.length
     }
 
-    pub fn get_next_block_request(&mut self) -> Option<BlockInfo> {
-        // Clean up timeouts
-        let now = Instant::now();
+    /// Bytes of the piece we already have
+    pub fn received_bytes(&self) -> usize {
+        self.blocks.values().map(|block| block.data.len()).sum()
+    }
 
+    /// Hands out the next missing block , `now` comes from the caller's clock so timeouts can be tested
+    pub fn get_next_block_request(&mut self, now: Instant) -> Option<BlockInfo> {
+        // Clean up timeouts
         // Gets timedout blocks
         let timed_out: Vec<BlockInfo> = self
             .requested_blocks
@@ -134,13 +151,57 @@ impl Piece {
             if self.requested_blocks.len() < MAX_PENDING_REQUESTS {
                 self.missing_blocks.remove(&block);
                 self.requested_blocks.insert(block, now);
+
+                // The piece clock starts with its first request
+                if self.download_start.is_none() {
+                    self.download_start = Some(now);
+                }
                 return Some(block);
             }
         }
         None
     }
 
-    pub fn add_block(&mut self, block: Block) -> Result<()> {
+    /// Whether the piece has been in progress for longer than PIECE_TIMEOUT
+    pub fn is_stalled(&self, now: Instant) -> bool {
+        if self.state != PieceState::InProgress {
+            return false;
+        }
+
+        match self.download_start {
+            Some(start) => now.duration_since(start) > PIECE_TIMEOUT,
+            None => false,
+        }
+    }
+
+    /// Counts outstanding requests and the piece's own timeout from `now` , e.g after a suspend
+    pub fn restart_timers(&mut self, now: Instant) {
+        for sent_at in self.requested_blocks.values_mut() {
+            *sent_at = now;
+        }
+        if self.download_start.is_some() {
+            self.download_start = Some(now);
+        }
+    }
+
+    /// Takes back a request that was never sent , the block goes back to missing
+    pub fn cancel_request(&mut self, block: &BlockInfo) {
+        if self.requested_blocks.remove(block).is_some() {
+            self.missing_blocks.insert(*block);
+        }
+    }
+
+    /// Puts every outstanding request back into the missing set so the blocks can go to other peers
+    ///
+    /// Blocks we already received are kept
+    pub fn release_requests(&mut self) {
+        for (block, _) in self.requested_blocks.drain() {
+            self.missing_blocks.insert(block);
+        }
+        self.download_start = None;
+    }
+
+    pub fn add_block(&mut self, block: Block, now: Instant) -> Result<()> {
         // Validate block
         // Makes sure the blocks parent PIECE is the PIECE
         if block.info.piece_index != self.index {
@@ -152,12 +213,15 @@ impl Piece {
         }
 
         self.requested_blocks.remove(&block.info);
+        self.missing_blocks.remove(&block.info);
         self.blocks.insert(block.info.begin, block);
 
-        self.download_start = Some(Instant::now());
+        if self.download_start.is_none() {
+            self.download_start = Some(now);
+        }
 
         if self.is_complete() {
-            self.download_complete = Some(Instant::now());
+            self.download_complete = Some(now);
             self.state = PieceState::Complete;
         }
 
@@ -194,6 +258,12 @@ impl Piece {
         hash.as_slice() == &self.hash
     }
 
+    /// Frees the block data once the piece is on disk , nothing reads it from memory after that
+    pub fn release_buffers(&mut self) {
+        self.blocks = HashMap::new();
+        self.requested_blocks = HashMap::new();
+    }
+
     pub fn reset(&mut self) {
         self.state = PieceState::Pending;
         self.blocks.clear();
@@ -202,18 +272,25 @@ impl Piece {
         self.download_complete = None;
 
         // Rebuild missing blocks
-        let num_blocks = (self.length + BLOCK_SIZE - 1) / BLOCK_SIZE;
-        self.missing_blocks.clear();
+        self.missing_blocks = block_layout(self.index, self.length, self.block_size);
+    }
+}
 
-        for i in 0..num_blocks {
-            let begin = i * BLOCK_SIZE;
-            let block_length = if i == num_blocks - 1 {
-                self.length - begin
-            } else {
-                BLOCK_SIZE
-            };
-            self.missing_blocks
-                .insert(BlockInfo::new(self.index, begin, block_length));
-        }
+/// Every block of a piece of `length` bytes split into `block_size` chunks
+fn block_layout(index: usize, length: usize, block_size: usize) -> HashSet<BlockInfo> {
+    let num_blocks = length.div_ceil(block_size);
+    let mut blocks = HashSet::new();
+
+    for i in 0..num_blocks {
+        let begin = i * block_size;
+        let block_length = if i == num_blocks - 1 {
+            length - begin
+        } else {
+            block_size
+        };
+
+        blocks.insert(BlockInfo::new(index, begin, block_length));
     }
+
+    blocks
 }
@@ -0,0 +1,56 @@
+use anyhow::{Result, anyhow};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+/// Service ports above 1024 we won't dial even if a peer advertises them
+///
+/// Trackers and DHT nodes can be fed fake peers pointing at other services , connecting to them
+/// would turn the swarm into a reflector against those services
+pub const DEFAULT_BLOCKED_PORTS: [u16; 14] = [
+    1433, 1900, 3306, 3389, 5060, 5432, 6379, 6666, 6667, 6668, 6669, 9200, 11211, 27017,
+];
+
+/// Which peer ports we're willing to connect to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortPolicy {
+    /// Allow ports below 1024 , off unless the user knows their peers run there
+    pub allow_privileged: bool,
+    pub blocked: BTreeSet<u16>,
+}
+
+impl Default for PortPolicy {
+    fn default() -> Self {
+        Self {
+            allow_privileged: false,
+            blocked: DEFAULT_BLOCKED_PORTS.into_iter().collect(),
+        }
+    }
+}
+
+impl PortPolicy {
+    /// Connects to any port , for advanced users who want no filtering at all
+    pub fn allow_all() -> Self {
+        Self {
+            allow_privileged: true,
+            blocked: BTreeSet::new(),
+        }
+    }
+
+    pub fn allows(&self, port: u16) -> bool {
+        port != 0 && (self.allow_privileged || port >= 1024) && !self.blocked.contains(&port)
+    }
+
+    /// Fails with the reason when `addr` may not be dialed
+    pub fn check(&self, addr: &SocketAddr) -> Result<()> {
+        let port = addr.port();
+        if self.allows(port) {
+            return Ok(());
+        }
+
+        if port < 1024 {
+            Err(anyhow!("{} uses privileged port {}", addr, port))
+        } else {
+            Err(anyhow!("{} uses blocked port {}", addr, port))
+        }
+    }
+}
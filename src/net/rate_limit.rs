@@ -0,0 +1,192 @@
+use crate::core::clock::{SharedClock, system_clock};
+use anyhow::{Result, anyhow};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// Caps bytes per second over everything sharing it , clones share one budget
+///
+/// A token bucket holding up to one second of traffic. A write takes its bytes out even when the
+/// bucket runs dry , then waits until the debt is paid back , so large blocks aren't starved by a
+/// small budget
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+    clock: SharedClock,
+}
+
+#[derive(Debug, Default)]
+struct TokenBucket {
+    /// Bytes per second , None lets everything through
+    rate: Option<u64>,
+    tokens: f64,
+    refilled_at: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, now: Instant) {
+        let Some(rate) = self.rate else {
+            return;
+        };
+        let elapsed = self
+            .refilled_at
+            .map_or(Duration::ZERO, |at| now.saturating_duration_since(at));
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate as f64).min(rate as f64);
+        self.refilled_at = Some(now);
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            bucket: Arc::default(),
+            clock: system_clock(),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Lets everything through until a rate is set
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn new(bytes_per_second: u64) -> Self {
+        let limiter = Self::default();
+        limiter.set_rate(Some(bytes_per_second));
+        limiter
+    }
+
+    /// Replaces the clock the bucket refills by
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Bytes per second , None when unlimited
+    pub fn rate(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    /// Changes the cap for everything sharing the limiter , None lifts it. 0 counts as None
+    pub fn set_rate(&self, bytes_per_second: Option<u64>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.rate = bytes_per_second.filter(|&rate| rate > 0);
+        bucket.tokens = bucket.rate.unwrap_or(0) as f64;
+        bucket.refilled_at = Some(self.clock.now());
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.rate().is_some()
+    }
+
+    /// Takes `bytes` out of the budget and returns how long to wait before sending them
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill(self.clock.now());
+        let Some(rate) = bucket.rate else {
+            return Duration::ZERO;
+        };
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-bucket.tokens / rate as f64)
+    }
+
+    /// Waits until `bytes` fit the budget
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Caps in bytes per second for each direction , None is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rates {
+    pub download: Option<u64>,
+    pub upload: Option<u64>,
+}
+
+impl Rates {
+    pub fn new(download: Option<u64>, upload: Option<u64>) -> Self {
+        Self { download, upload }
+    }
+}
+
+/// Limiters for everything that shares them (a session , a torrent) and the cap each single peer
+/// gets , clones share all of it so changes reach running connections
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    pub download: RateLimiter,
+    pub upload: RateLimiter,
+    per_peer: Arc<Mutex<Rates>>,
+}
+
+impl RateLimits {
+    /// Lets everything through until rates are set
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the clock of both limiters
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.download = self.download.with_clock(clock.clone());
+        self.upload = self.upload.with_clock(clock);
+        self
+    }
+
+    /// Caps over every connection together
+    pub fn global(&self) -> Rates {
+        Rates::new(self.download.rate(), self.upload.rate())
+    }
+
+    pub fn set_global(&self, rates: Rates) {
+        self.download.set_rate(rates.download);
+        self.upload.set_rate(rates.upload);
+    }
+
+    /// Caps each connection gets on its own , on top of the global ones
+    pub fn per_peer(&self) -> Rates {
+        *self.per_peer.lock().unwrap()
+    }
+
+    /// Changes the per peer caps , connections pick them up on their manager's next poll
+    pub fn set_per_peer(&self, rates: Rates) {
+        *self.per_peer.lock().unwrap() = Rates::new(
+            rates.download.filter(|&rate| rate > 0),
+            rates.upload.filter(|&rate| rate > 0),
+        );
+    }
+}
+
+/// Reads a rate like "500K" , "2M" or "1.5MiB" (binary units , per second). 0 , "unlimited" and
+/// "none" mean no cap
+pub fn parse_rate(input: &str) -> Result<Option<u64>> {
+    let text = input.trim().to_ascii_lowercase();
+    if matches!(text.as_str(), "unlimited" | "none") {
+        return Ok(None);
+    }
+
+    let text = text.strip_suffix("/s").unwrap_or(&text);
+    let text = text.strip_suffix("ib").unwrap_or(text);
+    let text = text.strip_suffix('b').unwrap_or(text);
+    let (number, scale) = match text.chars().last() {
+        Some('k') => (&text[..text.len() - 1], 1024.0),
+        Some('m') => (&text[..text.len() - 1], 1024.0 * 1024.0),
+        Some('g') => (&text[..text.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (text, 1.0),
+    };
+
+    let value: f64 = number
+        .trim()
+        .parse()
+        .ok()
+        .filter(|value: &f64| value.is_finite() && *value >= 0.0)
+        .ok_or_else(|| anyhow!("Invalid rate : {}", input))?;
+    let bytes = (value * scale).round() as u64;
+    Ok((bytes > 0).then_some(bytes))
+}
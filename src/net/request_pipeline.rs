@@ -0,0 +1,178 @@
+use crate::net::piece_manager::{BLOCK_SIZE, BlockInfo, MAX_PENDING_REQUESTS};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Requests a new connection may have outstanding before anything has been measured
+pub const INITIAL_PIPELINE_DEPTH: usize = 4;
+
+/// Fewest requests kept outstanding , one being answered while the next is already queued
+pub const MIN_PIPELINE_DEPTH: usize = 2;
+
+/// Received bytes are summed over this long before they count as a rate sample
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Weight of a new sample in the moving averages
+const SMOOTHING: f64 = 0.25;
+
+/// Block requests outstanding on one connection
+///
+/// The depth follows the peer's bandwidth-delay product , i.e enough requests that the peer never
+/// sits idle waiting for the next one while its last answer is on the wire. Measured from round
+/// trip times and delivered bytes , never above MAX_PENDING_REQUESTS
+#[derive(Debug, Clone)]
+pub struct RequestPipeline {
+    /// Requests sent and not answered yet , with when they went out
+    outstanding: HashMap<BlockInfo, Instant>,
+    depth: usize,
+    /// Smoothed time from request to block
+    rtt: Option<Duration>,
+    /// Smoothed bytes per second delivered
+    rate: f64,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    /// Length of the last block received , what the depth is counted in
+    block_length: usize,
+}
+
+impl Default for RequestPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestPipeline {
+    pub fn new() -> Self {
+        Self {
+            outstanding: HashMap::new(),
+            depth: INITIAL_PIPELINE_DEPTH,
+            rtt: None,
+            rate: 0.0,
+            window_start: None,
+            window_bytes: 0,
+            block_length: BLOCK_SIZE,
+        }
+    }
+
+    /// Requests the pipeline aims to keep outstanding
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Requests outstanding right now
+    pub fn len(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outstanding.is_empty()
+    }
+
+    /// Requests that can go out before the pipeline is full
+    pub fn room(&self) -> usize {
+        self.depth.saturating_sub(self.outstanding.len())
+    }
+
+    pub fn has_room(&self) -> bool {
+        self.room() > 0
+    }
+
+    /// Measured bytes per second , 0.0 until a full window was seen
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    pub fn is_outstanding(&self, block: &BlockInfo) -> bool {
+        self.outstanding.contains_key(block)
+    }
+
+    /// A request went out
+    pub fn sent(&mut self, block: BlockInfo, now: Instant) {
+        self.outstanding.insert(block, now);
+        if self.window_start.is_none() {
+            self.window_start = Some(now);
+        }
+    }
+
+    /// A block arrived , returns false if it wasn't requested on this connection
+    pub fn received(&mut self, block: &BlockInfo, now: Instant) -> bool {
+        let Some(sent_at) = self.outstanding.remove(block) else {
+            return false;
+        };
+
+        let sample = now.saturating_duration_since(sent_at);
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt.mul_f64(1.0 - SMOOTHING) + sample.mul_f64(SMOOTHING),
+            None => sample,
+        });
+
+        self.block_length = block.length.max(1);
+        self.window_bytes += block.length as u64;
+        let start = *self.window_start.get_or_insert(sent_at);
+        let elapsed = now.saturating_duration_since(start);
+        if elapsed >= RATE_WINDOW {
+            let sample = self.window_bytes as f64 / elapsed.as_secs_f64();
+            self.rate = if self.rate > 0.0 {
+                self.rate * (1.0 - SMOOTHING) + sample * SMOOTHING
+            } else {
+                sample
+            };
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+        }
+
+        self.adapt();
+        true
+    }
+
+    /// A request was cancelled (endgame duplicate answered elsewhere) , frees its slot
+    pub fn cancelled(&mut self, block: &BlockInfo) -> bool {
+        self.outstanding.remove(block).is_some()
+    }
+
+    /// Counts outstanding requests from `now` , e.g after a suspend. The rate window starts over too
+    pub fn restart_timers(&mut self, now: Instant) {
+        for sent_at in self.outstanding.values_mut() {
+            *sent_at = now;
+        }
+        self.window_start = None;
+        self.window_bytes = 0;
+    }
+
+    /// Drops requests older than `timeout` and returns them
+    ///
+    /// A timeout means the peer can't keep up with this many , the depth is halved
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<BlockInfo> {
+        let expired: Vec<BlockInfo> = self
+            .outstanding
+            .iter()
+            .filter(|&(_, &sent_at)| now.saturating_duration_since(sent_at) > timeout)
+            .map(|(block, _)| *block)
+            .collect();
+
+        if !expired.is_empty() {
+            for block in &expired {
+                self.outstanding.remove(block);
+            }
+            self.depth = (self.depth / 2).max(MIN_PIPELINE_DEPTH);
+        }
+        expired
+    }
+
+    /// Depth from the bandwidth-delay product , plus one so the pipe stays full while a block is read
+    fn adapt(&mut self) {
+        let Some(rtt) = self.rtt else {
+            return;
+        };
+        if self.rate <= 0.0 {
+            return;
+        }
+
+        let in_flight = self.rate * rtt.as_secs_f64() / self.block_length as f64;
+        self.depth =
+            (in_flight.ceil() as usize + 1).clamp(MIN_PIPELINE_DEPTH, MAX_PENDING_REQUESTS);
+    }
+}
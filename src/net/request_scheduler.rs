@@ -0,0 +1,183 @@
+use crate::net::{
+    piece_manager::{BlockInfo, REQUEST_TIMEOUT},
+    request_pipeline::RequestPipeline,
+};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Weight given to peers we haven't measured yet (bytes per second)
+///
+/// Without a floor a freshly connected peer has a rate of 0 and would never be picked , so it could never prove itself
+pub const MIN_PEER_WEIGHT: f64 = 16.0 * 1024.0;
+
+/// Most peers a block is requested from at once in endgame
+///
+/// Duplicates only pay off while the first request is slow , past a couple of peers they just burn bandwidth
+pub const ENDGAME_MAX_PEERS_PER_BLOCK: usize = 3;
+
+/// Bookkeeping for one peer in the assignment loop
+#[derive(Debug, Clone)]
+pub struct PeerSlot {
+    pub addr: SocketAddr,
+    /// Download rate from this peer in bytes per second , as measured outside the scheduler
+    pub rate: f64,
+    /// Requests sent to this peer that haven't been answered yet
+    pub pipeline: RequestPipeline,
+    /// Smooth weighted round robin counter
+    credit: f64,
+}
+
+impl PeerSlot {
+    /// Whichever rate is known , the pipeline measures its own from the blocks it gets
+    fn weight(&self) -> f64 {
+        self.rate.max(self.pipeline.rate()).max(MIN_PEER_WEIGHT)
+    }
+
+    fn has_room(&self) -> bool {
+        self.pipeline.has_room()
+    }
+}
+
+/// Decides which peer gets the next block request
+///
+/// Uses smooth weighted round robin (the nginx upstream algorithm) weighted by peer speed , so fast peers get more
+/// requests but every peer with room in its pipeline gets a turn instead of the first peer being filled up over and over
+#[derive(Debug, Default)]
+pub struct RequestScheduler {
+    slots: Vec<PeerSlot>,
+}
+
+impl RequestScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_peer(&mut self, addr: SocketAddr) {
+        if self.slots.iter().any(|slot| slot.addr == addr) {
+            return;
+        }
+
+        self.slots.push(PeerSlot {
+            addr,
+            rate: 0.0,
+            pipeline: RequestPipeline::new(),
+            credit: 0.0,
+        });
+    }
+
+    pub fn remove_peer(&mut self, addr: &SocketAddr) {
+        self.slots.retain(|slot| &slot.addr != addr);
+    }
+
+    pub fn set_rate(&mut self, addr: &SocketAddr, rate: f64) {
+        if let Some(slot) = self.slot_mut(addr) {
+            slot.rate = rate;
+        }
+    }
+
+    /// Call once a request to the peer went out , after `next_peer` picked it
+    pub fn request_sent(&mut self, addr: &SocketAddr, block: BlockInfo, now: Instant) {
+        if let Some(slot) = self.slot_mut(addr) {
+            slot.pipeline.sent(block, now);
+        }
+    }
+
+    /// Call when the peer answered a request , frees room for the next one and feeds the depth estimate
+    pub fn block_received(&mut self, addr: &SocketAddr, block: &BlockInfo, now: Instant) {
+        if let Some(slot) = self.slot_mut(addr) {
+            slot.pipeline.received(block, now);
+        }
+    }
+
+    /// Call when we cancelled a request to the peer
+    pub fn request_cancelled(&mut self, addr: &SocketAddr, block: &BlockInfo) {
+        if let Some(slot) = self.slot_mut(addr) {
+            slot.pipeline.cancelled(block);
+        }
+    }
+
+    /// Counts every outstanding request from `now` , so a suspend doesn't expire them all at once
+    pub fn restart_timers(&mut self, now: Instant) {
+        for slot in &mut self.slots {
+            slot.pipeline.restart_timers(now);
+        }
+    }
+
+    /// Frees the room taken by requests unanswered for REQUEST_TIMEOUT , returns them per peer
+    ///
+    /// The pieces put the blocks up for grabs again on their own , this only keeps slow peers'
+    /// pipelines from staying full of requests that will never be answered
+    pub fn expire_requests(&mut self, now: Instant) -> Vec<(SocketAddr, BlockInfo)> {
+        let mut expired = Vec::new();
+        for slot in &mut self.slots {
+            let addr = slot.addr;
+            expired.extend(
+                slot.pipeline
+                    .expire(now, REQUEST_TIMEOUT)
+                    .into_iter()
+                    .map(|block| (addr, block)),
+            );
+        }
+        expired
+    }
+
+    /// Whether any peer can take another request
+    pub fn has_capacity(&self) -> bool {
+        self.slots.iter().any(PeerSlot::has_room)
+    }
+
+    /// Picks the peer that gets the next request , count it with `request_sent` once a block is found for it
+    pub fn next_peer(&mut self) -> Option<SocketAddr> {
+        self.next_peer_except(&[])
+    }
+
+    /// `next_peer` leaving out `exclude` , e.g peers that have nothing we still need
+    pub fn next_peer_except(&mut self, exclude: &[SocketAddr]) -> Option<SocketAddr> {
+        let mut total = 0.0;
+        let mut best: Option<(usize, f64)> = None;
+
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            if !slot.has_room() || exclude.contains(&slot.addr) {
+                continue;
+            }
+
+            slot.credit += slot.weight();
+            total += slot.weight();
+
+            match best {
+                Some((_, credit)) if credit >= slot.credit => {}
+                _ => best = Some((i, slot.credit)),
+            }
+        }
+
+        let (best, _) = best?;
+        let chosen = &mut self.slots[best];
+        chosen.credit -= total;
+        Some(chosen.addr)
+    }
+
+    /// Picks the fastest peer with room that isn't in `exclude` , count it with `request_sent`
+    ///
+    /// Used for endgame duplicates , those should go where they're most likely to win the race
+    pub fn next_fastest_peer(&self, exclude: &[SocketAddr]) -> Option<SocketAddr> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.has_room() && !exclude.contains(&slot.addr))
+            .reduce(|best, slot| {
+                if slot.weight() > best.weight() {
+                    slot
+                } else {
+                    best
+                }
+            })
+            .map(|slot| slot.addr)
+    }
+
+    pub fn slots(&self) -> &[PeerSlot] {
+        &self.slots
+    }
+
+    fn slot_mut(&mut self, addr: &SocketAddr) -> Option<&mut PeerSlot> {
+        self.slots.iter_mut().find(|slot| &slot.addr == addr)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{core::peer::PeerLocation, protocol::peer::PeerSource};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+/// How many peers `top_peers` lists
+pub const TOP_PEERS: usize = 3;
+
+/// A peer that sent us the most data
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopPeer {
+    pub addr: SocketAddr,
+    pub client: String,
+    pub downloaded: u64,
+    /// Filled in by a GeoIP lookup when one is set up
+    pub location: Option<PeerLocation>,
+}
+
+/// Swarm summary for one torrent , what the header line of the torrent view shows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SwarmHealth {
+    /// Connected peers that have every piece
+    pub seeds: usize,
+    /// Connected peers still downloading
+    pub leechers: usize,
+    /// Mean progress of the connected peers , 0.0 - 100.0
+    pub average_progress: f64,
+    /// Best uploaders to us , most data first
+    pub top_peers: Vec<TopPeer>,
+    /// Peers discovered per source , connected or not. A peer found by two sources counts for both
+    pub known_by_source: BTreeMap<PeerSource, usize>,
+    /// Distinct peers discovered , whatever the source
+    pub known_peers: usize,
+}
+
+impl SwarmHealth {
+    /// Takes the discovery counts , usually `PeerCandidates::known_by_source` and `PeerCandidates::known`
+    pub fn with_known_sources(
+        mut self,
+        known: &BTreeMap<PeerSource, usize>,
+        known_peers: usize,
+    ) -> Self {
+        self.known_by_source = known.clone();
+        self.known_peers = known_peers;
+        self
+    }
+
+    pub fn connected(&self) -> usize {
+        self.seeds + self.leechers
+    }
+
+    pub fn known(&self) -> usize {
+        self.known_peers
+    }
+
+    /// One line summary , e.g `Swarm: 3 seeds , 5 leechers , avg 41.2% | known 40 (tracker 32 , DHT 8) | top 1.2.3.4:6881`
+    pub fn header_line(&self) -> String {
+        let mut line = format!(
+            "Swarm: {} seeds , {} leechers , avg {:.1}%",
+            self.seeds, self.leechers, self.average_progress
+        );
+
+        if !self.known_by_source.is_empty() {
+            let sources: Vec<String> = self
+                .known_by_source
+                .iter()
+                .map(|(source, count)| format!("{} {}", source.label(), count))
+                .collect();
+            line.push_str(&format!(
+                " | known {} ({})",
+                self.known(),
+                sources.join(" , ")
+            ));
+        }
+
+        if !self.top_peers.is_empty() {
+            let top: Vec<String> = self
+                .top_peers
+                .iter()
+                .map(|peer| peer.addr.to_string())
+                .collect();
+            line.push_str(&format!(" | top {}", top.join(" , ")));
+        }
+
+        line
+    }
+
+    pub fn to_json(&self) -> Value {
+        let known: serde_json::Map<String, Value> = self
+            .known_by_source
+            .iter()
+            .map(|(source, count)| (source.label().to_ascii_lowercase(), json!(count)))
+            .collect();
+        let top: Vec<Value> = self
+            .top_peers
+            .iter()
+            .map(|peer| {
+                json!({
+                    "addr": peer.addr.to_string(),
+                    "client": peer.client,
+                    "downloaded": peer.downloaded,
+                    "country": peer.location.as_ref().and_then(|l| l.country.clone()),
+                    "asn": peer.location.as_ref().and_then(|l| l.asn),
+                    "as_org": peer.location.as_ref().and_then(|l| l.as_org.clone()),
+                })
+            })
+            .collect();
+
+        json!({
+            "seeds": self.seeds,
+            "leechers": self.leechers,
+            "average_progress": self.average_progress,
+            "known_by_source": known,
+            "known_peers": self.known_peers,
+            "top_peers": top,
+        })
+    }
+}
@@ -0,0 +1,181 @@
+//! Downloads a .torrent file over http(s)
+//!
+//! Private trackers serve their download links behind a login , so requests can carry a cookie and
+//! extra headers. The body is capped while it streams in and anything that looks like a web page
+//! is refused , a login page saved as .torrent fails in confusing ways later on
+
+use crate::{
+    core::bind::BindTarget,
+    net::tracker::http_client,
+    protocol::torrent::{Torrent, TorrentLimits},
+    util::humanize,
+};
+use anyhow::{Result, anyhow};
+use reqwest::header::{CONTENT_TYPE, COOKIE, HeaderName, HeaderValue};
+use std::time::Duration;
+
+/// Largest .torrent accepted by default , real ones are rarely above a few MiB
+pub const DEFAULT_MAX_TORRENT_FILE_SIZE: usize = 10 * 1024 * 1024;
+
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Content types a .torrent may come with , servers that don't know the extension send octet-stream
+const TORRENT_CONTENT_TYPES: [&str; 3] = [
+    "application/x-bittorrent",
+    "application/octet-stream",
+    "binary/octet-stream",
+];
+
+/// Whether `source` should be fetched rather than read from disk
+pub fn is_url(source: &str) -> bool {
+    let lower = source.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// A .torrent download , configured with the `with_*` methods and run with `fetch`
+#[derive(Debug, Clone)]
+pub struct TorrentFetch {
+    pub url: String,
+    headers: Vec<(String, String)>,
+    cookie: Option<String>,
+    max_size: usize,
+    bind: Option<BindTarget>,
+    limits: TorrentLimits,
+}
+
+impl TorrentFetch {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: Vec::new(),
+            cookie: None,
+            max_size: DEFAULT_MAX_TORRENT_FILE_SIZE,
+            bind: None,
+            limits: TorrentLimits::default(),
+        }
+    }
+
+    /// Extra request header , e.g an `Authorization` a tracker wants
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Parses a `Name: value` header as given on the command line
+    pub fn with_header_line(self, line: &str) -> Result<Self> {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Header \"{}\" is not in Name: value form", line))?;
+        Ok(self.with_header(name.trim(), value.trim()))
+    }
+
+    /// Cookie header , e.g the session cookie of a private tracker's site
+    pub fn with_cookie(mut self, cookie: impl Into<String>) -> Self {
+        self.cookie = Some(cookie.into());
+        self
+    }
+
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sends the request through an address or interface , like tracker announces
+    pub fn with_bind(mut self, bind: Option<BindTarget>) -> Self {
+        self.bind = bind;
+        self
+    }
+
+    /// Limits the fetched torrent has to stay within
+    pub fn with_limits(mut self, limits: TorrentLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Downloads the file and parses it
+    pub async fn fetch(&self) -> Result<Torrent> {
+        let bytes = self.fetch_bytes().await?;
+        Torrent::from_bytes_with_limits(&bytes, &self.limits)
+            .map_err(|e| anyhow!("{} is not a valid torrent : {}", self.url, e))
+    }
+
+    /// Downloads the raw .torrent bytes
+    pub async fn fetch_bytes(&self) -> Result<Vec<u8>> {
+        if !is_url(&self.url) {
+            return Err(anyhow!("{} is not an http(s) url", self.url));
+        }
+
+        let builder = reqwest::Client::builder().timeout(FETCH_TIMEOUT);
+        let client = http_client(builder, self.bind.as_ref())?;
+
+        let mut request = client.get(&self.url);
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow!("Invalid header name \"{}\"", name))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| anyhow!("Invalid value for header {}", name))?;
+            request = request.header(name, value);
+        }
+        if let Some(cookie) = &self.cookie {
+            let cookie = HeaderValue::from_str(cookie).map_err(|_| anyhow!("Invalid cookie"))?;
+            request = request.header(COOKIE, cookie);
+        }
+
+        let mut response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("{} answered {}", self.url, status));
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| {
+                v.split(';')
+                    .next()
+                    .unwrap_or("")
+                    .trim()
+                    .to_ascii_lowercase()
+            });
+        if let Some(content_type) = &content_type
+            && !TORRENT_CONTENT_TYPES.contains(&content_type.as_str())
+        {
+            let hint = if content_type.starts_with("text/") {
+                " , probably a login or error page (check the cookie)"
+            } else {
+                ""
+            };
+            return Err(anyhow!(
+                "{} sent {} instead of a torrent{}",
+                self.url,
+                content_type,
+                hint
+            ));
+        }
+
+        if let Some(length) = response.content_length()
+            && length > self.max_size as u64
+        {
+            return Err(self.too_large());
+        }
+
+        // Content-Length can be missing or wrong , the cap is enforced on what actually arrives
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > self.max_size {
+                return Err(self.too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    fn too_large(&self) -> anyhow::Error {
+        anyhow!(
+            "{} is larger than {} , not fetching it",
+            self.url,
+            humanize::bytes(self.max_size as u64)
+        )
+    }
+}
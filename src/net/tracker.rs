@@ -1,22 +1,97 @@
-use crate::protocol::{bencode::BencodeValue, peer::Peer};
+use crate::{
+    core::bind::BindTarget,
+    net::{
+        tracker_url::{TrackerStatus, TrackerUrl},
+        udp_tracker::UdpAnnounce,
+    },
+    protocol::{
+        bencode::BencodeValue,
+        peer::{DiscoveredPeer, PeerHost},
+    },
+};
 use anyhow::{Result, anyhow};
-use color_eyre::{eyre::Ok, owo_colors::OwoColorize};
+#[cfg(all(feature = "color", feature = "http-tracker"))]
+use colored::Colorize;
 
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long an http announce may take , a hung tracker would otherwise hold up its host for good
+pub const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Announce outcomes kept per tracker to work out its recent success rate
+pub const TRACKER_HISTORY_LEN: usize = 20;
+
+/// How a tracker has been doing over time , to tell flaky trackers from dead ones
+#[derive(Debug, Clone, Default)]
+pub struct TrackerStats {
+    pub successes: u64,
+    pub failures: u64,
+    /// Failures since the last success
+    pub consecutive_failures: u32,
+    pub last_success: Option<SystemTime>,
+    pub last_failure: Option<SystemTime>,
+    /// Peers the last successful announce handed us
+    pub last_peers: Option<usize>,
+    /// Latest outcomes , oldest first , true for a success
+    recent: VecDeque<bool>,
+}
+
+impl TrackerStats {
+    pub fn record(&mut self, success: bool) {
+        let now = SystemTime::now();
+        if success {
+            self.successes += 1;
+            self.consecutive_failures = 0;
+            self.last_success = Some(now);
+        } else {
+            self.failures += 1;
+            self.consecutive_failures += 1;
+            self.last_failure = Some(now);
+        }
+
+        if self.recent.len() == TRACKER_HISTORY_LEN {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(success);
+    }
+
+    /// Share of the last TRACKER_HISTORY_LEN announces that worked , None before the first one
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.recent.is_empty() {
+            return None;
+        }
+        let worked = self.recent.iter().filter(|&&success| success).count();
+        Some(worked as f64 / self.recent.len() as f64)
+    }
+
+    /// Not a single success in a full history window , a candidate for removal
+    pub fn looks_dead(&self) -> bool {
+        self.recent.len() == TRACKER_HISTORY_LEN && self.success_rate() == Some(0.0)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum TrackerEvent {
     Started,
     Completed,
     Stopped,
+    /// We're a partial seed , sent on every announce while we are one (BEP 21)
+    Paused,
 }
 
 impl TrackerEvent {
-    fn as_str(&self) -> &str {
+    /// Name sent in the `event` parameter
+    #[cfg(feature = "http-tracker")]
+    pub fn as_str(&self) -> &str {
         match self {
             TrackerEvent::Started => "started",
             TrackerEvent::Completed => "completed",
             TrackerEvent::Stopped => "stopped",
+            TrackerEvent::Paused => "paused",
         }
     }
 }
@@ -35,71 +110,194 @@ pub struct TrackerRequest {
 #[derive(Debug, Default, Clone)]
 pub struct TrackerResponse {
     pub interval: u64,
-    pub peers: Vec<Peer>,
+    /// Soonest the tracker wants to hear from us again , in seconds
+    pub min_interval: Option<u64>,
+    pub peers: Vec<DiscoveredPeer>,
     pub complete: Option<u64>,   // No of complete pieces
     pub incomplete: Option<u64>, // No of incomplete pieces
     pub tracker_id: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Tracker {
     announce_url: String,
+    /// Parsed form of `announce_url` , None when it didn't parse
+    url: Option<TrackerUrl>,
+    status: TrackerStatus,
     peer_id: [u8; 20],
+    /// Local address or interface announces go out through
+    bind: Option<BindTarget>,
+    last_error: Option<String>,
+    stats: TrackerStats,
 }
 
 impl Tracker {
     pub fn new(announce_url: String) -> Self {
         let peer_id = Self::generate_peer_id();
+        Self::with_peer_id(announce_url, peer_id)
+    }
+
+    /// Creates a tracker that announces with an existing peer id , a session uses one id for all its torrents
+    pub fn with_peer_id(announce_url: String, peer_id: [u8; 20]) -> Self {
+        let (url, status) = TrackerUrl::check(&announce_url);
         Self {
             announce_url,
+            url,
+            status,
             peer_id,
+            bind: None,
+            last_error: None,
+            stats: TrackerStats::default(),
         }
     }
 
+    /// Sends announces through a specific address or interface
+    pub fn set_bind(&mut self, bind: Option<BindTarget>) {
+        self.bind = bind;
+    }
+
+    pub fn bind(&self) -> Option<&BindTarget> {
+        self.bind.as_ref()
+    }
+
+    pub fn announce_url(&self) -> &str {
+        &self.announce_url
+    }
+
+    /// Why the last announce failed , None if it worked or none was made yet
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Remembers the outcome of an announce , shown next to the tracker's status
+    pub fn record_result<T>(&mut self, result: &Result<T>) {
+        self.last_error = result.as_ref().err().map(|e| e.to_string());
+        self.stats.record(result.is_ok());
+    }
+
+    /// Remembers how many peers an announce that worked returned
+    pub fn record_peers(&mut self, peers: usize) {
+        self.stats.last_peers = Some(peers);
+    }
+
+    pub fn stats(&self) -> &TrackerStats {
+        &self.stats
+    }
+
+    pub fn url(&self) -> Option<&TrackerUrl> {
+        self.url.as_ref()
+    }
+
+    /// Whether this tracker can be announced to , worked out when the tracker was created
+    pub fn status(&self) -> &TrackerStatus {
+        &self.status
+    }
+
     pub fn generate_peer_id() -> [u8; 20] {
         let mut peer_id = [0u8; 20];
         peer_id[0..8].copy_from_slice(b"-RS0000-");
 
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .unwrap()
+            .unwrap_or_default()
             .as_secs();
 
-        for (i, byte) in peer_id[8..].iter_mut().enumerate() {
-            *byte = ((timestamp >> (i * 8)) & 0xFF) as u8;
-        }
+        // 8 bytes of timestamp , the other 4 random so peers started in the same second still differ.
+        // Shifting a u64 by 64 or more overflows , the timestamp only covers the first 8 bytes
+        peer_id[8..16].copy_from_slice(&timestamp.to_le_bytes());
+        let random = RandomState::new().hash_one(timestamp);
+        peer_id[16..].copy_from_slice(&random.to_le_bytes()[..4]);
 
         peer_id
     }
 
+    #[cfg(feature = "http-tracker")]
     pub async fn announce(
         &self,
         request: TrackerRequest,
     ) -> Result<TrackerResponse, anyhow::Error> {
+        if !self.status.is_ready() {
+            return Err(anyhow!("Tracker {} is {}", self.announce_url, self.status));
+        }
+
+        if let Some(TrackerUrl::Udp { host, port, path }) = &self.url {
+            crate::log_line!("Contacting udp tracker at : {}", self.announce_url);
+            return self.announce_udp(host, *port, path, &request).await;
+        }
+
         let url = self.build_announce_url(&request);
-        println!("Contacting tracker at : {}", self.announce_url.green()).bright_black();
+        #[cfg(feature = "color")]
+        crate::log_line!("Contacting tracker at : {}", self.announce_url.green());
+        #[cfg(not(feature = "color"))]
+        crate::log_line!("Contacting tracker at : {}", self.announce_url);
 
-        let client = reqwest::Client::new();
+        let client = self.http_client()?;
         let response = client.get(&url).send().await?;
 
-        if !response.status().is_success() {
-            return Err(anyhow!("Tracker returned error: {}", response.status()));
-        }
+        let status = response.status();
+        let encoding = response
+            .headers()
+            .get(reqwest::header::CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_ascii_lowercase());
 
         let body = response
             .bytes()
             .await
             .map_err(|e| anyhow!("Failed to read response body : {}", e))?;
+        let body = decompress_body(encoding.as_deref(), &body)?;
 
-        println!("Response bytes: {:?}", body);
+        if !status.is_success() {
+            return Err(anyhow!(
+                "Tracker returned error: {} ({})",
+                status,
+                body_preview(&body)
+            ));
+        }
+
+        crate::log_line!("Response bytes: {:?}", body);
 
         self.parse_tracker_response(&body)
     }
 
+    /// Announces over the udp tracker protocol , works without the http-tracker feature
+    pub async fn announce_udp(
+        &self,
+        host: &str,
+        port: u16,
+        path: &str,
+        request: &TrackerRequest,
+    ) -> Result<TrackerResponse> {
+        UdpAnnounce {
+            host,
+            port,
+            path,
+            peer_id: self.peer_id,
+            bind: self.bind.as_ref(),
+        }
+        .announce(request)
+        .await
+    }
+
+    #[cfg(feature = "http-tracker")]
+    fn http_client(&self) -> Result<reqwest::Client> {
+        let builder = reqwest::Client::builder().timeout(ANNOUNCE_TIMEOUT);
+        http_client(builder, self.bind.as_ref())
+    }
+
+    #[cfg(feature = "http-tracker")]
     fn build_announce_url(&self, req: &TrackerRequest) -> String {
+        let base = match &self.url {
+            Some(url) => url.to_string(),
+            None => self.announce_url.clone(),
+        };
+        // Some trackers carry their own query (passkeys) , ours goes after it
+        let separator = if base.contains('?') { '&' } else { '?' };
+
         let mut url = format!(
-            "{}?info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact={}",
-            self.announce_url,
+            "{}{}info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact={}",
+            base,
+            separator,
             Self::url_encode(&req.info_hash),
             Self::url_encode(&self.peer_id),
             req.port,
@@ -116,6 +314,7 @@ impl Tracker {
         url
     }
 
+    #[cfg(feature = "http-tracker")]
     fn url_encode(bytes: &[u8]) -> String {
         bytes
             .iter()
@@ -129,7 +328,16 @@ impl Tracker {
     }
 
     pub fn parse_tracker_response(&self, data: &[u8]) -> Result<TrackerResponse> {
-        let value = BencodeValue::decode(data)?;
+        // HTML error pages and the like , report what came back instead of a bencode error
+        let value = match BencodeValue::decode(data) {
+            Ok(value) if data.first() == Some(&b'd') => value,
+            _ => {
+                return Err(anyhow!(
+                    "Tracker returned non-bencode response (first 100 bytes : {})",
+                    body_preview(data)
+                ));
+            }
+        };
 
         let dict = match value {
             BencodeValue::Dictionary(map) => map,
@@ -137,41 +345,52 @@ impl Tracker {
         };
 
         let mut interval = None;
+        let mut min_interval = None;
         let mut peers_data = None;
+        let mut peers6_data = None;
         let mut complete = None;
         let mut incomplete = None;
         let mut tracker_id = None;
         let mut failure_reason = None;
 
-        // Iterate over dictionary entries
-        for (key_bytes, val) in dict {
-            let key = String::from_utf8_lossy(&key_bytes).to_string();
+        // Iterate over dictionary entries , keys and values alternate
+        for pair in dict.chunks_exact(2) {
+            let (BencodeValue::Bytes(key_bytes), val) = (&pair[0], &pair[1]) else {
+                continue;
+            };
+            let key = String::from_utf8_lossy(key_bytes).to_string();
 
             match key.as_str() {
                 "interval" => {
                     if let BencodeValue::Integer(v) = val {
-                        interval = Some(v as u64);
+                        interval = Some(*v as u64);
+                    }
+                }
+                "min interval" => {
+                    if let BencodeValue::Integer(v) = val {
+                        min_interval = Some(*v as u64);
                     }
                 }
                 "peers" => peers_data = Some(val),
+                "peers6" => peers6_data = Some(val),
                 "complete" => {
                     if let BencodeValue::Integer(v) = val {
-                        complete = Some(v as u64);
+                        complete = Some(*v as u64);
                     }
                 }
                 "incomplete" => {
                     if let BencodeValue::Integer(v) = val {
-                        incomplete = Some(v as u64);
+                        incomplete = Some(*v as u64);
                     }
                 }
                 "tracker id" => {
                     if let BencodeValue::Bytes(bytes) = val {
-                        tracker_id = Some(String::from_utf8_lossy(&bytes).to_string());
+                        tracker_id = Some(String::from_utf8_lossy(bytes).to_string());
                     }
                 }
                 "failure reason" => {
                     if let BencodeValue::Bytes(bytes) = val {
-                        failure_reason = Some(String::from_utf8_lossy(&bytes).to_string());
+                        failure_reason = Some(String::from_utf8_lossy(bytes).to_string());
                     }
                 }
                 _ => {}
@@ -182,42 +401,161 @@ impl Tracker {
             return Err(anyhow!("Tracker failure: {}", reason));
         }
 
+        let mut peers = match peers_data {
+            Some(peers) => Self::parse_peers(peers)?,
+            None => vec![],
+        };
+        // IPv6 peers come separately , always compact (BEP 7)
+        if let Some(BencodeValue::Bytes(bytes)) = peers6_data {
+            peers.extend(parse_compact_peers(bytes, true));
+        }
+
         Ok(TrackerResponse {
             interval: interval.unwrap_or(0),
-            peers: vec![], // left as placeholder (don’t add parsing logic)
+            min_interval,
+            peers,
             complete,
             incomplete,
             tracker_id,
         })
     }
 
-    pub fn parse_peers(peers_value: &BencodeValue) -> Result<Vec<Peer>> {
+    pub fn parse_peers(peers_value: &BencodeValue) -> Result<Vec<DiscoveredPeer>> {
         match peers_value {
             // Dictionary list form (non-compact)
             BencodeValue::List(list) => {
                 let mut peers = Vec::new();
                 for item in list {
-                    if let BencodeValue::Dictionary(map) = item {
-                        let ip = match map.get(0) {
-                            Some(BencodeValue::Bytes(ip)) => ip.clone(),
-                            _ => continue,
-                        };
-                        let port = match map.get(1) {
-                            Some(BencodeValue::Integer(port)) => port.clone(),
-                            _ => continue,
-                        };
-
-                        peers.push(Peer::new(ip, port as u16));
+                    let Some(peer) = Self::parse_peer_entry(item) else {
+                        continue;
+                    };
+
+                    // Trackers sometimes list a peer more than once
+                    if !peers
+                        .iter()
+                        .any(|known: &DiscoveredPeer| known.is_same_peer(&peer))
+                    {
+                        peers.push(peer);
                     }
                 }
                 Ok(peers)
             }
-            // TODO : Implement binary format
+            // Compact form , 4 byte ip + 2 byte port per peer (BEP 23)
+            BencodeValue::Bytes(bytes) => {
+                if bytes.len() % 6 != 0 {
+                    return Err(anyhow!(
+                        "Compact peer list is {} bytes , not a multiple of 6",
+                        bytes.len()
+                    ));
+                }
+                Ok(parse_compact_peers(bytes, false))
+            }
             _ => Err(anyhow!("Invalid Peer Format")),
         }
     }
 
+    /// Reads one `{ip, port, peer id}` entry , entries without a usable ip or port are skipped
+    fn parse_peer_entry(item: &BencodeValue) -> Option<DiscoveredPeer> {
+        let ip = item.get(b"ip")?.as_bytes()?;
+        let ip = std::str::from_utf8(ip).ok()?;
+        let port = u16::try_from(item.get(b"port")?.as_integer()?).ok()?;
+
+        let mut peer = DiscoveredPeer::new(PeerHost::parse(ip), port);
+        peer.peer_id = item
+            .get(b"peer id")
+            .and_then(BencodeValue::as_bytes)
+            .and_then(|id| <[u8; 20]>::try_from(id.as_ref()).ok());
+
+        Some(peer)
+    }
+
     pub fn get_peer_id(&self) -> [u8; 20] {
         self.peer_id
     }
 }
+
+/// Undoes the Content-Encoding of a tracker response
+///
+/// Some trackers compress even when not asked to , and a few send gzip without saying so , so the gzip magic is checked too
+#[cfg(feature = "http-tracker")]
+fn decompress_body(encoding: Option<&str>, body: &[u8]) -> Result<Vec<u8>> {
+    use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    let read_all = |mut reader: Box<dyn Read + '_>| -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out)?;
+        Ok(out)
+    };
+
+    let gzip_magic = body.starts_with(&[0x1f, 0x8b]);
+    let decoded = match encoding {
+        Some("gzip") | Some("x-gzip") => read_all(Box::new(GzDecoder::new(body))),
+        // "deflate" is meant to be zlib wrapped but plenty of servers send raw deflate
+        Some("deflate") => read_all(Box::new(ZlibDecoder::new(body)))
+            .or_else(|_| read_all(Box::new(DeflateDecoder::new(body)))),
+        _ if gzip_magic => read_all(Box::new(GzDecoder::new(body))),
+        None | Some("identity") | Some("") => return Ok(body.to_vec()),
+        Some(other) => {
+            return Err(anyhow!(
+                "Tracker response uses unsupported encoding '{}'",
+                other
+            ));
+        }
+    };
+
+    decoded.map_err(|e| anyhow!("Could not decompress tracker response : {}", e))
+}
+
+/// Finishes an http client that goes out through `bind` , if set
+#[cfg(feature = "http-tracker")]
+pub(crate) fn http_client(
+    builder: reqwest::ClientBuilder,
+    bind: Option<&BindTarget>,
+) -> Result<reqwest::Client> {
+    let builder = match bind {
+        None => builder,
+        Some(BindTarget::Addr(ip)) => builder.local_address(*ip),
+        #[cfg(target_os = "linux")]
+        Some(BindTarget::Interface(name)) => builder.interface(name),
+        #[cfg(not(target_os = "linux"))]
+        Some(BindTarget::Interface(name)) => {
+            return Err(anyhow!(
+                "Binding to interface {} is only supported on Linux",
+                name
+            ));
+        }
+    };
+    Ok(builder.build()?)
+}
+
+/// Reads compact peer entries , 6 bytes each (IPv4) or 18 (IPv6 , BEP 7). A trailing partial entry is dropped
+pub fn parse_compact_peers(data: &[u8], ipv6: bool) -> Vec<DiscoveredPeer> {
+    let entry = if ipv6 { 18 } else { 6 };
+
+    data.chunks_exact(entry)
+        .map(|chunk| {
+            let (ip, port) = chunk.split_at(entry - 2);
+            let ip = match <[u8; 16]>::try_from(ip) {
+                Ok(octets) => IpAddr::V6(Ipv6Addr::from(octets)),
+                Err(_) => IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])),
+            };
+            DiscoveredPeer::new(PeerHost::Ip(ip), u16::from_be_bytes([port[0], port[1]]))
+        })
+        .collect()
+}
+
+/// First 100 bytes of a response as printable text , for error messages
+fn body_preview(body: &[u8]) -> String {
+    let end = body.len().min(100);
+    let text: String = String::from_utf8_lossy(&body[..end])
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+
+    if body.len() > end {
+        format!("{}…", text.trim_end())
+    } else {
+        text.trim_end().to_string()
+    }
+}
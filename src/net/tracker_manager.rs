@@ -0,0 +1,130 @@
+use crate::{
+    core::bind::BindTarget,
+    net::{tracker::Tracker, tracker_tier::TrackerTier},
+    protocol::torrent::Torrent,
+};
+use anyhow::Result;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+/// Every tracker of a torrent , in the tiers of its announce-list (BEP 12)
+///
+/// Announces go to one tracker of one tier at a time. Trackers inside a tier are shuffled once when
+/// the torrent is loaded and a tracker that answers moves to the front of its tier. Once every
+/// tracker of a tier has failed the next tier is tried , the manager stays on the tier that answers
+/// and starts over from the first tier after the last one ran out
+#[derive(Debug, Clone)]
+pub struct TrackerManager {
+    tiers: Vec<TrackerTier>,
+    current: usize,
+}
+
+impl TrackerManager {
+    /// One tier holding a single tracker , for torrents with only `announce`
+    pub fn new(tracker: Tracker) -> Self {
+        Self {
+            tiers: vec![TrackerTier::new(tracker)],
+            current: 0,
+        }
+    }
+
+    /// Builds the tiers from the torrent's announce-list , or its announce url
+    pub fn from_torrent(torrent: &Torrent, peer_id: [u8; 20]) -> Self {
+        let tiers: Vec<TrackerTier> = torrent
+            .tracker_tiers()
+            .into_iter()
+            .map(|mut urls| {
+                // The spec wants each tier shuffled so clients don't all hit the first tracker
+                let state = RandomState::new();
+                urls.sort_by_cached_key(|url| state.hash_one(url));
+                let trackers = urls
+                    .into_iter()
+                    .map(|url| Tracker::with_peer_id(url, peer_id))
+                    .collect();
+                TrackerTier::from_trackers(trackers)
+            })
+            .collect();
+
+        if tiers.is_empty() {
+            return Self::new(Tracker::with_peer_id(torrent.announce.clone(), peer_id));
+        }
+        Self { tiers, current: 0 }
+    }
+
+    /// Tracker announces go to right now
+    pub fn current(&self) -> &Tracker {
+        self.tiers[self.current].current()
+    }
+
+    /// Index of the tier in use
+    pub fn current_tier(&self) -> usize {
+        self.current
+    }
+
+    pub fn tiers(&self) -> &[TrackerTier] {
+        &self.tiers
+    }
+
+    /// Every tracker , tier by tier
+    pub fn trackers(&self) -> impl Iterator<Item = &Tracker> {
+        self.tiers.iter().flat_map(|tier| tier.trackers())
+    }
+
+    /// Adds a backup tracker to the last tier , false if any tier already has its url
+    pub fn add(&mut self, tracker: Tracker) -> bool {
+        if self
+            .trackers()
+            .any(|t| t.announce_url() == tracker.announce_url())
+        {
+            return false;
+        }
+        let last = self.tiers.len() - 1;
+        self.tiers[last].add(tracker)
+    }
+
+    pub fn set_bind(&mut self, bind: Option<BindTarget>) {
+        for tier in &mut self.tiers {
+            tier.set_bind(bind.clone());
+        }
+    }
+
+    /// Failures in a row before a tier gives up on a tracker , for every tier
+    pub fn set_failover_after(&mut self, failures: u32) {
+        for tier in &mut self.tiers {
+            tier.failover_after = failures;
+        }
+    }
+
+    /// Peers the current tracker's last announce returned , call it before `record_result`
+    pub fn record_peers(&mut self, peers: usize) {
+        self.tiers[self.current].record_peers(peers);
+    }
+
+    /// Records an announce to the current tracker
+    ///
+    /// Returns true when that moved announces to another tracker , the caller should announce to it
+    /// now instead of at the next interval. False once every tier failed , the first tier gets the
+    /// next regular announce
+    pub fn record_result<T>(&mut self, result: &Result<T>) -> bool {
+        let tier = &mut self.tiers[self.current];
+        let failed_over = tier.record_result(result);
+        if !tier.exhausted() {
+            return failed_over;
+        }
+
+        tier.restart();
+        let from = self.current;
+        self.current = (self.current + 1) % self.tiers.len();
+        if self.current == from {
+            return failed_over;
+        }
+
+        crate::log_line!(
+            "Every tracker of tier {} failed , moving to tier {} ({})",
+            from,
+            self.current,
+            self.current().announce_url()
+        );
+        self.current != 0
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{core::bind::BindTarget, net::tracker::Tracker};
+use anyhow::Result;
+
+/// Failures in a row before the tier moves on to its next tracker
+pub const DEFAULT_FAILOVER_AFTER: u32 = 2;
+
+/// Trackers that serve the same swarm , one is announced to at a time
+///
+/// When the one in use keeps failing the tier switches to the next , straight away rather than
+/// waiting out the announce interval. A tracker that answers moves to the front of the tier (BEP 12).
+/// Every tracker keeps its own stats so dead ones can be spotted
+#[derive(Debug, Clone)]
+pub struct TrackerTier {
+    trackers: Vec<Tracker>,
+    current: usize,
+    pub failover_after: u32,
+    /// Trackers given up on since the last one that answered
+    failovers: usize,
+}
+
+impl TrackerTier {
+    pub fn new(tracker: Tracker) -> Self {
+        Self::from_trackers(vec![tracker])
+    }
+
+    /// Tier tried in the order given , panics on an empty list
+    pub fn from_trackers(trackers: Vec<Tracker>) -> Self {
+        assert!(!trackers.is_empty(), "a tracker tier needs a tracker");
+        Self {
+            trackers,
+            current: 0,
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            failovers: 0,
+        }
+    }
+
+    /// Adds a backup tracker , false if the tier already has its url
+    pub fn add(&mut self, tracker: Tracker) -> bool {
+        if self
+            .trackers
+            .iter()
+            .any(|t| t.announce_url() == tracker.announce_url())
+        {
+            return false;
+        }
+        self.trackers.push(tracker);
+        true
+    }
+
+    /// Tracker announces go to right now
+    pub fn current(&self) -> &Tracker {
+        &self.trackers[self.current]
+    }
+
+    pub fn trackers(&self) -> &[Tracker] {
+        &self.trackers
+    }
+
+    pub fn len(&self) -> usize {
+        self.trackers.len()
+    }
+
+    /// Never true , a tier always holds a tracker
+    pub fn is_empty(&self) -> bool {
+        self.trackers.is_empty()
+    }
+
+    /// Every tracker failed since the last answer , time to try another tier
+    pub fn exhausted(&self) -> bool {
+        self.failovers >= self.trackers.len()
+    }
+
+    /// Starts the tier over from its first tracker
+    pub fn restart(&mut self) {
+        self.current = 0;
+        self.failovers = 0;
+    }
+
+    pub fn set_bind(&mut self, bind: Option<BindTarget>) {
+        for tracker in &mut self.trackers {
+            tracker.set_bind(bind.clone());
+        }
+    }
+
+    /// Peers the current tracker's last announce returned , before its result is recorded
+    pub fn record_peers(&mut self, peers: usize) {
+        self.trackers[self.current].record_peers(peers);
+    }
+
+    /// Records an announce to the current tracker
+    ///
+    /// Returns true when that made the tier fail over , the caller should announce to the new
+    /// tracker now instead of at the next interval
+    pub fn record_result<T>(&mut self, result: &Result<T>) -> bool {
+        let tracker = &mut self.trackers[self.current];
+        tracker.record_result(result);
+
+        if result.is_ok() {
+            // Working trackers are tried first next time
+            let tracker = self.trackers.remove(self.current);
+            self.trackers.insert(0, tracker);
+            self.restart();
+            return false;
+        }
+
+        let failing = tracker.stats().consecutive_failures >= self.failover_after;
+        if !failing {
+            return false;
+        }
+        self.failovers += 1;
+        if self.trackers.len() < 2 {
+            return false;
+        }
+
+        let from = self.current;
+        self.current = (self.current + 1) % self.trackers.len();
+        crate::log_line!(
+            "Tracker {} keeps failing , switching to {}",
+            self.trackers[from].announce_url(),
+            self.current().announce_url()
+        );
+        true
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// A tracker url that has been checked and normalized
+///
+/// Parsed once when the torrent is added , so a tracker we can't talk to shows up with a clear status
+/// instead of every announce failing with a confusing request error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackerUrl {
+    Http {
+        secure: bool,
+        host: String,
+        port: u16,
+        /// Path and query , always starts with '/'
+        path: String,
+    },
+    Udp {
+        host: String,
+        port: u16,
+        /// Path and query , sent along as URLData (BEP 41). "/" when the url has none
+        path: String,
+    },
+    /// WebTorrent trackers (ws / wss)
+    Ws {
+        secure: bool,
+        host: String,
+        port: u16,
+        path: String,
+    },
+}
+
+/// Whether we can announce to a tracker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackerStatus {
+    Ready,
+    /// Valid url but we don't speak the protocol (or it was compiled out)
+    Unsupported(String),
+    /// The url itself is broken
+    Invalid(String),
+}
+
+impl TrackerStatus {
+    pub fn is_ready(&self) -> bool {
+        *self == TrackerStatus::Ready
+    }
+}
+
+impl fmt::Display for TrackerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackerStatus::Ready => write!(f, "ready"),
+            TrackerStatus::Unsupported(reason) => write!(f, "unsupported : {}", reason),
+            TrackerStatus::Invalid(reason) => write!(f, "invalid : {}", reason),
+        }
+    }
+}
+
+impl TrackerUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let url = url.trim();
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| anyhow!("'{}' has no scheme", url))?;
+        let scheme = scheme.to_ascii_lowercase();
+
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_end);
+
+        // Drop user info , trackers never need it
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+        let (host, port) = split_host_port(authority)?;
+
+        // Fragments never go to the server
+        let path = path.split('#').next().unwrap_or("");
+        let path = match path {
+            "" => String::from("/"),
+            p if p.starts_with('?') => format!("/{}", p),
+            p => p.to_string(),
+        };
+
+        match scheme.as_str() {
+            "http" | "https" => {
+                let secure = scheme == "https";
+                Ok(TrackerUrl::Http {
+                    secure,
+                    host,
+                    port: port.unwrap_or(if secure { 443 } else { 80 }),
+                    path,
+                })
+            }
+            "udp" => Ok(TrackerUrl::Udp {
+                host,
+                port: port.ok_or_else(|| anyhow!("udp tracker '{}' has no port", url))?,
+                path,
+            }),
+            "ws" | "wss" => {
+                let secure = scheme == "wss";
+                Ok(TrackerUrl::Ws {
+                    secure,
+                    host,
+                    port: port.unwrap_or(if secure { 443 } else { 80 }),
+                    path,
+                })
+            }
+            other => Err(anyhow!("unknown tracker scheme '{}'", other)),
+        }
+    }
+
+    /// Parses a url and works out whether we can announce to it
+    pub fn check(url: &str) -> (Option<TrackerUrl>, TrackerStatus) {
+        match Self::parse(url) {
+            Ok(parsed) => {
+                let status = parsed.support();
+                (Some(parsed), status)
+            }
+            Err(e) => (None, TrackerStatus::Invalid(e.to_string())),
+        }
+    }
+
+    fn support(&self) -> TrackerStatus {
+        match self {
+            #[cfg(feature = "http-tracker")]
+            TrackerUrl::Http { .. } => TrackerStatus::Ready,
+            #[cfg(not(feature = "http-tracker"))]
+            TrackerUrl::Http { .. } => {
+                TrackerStatus::Unsupported("built without the http-tracker feature".to_string())
+            }
+            TrackerUrl::Udp { .. } => TrackerStatus::Ready,
+            TrackerUrl::Ws { .. } => {
+                TrackerStatus::Unsupported("WebTorrent trackers are not supported".to_string())
+            }
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        match self {
+            TrackerUrl::Http { host, .. }
+            | TrackerUrl::Udp { host, .. }
+            | TrackerUrl::Ws { host, .. } => host,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            TrackerUrl::Http { port, .. }
+            | TrackerUrl::Udp { port, .. }
+            | TrackerUrl::Ws { port, .. } => *port,
+        }
+    }
+}
+
+/// Normalized form , lowercase scheme and host with the port always spelled out
+impl fmt::Display for TrackerUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = |host: &str| match host.contains(':') {
+            true => format!("[{}]", host),
+            false => host.to_string(),
+        };
+
+        match self {
+            TrackerUrl::Http {
+                secure,
+                host: h,
+                port,
+                path,
+            } => {
+                let scheme = if *secure { "https" } else { "http" };
+                write!(f, "{}://{}:{}{}", scheme, host(h), port, path)
+            }
+            TrackerUrl::Udp {
+                host: h,
+                port,
+                path,
+            } => match path.as_str() {
+                "/" => write!(f, "udp://{}:{}", host(h), port),
+                path => write!(f, "udp://{}:{}{}", host(h), port, path),
+            },
+            TrackerUrl::Ws {
+                secure,
+                host: h,
+                port,
+                path,
+            } => {
+                let scheme = if *secure { "wss" } else { "ws" };
+                write!(f, "{}://{}:{}{}", scheme, host(h), port, path)
+            }
+        }
+    }
+}
+
+/// Splits `host[:port]` , IPv6 hosts come in brackets (`[::1]:6969`)
+fn split_host_port(authority: &str) -> Result<(String, Option<u16>)> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, after) = rest
+            .split_once(']')
+            .ok_or_else(|| anyhow!("unclosed '[' in host '{}'", authority))?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(anyhow!("tracker url has no host"));
+    }
+
+    let port = match port {
+        Some(port) => Some(
+            port.parse::<u16>()
+                .map_err(|_| anyhow!("invalid port '{}'", port))?,
+        ),
+        None => None,
+    };
+    if port == Some(0) {
+        return Err(anyhow!("port 0 is not a valid tracker port"));
+    }
+
+    Ok((host.to_ascii_lowercase(), port))
+}
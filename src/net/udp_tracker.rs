@@ -0,0 +1,253 @@
+//! UDP tracker protocol (BEP 15) with the URL data option (BEP 41)
+//!
+//! Announces go to every address the tracker host resolves to , IPv6 first , until one answers.
+//! Over IPv6 the tracker sends 18 byte peer entries instead of 6. The url's path and query travel
+//! in URLData options , private trackers put their passkey there
+
+use crate::{
+    core::bind::BindTarget,
+    net::tracker::{TrackerEvent, TrackerRequest, TrackerResponse, parse_compact_peers},
+};
+use anyhow::{Result, anyhow};
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{UdpSocket, lookup_host};
+use tokio::time::timeout;
+
+/// Magic connection id of a connect request
+pub const PROTOCOL_ID: u64 = 0x417_2710_1980;
+
+/// First wait for an answer , doubled on every retry (BEP 15 says 15s)
+pub const UDP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Tries per request before the tracker counts as unreachable
+pub const UDP_ATTEMPTS: u32 = 2;
+
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_ERROR: u32 = 3;
+
+/// BEP 41 option types
+const OPTION_END: u8 = 0;
+const OPTION_URL_DATA: u8 = 2;
+
+/// Largest datagram we expect , a full announce reply of IPv6 peers fits
+const MAX_DATAGRAM: usize = 2048;
+
+/// Announces to a udp tracker
+#[derive(Debug, Clone)]
+pub struct UdpAnnounce<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    /// Path and query of the url , sent as URLData unless it's just "/"
+    pub path: &'a str,
+    pub peer_id: [u8; 20],
+    pub bind: Option<&'a BindTarget>,
+}
+
+impl UdpAnnounce<'_> {
+    pub async fn announce(&self, request: &TrackerRequest) -> Result<TrackerResponse> {
+        let mut addrs: Vec<SocketAddr> = lookup_host((self.host, self.port)).await?.collect();
+        if addrs.is_empty() {
+            return Err(anyhow!("{} did not resolve", self.host));
+        }
+        // IPv6 first , it gets us IPv6 peers
+        addrs.sort_by_key(|addr| addr.is_ipv4());
+
+        let mut last_error = None;
+        for addr in addrs {
+            match self.announce_to(addr, request).await {
+                Ok(response) => return Ok(response),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("No address of {} answered", self.host)))
+    }
+
+    async fn announce_to(
+        &self,
+        addr: SocketAddr,
+        request: &TrackerRequest,
+    ) -> Result<TrackerResponse> {
+        let socket = self.bind_socket(addr).await?;
+        socket.connect(addr).await?;
+
+        let connection_id = connect(&socket).await?;
+
+        let transaction = transaction_id();
+        let packet = announce_packet(
+            connection_id,
+            transaction,
+            request,
+            &self.peer_id,
+            self.path,
+        );
+        let reply = exchange(&socket, &packet, transaction, ACTION_ANNOUNCE).await?;
+        parse_announce_reply(&reply, addr.is_ipv6())
+    }
+
+    async fn bind_socket(&self, remote: SocketAddr) -> Result<UdpSocket> {
+        let unspecified = match remote {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let local = match self.bind {
+            Some(BindTarget::Addr(ip)) if ip.is_ipv4() == remote.is_ipv4() => *ip,
+            Some(BindTarget::Addr(ip)) => {
+                return Err(anyhow!("Bind address {} can't reach {}", ip, remote));
+            }
+            _ => unspecified,
+        };
+
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+        if let Some(bind) = self.bind {
+            bind.prepare_udp(&socket)?;
+        }
+        Ok(socket)
+    }
+}
+
+/// Connect handshake , returns the connection id announces have to carry
+async fn connect(socket: &UdpSocket) -> Result<u64> {
+    let transaction = transaction_id();
+    let mut packet = BytesMut::with_capacity(16);
+    packet.put_u64(PROTOCOL_ID);
+    packet.put_u32(ACTION_CONNECT);
+    packet.put_u32(transaction);
+
+    let mut reply = exchange(socket, &packet, transaction, ACTION_CONNECT).await?;
+    if reply.len() < 8 {
+        return Err(anyhow!("Connect reply is {} bytes", reply.len() + 8));
+    }
+    Ok(reply.get_u64())
+}
+
+/// Sends `packet` until a reply with our transaction id comes back , returns the reply past its header
+///
+/// Error replies (action 3) come back as errors carrying the tracker's message , replies with any
+/// action but `expected` are refused
+async fn exchange(
+    socket: &UdpSocket,
+    packet: &[u8],
+    transaction: u32,
+    expected: u32,
+) -> Result<BytesMut> {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+
+    for attempt in 0..UDP_ATTEMPTS {
+        socket.send(packet).await?;
+        let wait = UDP_TIMEOUT * 2u32.pow(attempt);
+
+        let Ok(received) = timeout(wait, socket.recv(&mut buf)).await else {
+            continue;
+        };
+        let received = received?;
+        if received < 8 {
+            continue;
+        }
+
+        let mut reply = BytesMut::from(&buf[..received]);
+        let action = reply.get_u32();
+        if reply.get_u32() != transaction {
+            continue;
+        }
+        if action == ACTION_ERROR {
+            return Err(anyhow!(
+                "Tracker failure: {}",
+                String::from_utf8_lossy(&reply)
+            ));
+        }
+        if action != expected {
+            return Err(anyhow!(
+                "Tracker replied with action {} , expected {}",
+                action,
+                expected
+            ));
+        }
+        return Ok(reply);
+    }
+
+    Err(anyhow!(
+        "Tracker did not answer after {} attempts",
+        UDP_ATTEMPTS
+    ))
+}
+
+fn announce_packet(
+    connection_id: u64,
+    transaction: u32,
+    request: &TrackerRequest,
+    peer_id: &[u8; 20],
+    path: &str,
+) -> BytesMut {
+    let event = match request.event {
+        Some(TrackerEvent::Completed) => 1,
+        Some(TrackerEvent::Started) => 2,
+        Some(TrackerEvent::Stopped) => 3,
+        // BEP 15 has no paused event
+        Some(TrackerEvent::Paused) | None => 0,
+    };
+
+    let mut packet = BytesMut::with_capacity(98 + path.len() + 8);
+    packet.put_u64(connection_id);
+    packet.put_u32(ACTION_ANNOUNCE);
+    packet.put_u32(transaction);
+    packet.put_slice(&request.info_hash);
+    packet.put_slice(peer_id);
+    packet.put_u64(request.downloaded);
+    packet.put_u64(request.left);
+    packet.put_u64(request.uploaded);
+    packet.put_u32(event);
+    // IP 0 , the tracker uses the address the packet came from
+    packet.put_u32(0);
+    packet.put_u32(transaction_id());
+    // Default number of peers
+    packet.put_i32(-1);
+    packet.put_u16(request.port);
+    put_url_data(&mut packet, path);
+    packet
+}
+
+/// Appends the url path and query as BEP 41 URLData options , split into 255 byte chunks
+pub fn put_url_data(packet: &mut BytesMut, path: &str) {
+    if path.is_empty() || path == "/" {
+        return;
+    }
+
+    for chunk in path.as_bytes().chunks(u8::MAX as usize) {
+        packet.put_u8(OPTION_URL_DATA);
+        packet.put_u8(chunk.len() as u8);
+        packet.put_slice(chunk);
+    }
+    packet.put_u8(OPTION_END);
+}
+
+fn parse_announce_reply(reply: &[u8], ipv6: bool) -> Result<TrackerResponse> {
+    if reply.len() < 12 {
+        return Err(anyhow!("Announce reply is {} bytes", reply.len() + 8));
+    }
+
+    let mut header = &reply[..12];
+    let interval = header.get_u32() as u64;
+    let leechers = header.get_u32() as u64;
+    let seeders = header.get_u32() as u64;
+
+    Ok(TrackerResponse {
+        interval,
+        min_interval: None,
+        peers: parse_compact_peers(&reply[12..], ipv6),
+        complete: Some(seeders),
+        incomplete: Some(leechers),
+        tracker_id: None,
+    })
+}
+
+fn transaction_id() -> u32 {
+    // std has no rng , RandomState is seeded from the OS
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish() as u32
+}
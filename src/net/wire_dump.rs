@@ -0,0 +1,155 @@
+use crate::core::clock::{SharedClock, system_clock};
+use crate::protocol::message::PeerMessage;
+use anyhow::Result;
+use serde_json::{Map, Value, json};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn as_str(&self) -> &str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "recv",
+        }
+    }
+}
+
+/// Records every message of one peer connection as JSON lines
+///
+/// Debug aid for comparing our behaviour with other clients without a packet capture. One line per message :
+///
+/// `{"ts":1700000000.123,"elapsed_ms":52.1,"peer":"1.2.3.4:6881","dir":"recv","type":"piece","length":16393,"piece":3,"offset":16384,"block_length":16384}`
+///
+/// Payloads are never written , only their size
+#[derive(Debug)]
+pub struct WireDump {
+    peer: SocketAddr,
+    path: PathBuf,
+    out: BufWriter<File>,
+    opened_at: Instant,
+    clock: SharedClock,
+}
+
+impl WireDump {
+    /// Opens `<dir>/<ip>_<port>-<unix time>.jsonl` for a connection
+    pub fn create(dir: &Path, peer: SocketAddr) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // Colons in IPv6 addresses aren't allowed in file names everywhere
+        let ip = peer.ip().to_string().replace(':', "-");
+        let path = dir.join(format!("{}_{}-{}.jsonl", ip, peer.port(), started));
+
+        let clock = system_clock();
+        Ok(Self {
+            peer,
+            out: BufWriter::new(File::create(&path)?),
+            path,
+            opened_at: clock.now(),
+            clock,
+        })
+    }
+
+    /// Like `create` , but logs a failure instead of returning it , dumping must never stop a connection
+    pub fn open(dir: &Path, peer: SocketAddr) -> Option<Self> {
+        match Self::create(dir, peer) {
+            Ok(dump) => Some(dump),
+            Err(e) => {
+                crate::log_line!("Could not open wire dump for {} : {}", peer, e);
+                None
+            }
+        }
+    }
+
+    /// Replaces the clock `elapsed_ms` is measured with , counting from now
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.opened_at = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, direction: Direction, message: &PeerMessage) -> Result<()> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+
+        let mut line = Map::new();
+        line.insert("ts".into(), json!(ts));
+        line.insert(
+            "elapsed_ms".into(),
+            json!(
+                self.clock
+                    .now()
+                    .saturating_duration_since(self.opened_at)
+                    .as_secs_f64()
+                    * 1000.0
+            ),
+        );
+        line.insert("peer".into(), json!(self.peer.to_string()));
+        line.insert("dir".into(), json!(direction.as_str()));
+        line.insert("type".into(), json!(message.name()));
+        line.insert("length".into(), json!(message.wire_length()));
+
+        match message {
+            PeerMessage::Have { index } => {
+                line.insert("piece".into(), json!(index));
+            }
+            PeerMessage::Request {
+                index,
+                begin,
+                length,
+            }
+            | PeerMessage::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                line.insert("piece".into(), json!(index));
+                line.insert("offset".into(), json!(begin));
+                line.insert("block_length".into(), json!(length));
+            }
+            PeerMessage::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                line.insert("piece".into(), json!(index));
+                line.insert("offset".into(), json!(begin));
+                line.insert("block_length".into(), json!(block.len()));
+            }
+            PeerMessage::Port(port) => {
+                line.insert("port".into(), json!(port));
+            }
+            PeerMessage::Extended { id, .. } => {
+                line.insert("extension_id".into(), json!(id));
+            }
+            _ => {}
+        }
+
+        serde_json::to_writer(&mut self.out, &Value::Object(line))?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
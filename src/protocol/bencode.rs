@@ -151,4 +151,160 @@ impl BencodeValue {
         let value = reader.copy_to_bytes(length);
         Ok(BencodeValue::Bytes(value))
     }
+
+    /// Encodes the value back into bencode
+    ///
+    /// Dictionaries are written in the order their pairs are stored, so callers building
+    /// their own dictionaries should push keys in sorted order
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            BencodeValue::Integer(i) => {
+                buf.push(b'i');
+                buf.extend_from_slice(i.to_string().as_bytes());
+                buf.push(b'e');
+            }
+            BencodeValue::Bytes(bytes) => {
+                buf.extend_from_slice(bytes.len().to_string().as_bytes());
+                buf.push(b':');
+                buf.extend_from_slice(bytes);
+            }
+            BencodeValue::List(list) => {
+                buf.push(b'l');
+                for item in list {
+                    item.encode_into(buf);
+                }
+                buf.push(b'e');
+            }
+            BencodeValue::Dictionary(pairs) => {
+                buf.push(b'd');
+                for item in pairs {
+                    item.encode_into(buf);
+                }
+                buf.push(b'e');
+            }
+        }
+    }
+
+    /// Looks up a key in a dictionary , returns None if this is not a dictionary
+    pub fn get(&self, key: &[u8]) -> Option<&BencodeValue> {
+        let BencodeValue::Dictionary(pairs) = self else {
+            return None;
+        };
+
+        let mut i = 0;
+        while i + 1 < pairs.len() {
+            if let BencodeValue::Bytes(k) = &pairs[i]
+                && k.as_ref() == key
+            {
+                return Some(&pairs[i + 1]);
+            }
+            i += 2;
+        }
+        None
+    }
+
+    /// (key , value) pairs of a dictionary in stored order , duplicates included
+    pub fn pairs(&self) -> impl Iterator<Item = (&Bytes, &BencodeValue)> {
+        let pairs = match self {
+            BencodeValue::Dictionary(pairs) => pairs.as_slice(),
+            _ => &[],
+        };
+
+        pairs.chunks_exact(2).filter_map(|pair| match &pair[0] {
+            BencodeValue::Bytes(key) => Some((key, &pair[1])),
+            _ => None,
+        })
+    }
+
+    /// Keys that appear more than once in a dictionary
+    ///
+    /// Lookups are first-wins , later copies are kept (so re-encoding is lossless) but never read
+    pub fn duplicate_keys(&self) -> Vec<Bytes> {
+        let mut seen = Vec::new();
+        let mut duplicates = Vec::new();
+
+        for (key, _) in self.pairs() {
+            if seen.contains(&key) {
+                if !duplicates.contains(key) {
+                    duplicates.push(key.clone());
+                }
+            } else {
+                seen.push(key);
+            }
+        }
+
+        duplicates
+    }
+
+    /// Finds the raw encoded bytes of a value in a top level dictionary without re-encoding it
+    ///
+    /// Hashing these bytes gives the same result as hashing the original file , which re-encoding
+    /// a decoded value can't promise (e.g `i03e` comes back as `i3e`). First-wins like `get`
+    pub fn raw_dict_value<'a>(bytes: &'a [u8], key: &[u8]) -> Result<Option<&'a [u8]>, Error> {
+        let mut reader = Bytes::from(bytes.to_vec());
+
+        if !reader.has_remaining() || reader.chunk()[0] != b'd' {
+            return Err(anyhow!("Not a dictionary"));
+        }
+        reader.advance(1);
+
+        while reader.has_remaining() && reader.chunk()[0] != b'e' {
+            let found_key = Self::decode_from_reader(&mut reader)?;
+            let start = bytes.len() - reader.remaining();
+            Self::decode_from_reader(&mut reader)?;
+            let end = bytes.len() - reader.remaining();
+
+            if let BencodeValue::Bytes(k) = found_key
+                && k.as_ref() == key
+            {
+                return Ok(Some(&bytes[start..end]));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            BencodeValue::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        match self {
+            BencodeValue::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&Vec<BencodeValue>> {
+        match self {
+            BencodeValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Builds a byte string value
+    pub fn bytes(data: &[u8]) -> BencodeValue {
+        BencodeValue::Bytes(Bytes::copy_from_slice(data))
+    }
+
+    /// Builds a dictionary out of (key , value) pairs , keys get sorted as the spec requires
+    pub fn dict(mut entries: Vec<(&[u8], BencodeValue)>) -> BencodeValue {
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut pairs = Vec::with_capacity(entries.len() * 2);
+        for (key, value) in entries {
+            pairs.push(BencodeValue::bytes(key));
+            pairs.push(value);
+        }
+        BencodeValue::Dictionary(pairs)
+    }
 }
@@ -0,0 +1,743 @@
+use crate::{
+    core::events::{Event, EventBus},
+    protocol::{
+        bencode::BencodeValue,
+        merkle::{self, FileMerkle, MERKLE_BLOCK_SIZE},
+        torrent::{Torrent, TorrentVersion},
+    },
+    storage::hash_cache::HashCache,
+};
+use anyhow::{Result, anyhow};
+use sha1::{Digest, Sha1};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+/// Smallest piece length picked automatically (16 KiB)
+pub const MIN_AUTO_PIECE_LENGTH: usize = 16 * 1024;
+
+/// Largest piece length picked automatically (16 MiB)
+pub const MAX_AUTO_PIECE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Most pieces an automatic piece length aims for , doubling from there lands between half this and this
+pub const AUTO_TARGET_PIECES: u64 = 2000;
+
+/// Piece length for `total_bytes` of data when the caller doesn't pick one
+///
+/// The smallest power of two that keeps the torrent at AUTO_TARGET_PIECES pieces or fewer , so
+/// between 1000 and 2000 pieces unless the data is too small or too large for the bounds
+pub fn auto_piece_length(total_bytes: u64) -> usize {
+    let mut piece_length = MIN_AUTO_PIECE_LENGTH;
+    while piece_length < MAX_AUTO_PIECE_LENGTH
+        && total_bytes.div_ceil(piece_length as u64) > AUTO_TARGET_PIECES
+    {
+        piece_length *= 2;
+    }
+    piece_length
+}
+
+/// A file that goes into a created torrent
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// Where the file is on disk
+    pub full_path: PathBuf,
+    /// Path components inside the torrent , empty for single file torrents
+    pub path: Vec<String>,
+    pub length: u64,
+    /// BEP 47 pad file , zeros that line the next file up with a piece boundary. Never read from disk
+    pub padding: bool,
+}
+
+impl SourceFile {
+    fn pad(length: u64) -> Self {
+        Self {
+            full_path: PathBuf::new(),
+            path: vec![String::from(".pad"), length.to_string()],
+            length,
+            padding: true,
+        }
+    }
+}
+
+/// Builds a .torrent out of a file or a directory
+///
+/// Besides the usual fields it can add web seeds (BEP 19 `url-list`) and the BEP 38 `similar` and
+/// `collections` keys , which live inside the info dictionary so they become part of the info hash
+#[derive(Debug, Clone)]
+pub struct TorrentCreator {
+    root: PathBuf,
+    announce: String,
+    /// Replaced by one picked from the total size while `auto_piece_length` is set
+    piece_length: usize,
+    auto_piece_length: bool,
+    private: bool,
+    /// Goes into the info dictionary , trackers use it to tell cross-seeded copies apart
+    source: Option<String>,
+    comment: Option<String>,
+    web_seeds: Vec<String>,
+    /// Info hashes of torrents that share files with this one
+    similar: Vec<[u8; 20]>,
+    collections: Vec<String>,
+    /// Where piece hashes of earlier runs over the same data are looked up
+    hash_cache: Option<HashCache>,
+    /// Number of hashing threads
+    threads: usize,
+    /// Receives HashProgress events while pieces are hashed
+    events: Option<EventBus>,
+    version: TorrentVersion,
+}
+
+impl TorrentCreator {
+    pub fn new(root: impl Into<PathBuf>, announce: impl Into<String>) -> Self {
+        Self {
+            root: root.into(),
+            announce: announce.into(),
+            piece_length: MIN_AUTO_PIECE_LENGTH,
+            auto_piece_length: true,
+            private: false,
+            source: None,
+            comment: None,
+            web_seeds: Vec::new(),
+            similar: Vec::new(),
+            collections: Vec::new(),
+            hash_cache: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            events: None,
+            version: TorrentVersion::default(),
+        }
+    }
+
+    /// Picks v1 , v2 or hybrid output
+    pub fn with_version(mut self, version: TorrentVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Fixes the piece length , by default one is picked from the total size
+    pub fn with_piece_length(mut self, piece_length: usize) -> Self {
+        self.piece_length = piece_length;
+        self.auto_piece_length = false;
+        self
+    }
+
+    /// Piece length a torrent over `files` gets , the fixed one or `auto_piece_length` of their size
+    pub fn piece_length_for(&self, files: &[SourceFile]) -> usize {
+        if !self.auto_piece_length {
+            return self.piece_length;
+        }
+        let total_bytes = files
+            .iter()
+            .filter(|file| !file.padding)
+            .map(|file| file.length)
+            .sum();
+        auto_piece_length(total_bytes)
+    }
+
+    /// This creator with the piece length for `files` fixed , so every step agrees on it
+    fn resolved(&self, files: &[SourceFile]) -> Cow<'_, Self> {
+        if !self.auto_piece_length {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(self.clone().with_piece_length(self.piece_length_for(files)))
+    }
+
+    pub fn with_private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn with_web_seeds(mut self, web_seeds: Vec<String>) -> Self {
+        self.web_seeds = web_seeds;
+        self
+    }
+
+    pub fn with_similar(mut self, similar: Vec<[u8; 20]>) -> Self {
+        self.similar = similar;
+        self
+    }
+
+    pub fn with_collections(mut self, collections: Vec<String>) -> Self {
+        self.collections = collections;
+        self
+    }
+
+    /// Reuses piece hashes from earlier runs over unchanged files , e.g when making one torrent per tracker
+    pub fn with_hash_cache(mut self, cache: HashCache) -> Self {
+        self.hash_cache = Some(cache);
+        self
+    }
+
+    /// Number of threads pieces are hashed on , reading always happens on one extra thread
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Publishes hashing progress on the bus
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Every file under the root in the order they'll be hashed (sorted by path)
+    pub fn source_files(&self) -> Result<Vec<SourceFile>> {
+        let metadata = fs::metadata(&self.root)?;
+
+        if metadata.is_file() {
+            return Ok(vec![SourceFile {
+                full_path: self.root.clone(),
+                path: Vec::new(),
+                length: metadata.len(),
+                padding: false,
+            }]);
+        }
+
+        let mut files = Vec::new();
+        collect_files(&self.root, &mut Vec::new(), &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        if files.is_empty() {
+            return Err(anyhow!("{} has no files to share", self.root.display()));
+        }
+        Ok(files)
+    }
+
+    /// Reads every file and hashes it into pieces
+    ///
+    /// One thread reads the files in order and hands whole pieces to a pool of hashing threads , results are
+    /// put back in piece order. Memory stays bounded since the reader can only be a few pieces ahead
+    pub fn hash_pieces(&self, files: &[SourceFile]) -> Result<Vec<[u8; 20]>> {
+        let creator = self.resolved(files);
+        let piece_length = creator.piece_length;
+        if piece_length == 0 {
+            return Err(anyhow!("Piece length is zero"));
+        }
+
+        let total_bytes: u64 = files.iter().map(|file| file.length).sum();
+        let total_pieces = total_bytes.div_ceil(piece_length as u64) as usize;
+
+        let (piece_tx, piece_rx) = mpsc::sync_channel::<(usize, Vec<u8>)>(self.threads * 2);
+        let piece_rx = Arc::new(Mutex::new(piece_rx));
+        let (hash_tx, hash_rx) = mpsc::channel::<(usize, usize, [u8; 20])>();
+
+        thread::scope(|scope| {
+            let reader = scope.spawn(move || read_pieces(files, piece_length, piece_tx));
+
+            for _ in 0..self.threads {
+                let piece_rx = piece_rx.clone();
+                let hash_tx = hash_tx.clone();
+
+                scope.spawn(move || {
+                    loop {
+                        // Only hold the lock while taking a piece , hashing happens unlocked
+                        let next = piece_rx.lock().unwrap().recv();
+                        let Ok((index, data)) = next else {
+                            break;
+                        };
+
+                        let hash: [u8; 20] = Sha1::digest(&data).into();
+                        if hash_tx.send((index, data.len(), hash)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(hash_tx);
+
+            let mut pieces = vec![[0u8; 20]; total_pieces];
+            let mut hashed_pieces = 0;
+            let mut hashed_bytes = 0;
+
+            for (index, length, hash) in hash_rx {
+                if index >= pieces.len() {
+                    pieces.resize(index + 1, [0u8; 20]);
+                }
+                pieces[index] = hash;
+                hashed_pieces += 1;
+                hashed_bytes += length as u64;
+
+                if let Some(events) = &self.events {
+                    events.publish(Event::HashProgress {
+                        hashed_pieces,
+                        total_pieces,
+                        hashed_bytes,
+                        total_bytes,
+                    });
+                }
+            }
+
+            let read_pieces = reader
+                .join()
+                .map_err(|_| anyhow!("Reader thread panicked"))??;
+
+            // Files that changed size while we read them would leave holes
+            if read_pieces != hashed_pieces {
+                return Err(anyhow!(
+                    "Read {} pieces but only {} were hashed",
+                    read_pieces,
+                    hashed_pieces
+                ));
+            }
+            pieces.truncate(read_pieces);
+
+            Ok(pieces)
+        })
+    }
+
+    /// Hashes the data and builds the torrent
+    pub fn create(&self) -> Result<Torrent> {
+        Torrent::from_bytes(&self.create_bytes()?)
+    }
+
+    /// Hashes the data and encodes the .torrent file in the chosen version
+    pub fn create_bytes(&self) -> Result<Vec<u8>> {
+        Ok(self.create_report()?.bytes)
+    }
+
+    /// `create_bytes` plus what went into the torrent , the piece length it ended up with included
+    pub fn create_report(&self) -> Result<CreationReport> {
+        let files = self.source_files()?;
+        let mut report = self.resolved(&files).create_report_from(&files)?;
+        report.auto_piece_length = self.auto_piece_length;
+        Ok(report)
+    }
+
+    fn create_report_from(&self, files: &[SourceFile]) -> Result<CreationReport> {
+        if self.version.has_v2()
+            && (self.piece_length < MERKLE_BLOCK_SIZE || !self.piece_length.is_power_of_two())
+        {
+            return Err(anyhow!(
+                "v2 torrents need a power of two piece length of at least {} bytes",
+                MERKLE_BLOCK_SIZE
+            ));
+        }
+
+        let v1 = match self.version.has_v1() {
+            true => {
+                let v1_files = self.v1_layout(files);
+                let pieces = self.v1_pieces(&v1_files)?;
+                Some((v1_files, pieces))
+            }
+            false => None,
+        };
+
+        let v2 = match self.version.has_v2() {
+            true => Some(self.hash_files_v2(files)?),
+            false => None,
+        };
+
+        let piece_count = match (&v1, &v2) {
+            (Some((_, pieces)), _) => pieces.len(),
+            (None, Some(trees)) => trees
+                .iter()
+                .map(|(file, _)| file.length.div_ceil(self.piece_length as u64) as usize)
+                .sum(),
+            (None, None) => 0,
+        };
+        let bytes = self.encode(
+            v1.as_ref()
+                .map(|(files, pieces)| (files.as_slice(), pieces.as_slice())),
+            v2.as_deref(),
+        )?;
+
+        Ok(CreationReport {
+            bytes,
+            piece_length: self.piece_length,
+            auto_piece_length: self.auto_piece_length,
+            piece_count,
+            total_bytes: files.iter().map(|file| file.length).sum(),
+            files: files.len(),
+        })
+    }
+
+    /// Builds a v1 torrent from piece hashes that were worked out before , nothing is read from disk
+    ///
+    /// With the piece length left on auto it has to come out the same as when `pieces` were hashed ,
+    /// i.e `files` has to be the same list
+    pub fn create_with_pieces(&self, files: &[SourceFile], pieces: &[[u8; 20]]) -> Result<Torrent> {
+        let bytes = self.resolved(files).encode(Some((files, pieces)), None)?;
+        Torrent::from_bytes(&bytes)
+    }
+
+    /// Files as the v1 part of the torrent sees them
+    ///
+    /// Hybrid torrents need every file to start on a piece boundary so both versions agree on the pieces ,
+    /// pad files fill the gaps
+    fn v1_layout(&self, files: &[SourceFile]) -> Vec<SourceFile> {
+        if self.version != TorrentVersion::Hybrid || files.len() < 2 {
+            return files.to_vec();
+        }
+
+        let piece_length = self.piece_length as u64;
+        let mut layout = Vec::new();
+
+        for (i, file) in files.iter().enumerate() {
+            layout.push(file.clone());
+
+            let remainder = file.length % piece_length;
+            if i + 1 < files.len() && remainder != 0 {
+                layout.push(SourceFile::pad(piece_length - remainder));
+            }
+        }
+
+        layout
+    }
+
+    /// v1 piece hashes , going through the hash cache when one is set
+    fn v1_pieces(&self, files: &[SourceFile]) -> Result<Vec<[u8; 20]>> {
+        let cached = self
+            .hash_cache
+            .as_ref()
+            .and_then(|cache| cache.get(files, self.piece_length));
+
+        if let Some(pieces) = cached {
+            return Ok(pieces);
+        }
+
+        let pieces = self.hash_pieces(files)?;
+        if let Some(cache) = &self.hash_cache
+            && let Err(e) = cache.put(files, self.piece_length, &pieces)
+        {
+            crate::log_line!("Could not cache piece hashes : {}", e);
+        }
+        Ok(pieces)
+    }
+
+    /// Builds the merkle tree of every file
+    fn hash_files_v2(&self, files: &[SourceFile]) -> Result<Vec<(SourceFile, FileMerkle)>> {
+        let piece_length = self.piece_length as u64;
+        let total_bytes: u64 = files.iter().map(|file| file.length).sum();
+        let total_pieces: usize = files
+            .iter()
+            .map(|file| file.length.div_ceil(piece_length) as usize)
+            .sum();
+
+        let mut hashed_pieces = 0;
+        let mut hashed_bytes = 0;
+        let mut trees = Vec::with_capacity(files.len());
+
+        for file in files {
+            let tree = merkle::hash_file(&file.full_path, self.piece_length)?;
+            trees.push((file.clone(), tree));
+
+            hashed_pieces += file.length.div_ceil(piece_length) as usize;
+            hashed_bytes += file.length;
+
+            // Hybrid torrents already report progress from the v1 pass
+            if self.version == TorrentVersion::V2
+                && let Some(events) = &self.events
+            {
+                events.publish(Event::HashProgress {
+                    hashed_pieces,
+                    total_pieces,
+                    hashed_bytes,
+                    total_bytes,
+                });
+            }
+        }
+
+        Ok(trees)
+    }
+
+    fn encode(
+        &self,
+        v1: Option<(&[SourceFile], &[[u8; 20]])>,
+        v2: Option<&[(SourceFile, FileMerkle)]>,
+    ) -> Result<Vec<u8>> {
+        let name = self
+            .root
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| anyhow!("Can't name a torrent after {}", self.root.display()))?;
+
+        let mut info: Vec<(&[u8], BencodeValue)> = vec![
+            (b"name", BencodeValue::bytes(name.as_bytes())),
+            (
+                b"piece length",
+                BencodeValue::Integer(self.piece_length as i64),
+            ),
+        ];
+
+        if let Some((files, pieces)) = v1 {
+            info.push((b"pieces", BencodeValue::bytes(&pieces.concat())));
+
+            match files {
+                [single] if single.path.is_empty() => {
+                    info.push((b"length", BencodeValue::Integer(single.length as i64)));
+                }
+                _ => {
+                    let list = files.iter().map(v1_file_entry).collect();
+                    info.push((b"files", BencodeValue::List(list)));
+                }
+            }
+        }
+
+        let mut piece_layers = None;
+        if let Some(trees) = v2 {
+            info.push((b"meta version", BencodeValue::Integer(2)));
+            info.push((b"file tree", file_tree(&name, trees)));
+            piece_layers = Some(piece_layers_value(trees));
+        }
+
+        if self.private {
+            info.push((b"private", BencodeValue::Integer(1)));
+        }
+        if let Some(source) = &self.source {
+            info.push((b"source", BencodeValue::bytes(source.as_bytes())));
+        }
+        if !self.similar.is_empty() {
+            let similar = self
+                .similar
+                .iter()
+                .map(|hash| BencodeValue::bytes(hash))
+                .collect();
+            info.push((b"similar", BencodeValue::List(similar)));
+        }
+        if !self.collections.is_empty() {
+            let collections = self
+                .collections
+                .iter()
+                .map(|name| BencodeValue::bytes(name.as_bytes()))
+                .collect();
+            info.push((b"collections", BencodeValue::List(collections)));
+        }
+
+        let mut meta: Vec<(&[u8], BencodeValue)> = vec![
+            (b"announce", BencodeValue::bytes(self.announce.as_bytes())),
+            (b"created by", BencodeValue::bytes(b"Sekiro")),
+            (b"info", BencodeValue::dict(info)),
+        ];
+        if let Some(layers) = piece_layers {
+            meta.push((b"piece layers", layers));
+        }
+        if let Some(comment) = &self.comment {
+            meta.push((b"comment", BencodeValue::bytes(comment.as_bytes())));
+        }
+        if !self.web_seeds.is_empty() {
+            let urls = self
+                .web_seeds
+                .iter()
+                .map(|url| BencodeValue::bytes(url.as_bytes()))
+                .collect();
+            meta.push((b"url-list", BencodeValue::List(urls)));
+        }
+
+        Ok(BencodeValue::dict(meta).encode())
+    }
+}
+
+/// What `create_report` made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreationReport {
+    /// The encoded .torrent file
+    pub bytes: Vec<u8>,
+    pub piece_length: usize,
+    /// Whether the piece length was picked from the total size instead of set by the caller
+    pub auto_piece_length: bool,
+    /// Pieces of the v1 data , or of every file's merkle tree for v2 only torrents
+    pub piece_count: usize,
+    pub total_bytes: u64,
+    pub files: usize,
+}
+
+/// Makes a copy of a torrent for another tracker without re-hashing anything
+///
+/// The info dictionary (piece hashes included) is reused with its `source` swapped , so the copy gets
+/// its own info hash while pointing at the same data. Passing None drops the source tag
+pub fn cross_seed(torrent: &Torrent, announce: &str, source: Option<&str>) -> Result<Torrent> {
+    let info = torrent.info_dict()?;
+
+    // Rebuild the info dictionary with every other field (unknown ones too) left as it was
+    let mut entries: Vec<(&[u8], BencodeValue)> = info
+        .pairs()
+        .filter(|(key, _)| key.as_ref() != b"source")
+        .map(|(key, value)| (key.as_ref(), value.clone()))
+        .collect();
+    if let Some(source) = source {
+        entries.push((b"source", BencodeValue::bytes(source.as_bytes())));
+    }
+
+    let mut copy = torrent.clone();
+    copy.announce = announce.to_string();
+    // The old tracker's tiers don't belong on the copy
+    copy.announce_list.clear();
+    copy.info_bytes = BencodeValue::dict(entries).encode().into();
+
+    Torrent::from_bytes(&copy.to_bytes())
+}
+
+/// Reads the files as one stream cut into pieces , returns how many pieces were sent
+fn read_pieces(
+    files: &[SourceFile],
+    piece_length: usize,
+    pieces: mpsc::SyncSender<(usize, Vec<u8>)>,
+) -> Result<usize> {
+    let mut index = 0;
+    let mut piece = Vec::with_capacity(piece_length);
+
+    // Pieces run across file boundaries
+    for file in files {
+        let mut reader: Box<dyn Read> = match file.padding {
+            true => Box::new(std::io::repeat(0).take(file.length)),
+            false => Box::new(File::open(&file.full_path)?),
+        };
+
+        loop {
+            let filled = piece.len();
+            piece.resize(piece_length, 0);
+            let read = reader.read(&mut piece[filled..])?;
+            piece.truncate(filled + read);
+
+            if read == 0 {
+                break;
+            }
+
+            if piece.len() == piece_length {
+                let full = std::mem::replace(&mut piece, Vec::with_capacity(piece_length));
+                if pieces.send((index, full)).is_err() {
+                    return Err(anyhow!("Hashing threads stopped early"));
+                }
+                index += 1;
+            }
+        }
+    }
+
+    if !piece.is_empty() {
+        if pieces.send((index, piece)).is_err() {
+            return Err(anyhow!("Hashing threads stopped early"));
+        }
+        index += 1;
+    }
+
+    Ok(index)
+}
+
+fn v1_file_entry(file: &SourceFile) -> BencodeValue {
+    let path = BencodeValue::List(
+        file.path
+            .iter()
+            .map(|part| BencodeValue::bytes(part.as_bytes()))
+            .collect(),
+    );
+
+    let mut entry: Vec<(&[u8], BencodeValue)> = vec![
+        (b"length", BencodeValue::Integer(file.length as i64)),
+        (b"path", path),
+    ];
+    if file.padding {
+        entry.push((b"attr", BencodeValue::bytes(b"p")));
+    }
+    BencodeValue::dict(entry)
+}
+
+/// A directory level of the v2 `file tree`
+#[derive(Default)]
+struct TreeDir {
+    dirs: BTreeMap<String, TreeDir>,
+    files: BTreeMap<String, BencodeValue>,
+}
+
+impl TreeDir {
+    fn insert(&mut self, path: &[String], leaf: BencodeValue) {
+        match path {
+            [] => {}
+            [file] => {
+                self.files.insert(file.clone(), leaf);
+            }
+            [dir, rest @ ..] => self.dirs.entry(dir.clone()).or_default().insert(rest, leaf),
+        }
+    }
+
+    /// Keys are path components , files end in an entry keyed by the empty string
+    fn into_value(self) -> BencodeValue {
+        let mut entries: BTreeMap<String, BencodeValue> = self.files;
+        for (name, dir) in self.dirs {
+            entries.insert(name, dir.into_value());
+        }
+
+        let mut pairs = Vec::with_capacity(entries.len() * 2);
+        for (name, value) in entries {
+            pairs.push(BencodeValue::bytes(name.as_bytes()));
+            pairs.push(value);
+        }
+        BencodeValue::Dictionary(pairs)
+    }
+}
+
+fn file_tree(name: &str, trees: &[(SourceFile, FileMerkle)]) -> BencodeValue {
+    let mut root = TreeDir::default();
+
+    for (file, tree) in trees {
+        let mut attributes: Vec<(&[u8], BencodeValue)> =
+            vec![(b"length", BencodeValue::Integer(file.length as i64))];
+        if let Some(hash) = tree.root {
+            attributes.push((b"pieces root", BencodeValue::bytes(&hash)));
+        }
+        let leaf = BencodeValue::dict(vec![(b"", BencodeValue::dict(attributes))]);
+
+        // Single file torrents list the file under the torrent's name
+        match file.path.is_empty() {
+            true => root.insert(&[name.to_string()], leaf),
+            false => root.insert(&file.path, leaf),
+        }
+    }
+
+    root.into_value()
+}
+
+/// `piece layers` , pieces root -> piece hashes for every file bigger than one piece
+fn piece_layers_value(trees: &[(SourceFile, FileMerkle)]) -> BencodeValue {
+    let mut layers = BTreeMap::new();
+
+    for (_, tree) in trees {
+        if let Some(root) = tree.root
+            && !tree.piece_layer.is_empty()
+        {
+            layers.insert(root, tree.piece_layer.concat());
+        }
+    }
+
+    let mut pairs = Vec::with_capacity(layers.len() * 2);
+    for (root, layer) in layers {
+        pairs.push(BencodeValue::bytes(&root));
+        pairs.push(BencodeValue::bytes(&layer));
+    }
+    BencodeValue::Dictionary(pairs)
+}
+
+fn collect_files(dir: &Path, prefix: &mut Vec<String>, files: &mut Vec<SourceFile>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+
+        prefix.push(name);
+        if file_type.is_dir() {
+            collect_files(&entry.path(), prefix, files)?;
+        } else if file_type.is_file() {
+            files.push(SourceFile {
+                full_path: entry.path(),
+                path: prefix.clone(),
+                length: entry.metadata()?.len(),
+                padding: false,
+            });
+        }
+        prefix.pop();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use crate::protocol::{
+    bencode::BencodeValue,
+    message::PeerMessage,
+    metadata::{LOCAL_UT_METADATA_ID, UT_METADATA},
+};
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+
+/// Message id every extension message is sent under (BEP 10)
+pub const EXTENDED_MESSAGE_ID: u8 = 20;
+
+/// Extended message id of the extension handshake itself
+pub const EXTENDED_HANDSHAKE_ID: u8 = 0;
+
+/// Name of the upload-only extension (BEP 21)
+pub const UPLOAD_ONLY: &str = "upload_only";
+
+/// Id we ask peers to use when they send us upload_only messages
+pub const LOCAL_UPLOAD_ONLY_ID: u8 = 3;
+
+/// The extension handshake , sent right after the BitTorrent handshake to both sides that set the extension bit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtendedHandshake {
+    /// Extension name -> the id the sender wants to receive it under , 0 means disabled
+    pub messages: Vec<(String, u8)>,
+    /// Sender won't download anything , a seed or a partial seed (BEP 21)
+    pub upload_only: bool,
+    /// Client name and version (`v`)
+    pub client: Option<String>,
+    /// Port the sender listens on (`p`)
+    pub listen_port: Option<u16>,
+    /// Size of the info dictionary , from peers that can send it (BEP 9)
+    pub metadata_size: Option<usize>,
+}
+
+impl ExtendedHandshake {
+    /// What we send , every extension we support with our local ids
+    pub fn ours(upload_only: bool, listen_port: u16) -> Self {
+        Self {
+            messages: vec![
+                (UT_METADATA.to_string(), LOCAL_UT_METADATA_ID),
+                (UPLOAD_ONLY.to_string(), LOCAL_UPLOAD_ONLY_ID),
+            ],
+            upload_only,
+            client: Some(format!("Sekiro {}", env!("CARGO_PKG_VERSION"))),
+            listen_port: Some(listen_port),
+            metadata_size: None,
+        }
+    }
+
+    /// Advertises the size of our info dictionary , so peers on a magnet link can fetch it from us
+    pub fn with_metadata_size(mut self, size: usize) -> Self {
+        self.metadata_size = Some(size);
+        self
+    }
+
+    /// Id the sender wants to receive an extension under , None if it doesn't support it
+    pub fn message_id(&self, name: &str) -> Option<u8> {
+        self.messages
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, id)| *id)
+            .filter(|&id| id != 0)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let messages: Vec<(&[u8], BencodeValue)> = self
+            .messages
+            .iter()
+            .map(|(name, id)| (name.as_bytes(), BencodeValue::Integer(*id as i64)))
+            .collect();
+
+        let mut entries = vec![(b"m".as_slice(), BencodeValue::dict(messages))];
+        if self.upload_only {
+            entries.push((b"upload_only", BencodeValue::Integer(1)));
+        }
+        if let Some(client) = &self.client {
+            entries.push((b"v", BencodeValue::bytes(client.as_bytes())));
+        }
+        if let Some(port) = self.listen_port {
+            entries.push((b"p", BencodeValue::Integer(port as i64)));
+        }
+        if let Some(size) = self.metadata_size {
+            entries.push((b"metadata_size", BencodeValue::Integer(size as i64)));
+        }
+
+        BencodeValue::dict(entries).encode()
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let value = BencodeValue::decode(payload)?;
+        if !matches!(value, BencodeValue::Dictionary(_)) {
+            return Err(anyhow!("Extension handshake is not a dictionary"));
+        }
+
+        let messages = value
+            .get(b"m")
+            .map(|m| {
+                m.pairs()
+                    .filter_map(|(name, id)| {
+                        let id = u8::try_from(id.as_integer()?).ok()?;
+                        Some((String::from_utf8_lossy(name).to_string(), id))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            messages,
+            upload_only: value
+                .get(b"upload_only")
+                .and_then(|v| v.as_integer())
+                .is_some_and(|v| v != 0),
+            client: value
+                .get(b"v")
+                .and_then(|v| v.as_bytes())
+                .map(|v| String::from_utf8_lossy(v).to_string()),
+            listen_port: value
+                .get(b"p")
+                .and_then(|v| v.as_integer())
+                .and_then(|p| u16::try_from(p).ok()),
+            metadata_size: value
+                .get(b"metadata_size")
+                .and_then(|v| v.as_integer())
+                .and_then(|size| usize::try_from(size).ok()),
+        })
+    }
+
+    pub fn to_message(&self) -> PeerMessage {
+        PeerMessage::Extended {
+            id: EXTENDED_HANDSHAKE_ID,
+            payload: Bytes::from(self.encode()),
+        }
+    }
+}
+
+/// Tells a peer whether we're upload-only now , `remote_id` is the id from its handshake
+pub fn upload_only_message(remote_id: u8, upload_only: bool) -> PeerMessage {
+    PeerMessage::Extended {
+        id: remote_id,
+        payload: Bytes::from(vec![upload_only as u8]),
+    }
+}
+
+/// Reads the payload of an upload_only message , a single 0 or 1 byte
+pub fn parse_upload_only(payload: &[u8]) -> Option<bool> {
+    payload.first().map(|&flag| flag != 0)
+}
@@ -0,0 +1,141 @@
+//! The v2 parts of a torrent (BEP 52)
+//!
+//! v2 metadata describes files in a `file tree` inside the info dictionary , each file with the
+//! root of its own merkle tree. The hashes one level above the leaves that cover a whole piece go
+//! in `piece layers` , next to the info dictionary so they don't count towards the info hash
+
+use crate::protocol::bencode::BencodeValue;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+
+/// Directories deeper than this are refused , real torrents are nowhere near it
+pub const MAX_TREE_DEPTH: usize = 64;
+
+/// A file in a v2 file tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeFile {
+    /// Path inside the torrent , the torrent's name not included
+    pub path: Vec<String>,
+    pub length: usize,
+    /// Root of the file's merkle tree , None for empty files
+    pub pieces_root: Option<[u8; 32]>,
+}
+
+impl TreeFile {
+    /// Pieces of the file , v2 pieces never span two files
+    pub fn piece_count(&self, piece_length: usize) -> usize {
+        self.length.div_ceil(piece_length.max(1))
+    }
+
+    /// Whether the file gets an entry in `piece layers` , a file of one piece is covered by its root
+    pub fn has_piece_layer(&self, piece_length: usize) -> bool {
+        self.length > piece_length
+    }
+}
+
+/// Every file of a `file tree` , in the tree's (sorted) order
+pub fn parse_file_tree(tree: &BencodeValue) -> Result<Vec<TreeFile>> {
+    let mut files = Vec::new();
+    walk(tree, &mut Vec::new(), &mut files)?;
+    if files.is_empty() {
+        return Err(anyhow!("File tree has no files"));
+    }
+    Ok(files)
+}
+
+fn walk(node: &BencodeValue, path: &mut Vec<String>, files: &mut Vec<TreeFile>) -> Result<()> {
+    if !matches!(node, BencodeValue::Dictionary(_)) {
+        return Err(anyhow!(
+            "File tree entry {} is not a dictionary",
+            path.join("/")
+        ));
+    }
+    if path.len() > MAX_TREE_DEPTH {
+        return Err(anyhow!(
+            "File tree is nested deeper than {}",
+            MAX_TREE_DEPTH
+        ));
+    }
+
+    for (name, child) in node.pairs() {
+        // An empty key marks a file , its dictionary holds the length and root
+        if name.is_empty() {
+            if path.is_empty() {
+                return Err(anyhow!("File tree has a file without a name"));
+            }
+            files.push(tree_file(path.clone(), child)?);
+            continue;
+        }
+
+        let name = String::from_utf8(name.to_vec())
+            .map_err(|_| anyhow!("Invalid UTF-8 in file tree under {}", path.join("/")))?;
+        if name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(anyhow!("File tree has an unsafe path component '{}'", name));
+        }
+        path.push(name);
+        walk(child, path, files)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+fn tree_file(path: Vec<String>, entry: &BencodeValue) -> Result<TreeFile> {
+    let length = entry
+        .get(b"length")
+        .and_then(BencodeValue::as_integer)
+        .and_then(|length| usize::try_from(length).ok())
+        .ok_or_else(|| anyhow!("File {} has no valid length", path.join("/")))?;
+
+    let pieces_root = match entry.get(b"pieces root") {
+        Some(root) => Some(hash32(root).ok_or_else(|| {
+            anyhow!(
+                "File {} has a pieces root that isn't 32 bytes",
+                path.join("/")
+            )
+        })?),
+        None if length > 0 => {
+            return Err(anyhow!("File {} has no pieces root", path.join("/")));
+        }
+        None => None,
+    };
+
+    Ok(TreeFile {
+        path,
+        length,
+        pieces_root,
+    })
+}
+
+/// `piece layers` , the hashes of every piece keyed by the pieces root of their file
+pub fn parse_piece_layers(layers: &BencodeValue) -> Result<HashMap<[u8; 32], Vec<[u8; 32]>>> {
+    if !matches!(layers, BencodeValue::Dictionary(_)) {
+        return Err(anyhow!("Piece layers is not a dictionary"));
+    }
+
+    let mut parsed = HashMap::new();
+    for (root, layer) in layers.pairs() {
+        let root: [u8; 32] = root
+            .as_ref()
+            .try_into()
+            .map_err(|_| anyhow!("Piece layers has a key that isn't a 32 byte root"))?;
+        let bytes = layer
+            .as_bytes()
+            .filter(|bytes| !bytes.is_empty() && bytes.len() % 32 == 0)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Piece layer for {} is not a list of hashes",
+                    hex::encode(root)
+                )
+            })?;
+        let hashes = bytes
+            .chunks_exact(32)
+            .map(|hash| hash.try_into().unwrap())
+            .collect();
+        parsed.entry(root).or_insert(hashes);
+    }
+    Ok(parsed)
+}
+
+fn hash32(value: &BencodeValue) -> Option<[u8; 32]> {
+    value.as_bytes()?.as_ref().try_into().ok()
+}
@@ -0,0 +1,136 @@
+use anyhow::{Result, anyhow};
+
+/// Protocol string every BitTorrent handshake starts with
+pub const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+
+/// `<pstrlen><pstr><reserved 8><info_hash 20><peer_id 20>`
+pub const HANDSHAKE_LEN: usize = 1 + 19 + 8 + 20 + 20;
+
+/// Reserved bits are (byte , mask) pairs , byte 0 being the first reserved byte
+///
+/// Extension protocol (BEP 10)
+pub const EXTENSION_PROTOCOL_BIT: (usize, u8) = (5, 0x10);
+/// DHT , the peer sends a Port message (BEP 5)
+pub const DHT_BIT: (usize, u8) = (7, 0x01);
+/// Fast extension (BEP 6)
+pub const FAST_BIT: (usize, u8) = (7, 0x04);
+
+/// Extensions a peer advertised in its reserved bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Extensions {
+    pub extension_protocol: bool,
+    pub dht: bool,
+    pub fast: bool,
+}
+
+impl Extensions {
+    pub fn from_reserved(reserved: &[u8; 8]) -> Self {
+        let set = |(byte, mask): (usize, u8)| reserved[byte] & mask != 0;
+        Self {
+            extension_protocol: set(EXTENSION_PROTOCOL_BIT),
+            dht: set(DHT_BIT),
+            fast: set(FAST_BIT),
+        }
+    }
+
+    /// Extensions both sides advertised , the only ones either may use on the connection
+    pub fn shared(&self, other: &Extensions) -> Extensions {
+        Extensions {
+            extension_protocol: self.extension_protocol && other.extension_protocol,
+            dht: self.dht && other.dht,
+            fast: self.fast && other.fast,
+        }
+    }
+
+    pub fn to_reserved(&self) -> [u8; 8] {
+        let mut reserved = [0u8; 8];
+        for (on, (byte, mask)) in [
+            (self.extension_protocol, EXTENSION_PROTOCOL_BIT),
+            (self.dht, DHT_BIT),
+            (self.fast, FAST_BIT),
+        ] {
+            if on {
+                reserved[byte] |= mask;
+            }
+        }
+        reserved
+    }
+}
+
+/// The first message on every connection , both sides send one (BEP 3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    /// Kept as sent , bits we don't know about are passed through untouched
+    pub reserved: [u8; 8],
+    pub info_hash: [u8; 20],
+    pub peer_id: [u8; 20],
+}
+
+impl Handshake {
+    /// Our handshake , advertising the extension protocol. The DHT bit is left for
+    /// `with_extensions` once a node is actually running , see `Session::extensions`
+    pub fn new(info_hash: [u8; 20], peer_id: [u8; 20]) -> Self {
+        let extensions = Extensions {
+            extension_protocol: true,
+            dht: false,
+            fast: false,
+        };
+
+        Self {
+            reserved: extensions.to_reserved(),
+            info_hash,
+            peer_id,
+        }
+    }
+
+    pub fn with_extensions(mut self, extensions: Extensions) -> Self {
+        self.reserved = extensions.to_reserved();
+        self
+    }
+
+    pub fn extensions(&self) -> Extensions {
+        Extensions::from_reserved(&self.reserved)
+    }
+
+    pub fn to_bytes(&self) -> [u8; HANDSHAKE_LEN] {
+        let mut bytes = [0u8; HANDSHAKE_LEN];
+        bytes[0] = PROTOCOL.len() as u8;
+        bytes[1..20].copy_from_slice(PROTOCOL);
+        bytes[20..28].copy_from_slice(&self.reserved);
+        bytes[28..48].copy_from_slice(&self.info_hash);
+        bytes[48..68].copy_from_slice(&self.peer_id);
+        bytes
+    }
+
+    /// Parses a handshake , anything that isn't a 68 byte BitTorrent protocol handshake is rejected
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != HANDSHAKE_LEN {
+            return Err(anyhow!(
+                "Handshake is {} bytes , expected {}",
+                bytes.len(),
+                HANDSHAKE_LEN
+            ));
+        }
+        if bytes[0] as usize != PROTOCOL.len() || &bytes[1..20] != PROTOCOL {
+            return Err(anyhow!("Peer doesn't speak the BitTorrent protocol"));
+        }
+
+        Ok(Self {
+            reserved: bytes[20..28].try_into().unwrap(),
+            info_hash: bytes[28..48].try_into().unwrap(),
+            peer_id: bytes[48..68].try_into().unwrap(),
+        })
+    }
+
+    /// Fails unless the peer's handshake is for `info_hash`
+    pub fn check_info_hash(&self, info_hash: &[u8; 20]) -> Result<()> {
+        if &self.info_hash != info_hash {
+            return Err(anyhow!(
+                "Peer answered for info hash {} , expected {}",
+                hex::encode(self.info_hash),
+                hex::encode(info_hash)
+            ));
+        }
+        Ok(())
+    }
+}
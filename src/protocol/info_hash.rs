@@ -0,0 +1,73 @@
+use crate::protocol::bencode::BencodeValue;
+use anyhow::{Result, anyhow};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// The identities of a torrent on the network
+///
+/// v1 torrents only have the SHA-1 hash , v2 torrents only have the SHA-256 one and hybrid torrents have both.
+/// Anything speaking the v1 wire protocol (trackers , DHT , the handshake) uses 20 byte hashes , so the v2 hash
+/// is truncated to its first 20 bytes there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InfoHashes {
+    pub v1: Option<[u8; 20]>,
+    pub v2: Option<[u8; 32]>,
+}
+
+impl InfoHashes {
+    pub fn new(v1: Option<[u8; 20]>, v2: Option<[u8; 32]>) -> Self {
+        Self { v1, v2 }
+    }
+
+    /// Works out the hashes of a .torrent file , whichever versions it carries
+    pub fn of_metainfo(bytes: &[u8]) -> Result<Self> {
+        let raw_info = BencodeValue::raw_dict_value(bytes, b"info")?
+            .ok_or_else(|| anyhow!("Info field not found in dictionary"))?;
+        let info = BencodeValue::decode(raw_info)?;
+
+        let v1 = info.get(b"pieces").map(|_| Sha1::digest(raw_info).into());
+        let v2 = match info.get(b"meta version").and_then(|v| v.as_integer()) {
+            Some(2) => Some(Sha256::digest(raw_info).into()),
+            _ => None,
+        };
+
+        Ok(Self::new(v1, v2))
+    }
+
+    pub fn is_hybrid(&self) -> bool {
+        self.v1.is_some() && self.v2.is_some()
+    }
+
+    /// The v2 hash cut down to 20 bytes , the form it takes in trackers , DHT and handshakes
+    pub fn v2_truncated(&self) -> Option<[u8; 20]> {
+        self.v2.map(|hash| {
+            let mut truncated = [0u8; 20];
+            truncated.copy_from_slice(&hash[..20]);
+            truncated
+        })
+    }
+
+    /// The hash we lead with when we only get to pick one , v1 when we have it
+    pub fn primary(&self) -> [u8; 20] {
+        self.v1.or_else(|| self.v2_truncated()).unwrap_or([0u8; 20])
+    }
+
+    /// Every 20 byte hash this torrent should be announced under
+    ///
+    /// Hybrid torrents live in two swarms , so they get announced to trackers and the DHT under both
+    pub fn announce_hashes(&self) -> Vec<[u8; 20]> {
+        let mut hashes = Vec::new();
+        if let Some(v1) = self.v1 {
+            hashes.push(v1);
+        }
+        if let Some(v2) = self.v2_truncated() {
+            hashes.push(v2);
+        }
+        hashes
+    }
+
+    /// Whether a 20 byte hash from a handshake (or any v1 message) refers to this torrent
+    pub fn matches(&self, hash: &[u8; 20]) -> bool {
+        self.v1.as_ref() == Some(hash) || self.v2_truncated().as_ref() == Some(hash)
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Result, anyhow};
+use std::net::SocketAddr;
+
+/// A magnet link , `magnet:?xt=urn:btih:<hash>&dn=<name>&tr=<tracker>`
+///
+/// Only the info hash is required , the metadata itself comes from peers (BEP 9)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagnetUri {
+    pub info_hash: [u8; 20],
+    /// `dn` , a name to show until the metadata arrives
+    pub display_name: Option<String>,
+    /// `tr` , each one its own tier
+    pub trackers: Vec<String>,
+    /// `x.pe` , peers to ask straight away
+    pub peers: Vec<SocketAddr>,
+}
+
+impl MagnetUri {
+    /// Parses a magnet link , the hash may be 40 hex or 32 base32 characters
+    pub fn parse(uri: &str) -> Result<Self> {
+        let query = uri
+            .trim()
+            .strip_prefix("magnet:?")
+            .ok_or_else(|| anyhow!("Not a magnet link"))?;
+
+        let mut info_hash = None;
+        let mut display_name = None;
+        let mut trackers = Vec::new();
+        let mut peers = Vec::new();
+
+        for param in query.split('&') {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+            // Some clients number repeated keys , tr.1 tr.2 ...
+            let key = key.split('.').next().unwrap_or(key);
+            let value = percent_decode(value);
+
+            match key {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash = Some(parse_btih(hash)?);
+                    }
+                }
+                "dn" => display_name = Some(value),
+                "tr" if !trackers.contains(&value) => trackers.push(value),
+                "x" => {
+                    // x.pe , the key was cut at the dot above
+                    if param.starts_with("x.pe=")
+                        && let Ok(addr) = value.parse()
+                    {
+                        peers.push(addr);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let info_hash = info_hash.ok_or_else(|| anyhow!("Magnet link has no urn:btih hash"))?;
+        Ok(Self {
+            info_hash,
+            display_name,
+            trackers,
+            peers,
+        })
+    }
+
+    /// Name to show , the hash when the link has no `dn`
+    pub fn name(&self) -> String {
+        self.display_name
+            .clone()
+            .unwrap_or_else(|| hex::encode(self.info_hash))
+    }
+
+    /// Writes the link back out , hash in hex
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!("magnet:?xt=urn:btih:{}", hex::encode(self.info_hash));
+        if let Some(name) = &self.display_name {
+            uri.push_str(&format!("&dn={}", percent_encode(name)));
+        }
+        for tracker in &self.trackers {
+            uri.push_str(&format!("&tr={}", percent_encode(tracker)));
+        }
+        for peer in &self.peers {
+            uri.push_str(&format!("&x.pe={}", peer));
+        }
+        uri
+    }
+}
+
+fn parse_btih(hash: &str) -> Result<[u8; 20]> {
+    let bytes = match hash.len() {
+        40 => hex::decode(hash).ok(),
+        32 => base32_decode(hash),
+        _ => None,
+    };
+    bytes
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("Invalid btih hash \"{}\"", hash))
+}
+
+/// RFC 4648 base32 without padding , the older magnet hash encoding
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u64;
+    let mut bits = 0;
+
+    for c in text.chars() {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Undoes %XX escapes and `+` for space , invalid escapes are kept as they are
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let escape = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(escape, 16) {
+                    Ok(byte) => {
+                        out.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
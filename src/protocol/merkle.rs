@@ -0,0 +1,209 @@
+use crate::protocol::file_tree::TreeFile;
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// v2 torrents hash files in 16 KiB blocks , the leaves of every file's merkle tree
+pub const MERKLE_BLOCK_SIZE: usize = 16 * 1024;
+
+/// Hash of two sibling nodes
+pub fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of a tree whose `leaves` leaves are all zero hashes
+///
+/// This is what pads a layer out to a power of two , e.g the missing pieces after the end of a file
+pub fn pad_hash(leaves: usize) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    let mut width = 1;
+
+    while width < leaves {
+        hash = hash_pair(&hash, &hash);
+        width *= 2;
+    }
+    hash
+}
+
+/// Root over `nodes` , padded up to `width` nodes (a power of two) with `pad`
+pub fn merkle_root(nodes: &[[u8; 32]], width: usize, pad: [u8; 32]) -> [u8; 32] {
+    let mut layer = nodes.to_vec();
+    layer.resize(width.max(1), pad);
+
+    while layer.len() > 1 {
+        layer = layer
+            .chunks_exact(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+    }
+    layer[0]
+}
+
+/// Merkle hashes of one file in a v2 torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMerkle {
+    /// `pieces root` of the file , None for empty files which have no tree
+    pub root: Option<[u8; 32]>,
+    /// One hash per piece , what goes into `piece layers`. Empty when the file fits in one piece
+    pub piece_layer: Vec<[u8; 32]>,
+}
+
+/// Builds the merkle tree of a file as BEP 52 describes
+///
+/// Leaves are SHA-256 of 16 KiB blocks (the last one may be short) , padded with zero hashes.
+/// Files bigger than a piece also get their piece layer , the tree level where one node covers one piece
+pub fn hash_file(path: &Path, piece_length: usize) -> Result<FileMerkle> {
+    let mut reader = File::open(path)?;
+    let mut leaves = Vec::new();
+    let mut block = vec![0u8; MERKLE_BLOCK_SIZE];
+
+    loop {
+        let mut filled = 0;
+        while filled < block.len() {
+            let read = reader.read(&mut block[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+
+        if filled == 0 {
+            break;
+        }
+        leaves.push(Sha256::digest(&block[..filled]).into());
+
+        if filled < block.len() {
+            break;
+        }
+    }
+
+    Ok(file_merkle(&leaves, piece_length))
+}
+
+/// Tree of a file from its block hashes
+pub fn file_merkle(leaves: &[[u8; 32]], piece_length: usize) -> FileMerkle {
+    if leaves.is_empty() {
+        return FileMerkle {
+            root: None,
+            piece_layer: Vec::new(),
+        };
+    }
+
+    let blocks_per_piece = (piece_length / MERKLE_BLOCK_SIZE).max(1);
+
+    // Fits in one piece , the tree only goes as wide as the file needs
+    if leaves.len() <= blocks_per_piece {
+        let root = merkle_root(leaves, leaves.len().next_power_of_two(), [0u8; 32]);
+        return FileMerkle {
+            root: Some(root),
+            piece_layer: Vec::new(),
+        };
+    }
+
+    let piece_layer: Vec<[u8; 32]> = leaves
+        .chunks(blocks_per_piece)
+        .map(|piece| merkle_root(piece, blocks_per_piece, [0u8; 32]))
+        .collect();
+
+    let root = merkle_root(
+        &piece_layer,
+        piece_layer.len().next_power_of_two(),
+        pad_hash(blocks_per_piece),
+    );
+
+    FileMerkle {
+        root: Some(root),
+        piece_layer,
+    }
+}
+
+/// What one piece of a v2 torrent has to hash to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MerklePiece {
+    /// Bytes of the piece that belong to its file , whatever follows is padding up to the next file
+    pub data_length: usize,
+    /// Node of the file's tree covering the piece , the file's root when the file is one piece
+    pub expected: [u8; 32],
+    /// Leaves under `expected` , a power of two
+    pub width: usize,
+}
+
+impl MerklePiece {
+    /// Whether `data` hashes up to `expected` , with nothing but zeros after the file's bytes
+    pub fn verify(&self, data: &[u8]) -> bool {
+        if data.len() < self.data_length {
+            return false;
+        }
+        let (file_data, padding) = data.split_at(self.data_length);
+        if padding.iter().any(|&byte| byte != 0) {
+            return false;
+        }
+
+        let leaves: Vec<[u8; 32]> = file_data
+            .chunks(MERKLE_BLOCK_SIZE)
+            .map(|block| Sha256::digest(block).into())
+            .collect();
+        merkle_root(&leaves, self.width, [0u8; 32]) == self.expected
+    }
+}
+
+/// Expected hashes of every piece of `files` , each file starting on a piece of its own
+///
+/// Files bigger than a piece are checked against their entry in `piece layers` , smaller ones
+/// against their root. Empty files have no pieces
+pub fn merkle_pieces(
+    files: &[TreeFile],
+    piece_layers: &HashMap<[u8; 32], Vec<[u8; 32]>>,
+    piece_length: usize,
+) -> Result<Vec<MerklePiece>> {
+    let blocks_per_piece = (piece_length / MERKLE_BLOCK_SIZE).max(1);
+    let mut pieces = Vec::new();
+
+    for file in files {
+        let Some(root) = file.pieces_root else {
+            continue;
+        };
+
+        if !file.has_piece_layer(piece_length) {
+            pieces.push(MerklePiece {
+                data_length: file.length,
+                expected: root,
+                width: file.length.div_ceil(MERKLE_BLOCK_SIZE).next_power_of_two(),
+            });
+            continue;
+        }
+
+        let layer = piece_layers
+            .get(&root)
+            .ok_or_else(|| anyhow!("No piece layer for {}", file.path.join("/")))?;
+        if layer.len() != file.piece_count(piece_length) {
+            return Err(anyhow!(
+                "Piece layer of {} doesn't match its length",
+                file.path.join("/")
+            ));
+        }
+        // The layer has to add up to the root , or the torrent could vouch for any data
+        let width = layer.len().next_power_of_two();
+        if merkle_root(layer, width, pad_hash(blocks_per_piece)) != root {
+            return Err(anyhow!(
+                "Piece layer of {} doesn't hash to its root",
+                file.path.join("/")
+            ));
+        }
+
+        for (index, &expected) in layer.iter().enumerate() {
+            pieces.push(MerklePiece {
+                data_length: (file.length - index * piece_length).min(piece_length),
+                expected,
+                width: blocks_per_piece,
+            });
+        }
+    }
+    Ok(pieces)
+}
@@ -0,0 +1,333 @@
+use anyhow::{Result, anyhow};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Longest message we accept , a 128KB block or the bitfield of a ~16M piece torrent fit with room to spare
+pub const MAX_MESSAGE_LENGTH: usize = 2 * 1024 * 1024;
+
+/// How much the decoder reads from the socket at a time
+const READ_CHUNK: usize = 64 * 1024;
+
+/// A message on the peer wire protocol (BEP 3)
+///
+/// Every message but KeepAlive is `<length u32><id u8><payload>` on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerMessage {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have {
+        index: u32,
+    },
+    /// Pieces the peer has , high bit of the first byte is piece 0
+    Bitfield(Bytes),
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Bytes,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    /// DHT port of the peer (BEP 5)
+    Port(u16),
+    /// Extension protocol message (BEP 10) , `id` 0 is the extension handshake
+    Extended {
+        id: u8,
+        payload: Bytes,
+    },
+}
+
+impl PeerMessage {
+    /// Message id , None for KeepAlive which has no id
+    pub fn id(&self) -> Option<u8> {
+        match self {
+            PeerMessage::KeepAlive => None,
+            PeerMessage::Choke => Some(0),
+            PeerMessage::Unchoke => Some(1),
+            PeerMessage::Interested => Some(2),
+            PeerMessage::NotInterested => Some(3),
+            PeerMessage::Have { .. } => Some(4),
+            PeerMessage::Bitfield(_) => Some(5),
+            PeerMessage::Request { .. } => Some(6),
+            PeerMessage::Piece { .. } => Some(7),
+            PeerMessage::Cancel { .. } => Some(8),
+            PeerMessage::Port(_) => Some(9),
+            PeerMessage::Extended { .. } => Some(20),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PeerMessage::KeepAlive => "keep-alive",
+            PeerMessage::Choke => "choke",
+            PeerMessage::Unchoke => "unchoke",
+            PeerMessage::Interested => "interested",
+            PeerMessage::NotInterested => "not-interested",
+            PeerMessage::Have { .. } => "have",
+            PeerMessage::Bitfield(_) => "bitfield",
+            PeerMessage::Request { .. } => "request",
+            PeerMessage::Piece { .. } => "piece",
+            PeerMessage::Cancel { .. } => "cancel",
+            PeerMessage::Port(_) => "port",
+            PeerMessage::Extended { .. } => "extended",
+        }
+    }
+
+    /// Value of the length prefix , i.e the size of the message without the 4 length bytes
+    pub fn wire_length(&self) -> usize {
+        match self {
+            PeerMessage::KeepAlive => 0,
+            PeerMessage::Choke
+            | PeerMessage::Unchoke
+            | PeerMessage::Interested
+            | PeerMessage::NotInterested => 1,
+            PeerMessage::Have { .. } => 5,
+            PeerMessage::Bitfield(bits) => 1 + bits.len(),
+            PeerMessage::Request { .. } | PeerMessage::Cancel { .. } => 13,
+            PeerMessage::Piece { block, .. } => 9 + block.len(),
+            PeerMessage::Port(_) => 3,
+            PeerMessage::Extended { payload, .. } => 2 + payload.len(),
+        }
+    }
+
+    /// Serializes the message with its length prefix , ready to write to the socket
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(4 + self.wire_length());
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    /// Appends the framed message to `buf` , for batching several messages into one write
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.wire_length() as u32);
+        let Some(id) = self.id() else {
+            return;
+        };
+        buf.put_u8(id);
+
+        match self {
+            PeerMessage::Have { index } => buf.put_u32(*index),
+            PeerMessage::Bitfield(bits) => buf.put_slice(bits),
+            PeerMessage::Request {
+                index,
+                begin,
+                length,
+            }
+            | PeerMessage::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                buf.put_u32(*index);
+                buf.put_u32(*begin);
+                buf.put_u32(*length);
+            }
+            PeerMessage::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                buf.put_u32(*index);
+                buf.put_u32(*begin);
+                buf.put_slice(block);
+            }
+            PeerMessage::Port(port) => buf.put_u16(*port),
+            PeerMessage::Extended { id, payload } => {
+                buf.put_u8(*id);
+                buf.put_slice(payload);
+            }
+            _ => {}
+        }
+    }
+
+    /// Parses one message body , i.e what follows the length prefix. Empty is a KeepAlive
+    pub fn decode(mut body: Bytes) -> Result<Self> {
+        if body.is_empty() {
+            return Ok(PeerMessage::KeepAlive);
+        }
+
+        let id = body.get_u8();
+        let expect = |body: &Bytes, length: usize| {
+            if body.len() == length {
+                Ok(())
+            } else {
+                Err(anyhow!(
+                    "Message {} has a {} byte payload , expected {}",
+                    id,
+                    body.len(),
+                    length
+                ))
+            }
+        };
+        let at_least = |body: &Bytes, length: usize| {
+            if body.len() >= length {
+                Ok(())
+            } else {
+                Err(anyhow!("Message {} is truncated", id))
+            }
+        };
+
+        let message = match id {
+            0 => expect(&body, 0).map(|_| PeerMessage::Choke)?,
+            1 => expect(&body, 0).map(|_| PeerMessage::Unchoke)?,
+            2 => expect(&body, 0).map(|_| PeerMessage::Interested)?,
+            3 => expect(&body, 0).map(|_| PeerMessage::NotInterested)?,
+            4 => {
+                expect(&body, 4)?;
+                PeerMessage::Have {
+                    index: body.get_u32(),
+                }
+            }
+            5 => PeerMessage::Bitfield(body),
+            6 | 8 => {
+                expect(&body, 12)?;
+                let (index, begin, length) = (body.get_u32(), body.get_u32(), body.get_u32());
+                if id == 6 {
+                    PeerMessage::Request {
+                        index,
+                        begin,
+                        length,
+                    }
+                } else {
+                    PeerMessage::Cancel {
+                        index,
+                        begin,
+                        length,
+                    }
+                }
+            }
+            7 => {
+                at_least(&body, 8)?;
+                PeerMessage::Piece {
+                    index: body.get_u32(),
+                    begin: body.get_u32(),
+                    block: body,
+                }
+            }
+            9 => {
+                expect(&body, 2)?;
+                PeerMessage::Port(body.get_u16())
+            }
+            20 => {
+                at_least(&body, 1)?;
+                PeerMessage::Extended {
+                    id: body.get_u8(),
+                    payload: body,
+                }
+            }
+            other => return Err(anyhow!("Unknown message id {}", other)),
+        };
+
+        Ok(message)
+    }
+}
+
+/// Splits a byte stream into messages , bytes can arrive in any chunks
+///
+/// Feed it whatever the socket returned and take messages out until `next_message` says it needs more
+#[derive(Debug, Default)]
+pub struct MessageDecoder {
+    buffer: BytesMut,
+    /// What `read_message` reads the socket into , allocated on first use and kept
+    chunk: Vec<u8>,
+}
+
+impl MessageDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds bytes read from the socket
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Bytes waiting for the rest of their message
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Next complete message , None until enough bytes have been fed
+    ///
+    /// Errors are fatal for the connection , the stream can't be resynchronized after a bad frame
+    pub fn next_message(&mut self) -> Result<Option<PeerMessage>> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+
+        let length = u32::from_be_bytes(self.buffer[..4].try_into().unwrap()) as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(anyhow!(
+                "Message of {} bytes is over the {} byte limit",
+                length,
+                MAX_MESSAGE_LENGTH
+            ));
+        }
+        if self.buffer.len() < 4 + length {
+            // Make room for the rest up front instead of growing once per read
+            self.buffer.reserve(4 + length - self.buffer.len());
+            return Ok(None);
+        }
+
+        self.buffer.advance(4);
+        let body = self.buffer.split_to(length).freeze();
+        PeerMessage::decode(body).map(Some)
+    }
+
+    /// Reads from the socket until a whole message is buffered
+    ///
+    /// Ok(None) means the peer closed the connection cleanly between messages
+    pub async fn read_message(
+        &mut self,
+        stream: &mut (impl AsyncRead + Unpin),
+    ) -> Result<Option<PeerMessage>> {
+        if self.chunk.is_empty() {
+            self.chunk = vec![0u8; READ_CHUNK];
+        }
+        loop {
+            if let Some(message) = self.next_message()? {
+                return Ok(Some(message));
+            }
+
+            match stream.read(&mut self.chunk).await? {
+                0 if self.buffer.is_empty() => return Ok(None),
+                0 => return Err(anyhow!("Connection closed in the middle of a message")),
+                read => self.buffer.extend_from_slice(&self.chunk[..read]),
+            }
+        }
+    }
+}
+
+/// Fills `buf` from the socket , failing if the peer closes the connection first
+pub async fn read_exact(stream: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match stream.read(&mut buf[filled..]).await? {
+            0 => return Err(anyhow!("Connection closed after {} bytes", filled)),
+            read => filled += read,
+        }
+    }
+    Ok(())
+}
+
+/// Writes the whole of `bytes` to the socket
+pub async fn write_all(stream: &mut (impl AsyncWrite + Unpin), mut bytes: &[u8]) -> Result<()> {
+    while !bytes.is_empty() {
+        match stream.write(bytes).await? {
+            0 => return Err(anyhow!("Connection closed while writing")),
+            written => bytes = &bytes[written..],
+        }
+    }
+    stream.flush().await?;
+    Ok(())
+}
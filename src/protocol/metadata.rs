@@ -0,0 +1,242 @@
+//! Metadata exchange (BEP 9) , how a magnet link gets its info dictionary from peers
+//!
+//! The info dictionary is cut into 16 KiB pieces that are requested one at a time over the
+//! `ut_metadata` extension. Peers announce the total size in their extension handshake , the
+//! assembled dictionary has to hash to the magnet's info hash before it's trusted
+
+use crate::protocol::{bencode::BencodeValue, message::PeerMessage};
+use anyhow::{Result, anyhow};
+use bytes::{Buf, Bytes};
+use sha1::{Digest, Sha1};
+
+/// Name of the metadata extension in the extension handshake
+pub const UT_METADATA: &str = "ut_metadata";
+
+/// Id we ask peers to use when they send us ut_metadata messages
+pub const LOCAL_UT_METADATA_ID: u8 = 2;
+
+/// Size of every metadata piece but the last
+pub const METADATA_PIECE_SIZE: usize = 16 * 1024;
+
+/// Largest info dictionary we fetch , a peer claiming more is lying or hostile
+pub const DEFAULT_MAX_METADATA_SIZE: usize = 8 * 1024 * 1024;
+
+const MSG_REQUEST: i64 = 0;
+const MSG_DATA: i64 = 1;
+const MSG_REJECT: i64 = 2;
+
+/// A ut_metadata message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataMessage {
+    Request {
+        piece: u32,
+    },
+    Data {
+        piece: u32,
+        total_size: usize,
+        data: Bytes,
+    },
+    Reject {
+        piece: u32,
+    },
+}
+
+impl MetadataMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let (msg_type, piece) = match self {
+            MetadataMessage::Request { piece } => (MSG_REQUEST, piece),
+            MetadataMessage::Data { piece, .. } => (MSG_DATA, piece),
+            MetadataMessage::Reject { piece } => (MSG_REJECT, piece),
+        };
+
+        let mut entries = vec![
+            (b"msg_type".as_slice(), BencodeValue::Integer(msg_type)),
+            (b"piece", BencodeValue::Integer(*piece as i64)),
+        ];
+        if let MetadataMessage::Data { total_size, .. } = self {
+            entries.push((b"total_size", BencodeValue::Integer(*total_size as i64)));
+        }
+
+        let mut payload = BencodeValue::dict(entries).encode();
+        // Data follows the dictionary raw , outside the bencoding
+        if let MetadataMessage::Data { data, .. } = self {
+            payload.extend_from_slice(data);
+        }
+        payload
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Self> {
+        let mut reader = Bytes::copy_from_slice(payload);
+        let dict = BencodeValue::decode_from_reader(&mut reader)?;
+
+        let field = |key: &[u8]| dict.get(key).and_then(|v| v.as_integer());
+        let piece = field(b"piece")
+            .and_then(|piece| u32::try_from(piece).ok())
+            .ok_or_else(|| anyhow!("ut_metadata message has no piece"))?;
+
+        match field(b"msg_type") {
+            Some(MSG_REQUEST) => Ok(MetadataMessage::Request { piece }),
+            Some(MSG_DATA) => {
+                let total_size = field(b"total_size")
+                    .and_then(|size| usize::try_from(size).ok())
+                    .ok_or_else(|| anyhow!("ut_metadata data has no total_size"))?;
+                Ok(MetadataMessage::Data {
+                    piece,
+                    total_size,
+                    data: reader.copy_to_bytes(reader.remaining()),
+                })
+            }
+            Some(MSG_REJECT) => Ok(MetadataMessage::Reject { piece }),
+            other => Err(anyhow!("Unknown ut_metadata msg_type {:?}", other)),
+        }
+    }
+
+    /// Wraps the message for a peer , `remote_id` is the ut_metadata id from its handshake
+    pub fn to_message(&self, remote_id: u8) -> PeerMessage {
+        PeerMessage::Extended {
+            id: remote_id,
+            payload: Bytes::from(self.encode()),
+        }
+    }
+}
+
+/// Pieces of metadata the info dictionary splits into
+pub fn metadata_piece_count(size: usize) -> usize {
+    size.div_ceil(METADATA_PIECE_SIZE)
+}
+
+/// Answer to a peer's request for a piece of our info dictionary
+pub fn serve_metadata(info_bytes: &[u8], piece: u32) -> MetadataMessage {
+    let start = piece as usize * METADATA_PIECE_SIZE;
+    if start >= info_bytes.len() {
+        return MetadataMessage::Reject { piece };
+    }
+
+    let end = (start + METADATA_PIECE_SIZE).min(info_bytes.len());
+    MetadataMessage::Data {
+        piece,
+        total_size: info_bytes.len(),
+        data: Bytes::copy_from_slice(&info_bytes[start..end]),
+    }
+}
+
+/// An info dictionary being put together from peers
+#[derive(Debug, Clone)]
+pub struct MetadataDownload {
+    info_hash: [u8; 20],
+    size: usize,
+    pieces: Vec<Option<Bytes>>,
+    requested: Vec<bool>,
+}
+
+impl MetadataDownload {
+    /// Starts a download of `size` bytes , the size a peer announced
+    pub fn new(info_hash: [u8; 20], size: usize, max_size: usize) -> Result<Self> {
+        if size == 0 || size > max_size {
+            return Err(anyhow!(
+                "Metadata size of {} bytes is outside 1..={}",
+                size,
+                max_size
+            ));
+        }
+
+        let count = metadata_piece_count(size);
+        Ok(Self {
+            info_hash,
+            size,
+            pieces: vec![None; count],
+            requested: vec![false; count],
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn received(&self) -> usize {
+        self.pieces.iter().filter(|piece| piece.is_some()).count()
+    }
+
+    /// Next piece to ask for , marked as requested
+    pub fn next_request(&mut self) -> Option<u32> {
+        let index = (0..self.pieces.len())
+            .find(|&index| self.pieces[index].is_none() && !self.requested[index])?;
+        self.requested[index] = true;
+        Some(index as u32)
+    }
+
+    /// The peer refused or went away , the piece can be asked for again
+    pub fn release(&mut self, piece: u32) {
+        if let Some(requested) = self.requested.get_mut(piece as usize) {
+            *requested = false;
+        }
+    }
+
+    /// Stores a piece after checking it was asked for and fits the announced size
+    pub fn receive(&mut self, piece: u32, total_size: usize, data: Bytes) -> Result<()> {
+        if total_size != self.size {
+            return Err(anyhow!(
+                "Peer says the metadata is {} bytes , expected {}",
+                total_size,
+                self.size
+            ));
+        }
+
+        let index = piece as usize;
+        if index >= self.pieces.len() {
+            return Err(anyhow!("Metadata piece {} out of range", piece));
+        }
+        // Unasked pieces would let a peer push data faster than we pace requests
+        if !self.requested[index] || self.pieces[index].is_some() {
+            return Err(anyhow!("Metadata piece {} wasn't requested", piece));
+        }
+
+        let expected = (self.size - index * METADATA_PIECE_SIZE).min(METADATA_PIECE_SIZE);
+        if data.len() != expected {
+            return Err(anyhow!(
+                "Metadata piece {} is {} bytes , expected {}",
+                piece,
+                data.len(),
+                expected
+            ));
+        }
+
+        self.pieces[index] = Some(data);
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pieces.iter().all(|piece| piece.is_some())
+    }
+
+    /// The assembled info dictionary , once it hashes to the info hash
+    ///
+    /// On a mismatch every piece is dropped so the download starts over , likely from other peers
+    pub fn finish(&mut self) -> Result<Bytes> {
+        if !self.is_complete() {
+            return Err(anyhow!(
+                "Metadata incomplete , {}/{} pieces",
+                self.received(),
+                self.pieces.len()
+            ));
+        }
+
+        let mut info = Vec::with_capacity(self.size);
+        for piece in self.pieces.iter().flatten() {
+            info.extend_from_slice(piece);
+        }
+
+        if Sha1::digest(&info).as_slice() != self.info_hash {
+            self.pieces.iter_mut().for_each(|piece| *piece = None);
+            self.requested
+                .iter_mut()
+                .for_each(|requested| *requested = false);
+            return Err(anyhow!("Metadata does not match the info hash"));
+        }
+        Ok(Bytes::from(info))
+    }
+}
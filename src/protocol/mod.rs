@@ -1,3 +1,12 @@
 pub mod bencode;
+pub mod creator;
+pub mod extension;
+pub mod file_tree;
+pub mod handshake;
+pub mod info_hash;
+pub mod magnet;
+pub mod merkle;
+pub mod message;
+pub mod metadata;
 pub mod peer;
 pub mod torrent;
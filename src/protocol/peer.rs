@@ -15,3 +15,96 @@ impl Peer {
         SocketAddr::new(self.ip, self.port)
     }
 }
+
+/// Where a discovered peer can be reached , trackers may hand out hostnames as well as addresses
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerHost {
+    Ip(IpAddr),
+    Name(String),
+}
+
+impl PeerHost {
+    /// Parses the `ip` field of a tracker peer entry
+    pub fn parse(host: &str) -> Self {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => PeerHost::Ip(ip),
+            Err(_) => PeerHost::Name(host.to_string()),
+        }
+    }
+}
+
+/// Where we heard about a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PeerSource {
+    Tracker,
+    Dht,
+    /// Peer exchange , another peer told us
+    Pex,
+    /// Local service discovery
+    Lsd,
+    /// Added by the user
+    Manual,
+}
+
+impl PeerSource {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PeerSource::Tracker => "tracker",
+            PeerSource::Dht => "DHT",
+            PeerSource::Pex => "PEX",
+            PeerSource::Lsd => "LSD",
+            PeerSource::Manual => "manual",
+        }
+    }
+}
+
+/// A peer learned about from a tracker (or any other source) that we haven't connected to yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredPeer {
+    pub host: PeerHost,
+    pub port: u16,
+    /// Only non-compact tracker responses carry the peer id
+    pub peer_id: Option<[u8; 20]>,
+    pub source: PeerSource,
+}
+
+impl DiscoveredPeer {
+    /// A peer from a tracker , see `with_source` for anything else
+    pub fn new(host: PeerHost, port: u16) -> Self {
+        Self {
+            host,
+            port,
+            peer_id: None,
+            source: PeerSource::Tracker,
+        }
+    }
+
+    pub fn with_source(mut self, source: PeerSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Socket address of the peer , None when the host still needs resolving
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match &self.host {
+            PeerHost::Ip(ip) => Some(SocketAddr::new(*ip, self.port)),
+            PeerHost::Name(_) => None,
+        }
+    }
+
+    /// Whether both entries describe the same peer
+    ///
+    /// A matching peer id wins over the address , the same client can show up under several addresses
+    pub fn is_same_peer(&self, other: &DiscoveredPeer) -> bool {
+        match (&self.peer_id, &other.peer_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.host == other.host && self.port == other.port,
+        }
+    }
+}
+
+impl From<Peer> for DiscoveredPeer {
+    fn from(peer: Peer) -> Self {
+        DiscoveredPeer::new(PeerHost::Ip(peer.ip), peer.port)
+    }
+}
@@ -1,9 +1,17 @@
 use std::usize;
 
+use crate::core::piece_math::PieceLayout;
 use crate::protocol::bencode::{self as Bencoder, BencodeValue};
+use crate::protocol::file_tree::{TreeFile, parse_file_tree, parse_piece_layers};
+use crate::protocol::info_hash::InfoHashes;
+use crate::protocol::merkle::{self, MerklePiece};
+use crate::util::humanize;
 use anyhow::{Result, anyhow};
 use bytes::Bytes;
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
 
 /// Traits of the torrent
 // This simply allows us to create special functions which u can use to extract info from the torrent file
@@ -11,6 +19,7 @@ use sha1::{Digest, Sha1};
 pub trait TorrentParser {
     fn extract_announce(bytes: &[u8]) -> Result<String>;
     fn extract_info_hash(bytes: &[u8]) -> Result<[u8; 20]>;
+    fn extract_info_hash_v2(bytes: &[u8]) -> Result<Option<[u8; 32]>>;
     fn encode_bencode(value: &BencodeValue, buf: &mut Vec<u8>) -> Result<()>;
     fn extract_name(bytes: &[u8]) -> Result<String>;
     fn extract_piece_length(bytes: &[u8]) -> Result<usize>;
@@ -19,11 +28,90 @@ pub trait TorrentParser {
     fn extract_files(bytes: &[u8]) -> Result<Option<Vec<TorrentFile>>>;
 }
 
+/// Largest total size we accept by default (1 TiB)
+pub const DEFAULT_MAX_TOTAL_SIZE: u64 = 1 << 40;
+
+/// Largest piece length we accept , each piece is held in memory while it downloads
+pub const MAX_PIECE_LENGTH: usize = 256 * 1024 * 1024;
+
+/// Bounds a torrent's metadata has to stay within before we build anything from it
+///
+/// Metadata is untrusted input , without these checks a crafted file could make the engine allocate
+/// millions of pieces or divide by a zero piece length
+#[derive(Debug, Clone)]
+pub struct TorrentLimits {
+    /// Largest total size (sum of every file) in bytes
+    pub max_total_size: u64,
+    pub max_piece_length: usize,
+}
+
+impl Default for TorrentLimits {
+    fn default() -> Self {
+        Self {
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            max_piece_length: MAX_PIECE_LENGTH,
+        }
+    }
+}
+
+/// Smallest piece length v2 torrents may use , one merkle block
+pub const MIN_V2_PIECE_LENGTH: usize = 16 * 1024;
+
+/// Which metadata formats a torrent carries , parsed or picked when creating one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TorrentVersion {
+    /// `pieces` and `length` / `files` only
+    #[default]
+    V1,
+    /// `meta version` 2 with a `file tree` , no v1 piece hashes (BEP 52)
+    V2,
+    /// Both , so v1 and v2 clients can join the same torrent
+    Hybrid,
+}
+
+impl TorrentVersion {
+    /// Reads `meta version` and whether v1 `pieces` are there
+    pub fn of_info(info: &BencodeValue) -> Result<Self> {
+        let has_pieces = info.get(b"pieces").is_some();
+        match info.get(b"meta version").map(|v| v.as_integer()) {
+            None | Some(Some(1)) => Ok(TorrentVersion::V1),
+            Some(Some(2)) if has_pieces => Ok(TorrentVersion::Hybrid),
+            Some(Some(2)) => Ok(TorrentVersion::V2),
+            Some(Some(version)) => Err(anyhow!("Unsupported meta version {}", version)),
+            Some(None) => Err(anyhow!("meta version is not an integer")),
+        }
+    }
+
+    pub fn has_v1(&self) -> bool {
+        *self != TorrentVersion::V2
+    }
+
+    pub fn has_v2(&self) -> bool {
+        *self != TorrentVersion::V1
+    }
+}
+
+impl fmt::Display for TorrentVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TorrentVersion::V1 => "v1",
+            TorrentVersion::V2 => "v2",
+            TorrentVersion::Hybrid => "hybrid",
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Data representation of a Torrent
 pub struct Torrent {
     pub announce: String,
+    /// Tracker tiers from `announce-list` (BEP 12) , empty when the torrent only has `announce`
+    pub announce_list: Vec<Vec<String>>,
+    /// SHA-1 info hash , for v2 only torrents the truncated v2 hash they use on the wire
     pub info_hash: [u8; 20],
+    /// SHA-256 info hash , only present for v2 and hybrid torrents
+    pub info_hash_v2: Option<[u8; 32]>,
+    pub version: TorrentVersion,
     /// Lenght of a single piece in the torrent ( 256 - 1024kb  , might be 2,3mb depending on creator)
     pub piece_length: usize,
     /// Pieces of the torrent
@@ -31,6 +119,18 @@ pub struct Torrent {
     pub name: String,
     pub length: usize,
     pub files: Option<Vec<TorrentFile>>,
+    /// Files of the v2 `file tree` with their merkle roots , None for v1 torrents
+    pub file_tree: Option<Vec<TreeFile>>,
+    /// Piece hashes of every file bigger than a piece , keyed by its pieces root. Empty for v1
+    /// torrents and for metadata fetched from peers , which only carries the info dictionary.
+    /// The raw field also stays in `extra_fields` so re-saving writes it back as it was
+    pub piece_layers: HashMap<[u8; 32], Vec<[u8; 32]>>,
+    /// The info dictionary exactly as it appeared in the file , unknown fields and all
+    ///
+    /// Re-saving splices these bytes back in untouched so the info hash never changes
+    pub info_bytes: Bytes,
+    /// Top level keys we don't model (comment , created by , ...) , kept for re-encoding
+    pub extra_fields: Vec<(Bytes, BencodeValue)>,
 }
 
 #[derive(Debug, Clone)]
@@ -41,27 +141,381 @@ pub struct TorrentFile {
 }
 
 impl Torrent {
+    /// Parses a .torrent file , rejecting metadata outside the default TorrentLimits
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        let announce = Self::extract_announce(bytes)?;
-        let info_hash = Self::extract_info_hash(bytes)?;
+        Self::from_bytes_with_limits(bytes, &TorrentLimits::default())
+    }
+
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: &TorrentLimits) -> Result<Self> {
+        let meta = BencodeValue::decode(bytes)?;
+        let announce_list = Self::extract_announce_list(&meta);
+        // BEP 12 lets announce-list stand in for a missing announce
+        let announce = match Self::extract_announce(bytes) {
+            Ok(announce) => announce,
+            Err(_) if !announce_list.is_empty() => announce_list[0][0].clone(),
+            Err(e) => return Err(e),
+        };
+        let info = meta
+            .get(b"info")
+            .ok_or_else(|| anyhow!("Info field not found in dictionary"))?;
+        let version = TorrentVersion::of_info(info)?;
+        let info_hash_v2 = Self::extract_info_hash_v2(bytes)?;
         let name = Self::extract_name(bytes)?;
         let piece_length = Self::extract_piece_length(bytes)?;
-        let pieces = Self::extract_pieces(bytes)?;
-        let length = Self::extract_length(bytes)?;
-        let files = Self::extract_files(bytes)?;
 
-        Ok(Torrent {
+        let file_tree = match version {
+            TorrentVersion::V1 => None,
+            _ => {
+                let tree = info
+                    .get(b"file tree")
+                    .ok_or_else(|| anyhow!("v2 torrent has no file tree"))?;
+                Some(parse_file_tree(tree)?)
+            }
+        };
+        let piece_layers = match meta.get(b"piece layers") {
+            Some(layers) if version.has_v2() => parse_piece_layers(layers)?,
+            _ => HashMap::new(),
+        };
+
+        // v2 only torrents have no v1 view , files and size come from the file tree
+        let (info_hash, pieces, length, files) = match &file_tree {
+            Some(tree) if !version.has_v1() => (
+                InfoHashes::new(None, info_hash_v2).primary(),
+                Vec::new(),
+                tree.iter().map(|file| file.length).sum(),
+                v1_files(&name, tree),
+            ),
+            _ => (
+                Self::extract_info_hash(bytes)?,
+                Self::extract_pieces(bytes)?,
+                Self::extract_length(bytes)?,
+                Self::extract_files(bytes)?,
+            ),
+        };
+
+        let info_bytes = BencodeValue::raw_dict_value(bytes, b"info")?
+            .map(Bytes::copy_from_slice)
+            .ok_or_else(|| anyhow!("Info field not found in dictionary"))?;
+
+        // Every lookup is first-wins , later copies of a key are ignored
+        for key in meta.duplicate_keys() {
+            crate::log_line!(
+                "Warning : duplicate key '{}' in torrent , using the first one",
+                String::from_utf8_lossy(&key)
+            );
+        }
+        if let Some(info) = meta.get(b"info") {
+            for key in info.duplicate_keys() {
+                crate::log_line!(
+                    "Warning : duplicate key '{}' in info dictionary , using the first one",
+                    String::from_utf8_lossy(&key)
+                );
+            }
+        }
+
+        let mut extra_fields: Vec<(Bytes, BencodeValue)> = Vec::new();
+        for (key, value) in meta.pairs() {
+            let known = matches!(key.as_ref(), b"announce" | b"announce-list" | b"info");
+            if !known && !extra_fields.iter().any(|(k, _)| k == key) {
+                extra_fields.push((key.clone(), value.clone()));
+            }
+        }
+
+        let torrent = Torrent {
             announce,
+            announce_list,
             info_hash,
+            info_hash_v2,
+            version,
             piece_length,
             pieces,
             name,
             length,
             files,
-        })
+            file_tree,
+            piece_layers,
+            info_bytes,
+            extra_fields,
+        };
+        torrent.validate(limits)?;
+
+        Ok(torrent)
+    }
+
+    /// Checks the sizes in the metadata are sane and agree with each other
+    pub fn validate(&self, limits: &TorrentLimits) -> Result<()> {
+        if self.piece_length == 0 {
+            return Err(anyhow!("Piece length is zero"));
+        }
+
+        if self.piece_length > limits.max_piece_length {
+            return Err(anyhow!(
+                "Piece length of {} is over the limit of {}",
+                humanize::bytes(self.piece_length as u64),
+                humanize::bytes(limits.max_piece_length as u64)
+            ));
+        }
+
+        if !self.piece_length.is_power_of_two() {
+            crate::log_line!(
+                "Warning : piece length of {} bytes is not a power of two",
+                self.piece_length
+            );
+        }
+
+        if self.length == 0 {
+            return Err(anyhow!("Torrent has no data , total length is zero"));
+        }
+
+        if self.length as u64 > limits.max_total_size {
+            return Err(anyhow!(
+                "Total size of {} is over the limit of {}",
+                humanize::bytes(self.length as u64),
+                humanize::bytes(limits.max_total_size)
+            ));
+        }
+
+        if self.version.has_v2() {
+            self.validate_v2()?;
+        }
+        if !self.version.has_v1() {
+            return Ok(());
+        }
+
+        let expected_pieces = self.length.div_ceil(self.piece_length);
+        if self.pieces.len() != expected_pieces {
+            return Err(anyhow!(
+                "Torrent has {} piece hashes but {} bytes split into {} byte pieces needs {}",
+                self.pieces.len(),
+                self.length,
+                self.piece_length,
+                expected_pieces
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// BEP 52 rules : power of two pieces of at least 16 KiB , and piece layers that fit their file
+    fn validate_v2(&self) -> Result<()> {
+        if !self.piece_length.is_power_of_two() || self.piece_length < MIN_V2_PIECE_LENGTH {
+            return Err(anyhow!(
+                "v2 piece length of {} bytes is not a power of two of at least {}",
+                self.piece_length,
+                MIN_V2_PIECE_LENGTH
+            ));
+        }
+
+        for file in self.file_tree.iter().flatten() {
+            let Some(layer) = file
+                .pieces_root
+                .and_then(|root| self.piece_layers.get(&root))
+            else {
+                continue;
+            };
+            let expected = file.piece_count(self.piece_length);
+            if !file.has_piece_layer(self.piece_length) || layer.len() != expected {
+                return Err(anyhow!(
+                    "Piece layer of {} has {} hashes , the file has {} pieces",
+                    file.path.join("/"),
+                    layer.len(),
+                    expected
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Pieces to download , for v2 only torrents the sum over files since their pieces never span two
+    pub fn piece_count(&self) -> usize {
+        match &self.file_tree {
+            Some(tree) if !self.version.has_v1() => tree
+                .iter()
+                .map(|file| file.piece_count(self.piece_length))
+                .sum(),
+            _ => self.pieces.len(),
+        }
+    }
+
+    /// Encodes the torrent back into a .torrent file
+    ///
+    /// The info dictionary is written from `info_bytes` as is , so the result has the same info hash as the
+    /// file it was parsed from no matter how the top level fields were edited
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let announce = BencodeValue::bytes(self.announce.as_bytes()).encode();
+
+        let mut entries: Vec<(&[u8], Vec<u8>)> =
+            vec![(b"announce", announce), (b"info", self.info_bytes.to_vec())];
+        if !self.announce_list.is_empty() {
+            let tiers = self
+                .announce_list
+                .iter()
+                .map(|tier| {
+                    BencodeValue::List(
+                        tier.iter()
+                            .map(|url| BencodeValue::bytes(url.as_bytes()))
+                            .collect(),
+                    )
+                })
+                .collect();
+            entries.push((b"announce-list", BencodeValue::List(tiers).encode()));
+        }
+        for (key, value) in &self.extra_fields {
+            entries.push((key.as_ref(), value.encode()));
+        }
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut buf = vec![b'd'];
+        for (key, value) in entries {
+            BencodeValue::bytes(key).encode_into(&mut buf);
+            buf.extend_from_slice(&value);
+        }
+        buf.push(b'e');
+        buf
+    }
+
+    /// Builds a torrent around an info dictionary fetched from peers (BEP 9)
+    ///
+    /// Each tracker from the magnet link becomes its own tier , the info hash is that of `info`
+    pub fn from_info_bytes(info: &[u8], trackers: &[String]) -> Result<Self> {
+        let announce = trackers.first().map(String::as_str).unwrap_or("");
+
+        let mut buf = vec![b'd'];
+        BencodeValue::bytes(b"announce").encode_into(&mut buf);
+        BencodeValue::bytes(announce.as_bytes()).encode_into(&mut buf);
+        if trackers.len() > 1 {
+            let tiers = trackers
+                .iter()
+                .map(|url| BencodeValue::List(vec![BencodeValue::bytes(url.as_bytes())]))
+                .collect();
+            BencodeValue::bytes(b"announce-list").encode_into(&mut buf);
+            BencodeValue::List(tiers).encode_into(&mut buf);
+        }
+        BencodeValue::bytes(b"info").encode_into(&mut buf);
+        buf.extend_from_slice(info);
+        buf.push(b'e');
+
+        Self::from_bytes(&buf)
+    }
+
+    /// Tracker urls grouped in tiers , tried first tier first. Just `announce` without an announce-list
+    pub fn tracker_tiers(&self) -> Vec<Vec<String>> {
+        if !self.announce_list.is_empty() {
+            return self.announce_list.clone();
+        }
+        if self.announce.is_empty() {
+            return Vec::new();
+        }
+        vec![vec![self.announce.clone()]]
+    }
+
+    /// Tiers of `announce-list` , entries that aren't utf-8 urls and tiers left empty are dropped
+    fn extract_announce_list(meta: &BencodeValue) -> Vec<Vec<String>> {
+        let Some(tiers) = meta.get(b"announce-list").and_then(|v| v.as_list()) else {
+            return Vec::new();
+        };
+
+        tiers
+            .iter()
+            .filter_map(|tier| {
+                let urls: Vec<String> = tier
+                    .as_list()?
+                    .iter()
+                    .filter_map(|url| String::from_utf8(url.as_bytes()?.to_vec()).ok())
+                    .filter(|url| !url.is_empty())
+                    .collect();
+                (!urls.is_empty()).then_some(urls)
+            })
+            .collect()
+    }
+
+    /// Decoded info dictionary , including fields we don't model
+    pub fn info_dict(&self) -> Result<BencodeValue> {
+        BencodeValue::decode(&self.info_bytes)
+    }
+
+    /// How the data splits into pieces , fails when the sizes don't agree with the piece count
+    pub fn layout(&self) -> Result<PieceLayout> {
+        PieceLayout::new(self.piece_length, self.length, self.piece_count())
+    }
+
+    /// Merkle hashes every piece is checked against , for torrents with a v2 file tree
+    ///
+    /// None for v1 torrents , and for hybrids without piece layers (e.g fetched from a magnet link)
+    /// which fall back to their SHA-1 hashes
+    pub fn merkle_pieces(&self) -> Result<Option<Vec<MerklePiece>>> {
+        let Some(tree) = &self.file_tree else {
+            return Ok(None);
+        };
+        let needs_layers = tree
+            .iter()
+            .any(|file| file.has_piece_layer(self.piece_length));
+        if self.version.has_v1() && needs_layers && self.piece_layers.is_empty() {
+            return Ok(None);
+        }
+
+        self.check_file_alignment(tree)?;
+        let pieces = merkle::merkle_pieces(tree, &self.piece_layers, self.piece_length)?;
+        if pieces.len() != self.piece_count() {
+            return Err(anyhow!(
+                "File tree makes {} pieces , the torrent has {}",
+                pieces.len(),
+                self.piece_count()
+            ));
+        }
+        Ok(Some(pieces))
+    }
+
+    /// v2 pieces never span two files , every file has to start a piece in the data as it's stored
+    ///
+    /// Hybrids line their files up with pad files , v2 only torrents with several files have none
+    fn check_file_alignment(&self, tree: &[TreeFile]) -> Result<()> {
+        let Some(files) = &self.files else {
+            return Ok(());
+        };
+
+        // Pad files aren't in the file tree , only real files need to line up
+        let mut offset = 0;
+        for file in files {
+            let in_tree = tree.iter().any(|entry| entry.path == file.path);
+            if in_tree && file.length > 0 && offset % self.piece_length != 0 {
+                return Err(anyhow!(
+                    "{} doesn't start on a piece boundary , v2 only torrents with several files \
+                     can't be downloaded yet",
+                    file.path.join("/")
+                ));
+            }
+            offset += file.length;
+        }
+        Ok(())
+    }
+
+    /// Both identities of the torrent , see InfoHashes
+    pub fn info_hashes(&self) -> InfoHashes {
+        let v1 = self.version.has_v1().then_some(self.info_hash);
+        InfoHashes::new(v1, self.info_hash_v2)
     }
 }
 
+/// The v1 style file list of a v2 only torrent , None when it's a single file named like the torrent
+fn v1_files(name: &str, tree: &[TreeFile]) -> Option<Vec<TorrentFile>> {
+    if let [file] = tree
+        && file.path.len() == 1
+        && file.path[0] == name
+    {
+        return None;
+    }
+
+    Some(
+        tree.iter()
+            .map(|file| TorrentFile {
+                path: file.path.clone(),
+                length: file.length,
+            })
+            .collect(),
+    )
+}
+
 impl TorrentParser for Torrent {
     fn extract_announce(bytes: &[u8]) -> Result<String, anyhow::Error> {
         let value = Bencoder::BencodeValue::decode(bytes)?;
@@ -126,33 +580,35 @@ impl TorrentParser for Torrent {
         Err(anyhow!("Name field not found in info dictionary"))
     }
 
+    /// SHA-1 of the info dictionary's raw bytes , never of a re-encoded copy
     fn extract_info_hash(bytes: &[u8]) -> Result<[u8; 20]> {
-        let mut reader = Bytes::from(bytes.to_vec());
-        let value = BencodeValue::decode_from_reader(&mut reader);
-        let dict = match value {
-            Ok(BencodeValue::Dictionary(pairs)) => pairs,
-            _ => return Err(anyhow!("Torrent is not a dictionary at the top level")),
-        };
+        let info = BencodeValue::raw_dict_value(bytes, b"info")?
+            .ok_or_else(|| anyhow!("Info field not found in dictionary"))?;
 
-        let mut i = 0;
-        while i + 1 < dict.len() {
-            if let BencodeValue::Bytes(info_bytes) = &dict[i] {
-                if info_bytes.as_ref() == b"info" {
-                    let info = &dict[i + 1];
-                    let mut buf = Vec::new();
+        let mut hash_bytes = [0u8; 20];
+        hash_bytes.copy_from_slice(&Sha1::digest(info));
+        Ok(hash_bytes)
+    }
 
-                    Self::encode_bencode(info, &mut buf)?;
-                    let hash = Sha1::digest(&buf);
+    /// Hashes the info dictionary with SHA-256 when it declares `meta version` 2 (v2 or hybrid torrents)
+    fn extract_info_hash_v2(bytes: &[u8]) -> Result<Option<[u8; 32]>> {
+        let mut reader = Bytes::from(bytes.to_vec());
+        let value = BencodeValue::decode_from_reader(&mut reader)?;
 
-                    let mut hash_bytes = [0u8; 20];
-                    hash_bytes.copy_from_slice(&hash);
-                    return Ok(hash_bytes);
-                }
-            }
-            i += 2;
+        let info = value
+            .get(b"info")
+            .ok_or_else(|| anyhow!("Info field not found in dictionary"))?;
+
+        if info.get(b"meta version").and_then(|v| v.as_integer()) != Some(2) {
+            return Ok(None);
         }
 
-        Err(anyhow!("Info field not found in dictionary"))
+        let raw_info = BencodeValue::raw_dict_value(bytes, b"info")?
+            .ok_or_else(|| anyhow!("Info field not found in dictionary"))?;
+
+        let mut hash_bytes = [0u8; 32];
+        hash_bytes.copy_from_slice(&Sha256::digest(raw_info));
+        Ok(Some(hash_bytes))
     }
 
     fn extract_piece_length(bytes: &[u8]) -> Result<usize> {
@@ -173,7 +629,9 @@ impl TorrentParser for Torrent {
                             if let BencodeValue::Bytes(piece_key_bytes) = &info_dict[j] {
                                 if piece_key_bytes.as_ref() == b"piece length" {
                                     if let BencodeValue::Integer(piece_len) = info_dict[j + 1] {
-                                        return Ok(piece_len as usize);
+                                        return usize::try_from(piece_len).map_err(|_| {
+                                            anyhow!("piece length {} is out of range", piece_len)
+                                        });
                                     } else {
                                         return Err(anyhow!("piece length is not an integer"));
                                     }
@@ -257,14 +715,16 @@ impl TorrentParser for Torrent {
                                 match length_key_bytes.as_ref() {
                                     b"length" => {
                                         if let BencodeValue::Integer(length) = info_dict[j + 1] {
-                                            return Ok(length as usize);
+                                            return usize::try_from(length).map_err(|_| {
+                                                anyhow!("Length {} is out of range", length)
+                                            });
                                         } else {
                                             return Err(anyhow!("Length is not a usize"));
                                         }
                                     }
                                     b"files" => {
                                         if let BencodeValue::List(files_list) = &info_dict[j + 1] {
-                                            let mut total_length = 0;
+                                            let mut total_length: usize = 0;
                                             for file in files_list {
                                                 if let BencodeValue::Dictionary(file_dict) = file {
                                                     let mut k = 0;
@@ -279,8 +739,19 @@ impl TorrentParser for Torrent {
                                                                     file_length,
                                                                 ) = file_dict[k + 1]
                                                                 {
-                                                                    total_length +=
-                                                                        file_length as usize;
+                                                                    total_length = usize::try_from(
+                                                                        file_length,
+                                                                    )
+                                                                    .ok()
+                                                                    .and_then(|file_length| {
+                                                                        total_length
+                                                                            .checked_add(file_length)
+                                                                    })
+                                                                    .ok_or_else(|| {
+                                                                        anyhow!(
+                                                                            "File lengths are out of range"
+                                                                        )
+                                                                    })?;
                                                                 }
                                                             }
                                                         }
@@ -340,7 +811,15 @@ impl TorrentParser for Torrent {
                                                                     length,
                                                                 ) = file_dict[k + 1]
                                                                 {
-                                                                    file_length = length as usize;
+                                                                    file_length = usize::try_from(
+                                                                        length,
+                                                                    )
+                                                                    .map_err(|_| {
+                                                                        anyhow!(
+                                                                            "File length {} is out of range",
+                                                                            length
+                                                                        )
+                                                                    })?;
                                                                 }
                                                             }
                                                             b"path" => {
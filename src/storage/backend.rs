@@ -0,0 +1,302 @@
+//! Pluggable storage backends
+//!
+//! The engine never touches files directly , everything it stores or reads goes through the
+//! [`Storage`] trait. Three hooks are all it needs :
+//!
+//! - [`Storage::write_block`] stores bytes at an offset inside a piece. Whole verified pieces
+//!   arrive as a single call at offset 0
+//! - [`Storage::read_block`] hands bytes back , for uploads , re-checks and streaming
+//! - [`Storage::verify_piece`] tells whether a stored piece still matches its SHA-1. The default
+//!   reads the piece back and hashes it , backends that already know (e.g a content addressed
+//!   store keyed by hash) can answer without reading anything
+//!
+//! Piece boundaries come from the torrent (`piece_length` , the last piece being shorter) , a
+//! backend decides on its own how pieces map to objects , files or chunks.
+//!
+//! A backend for an object store could look like this :
+//!
+//! ```no_run
+//! use anyhow::Result;
+//! use mini_p2p_file_transfer_system::storage::backend::Storage;
+//!
+//! #[derive(Debug)]
+//! struct BucketStorage {
+//!     bucket: String,
+//! }
+//!
+//! impl BucketStorage {
+//!     fn key(&self, piece_index: usize) -> String {
+//!         format!("{}/pieces/{}", self.bucket, piece_index)
+//!     }
+//! }
+//!
+//! impl Storage for BucketStorage {
+//!     fn write_block(&mut self, piece_index: usize, offset: usize, data: &[u8]) -> Result<()> {
+//!         // PUT data at self.key(piece_index) , ranged from `offset`
+//!         # let _ = (self.key(piece_index), offset, data);
+//!         Ok(())
+//!     }
+//!
+//!     fn read_block(&self, piece_index: usize, offset: usize, length: usize) -> Result<Vec<u8>> {
+//!         // GET self.key(piece_index) with a `Range: bytes=offset-(offset+length-1)` header
+//!         # let _ = (self.key(piece_index), offset);
+//!         Ok(vec![0; length])
+//!     }
+//! }
+//! ```
+//!
+//! Hand it to the engine with `BlockManager::with_storage(torrent, Box::new(backend))`.
+
+use crate::{
+    core::{piece_math::PieceLayout, priority::FilePriority},
+    protocol::torrent::Torrent,
+    storage::{files::FileStorage, resume::FileStamp},
+};
+use anyhow::{Result, anyhow};
+use sha1::{Digest, Sha1};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Where downloaded data ends up
+///
+/// FileStorage writes it to disk , MemoryStorage keeps it in RAM. Implement it to plug in
+/// anything else , see the module docs
+pub trait Storage: Debug + Send {
+    /// Stores `data` at `offset` inside a piece , the engine has already checked the piece hash
+    fn write_block(&mut self, piece_index: usize, offset: usize, data: &[u8]) -> Result<()>;
+
+    /// Reads `length` bytes at `offset` inside a piece , fails if they were never stored
+    fn read_block(&self, piece_index: usize, offset: usize, length: usize) -> Result<Vec<u8>>;
+
+    /// Whether the `length` bytes stored for a piece hash to `expected`
+    ///
+    /// Errors mean the piece couldn't be read at all , Ok(false) that it reads but is wrong
+    fn verify_piece(&self, piece_index: usize, length: usize, expected: &[u8; 20]) -> Result<bool> {
+        let data = self.read_block(piece_index, 0, length)?;
+        Ok(Sha1::digest(&data).as_slice() == expected)
+    }
+
+    /// Pushes buffered writes out , nothing to do for unbuffered backends
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Directory for state that sits next to the data (spilled blocks) , None keeps no state around
+    fn download_dir(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Size and modification time of every file behind the torrent , in file order. None for
+    /// backends without files , their pieces are always rechecked on startup
+    fn file_stamps(&self) -> Option<Vec<Option<FileStamp>>> {
+        None
+    }
+
+    /// Files that are skipped because writing them kept failing , or because the user said so
+    fn quarantined_files(&self) -> Vec<QuarantinedFile> {
+        Vec::new()
+    }
+
+    /// Stops writing one file of the torrent so the others can still finish
+    fn quarantine_file(&mut self, file_index: usize, _reason: &str) -> Result<()> {
+        Err(anyhow!("Storage can't skip file {}", file_index))
+    }
+
+    /// Writes a quarantined file again
+    fn release_file(&mut self, file_index: usize) -> Result<()> {
+        Err(anyhow!("Storage can't skip file {}", file_index))
+    }
+
+    /// Records the priority of one file. Backends without files ignore it , the block manager
+    /// applies priorities either way
+    fn set_file_priority(&mut self, _file_index: usize, _priority: FilePriority) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A file the backend gave up writing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedFile {
+    /// Index in the torrent's file list
+    pub index: usize,
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Storage shared between the block manager and the hash worker
+pub type SharedStorage = Arc<Mutex<Box<dyn Storage>>>;
+
+impl Storage for FileStorage {
+    fn write_block(&mut self, piece_index: usize, offset: usize, data: &[u8]) -> Result<()> {
+        FileStorage::write_block(self, piece_index, offset, data)
+    }
+
+    fn read_block(&self, piece_index: usize, offset: usize, length: usize) -> Result<Vec<u8>> {
+        FileStorage::read_block(self, piece_index, offset, length)
+    }
+
+    fn flush(&self) -> Result<()> {
+        FileStorage::flush(self)
+    }
+
+    fn download_dir(&self) -> Option<&Path> {
+        Some(self.get_download_dir())
+    }
+
+    fn file_stamps(&self) -> Option<Vec<Option<FileStamp>>> {
+        Some(
+            self.file_map
+                .iter()
+                .map(|mapping| FileStamp::of(&mapping.path))
+                .collect(),
+        )
+    }
+
+    fn quarantined_files(&self) -> Vec<QuarantinedFile> {
+        FileStorage::quarantined_files(self)
+            .into_iter()
+            .map(|(index, mapping)| QuarantinedFile {
+                index,
+                path: mapping.path.clone(),
+                reason: mapping.quarantined.clone().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    fn quarantine_file(&mut self, file_index: usize, reason: &str) -> Result<()> {
+        FileStorage::quarantine_file(self, file_index, reason)
+    }
+
+    fn release_file(&mut self, file_index: usize) -> Result<()> {
+        FileStorage::release_file(self, file_index)
+    }
+
+    fn set_file_priority(&mut self, file_index: usize, priority: FilePriority) -> Result<()> {
+        FileStorage::set_file_priority(self, file_index, priority)
+    }
+}
+
+/// Keeps the whole torrent in memory
+///
+/// For tests and the swarm simulator (no temp directories) and for downloading to RAM and streaming
+/// the data out from there. Nothing survives the process
+#[derive(Debug, Clone)]
+pub struct MemoryStorage {
+    piece_length: usize,
+    data: Vec<u8>,
+    /// Ranges written inside each piece , sorted and merged. Bytes nobody wrote can't be read
+    written: Vec<Vec<(usize, usize)>>,
+}
+
+impl MemoryStorage {
+    pub fn new(torrent: Torrent) -> Self {
+        Self {
+            piece_length: torrent.piece_length,
+            data: vec![0; torrent.length],
+            written: vec![Vec::new(); torrent.piece_count()],
+        }
+    }
+
+    fn layout(&self) -> Result<PieceLayout> {
+        PieceLayout::new(self.piece_length, self.data.len(), self.written.len())
+    }
+
+    fn range(&self, piece_index: usize, offset: usize, length: usize) -> Result<(usize, usize)> {
+        let start = self
+            .layout()?
+            .block_start(piece_index.into(), offset as u64, length as u64)?
+            .to_usize()?;
+        // The block fits inside data , which is in memory already
+        Ok((start, start + length))
+    }
+
+    /// Whole payload , None until every piece has been written
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        let layout = self.layout().ok()?;
+        let complete = (0..self.written.len()).all(|index| {
+            layout
+                .piece_size(index.into())
+                .is_ok_and(|size| self.is_written(index, 0, size as usize))
+        });
+
+        complete.then_some(self.data)
+    }
+
+    /// Whether every byte of `offset..offset + length` inside the piece was written
+    fn is_written(&self, piece_index: usize, offset: usize, length: usize) -> bool {
+        let end = offset + length;
+        self.written[piece_index]
+            .iter()
+            .any(|&(start, stop)| start <= offset && end <= stop)
+    }
+
+    /// Adds a written range , merged with the ones it touches so a rewrite counts once
+    fn mark_written(&mut self, piece_index: usize, offset: usize, length: usize) {
+        let ranges = &mut self.written[piece_index];
+        let (mut start, mut end) = (offset, offset + length);
+        ranges.retain(|&(other_start, other_end)| {
+            let touches = other_start <= end && start <= other_end;
+            if touches {
+                start = start.min(other_start);
+                end = end.max(other_end);
+            }
+            !touches
+        });
+        let at = ranges.partition_point(|&(other_start, _)| other_start < start);
+        ranges.insert(at, (start, end));
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn write_block(&mut self, piece_index: usize, offset: usize, data: &[u8]) -> Result<()> {
+        let (start, end) = self.range(piece_index, offset, data.len())?;
+        self.data[start..end].copy_from_slice(data);
+        self.mark_written(piece_index, offset, data.len());
+        Ok(())
+    }
+
+    fn read_block(&self, piece_index: usize, offset: usize, length: usize) -> Result<Vec<u8>> {
+        let (start, end) = self.range(piece_index, offset, length)?;
+        if !self.is_written(piece_index, offset, length) {
+            return Err(anyhow!(
+                "Piece {} has no data stored at {}..{}",
+                piece_index,
+                offset,
+                offset + length
+            ));
+        }
+        Ok(self.data[start..end].to_vec())
+    }
+}
+
+/// Throws every piece away , only counts what went through
+///
+/// For measuring throughput without the disk in the way. Nothing can be read back , so pieces are
+/// never seen as complete on restart and can't be uploaded
+#[derive(Debug, Clone, Default)]
+pub struct SinkStorage {
+    written: u64,
+}
+
+impl SinkStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl Storage for SinkStorage {
+    fn write_block(&mut self, _piece_index: usize, _offset: usize, data: &[u8]) -> Result<()> {
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    fn read_block(&self, piece_index: usize, _offset: usize, _length: usize) -> Result<Vec<u8>> {
+        Err(anyhow!("Piece {} was discarded by the sink", piece_index))
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+/// How long a single disk operation may take before it counts as a failure
+pub const DEFAULT_STORAGE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Failures in a row that trip the breaker
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+/// Pause after the first trip , doubled on every trip after that
+pub const BASE_COOLDOWN: Duration = Duration::from_secs(5);
+pub const MAX_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Storage is healthy , everything goes through
+    Closed,
+    /// Storage keeps failing , nothing goes through until `until`
+    Open { until: Instant },
+    /// Cooldown is over , the next operation decides whether we close or open again
+    HalfOpen,
+}
+
+/// Circuit breaker for the storage backend
+///
+/// A download dir on NFS or SMB can stall for minutes. Rather than piling up work behind a stuck
+/// mount , the torrent stops asking for data once enough operations failed or timed out in a row and
+/// tries again after a backoff
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    state: BreakerState,
+    /// Failures since the last success
+    failures: u32,
+    /// Trips since the last success , drives the backoff
+    trips: u32,
+    threshold: u32,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            state: BreakerState::Closed,
+            failures: 0,
+            trips: 0,
+            threshold: threshold.max(1),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    /// Whether an operation may run now , moves an expired Open breaker to HalfOpen
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open { until } if now >= until => {
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open { .. } => false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self.state, BreakerState::Open { .. })
+    }
+
+    /// Records a successful operation , true if that closed a tripped breaker
+    pub fn record_success(&mut self) -> bool {
+        let recovered = self.state != BreakerState::Closed;
+        self.state = BreakerState::Closed;
+        self.failures = 0;
+        self.trips = 0;
+        recovered
+    }
+
+    /// Records a failed or timed out operation , returns the pause if that tripped the breaker
+    pub fn record_failure(&mut self, now: Instant) -> Option<Duration> {
+        self.failures += 1;
+
+        // A failed probe reopens straight away , no need to count up to the threshold again
+        let trip = match self.state {
+            BreakerState::Closed => self.failures >= self.threshold,
+            BreakerState::HalfOpen => true,
+            BreakerState::Open { .. } => false,
+        };
+        trip.then(|| self.trip(now))
+    }
+
+    /// Opens the breaker right away , for failures bad enough on their own (a hung write)
+    pub fn trip(&mut self, now: Instant) -> Duration {
+        let cooldown = self.cooldown();
+        self.trips += 1;
+        self.state = BreakerState::Open {
+            until: now + cooldown,
+        };
+        cooldown
+    }
+
+    fn cooldown(&self) -> Duration {
+        BASE_COOLDOWN
+            .checked_mul(1 << self.trips.min(16))
+            .unwrap_or(MAX_COOLDOWN)
+            .min(MAX_COOLDOWN)
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD)
+    }
+}
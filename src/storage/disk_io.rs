@@ -0,0 +1,247 @@
+use crate::{
+    core::runtime::{Runtime, TokioRuntime},
+    storage::backend::SharedStorage,
+};
+use anyhow::{Result, anyhow};
+use std::sync::mpsc as std_mpsc;
+use tokio::sync::{mpsc, oneshot};
+
+/// Disk jobs that may wait in line before callers are turned away (or made to wait)
+pub const DEFAULT_DISK_QUEUE_DEPTH: usize = 64;
+
+/// Work for the disk thread , each job answers on its own channel
+#[derive(Debug)]
+enum DiskJob {
+    Read {
+        piece_index: usize,
+        offset: usize,
+        length: usize,
+        reply: oneshot::Sender<Result<Vec<u8>>>,
+    },
+    Write {
+        piece_index: usize,
+        offset: usize,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+}
+
+/// Answer to a queued disk job , poll it with `try_take` or await it with `wait`
+#[derive(Debug)]
+pub struct DiskReply<T> {
+    reply: oneshot::Receiver<Result<T>>,
+}
+
+impl<T> DiskReply<T> {
+    /// The result once the job ran , None while it's still queued or running. Never blocks
+    pub fn try_take(&mut self) -> Option<Result<T>> {
+        match self.reply.try_recv() {
+            Ok(result) => Some(result),
+            Err(oneshot::error::TryRecvError::Empty) => None,
+            Err(oneshot::error::TryRecvError::Closed) => {
+                Some(Err(anyhow!("Disk thread has stopped")))
+            }
+        }
+    }
+
+    pub async fn wait(self) -> Result<T> {
+        self.reply
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("Disk thread has stopped")))
+    }
+}
+
+/// Dedicated thread doing storage reads and writes , so blocking file I/O never runs on the event loop
+///
+/// Jobs go through a bounded queue. Async callers wait for room when it's full , the `try_` calls
+/// fail instead so a caller that can't wait (e.g serving uploads) can drop the work
+#[derive(Debug)]
+pub struct DiskIo {
+    jobs: Option<mpsc::Sender<DiskJob>>,
+    /// Hangs up once the disk thread ends , whether it returned or panicked
+    finished: Option<std_mpsc::Receiver<()>>,
+}
+
+impl DiskIo {
+    pub fn spawn(storage: SharedStorage, queue_depth: usize) -> Self {
+        Self::spawn_on(&TokioRuntime::new(), storage, queue_depth)
+    }
+
+    /// Same as `spawn` , with the disk thread coming from `runtime`
+    pub fn spawn_on(runtime: &dyn Runtime, storage: SharedStorage, queue_depth: usize) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<DiskJob>(queue_depth.max(1));
+        let (done, finished) = std_mpsc::channel::<()>();
+
+        runtime
+            .spawn_blocking(
+                "disk-io",
+                Box::new(move || {
+                    let _done = done;
+                    run(storage, job_rx);
+                }),
+            )
+            .expect("failed to spawn disk io thread");
+
+        Self {
+            jobs: Some(job_tx),
+            finished: Some(finished),
+        }
+    }
+
+    /// Reads `length` bytes at `offset` inside a piece , waiting for room in the queue
+    pub async fn read_piece(
+        &self,
+        piece_index: usize,
+        offset: usize,
+        length: usize,
+    ) -> Result<Vec<u8>> {
+        let (reply, answer) = oneshot::channel();
+        self.send(DiskJob::Read {
+            piece_index,
+            offset,
+            length,
+            reply,
+        })
+        .await?;
+        DiskReply { reply: answer }.wait().await
+    }
+
+    /// Stores `data` at `offset` inside a piece , waiting for room in the queue
+    pub async fn write_piece(
+        &self,
+        piece_index: usize,
+        offset: usize,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let (reply, answer) = oneshot::channel();
+        self.send(DiskJob::Write {
+            piece_index,
+            offset,
+            data,
+            reply,
+        })
+        .await?;
+        DiskReply { reply: answer }.wait().await
+    }
+
+    /// Queues a read without waiting , fails when the queue is full
+    pub fn try_read_piece(
+        &self,
+        piece_index: usize,
+        offset: usize,
+        length: usize,
+    ) -> Result<DiskReply<Vec<u8>>> {
+        let (reply, answer) = oneshot::channel();
+        self.try_send(DiskJob::Read {
+            piece_index,
+            offset,
+            length,
+            reply,
+        })?;
+        Ok(DiskReply { reply: answer })
+    }
+
+    /// Queues a write without waiting , fails when the queue is full
+    pub fn try_write_piece(
+        &self,
+        piece_index: usize,
+        offset: usize,
+        data: Vec<u8>,
+    ) -> Result<DiskReply<()>> {
+        let (reply, answer) = oneshot::channel();
+        self.try_send(DiskJob::Write {
+            piece_index,
+            offset,
+            data,
+            reply,
+        })?;
+        Ok(DiskReply { reply: answer })
+    }
+
+    /// Jobs waiting for the disk thread
+    pub fn queued(&self) -> usize {
+        self.jobs
+            .as_ref()
+            .map_or(0, |jobs| jobs.max_capacity() - jobs.capacity())
+    }
+
+    /// Whether the queue is full , new `try_` jobs are turned away until it drains
+    pub fn is_backed_up(&self) -> bool {
+        self.jobs.as_ref().is_some_and(|jobs| jobs.capacity() == 0)
+    }
+
+    async fn send(&self, job: DiskJob) -> Result<()> {
+        let jobs = self
+            .jobs
+            .as_ref()
+            .ok_or_else(|| anyhow!("Disk thread has stopped"))?;
+        jobs.send(job)
+            .await
+            .map_err(|_| anyhow!("Disk thread has stopped"))
+    }
+
+    fn try_send(&self, job: DiskJob) -> Result<()> {
+        let jobs = self
+            .jobs
+            .as_ref()
+            .ok_or_else(|| anyhow!("Disk thread has stopped"))?;
+        jobs.try_send(job).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => anyhow!("Disk queue is full"),
+            mpsc::error::TrySendError::Closed(_) => anyhow!("Disk thread has stopped"),
+        })
+    }
+}
+
+impl Drop for DiskIo {
+    fn drop(&mut self) {
+        // Closing the queue ends the thread once the jobs already in it are done
+        self.jobs.take();
+        if let Some(finished) = self.finished.take() {
+            let _ = finished.recv();
+        }
+    }
+}
+
+/// The disk thread , runs jobs until the queue is closed and empty
+fn run(storage: SharedStorage, mut job_rx: mpsc::Receiver<DiskJob>) {
+    while let Some(job) = job_rx.blocking_recv() {
+        let mut storage = match storage.lock() {
+            Ok(storage) => storage,
+            Err(_) => {
+                fail(job, anyhow!("Storage lock is poisoned"));
+                continue;
+            }
+        };
+
+        // A dropped reply means the caller gave up on it , nothing left to do
+        match job {
+            DiskJob::Read {
+                piece_index,
+                offset,
+                length,
+                reply,
+            } => {
+                let _ = reply.send(storage.read_block(piece_index, offset, length));
+            }
+            DiskJob::Write {
+                piece_index,
+                offset,
+                data,
+                reply,
+            } => {
+                let _ = reply.send(storage.write_block(piece_index, offset, &data));
+            }
+        }
+    }
+}
+
+fn fail(job: DiskJob, error: anyhow::Error) {
+    match job {
+        DiskJob::Read { reply, .. } => {
+            let _ = reply.send(Err(error));
+        }
+        DiskJob::Write { reply, .. } => {
+            let _ = reply.send(Err(error));
+        }
+    }
+}
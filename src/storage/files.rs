@@ -1,9 +1,21 @@
+use crate::core::priority::FilePriority;
+use crate::core::resources::{Resource, Tracked};
 use crate::protocol::torrent::*;
 use anyhow::anyhow;
 use sha1::{Digest, Sha1};
 use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Pieces that have to complete back to back before writes switch to sequential mode
+pub const SEQUENTIAL_THRESHOLD: usize = 4;
+
+/// Write buffer used in sequential mode (4 MiB)
+pub const SEQUENTIAL_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Writes in a row a file may fail before it's quarantined and skipped
+pub const QUARANTINE_AFTER: u32 = 3;
 
 #[derive(Debug)]
 pub struct FileStorage {
@@ -20,6 +32,24 @@ pub struct FileStorage {
     pub file_map: Vec<FileMapping>,
     /// Total length of a SINGLE FILE PAYLOAD / Size of a SINGLE FILE PAYLOAD (in mb/kb/gb)
     pub total_length: usize,
+    /// Piece that would continue the current in-order run
+    next_piece: usize,
+    /// How many pieces in a row were written in order
+    sequential_run: usize,
+    /// Open buffered file used while in sequential mode
+    ///
+    /// Behind a Mutex so `read_piece` (which only has &self) can flush it before reading
+    writer: Mutex<Option<SequentialWriter>>,
+}
+
+/// Buffered writer that appends to one file without seeking between writes
+#[derive(Debug)]
+struct SequentialWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Offset in the file the next buffered byte goes to
+    position: usize,
+    _handle: Tracked,
 }
 
 #[derive(Debug, Clone)]
@@ -33,6 +63,12 @@ pub struct FileMapping {
     pub length: usize,
     /// Whether the file exists and is complete
     pub is_complete: bool,
+    /// Writes to this file that failed in a row
+    pub write_failures: u32,
+    /// Why the file is skipped , None while it's written normally
+    pub quarantined: Option<String>,
+    /// How much the user wants the file , set through the block manager
+    pub priority: FilePriority,
 }
 
 #[derive(Debug)]
@@ -51,6 +87,9 @@ impl FileStorage {
             torrent,
             file_map,
             total_length,
+            next_piece: 0,
+            sequential_run: 0,
+            writer: Mutex::new(None),
         };
 
         // Create directory structure
@@ -87,6 +126,9 @@ impl FileStorage {
                         start_offset: current_offset,
                         length: file.length,
                         is_complete: false,
+                        write_failures: 0,
+                        quarantined: None,
+                        priority: FilePriority::default(),
                     });
 
                     current_offset += file.length;
@@ -100,6 +142,9 @@ impl FileStorage {
                     start_offset: 0,
                     length: torrent.length,
                     is_complete: false,
+                    write_failures: 0,
+                    quarantined: None,
+                    priority: FilePriority::default(),
                 });
             }
         }
@@ -129,9 +174,9 @@ impl FileStorage {
 
                 if metadata.len() as usize == mapping.length {
                     mapping.is_complete = true;
-                    println!("✅ Found complete file : {}", mapping.path.display());
+                    crate::log_line!("✅ Found complete file : {}", mapping.path.display());
                 } else {
-                    println!(
+                    crate::log_line!(
                         "⚠️  Found partial file: {} ({} bytes, expected {})",
                         mapping.path.display(),
                         metadata.len(),
@@ -158,34 +203,221 @@ impl FileStorage {
             return Err(anyhow!("Piece {} hash verification failed", piece_index));
         }
 
-        // start of the piece , usually the index * piece length
-        let piece_start = piece_index * self.torrent.piece_length;
-        let diff = (piece_start + data.len()).min(self.total_length);
-        let piece_end = diff;
-        let affected_files = self.get_affected_files(piece_start, piece_end)?;
+        self.write_block(piece_index, 0, data)
+    }
+
+    /// Writes `data` at `offset` inside a piece , without checking any hash
+    ///
+    /// Writes that start a piece count towards sequential mode , anything else seeks
+    pub fn write_block(
+        &mut self,
+        piece_index: usize,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        let sequential = if offset == 0 {
+            self.track_order(piece_index)?
+        } else {
+            false
+        };
 
-        // Let's say the file starts at the beginning of the piece
+        // start of the block , the piece's start plus the offset (checked , the block has to fit the piece)
+        let block_start = self
+            .torrent
+            .layout()?
+            .block_start(piece_index.into(), offset as u64, data.len() as u64)?
+            .to_usize()?;
+        let block_end = (block_start + data.len()).min(self.total_length);
+        let affected_files: Vec<(usize, PathBuf, usize, usize)> = self
+            .get_affected_files(block_start, block_end)?
+            .into_iter()
+            .map(|(index, mapping, file_start, file_end)| {
+                (
+                    index,
+                    mapping.path.clone(),
+                    file_start - mapping.start_offset,
+                    file_end - file_start,
+                )
+            })
+            .collect();
+
+        // Let's say the file starts at the beginning of the block
         let mut offset = 0;
-        for (file_mapping, file_start, file_end) in affected_files {
-            // postion to start writing
-            let write_start = file_start - piece_start;
-            let write_length = file_end - file_start;
+        for (index, path, write_start, write_length) in affected_files {
+            // postion to start writing , relative to the start of the file
             let file_data = &data[offset..offset + write_length];
-            self.write_to_file(&file_mapping.path, write_start, file_data)?;
             offset += write_length;
+
+            // Skipped files lose their part of the block , the other files still get theirs
+            if self.file_map[index].quarantined.is_some() {
+                continue;
+            }
+
+            let written = if sequential {
+                self.write_sequential(&path, write_start, file_data)
+            } else {
+                self.write_to_file(&path, write_start, file_data)
+            };
+            self.record_write(index, written)?;
+        }
+
+        Ok(())
+    }
+
+    /// Counts failed writes per file , quarantining a file once it failed QUARANTINE_AFTER times in a row
+    ///
+    /// The write that quarantines a file is reported as done , so the piece isn't retried forever
+    fn record_write(
+        &mut self,
+        index: usize,
+        written: Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let mapping = &mut self.file_map[index];
+        match written {
+            Ok(()) => {
+                mapping.write_failures = 0;
+                Ok(())
+            }
+            Err(e) => {
+                mapping.write_failures += 1;
+                if mapping.write_failures < QUARANTINE_AFTER {
+                    return Err(e);
+                }
+
+                crate::log_line!(
+                    "⚠️  Skipping {} after {} failed writes : {}",
+                    mapping.path.display(),
+                    mapping.write_failures,
+                    e
+                );
+                mapping.quarantined = Some(e.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Stops writing a file , the rest of the torrent can still finish without it
+    pub fn quarantine_file(&mut self, index: usize, reason: &str) -> Result<(), anyhow::Error> {
+        let mapping = self
+            .file_map
+            .get_mut(index)
+            .ok_or_else(|| anyhow!("File index {} out of range", index))?;
+        mapping.quarantined = Some(reason.to_string());
+        Ok(())
+    }
+
+    /// Writes a quarantined file again , e.g after the path problem was fixed
+    pub fn release_file(&mut self, index: usize) -> Result<(), anyhow::Error> {
+        let mapping = self
+            .file_map
+            .get_mut(index)
+            .ok_or_else(|| anyhow!("File index {} out of range", index))?;
+        mapping.quarantined = None;
+        mapping.write_failures = 0;
+        Ok(())
+    }
+
+    /// Remembers how much the user wants a file , the block manager decides what gets downloaded
+    pub fn set_file_priority(
+        &mut self,
+        index: usize,
+        priority: FilePriority,
+    ) -> Result<(), anyhow::Error> {
+        let mapping = self
+            .file_map
+            .get_mut(index)
+            .ok_or_else(|| anyhow!("File index {} out of range", index))?;
+        mapping.priority = priority;
+        Ok(())
+    }
+
+    /// Files currently skipped , with their index in the file map
+    pub fn quarantined_files(&self) -> Vec<(usize, &FileMapping)> {
+        self.file_map
+            .iter()
+            .enumerate()
+            .filter(|(_, mapping)| mapping.quarantined.is_some())
+            .collect()
+    }
+
+    /// Whether writes are currently going through the sequential writer
+    pub fn is_sequential(&self) -> bool {
+        self.sequential_run >= SEQUENTIAL_THRESHOLD
+    }
+
+    /// Records the order pieces complete in and returns whether this write should be sequential
+    ///
+    /// Pieces completing back to back (streaming , a single fast peer) switch to buffered appends ,
+    /// the first out of order piece flushes the buffer and goes back to seeking per write
+    fn track_order(&mut self, piece_index: usize) -> Result<bool, anyhow::Error> {
+        if piece_index == self.next_piece {
+            self.sequential_run += 1;
+        } else {
+            self.sequential_run = 1;
+            self.flush()?;
+        }
+        self.next_piece = piece_index + 1;
+
+        Ok(self.is_sequential())
+    }
+
+    /// Appends through the buffered writer , reopening it when the write doesn't continue where the last one ended
+    fn write_sequential(
+        &self,
+        path: &Path,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        let mut writer = self.writer.lock().unwrap();
+
+        let continues = writer
+            .as_ref()
+            .is_some_and(|w| w.path == path && w.position == offset);
+
+        if !continues {
+            if let Some(mut old) = writer.take() {
+                old.file.flush()?;
+            }
+
+            let mut file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)?;
+            file.seek(SeekFrom::Start(offset as u64))?;
+
+            *writer = Some(SequentialWriter {
+                path: path.to_path_buf(),
+                file: BufWriter::with_capacity(SEQUENTIAL_BUFFER_SIZE, file),
+                position: offset,
+                _handle: Tracked::new(Resource::FileHandle),
+            });
+        }
+
+        if let Some(w) = writer.as_mut() {
+            w.file.write_all(data)?;
+            w.position += data.len();
         }
 
         Ok(())
     }
 
+    /// Writes out anything the sequential writer is holding
+    pub fn flush(&self) -> Result<(), anyhow::Error> {
+        if let Some(w) = self.writer.lock().unwrap().as_mut() {
+            w.file.flush()?;
+        }
+        Ok(())
+    }
+
     fn get_affected_files(
         &self,
         start: usize,
         end: usize,
-    ) -> Result<Vec<(&FileMapping, usize, usize)>, anyhow::Error> {
+    ) -> Result<Vec<(usize, &FileMapping, usize, usize)>, anyhow::Error> {
         let mut affected = Vec::new();
 
-        for mapping in &self.file_map {
+        for (index, mapping) in self.file_map.iter().enumerate() {
             let file_start = mapping.start_offset;
             let file_end = mapping.start_offset + mapping.length;
 
@@ -193,7 +425,7 @@ impl FileStorage {
             if start < file_end && end > file_start {
                 let overlap_start = start.max(file_start);
                 let overlap_end = end.min(file_end);
-                affected.push((mapping, overlap_start, overlap_end));
+                affected.push((index, mapping, overlap_start, overlap_end));
             }
         }
 
@@ -201,41 +433,59 @@ impl FileStorage {
     }
 
     pub fn read_piece(&self, piece_index: usize) -> Result<Vec<u8>, anyhow::Error> {
-        // Gets the piece start of the passed index
-        // The start of a piece multiplied by the defined piece_length (eg 2 * 45kb = 90kb)
-        let piece_start = piece_index * self.torrent.piece_length;
-
-        // Usuallly the last piece of a file is smaller , so we check if a pieces index is equal to that of the last element and if so we get its actual size
-        let piece_length = if piece_index == self.torrent.pieces.len() - 1 {
-            // Last piece is usually shorter
-            // eg (100kb - 90kb = 10kb)
-            self.total_length - piece_start
-        } else {
-            // Simply return the piece length
-            self.torrent.piece_length
-        };
+        // Usually the last piece is shorter , it gets whatever is left of the torrent
+        // eg (100kb - 90kb = 10kb)
+        let piece_length = self.torrent.layout()?.piece_size(piece_index.into())?;
 
-        // End of a piece (eg 19kb + 16kb = 35kb)
-        let piece_end = piece_start + piece_length;
-        // Offset to read the file from / Simply the position in the file to read from
+        self.read_block(piece_index, 0, piece_length as usize)
+    }
+
+    /// Reads `length` bytes at `offset` inside a piece
+    pub fn read_block(
+        &self,
+        piece_index: usize,
+        offset: usize,
+        length: usize,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        // Buffered sequential writes have to hit the file before we read it back
+        self.flush()?;
+
+        // Fails for blocks that don't fit the piece , instead of reading into the next one
+        let block_start = self
+            .torrent
+            .layout()?
+            .block_start(piece_index.into(), offset as u64, length as u64)?
+            .to_usize()?;
+        // End of the block (eg 19kb + 16kb = 35kb)
+        let block_end = block_start + length;
+
+        // Offset into our buffer
         let mut offset = 0;
 
         // Buffer to hold the data
-        let mut piece_data = vec![0u8; piece_length];
-        // This gets a list of all files (and the overlapping byte ranges) that this piece belongs to.
-        let affected_files = self.get_affected_files(piece_start, piece_end)?;
+        let mut block_data = vec![0u8; length];
+        // This gets a list of all files (and the overlapping byte ranges) that this block belongs to.
+        let affected_files = self.get_affected_files(block_start, block_end)?;
+
+        for (_, file_mapping, file_start, file_end) in affected_files {
+            if let Some(reason) = &file_mapping.quarantined {
+                return Err(anyhow!(
+                    "{} is skipped : {}",
+                    file_mapping.path.display(),
+                    reason
+                ));
+            }
 
-        for (file_mapping, file_start, file_end) in affected_files {
             let read_start = file_start - file_mapping.start_offset;
             let read_length = file_end - file_start;
 
             // Reads the file and we then push the data to our buffer
             let file_data = self.read_from_file(&file_mapping.path, read_start, read_length)?;
-            piece_data[offset..offset + read_length].copy_from_slice(&file_data);
+            block_data[offset..offset + read_length].copy_from_slice(&file_data);
             offset += read_length;
         }
 
-        Ok(piece_data)
+        Ok(block_data)
     }
 
     #[doc = r"Simply reads a file
@@ -0,0 +1,112 @@
+use crate::{
+    core::config::config_dir,
+    protocol::{bencode::BencodeValue, creator::SourceFile},
+    storage::resume::{Envelope, FileStamp},
+};
+use anyhow::{Result, anyhow};
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::PathBuf;
+
+/// Format tag of hash cache entries
+pub const HASH_CACHE_FORMAT: &str = "sekiro-hash-cache";
+
+pub const HASH_CACHE_VERSION: i64 = 1;
+
+/// Piece hashes of data we created torrents for before
+///
+/// Pieces run across file boundaries so the whole file set is one entry , keyed by every file's
+/// (path , mtime , size) plus the piece length. Touching any file changes the key and the data gets hashed again
+#[derive(Debug, Clone)]
+pub struct HashCache {
+    pub dir: PathBuf,
+}
+
+impl HashCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Cache under the config directory , None if there is no config directory
+    pub fn open_default() -> Option<Self> {
+        config_dir().map(|dir| Self::new(dir.join("hash-cache")))
+    }
+
+    /// Cached piece hashes for these files , None on a miss or if any file changed
+    pub fn get(&self, files: &[SourceFile], piece_length: usize) -> Option<Vec<[u8; 20]>> {
+        let key = cache_key(files, piece_length)?;
+        let bytes = fs::read(self.entry_path(&key)).ok()?;
+        let envelope = Envelope::decode(&bytes, HASH_CACHE_FORMAT).ok()?;
+
+        if envelope.version != HASH_CACHE_VERSION {
+            return None;
+        }
+
+        // Guard against hash collisions on the file name
+        let stored_key = envelope.chunks.get(b"key")?.as_bytes()?;
+        if stored_key.as_ref() != key.as_slice() {
+            return None;
+        }
+
+        let pieces = envelope.chunks.get(b"pieces")?.as_bytes()?;
+        if pieces.len() % 20 != 0 {
+            return None;
+        }
+
+        Some(
+            pieces
+                .chunks_exact(20)
+                .map(|chunk| chunk.try_into().unwrap())
+                .collect(),
+        )
+    }
+
+    pub fn put(
+        &self,
+        files: &[SourceFile],
+        piece_length: usize,
+        pieces: &[[u8; 20]],
+    ) -> Result<()> {
+        let key = cache_key(files, piece_length)
+            .ok_or_else(|| anyhow!("Files changed while they were being hashed"))?;
+
+        let chunks = BencodeValue::dict(vec![
+            (b"key", BencodeValue::bytes(&key)),
+            (b"pieces", BencodeValue::bytes(&pieces.concat())),
+        ]);
+        let envelope = Envelope::new(HASH_CACHE_FORMAT, HASH_CACHE_VERSION, chunks);
+
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.entry_path(&key), envelope.encode())?;
+        Ok(())
+    }
+
+    fn entry_path(&self, key: &[u8]) -> PathBuf {
+        self.dir
+            .join(format!("{}.cache", hex::encode(Sha1::digest(key))))
+    }
+}
+
+/// Fingerprint of a file set , None if a file can't be stamped
+fn cache_key(files: &[SourceFile], piece_length: usize) -> Option<Vec<u8>> {
+    let mut entries = vec![BencodeValue::Integer(piece_length as i64)];
+
+    for file in files {
+        // Pad files are zeros , only their size matters
+        if file.padding {
+            entries.push(BencodeValue::Integer(file.length as i64));
+            continue;
+        }
+
+        let path = fs::canonicalize(&file.full_path).ok()?;
+        let stamp = FileStamp::of(&path)?;
+
+        entries.push(BencodeValue::List(vec![
+            BencodeValue::bytes(path.to_string_lossy().as_bytes()),
+            BencodeValue::Integer(stamp.mtime as i64),
+            BencodeValue::Integer(stamp.length as i64),
+        ]));
+    }
+
+    Some(BencodeValue::List(entries).encode())
+}
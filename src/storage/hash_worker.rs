@@ -0,0 +1,249 @@
+use crate::core::clock::{SharedClock, system_clock};
+use crate::protocol::merkle::MerklePiece;
+use crate::storage::backend::{SharedStorage, Storage};
+use sha1::{Digest, Sha1};
+pub use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A finished piece waiting to be hashed and written
+#[derive(Debug)]
+pub struct HashJob {
+    pub piece_index: usize,
+    /// Expected SHA-1 of the piece
+    pub hash: [u8; 20],
+    /// Merkle hashes to check instead of `hash` , for v2 torrents
+    pub merkle: Option<MerklePiece>,
+    pub data: Vec<u8>,
+    /// Read the piece back from storage after writing it and hash it again
+    pub read_back: bool,
+}
+
+/// A verified piece to read back from storage and hash again , before it's uploaded
+#[derive(Debug)]
+pub struct CheckJob {
+    pub piece_index: usize,
+    pub length: usize,
+    pub hash: [u8; 20],
+    pub merkle: Option<MerklePiece>,
+}
+
+/// What the worker thread is handed
+#[derive(Debug)]
+enum Job {
+    Write(HashJob),
+    Check(CheckJob),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HashOutcome {
+    /// Hash matched and the piece is on disk
+    Verified,
+    /// Hash didn't match , nothing was written
+    HashMismatch,
+    /// Hash matched but writing the piece failed , or it didn't read back the same
+    WriteFailed(String),
+    /// A `CheckJob` found the stored piece intact
+    StoredIntact,
+    /// A `CheckJob` found the stored piece different , or couldn't read it
+    StoredCorrupt(String),
+}
+
+/// Completion event sent back by the worker
+#[derive(Debug, Clone)]
+pub struct HashResult {
+    pub piece_index: usize,
+    pub outcome: HashOutcome,
+}
+
+/// Background thread that verifies finished pieces and writes them to storage
+///
+/// SHA-1 over a multi megabyte piece takes long enough to hold up block processing , so the
+/// download path only hands pieces over and picks up the results later
+#[derive(Debug)]
+pub struct HashWorker {
+    jobs: Option<mpsc::Sender<Job>>,
+    results: mpsc::Receiver<HashResult>,
+    handle: Option<JoinHandle<()>>,
+    /// When the job being worked on was picked up , None while idle
+    busy_since: Arc<Mutex<Option<Instant>>>,
+    /// Stamps `busy_since` , shared with the worker thread so `set_clock` reaches it
+    clock: Arc<Mutex<SharedClock>>,
+}
+
+impl HashWorker {
+    pub fn spawn(storage: SharedStorage) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let (result_tx, result_rx) = mpsc::channel();
+        let busy_since = Arc::new(Mutex::new(None));
+        let busy = busy_since.clone();
+        let clock = Arc::new(Mutex::new(system_clock()));
+        let worker_clock = clock.clone();
+
+        let handle = thread::Builder::new()
+            .name(String::from("hash-worker"))
+            .spawn(move || {
+                while let Ok(job) = job_rx.recv() {
+                    let now = worker_clock.lock().unwrap().now();
+                    *busy.lock().unwrap() = Some(now);
+                    let result = match job {
+                        Job::Write(job) => HashResult {
+                            piece_index: job.piece_index,
+                            outcome: check_and_write(&storage, &job),
+                        },
+                        Job::Check(job) => HashResult {
+                            piece_index: job.piece_index,
+                            outcome: check_stored(&storage, &job),
+                        },
+                    };
+                    *busy.lock().unwrap() = None;
+
+                    // The manager went away , nobody is left to care
+                    if result_tx.send(result).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn hash worker thread");
+
+        Self {
+            jobs: Some(job_tx),
+            results: result_rx,
+            handle: Some(handle),
+            busy_since,
+            clock,
+        }
+    }
+
+    /// Replaces the clock `busy_for` is measured with
+    pub fn set_clock(&self, clock: SharedClock) {
+        *self.clock.lock().unwrap() = clock;
+    }
+
+    pub fn submit(&self, job: HashJob) -> anyhow::Result<()> {
+        self.send(Job::Write(job))
+    }
+
+    /// Queues a stored piece to be hashed again , the result comes back as StoredIntact or StoredCorrupt
+    pub fn check(&self, job: CheckJob) -> anyhow::Result<()> {
+        self.send(Job::Check(job))
+    }
+
+    fn send(&self, job: Job) -> anyhow::Result<()> {
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or_else(|| anyhow::anyhow!("Hash worker has stopped"))
+    }
+
+    /// Results that are ready , never blocks
+    pub fn try_results(&self) -> Vec<HashResult> {
+        self.results.try_iter().collect()
+    }
+
+    /// Blocks until the next result comes in , None if the worker has stopped
+    pub fn wait_result(&self) -> Option<HashResult> {
+        self.results.recv().ok()
+    }
+
+    /// Like `wait_result` but gives up after `timeout` , e.g when the disk under the worker hangs
+    pub fn wait_result_timeout(&self, timeout: Duration) -> Result<HashResult, RecvTimeoutError> {
+        self.results.recv_timeout(timeout)
+    }
+
+    /// How long the worker has been stuck on its current job , None while idle
+    pub fn busy_for(&self) -> Option<Duration> {
+        let now = self.clock.lock().unwrap().now();
+        self.busy_since
+            .lock()
+            .unwrap()
+            .map(|since| now.saturating_duration_since(since))
+    }
+}
+
+impl Drop for HashWorker {
+    fn drop(&mut self) {
+        // Closing the job channel ends the worker loop once the queue is drained
+        self.jobs.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Whether a piece's data is what the torrent says , by its merkle hashes when it has them
+pub fn piece_matches(data: &[u8], hash: &[u8; 20], merkle: Option<&MerklePiece>) -> bool {
+    match merkle {
+        Some(merkle) => merkle.verify(data),
+        None => Sha1::digest(data).as_slice() == hash,
+    }
+}
+
+/// `Storage::verify_piece` , against the merkle hashes for v2 torrents
+pub fn verify_stored(
+    storage: &dyn Storage,
+    piece_index: usize,
+    length: usize,
+    hash: &[u8; 20],
+    merkle: Option<&MerklePiece>,
+) -> anyhow::Result<bool> {
+    match merkle {
+        Some(merkle) => Ok(merkle.verify(&storage.read_block(piece_index, 0, length)?)),
+        None => storage.verify_piece(piece_index, length, hash),
+    }
+}
+
+fn check_stored(storage: &SharedStorage, job: &CheckJob) -> HashOutcome {
+    let storage = match storage.lock() {
+        Ok(storage) => storage,
+        Err(e) => return HashOutcome::StoredCorrupt(format!("read failed : {}", e)),
+    };
+    match verify_stored(
+        &**storage,
+        job.piece_index,
+        job.length,
+        &job.hash,
+        job.merkle.as_ref(),
+    ) {
+        Ok(true) => HashOutcome::StoredIntact,
+        Ok(false) => HashOutcome::StoredCorrupt(String::from("hash mismatch")),
+        Err(e) => HashOutcome::StoredCorrupt(format!("read failed : {}", e)),
+    }
+}
+
+fn check_and_write(storage: &SharedStorage, job: &HashJob) -> HashOutcome {
+    if !piece_matches(&job.data, &job.hash, job.merkle.as_ref()) {
+        return HashOutcome::HashMismatch;
+    }
+
+    let mut storage = match storage.lock() {
+        Ok(storage) => storage,
+        Err(e) => return HashOutcome::WriteFailed(e.to_string()),
+    };
+
+    if let Err(e) = storage.write_block(job.piece_index, 0, &job.data) {
+        return HashOutcome::WriteFailed(e.to_string());
+    }
+    if !job.read_back {
+        return HashOutcome::Verified;
+    }
+
+    // A flaky disk can accept a write and hand back something else , only reading tells
+    let read_back = storage.flush().and_then(|()| {
+        verify_stored(
+            &**storage,
+            job.piece_index,
+            job.data.len(),
+            &job.hash,
+            job.merkle.as_ref(),
+        )
+    });
+    match read_back {
+        Ok(true) => HashOutcome::Verified,
+        Ok(false) => HashOutcome::WriteFailed(String::from(
+            "piece read back different from what was written",
+        )),
+        Err(e) => HashOutcome::WriteFailed(format!("reading the piece back failed : {}", e)),
+    }
+}
@@ -1 +1,10 @@
-pub mod files;
\ No newline at end of file
+pub mod backend;
+pub mod breaker;
+pub mod disk_io;
+pub mod files;
+pub mod hash_cache;
+pub mod hash_worker;
+pub mod resume;
+pub mod scrub;
+pub mod spill;
+pub mod write_retry;
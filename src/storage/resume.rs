@@ -0,0 +1,439 @@
+use crate::{
+    core::{
+        config::config_dir,
+        events::{Event, EventBus},
+    },
+    protocol::{bencode::BencodeValue, torrent::Torrent},
+};
+use anyhow::{Result, anyhow};
+use sha1::{Digest, Sha1};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Format tag written into every resume file
+pub const RESUME_FORMAT: &str = "sekiro-resume";
+
+/// Current version of the resume file layout
+///
+/// Bump this whenever the chunks change and add a step to `migrate_resume` so older files still load
+pub const RESUME_VERSION: i64 = 1;
+
+/// Where resume files are kept , one per torrent. None if there is no config directory
+pub fn default_resume_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("resume"))
+}
+
+/// Resume file of a torrent inside `dir` , named after its info hash
+pub fn resume_path(dir: &Path, info_hash: &[u8; 20]) -> PathBuf {
+    dir.join(format!("{}.resume", hex::encode(info_hash)))
+}
+
+/// A versioned state file as it sits on disk
+///
+/// Resume and session files share this layout :
+///
+/// `d 6:chunks d...e 8:checksum 20:<sha1> 6:format <tag> 7:version i<n>e e`
+///
+/// The checksum covers the encoded chunks dictionary so a truncated or bit-flipped file is caught before we trust it
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub format: String,
+    pub version: i64,
+    /// Dictionary of named chunks , readers skip chunks they don't know about
+    pub chunks: BencodeValue,
+}
+
+impl Envelope {
+    pub fn new(format: &str, version: i64, chunks: BencodeValue) -> Self {
+        Self {
+            format: format.to_string(),
+            version,
+            chunks,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let chunk_bytes = self.chunks.encode();
+        let checksum = Sha1::digest(&chunk_bytes);
+
+        BencodeValue::dict(vec![
+            (b"chunks", self.chunks.clone()),
+            (b"checksum", BencodeValue::bytes(&checksum)),
+            (b"format", BencodeValue::bytes(self.format.as_bytes())),
+            (b"version", BencodeValue::Integer(self.version)),
+        ])
+        .encode()
+    }
+
+    /// Decodes an envelope and checks its format tag and checksum
+    pub fn decode(bytes: &[u8], expected_format: &str) -> Result<Self> {
+        let value = BencodeValue::decode(bytes)?;
+
+        let format = value
+            .get(b"format")
+            .and_then(|v| v.as_bytes())
+            .ok_or_else(|| anyhow!("State file has no format tag"))?;
+        if format.as_ref() != expected_format.as_bytes() {
+            return Err(anyhow!(
+                "Expected a {} file , found {}",
+                expected_format,
+                String::from_utf8_lossy(format)
+            ));
+        }
+
+        let version = value
+            .get(b"version")
+            .and_then(|v| v.as_integer())
+            .ok_or_else(|| anyhow!("State file has no version"))?;
+
+        let chunks = value
+            .get(b"chunks")
+            .cloned()
+            .ok_or_else(|| anyhow!("State file has no chunks"))?;
+
+        let checksum = value
+            .get(b"checksum")
+            .and_then(|v| v.as_bytes())
+            .ok_or_else(|| anyhow!("State file has no checksum"))?;
+        if Sha1::digest(chunks.encode()).as_slice() != checksum.as_ref() {
+            return Err(anyhow!("State file checksum mismatch , file is corrupt"));
+        }
+
+        Ok(Self {
+            format: expected_format.to_string(),
+            version,
+            chunks,
+        })
+    }
+}
+
+/// `path` with a suffix added to its file name , e.g `resume.dat` -> `resume.dat.bak`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Where the previous generation of a state file is kept
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, ".bak")
+}
+
+/// Writes a state file so a crash never leaves it half written
+///
+/// The bytes go to a synced temp file first , then the current file becomes the backup and the temp
+/// file takes its place. Rename is atomic , so at any point either the old or the new file is whole
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp = sibling(path, ".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+
+    if path.exists() {
+        fs::rename(path, backup_path(path))?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// A state file read by `read_with_backup`
+#[derive(Debug)]
+pub struct StateRead<T> {
+    pub value: T,
+    /// Why the current file was passed over for the backup , None when it loaded fine
+    pub fallback_reason: Option<String>,
+}
+
+/// Reads and decodes a state file , falling back to the backup when the current file is missing or
+/// doesn't decode (e.g a crash between the two renames of `write_atomic` , or a corrupt file)
+///
+/// The error is the current file's when neither can be used
+pub fn read_with_backup<T>(
+    path: &Path,
+    decode: impl Fn(&[u8]) -> Result<T>,
+) -> Result<StateRead<T>> {
+    let current = fs::read(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| decode(&bytes));
+    let error = match current {
+        Ok(value) => {
+            return Ok(StateRead {
+                value,
+                fallback_reason: None,
+            });
+        }
+        Err(e) => e,
+    };
+
+    let backup = backup_path(path);
+    match fs::read(&backup).map(|bytes| decode(&bytes)) {
+        Ok(Ok(value)) => {
+            crate::log_line!(
+                "{} is unusable ({}) , using the backup {}",
+                path.display(),
+                error,
+                backup.display()
+            );
+            Ok(StateRead {
+                value,
+                fallback_reason: Some(error.to_string()),
+            })
+        }
+        _ => Err(error),
+    }
+}
+
+/// Size and modification time of a file when the resume data was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub length: u64,
+    /// Seconds since the unix epoch
+    pub mtime: u64,
+}
+
+impl FileStamp {
+    /// Stamps a file on disk , None if it doesn't exist
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Some(Self {
+            length: metadata.len(),
+            mtime,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Everything we need to pick a torrent back up without re-hashing it
+pub struct ResumeData {
+    pub info_hash: [u8; 20],
+    pub piece_count: usize,
+    /// Verified pieces , packed high bit first like the wire bitfield
+    pub verified: Vec<u8>,
+    /// One stamp per file in the torrent , in file order
+    pub files: Vec<Option<FileStamp>>,
+    pub uploaded: u64,
+    pub downloaded: u64,
+}
+
+/// What happened when we tried to load resume data
+#[derive(Debug)]
+pub enum ResumeLoad {
+    Resumed(ResumeData),
+    /// Resume data was missing or unusable , the torrent has to be fully rechecked
+    Recheck(String),
+}
+
+impl ResumeData {
+    pub fn new(info_hash: [u8; 20], piece_count: usize) -> Self {
+        Self {
+            info_hash,
+            piece_count,
+            verified: vec![0u8; piece_count.div_ceil(8)],
+            files: Vec::new(),
+            uploaded: 0,
+            downloaded: 0,
+        }
+    }
+
+    pub fn has_piece(&self, index: usize) -> bool {
+        if index >= self.piece_count {
+            return false;
+        }
+        self.verified[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    pub fn set_piece(&mut self, index: usize) {
+        if index < self.piece_count {
+            self.verified[index / 8] |= 0x80 >> (index % 8);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let files = self
+            .files
+            .iter()
+            .map(|stamp| match stamp {
+                Some(stamp) => BencodeValue::dict(vec![
+                    (b"length", BencodeValue::Integer(stamp.length as i64)),
+                    (b"mtime", BencodeValue::Integer(stamp.mtime as i64)),
+                ]),
+                // Missing files are written as an empty dictionary
+                None => BencodeValue::dict(vec![]),
+            })
+            .collect();
+
+        let chunks = BencodeValue::dict(vec![
+            (
+                b"info",
+                BencodeValue::dict(vec![
+                    (b"info hash", BencodeValue::bytes(&self.info_hash)),
+                    (
+                        b"piece count",
+                        BencodeValue::Integer(self.piece_count as i64),
+                    ),
+                ]),
+            ),
+            (b"pieces", BencodeValue::bytes(&self.verified)),
+            (b"files", BencodeValue::List(files)),
+            (
+                b"stats",
+                BencodeValue::dict(vec![
+                    (b"downloaded", BencodeValue::Integer(self.downloaded as i64)),
+                    (b"uploaded", BencodeValue::Integer(self.uploaded as i64)),
+                ]),
+            ),
+        ]);
+
+        Envelope::new(RESUME_FORMAT, RESUME_VERSION, chunks).encode()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let envelope = Envelope::decode(bytes, RESUME_FORMAT)?;
+        let chunks = migrate_resume(envelope.version, envelope.chunks)?;
+
+        let info = chunks
+            .get(b"info")
+            .ok_or_else(|| anyhow!("Resume data has no info chunk"))?;
+        let hash_bytes = info
+            .get(b"info hash")
+            .and_then(|v| v.as_bytes())
+            .filter(|b| b.len() == 20)
+            .ok_or_else(|| anyhow!("Resume data has an invalid info hash"))?;
+        let mut info_hash = [0u8; 20];
+        info_hash.copy_from_slice(hash_bytes);
+
+        let piece_count = info
+            .get(b"piece count")
+            .and_then(|v| v.as_integer())
+            .filter(|&n| n >= 0)
+            .ok_or_else(|| anyhow!("Resume data has an invalid piece count"))?
+            as usize;
+
+        let verified = chunks
+            .get(b"pieces")
+            .and_then(|v| v.as_bytes())
+            .ok_or_else(|| anyhow!("Resume data has no pieces chunk"))?
+            .to_vec();
+        if verified.len() != piece_count.div_ceil(8) {
+            return Err(anyhow!(
+                "Resume bitfield is {} bytes , expected {}",
+                verified.len(),
+                piece_count.div_ceil(8)
+            ));
+        }
+
+        let mut files = Vec::new();
+        if let Some(list) = chunks.get(b"files").and_then(|v| v.as_list()) {
+            for entry in list {
+                let length = entry.get(b"length").and_then(|v| v.as_integer());
+                let mtime = entry.get(b"mtime").and_then(|v| v.as_integer());
+                files.push(match (length, mtime) {
+                    (Some(length), Some(mtime)) => Some(FileStamp {
+                        length: length as u64,
+                        mtime: mtime as u64,
+                    }),
+                    _ => None,
+                });
+            }
+        }
+
+        let stats = chunks.get(b"stats");
+        let stat = |key: &[u8]| {
+            stats
+                .and_then(|s| s.get(key))
+                .and_then(|v| v.as_integer())
+                .unwrap_or(0) as u64
+        };
+
+        Ok(Self {
+            info_hash,
+            piece_count,
+            verified,
+            files,
+            uploaded: stat(b"uploaded"),
+            downloaded: stat(b"downloaded"),
+        })
+    }
+
+    /// Loads resume data for a torrent , any problem with the file means a full recheck instead of an error
+    ///
+    /// A corrupt file is passed over for the previous generation when that one is still good
+    pub fn load(path: &Path, torrent: &Torrent) -> ResumeLoad {
+        if !path.exists() && !backup_path(path).exists() {
+            return ResumeLoad::Recheck("No resume data".to_string());
+        }
+
+        let data = match read_with_backup(path, Self::from_bytes) {
+            Ok(read) => read.value,
+            Err(e) => return ResumeLoad::Recheck(format!("Resume data unusable : {}", e)),
+        };
+
+        if data.info_hash != torrent.info_hash {
+            return ResumeLoad::Recheck("Resume data belongs to another torrent".to_string());
+        }
+
+        if data.piece_count != torrent.piece_count() {
+            return ResumeLoad::Recheck(format!(
+                "Resume data has {} pieces , torrent has {}",
+                data.piece_count,
+                torrent.piece_count()
+            ));
+        }
+
+        ResumeLoad::Resumed(data)
+    }
+
+    /// Like `load` , but unusable resume data is also reported on the bus so the UI can warn that the
+    /// torrent is being rechecked. A torrent that never had resume data rechecks quietly
+    pub fn load_or_warn(path: &Path, torrent: &Torrent, events: &EventBus) -> ResumeLoad {
+        let existed = path.exists() || backup_path(path).exists();
+        let load = Self::load(path, torrent);
+        if let ResumeLoad::Recheck(reason) = &load
+            && existed
+        {
+            crate::log_line!("{} , rechecking {}", reason, torrent.name);
+            events.publish(Event::ResumeDataRejected {
+                path: path.to_path_buf(),
+                reason: reason.clone(),
+            });
+        }
+        load
+    }
+
+    /// Writes the resume file atomically , the previous one is kept as backup
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomic(path, &self.to_bytes())
+    }
+}
+
+/// Upgrades resume chunks written by an older version to the current layout
+///
+/// Each step takes chunks at version `n` and returns them at `n + 1`
+fn migrate_resume(version: i64, chunks: BencodeValue) -> Result<BencodeValue> {
+    match version {
+        RESUME_VERSION => Ok(chunks),
+        v if v > RESUME_VERSION => Err(anyhow!(
+            "Resume data version {} is newer than this client supports ({})",
+            v,
+            RESUME_VERSION
+        )),
+        // No older layouts exist yet , when the format changes add an arm here that
+        // upgrades version n chunks to n + 1 and calls migrate_resume again
+        v => Err(anyhow!("Unknown resume data version {}", v)),
+    }
+}
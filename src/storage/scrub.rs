@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+/// Default scrub speed , slow enough to go unnoticed next to seeding
+pub const DEFAULT_SCRUB_PIECES_PER_MINUTE: u32 = 10;
+
+/// Paces background re-verification of pieces we already have
+///
+/// Walks the torrent front to back and starts over at the end , handing out `pieces_per_minute`
+/// pieces spread over each minute so the disk never sees a burst
+#[derive(Debug, Clone)]
+pub struct ScrubSchedule {
+    pub pieces_per_minute: u32,
+    /// Next piece to look at
+    cursor: usize,
+    /// Pieces earned but not yet handed out , fractional between ticks
+    budget: f64,
+    last_tick: Option<Instant>,
+    /// How many full passes over the torrent have finished
+    pub passes: u64,
+}
+
+impl ScrubSchedule {
+    pub fn new(pieces_per_minute: u32) -> Self {
+        Self {
+            pieces_per_minute: pieces_per_minute.max(1),
+            cursor: 0,
+            budget: 0.0,
+            last_tick: None,
+            passes: 0,
+        }
+    }
+
+    /// Pieces to verify now , out of `total_pieces`
+    pub fn due(&mut self, now: Instant, total_pieces: usize) -> Vec<usize> {
+        if total_pieces == 0 {
+            return Vec::new();
+        }
+
+        let elapsed = match self.last_tick {
+            Some(last) => now.duration_since(last),
+            None => Duration::ZERO,
+        };
+        self.last_tick = Some(now);
+
+        // A long gap (suspend , stalled loop) doesn't turn into a burst , at most one minute's worth
+        let earned = (elapsed.as_secs_f64() / 60.0).min(1.0) * self.pieces_per_minute as f64;
+        self.budget = (self.budget + earned).min(self.pieces_per_minute as f64);
+
+        let mut due = Vec::new();
+        while self.budget >= 1.0 {
+            due.push(self.cursor);
+            self.budget -= 1.0;
+
+            self.cursor += 1;
+            if self.cursor >= total_pieces {
+                self.cursor = 0;
+                self.passes += 1;
+            }
+        }
+        due
+    }
+
+    /// Where the scrub is in the current pass
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+}
+
+impl Default for ScrubSchedule {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCRUB_PIECES_PER_MINUTE)
+    }
+}
@@ -0,0 +1,154 @@
+use crate::protocol::bencode::BencodeValue;
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the hidden directory partial pieces get spilled into
+pub const SPILL_DIR_NAME: &str = ".sekiro-spill";
+
+/// Blocks of a piece that was only partly downloaded when we shut down
+#[derive(Debug, Clone)]
+pub struct SpilledPiece {
+    pub index: usize,
+    /// Hash of the piece , used to make sure the spill still belongs to the same piece
+    pub hash: [u8; 20],
+    /// (begin , data) of every block we had
+    pub blocks: Vec<(usize, Vec<u8>)>,
+}
+
+/// Directory where unverified blocks are kept between runs
+///
+/// Every torrent gets its own folder (named after the info hash) with one `<piece>.part` file per piece
+#[derive(Debug, Clone)]
+pub struct SpillArea {
+    pub dir: PathBuf,
+}
+
+impl SpillArea {
+    pub fn new(download_dir: &Path, info_hash: &[u8; 20]) -> Self {
+        Self {
+            dir: download_dir
+                .join(SPILL_DIR_NAME)
+                .join(hex::encode(info_hash)),
+        }
+    }
+
+    /// A spill area in `dir` itself , e.g a spool directory outside the download dir
+    pub fn at(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn piece_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{}.part", index))
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.piece_path(index).exists()
+    }
+
+    pub fn write(&self, piece: &SpilledPiece) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let blocks = piece
+            .blocks
+            .iter()
+            .map(|(begin, data)| {
+                BencodeValue::dict(vec![
+                    (b"begin", BencodeValue::Integer(*begin as i64)),
+                    (b"data", BencodeValue::bytes(data)),
+                ])
+            })
+            .collect();
+
+        let encoded = BencodeValue::dict(vec![
+            (b"blocks", BencodeValue::List(blocks)),
+            (b"hash", BencodeValue::bytes(&piece.hash)),
+            (b"piece", BencodeValue::Integer(piece.index as i64)),
+        ])
+        .encode();
+
+        fs::write(self.piece_path(piece.index), encoded)?;
+        Ok(())
+    }
+
+    /// Reads back the blocks of one piece
+    pub fn read(&self, index: usize) -> Result<SpilledPiece> {
+        Self::read_piece(&self.piece_path(index))
+    }
+
+    /// Deletes the blocks of one piece , nothing to do if there are none
+    pub fn remove(&self, index: usize) -> Result<()> {
+        match fs::remove_file(self.piece_path(index)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads every spilled piece , files that can't be parsed are skipped
+    pub fn read_all(&self) -> Result<Vec<SpilledPiece>> {
+        let mut pieces = Vec::new();
+        if !self.dir.exists() {
+            return Ok(pieces);
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("part") {
+                continue;
+            }
+
+            match Self::read_piece(&path) {
+                Ok(piece) => pieces.push(piece),
+                Err(e) => crate::log_line!("Skipping spill file {} : {}", path.display(), e),
+            }
+        }
+
+        Ok(pieces)
+    }
+
+    fn read_piece(path: &Path) -> Result<SpilledPiece> {
+        let value = BencodeValue::decode(&fs::read(path)?)?;
+
+        let index = value
+            .get(b"piece")
+            .and_then(|v| v.as_integer())
+            .filter(|&i| i >= 0)
+            .ok_or_else(|| anyhow!("Spill file has no piece index"))? as usize;
+
+        let hash_bytes = value
+            .get(b"hash")
+            .and_then(|v| v.as_bytes())
+            .filter(|b| b.len() == 20)
+            .ok_or_else(|| anyhow!("Spill file has no piece hash"))?;
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(hash_bytes);
+
+        let mut blocks = Vec::new();
+        for block in value
+            .get(b"blocks")
+            .and_then(|v| v.as_list())
+            .ok_or_else(|| anyhow!("Spill file has no blocks"))?
+        {
+            let begin = block.get(b"begin").and_then(|v| v.as_integer());
+            let data = block.get(b"data").and_then(|v| v.as_bytes());
+            if let (Some(begin), Some(data)) = (begin, data) {
+                blocks.push((begin as usize, data.to_vec()));
+            }
+        }
+
+        Ok(SpilledPiece {
+            index,
+            hash,
+            blocks,
+        })
+    }
+
+    /// Removes the spill area once its contents have been taken back
+    pub fn clear(&self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+}
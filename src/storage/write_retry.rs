@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Most bytes of verified pieces held in memory while their writes are retried
+pub const DEFAULT_RETRY_BUFFER: usize = 64 * 1024 * 1024;
+/// Writes tried per piece before it leaves memory
+pub const DEFAULT_MAX_WRITE_ATTEMPTS: u32 = 4;
+/// Wait before the first retry , doubled after every failure
+pub const BASE_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Longest wait between retries , spooled pieces are retried this often
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// What to do with a verified piece whose write just failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryPlan {
+    /// Keep the data in memory and write it again at the given time
+    Buffer(Instant),
+    /// Move the data to the spool directory and write it from there at the given time
+    Spool(Instant),
+    /// Nothing left to try , the piece has to be downloaded again
+    GiveUp,
+}
+
+#[derive(Debug, Clone)]
+struct RetryEntry {
+    attempts: u32,
+    retry_at: Instant,
+    /// Bytes held in memory , 0 once spooled
+    bytes: usize,
+    spooled: bool,
+}
+
+/// Verified pieces whose writes failed , and when to try each again
+///
+/// A write that fails once is usually a hiccup (full disk being cleaned up , a mount coming back) ,
+/// so the data is kept and written again with backoff instead of throwing a good piece away. Memory
+/// is bounded : pieces that don't fit or ran out of attempts go to the spool when there is one
+#[derive(Debug, Clone)]
+pub struct WriteRetries {
+    entries: BTreeMap<usize, RetryEntry>,
+    limit: usize,
+    max_attempts: u32,
+}
+
+impl Default for WriteRetries {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETRY_BUFFER)
+    }
+}
+
+impl WriteRetries {
+    /// Holds at most `limit` bytes in memory
+    pub fn new(limit: usize) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            limit,
+            max_attempts: DEFAULT_MAX_WRITE_ATTEMPTS,
+        }
+    }
+
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Counts a failed write of a `bytes` long piece and says what to do with its data
+    ///
+    /// `can_spool` tells whether there is a spool directory to fall back on
+    pub fn failed(
+        &mut self,
+        index: usize,
+        bytes: usize,
+        now: Instant,
+        can_spool: bool,
+    ) -> RetryPlan {
+        let others = self.buffered_bytes() - self.entries.get(&index).map_or(0, |e| e.bytes);
+        let entry = self.entries.entry(index).or_insert(RetryEntry {
+            attempts: 0,
+            retry_at: now,
+            bytes: 0,
+            spooled: false,
+        });
+        entry.attempts += 1;
+
+        // Already safe in the spool , only the next try moves
+        if entry.spooled {
+            entry.bytes = 0;
+            entry.retry_at = now + MAX_RETRY_DELAY;
+            return RetryPlan::Spool(entry.retry_at);
+        }
+
+        if entry.attempts < self.max_attempts && others + bytes <= self.limit {
+            entry.bytes = bytes;
+            entry.retry_at = now + backoff(entry.attempts);
+            return RetryPlan::Buffer(entry.retry_at);
+        }
+
+        if can_spool {
+            entry.bytes = 0;
+            entry.spooled = true;
+            entry.retry_at = now + MAX_RETRY_DELAY;
+            return RetryPlan::Spool(entry.retry_at);
+        }
+
+        self.entries.remove(&index);
+        RetryPlan::GiveUp
+    }
+
+    /// Pieces due for another write , (index , whether its data is in the spool)
+    pub fn due(&self, now: Instant) -> Vec<(usize, bool)> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.retry_at <= now)
+            .map(|(&index, entry)| (index, entry.spooled))
+            .collect()
+    }
+
+    /// Spooled data was read back into memory for a retry
+    pub fn unspooled(&mut self, index: usize, bytes: usize) {
+        if let Some(entry) = self.entries.get_mut(&index) {
+            entry.bytes = bytes;
+        }
+    }
+
+    /// Drops a piece once it's written (or no longer wanted) , Some(whether its data was in the
+    /// spool) if it was waiting here
+    pub fn remove(&mut self, index: usize) -> Option<bool> {
+        self.entries.remove(&index).map(|entry| entry.spooled)
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.entries.contains_key(&index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Bytes of pieces held in memory for a retry
+    pub fn buffered_bytes(&self) -> usize {
+        self.entries.values().map(|entry| entry.bytes).sum()
+    }
+
+    /// Pieces whose data waits in the spool
+    pub fn spooled(&self) -> usize {
+        self.entries.values().filter(|entry| entry.spooled).count()
+    }
+}
+
+fn backoff(attempts: u32) -> Duration {
+    BASE_RETRY_DELAY
+        .checked_mul(1 << attempts.saturating_sub(1).min(16))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
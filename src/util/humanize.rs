@@ -0,0 +1,53 @@
+//! Sizes , durations and rates the way people read them
+//!
+//! Sizes use binary units (1 KiB = 1024 bytes) with one decimal , durations keep their two largest
+//! units. Everything the TUI , CLI and log messages show goes through here so the same number
+//! always reads the same
+
+use std::time::Duration;
+
+const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// `1536` -> `"1.5 KiB"` , plain bytes below 1 KiB
+pub fn bytes(count: u64) -> String {
+    if count < 1024 {
+        return format!("{} B", count);
+    }
+
+    let mut value = count as f64 / 1024.0;
+    let mut unit = 0;
+    // 1023.96 would print as 1024.0 , move up a unit instead
+    while value >= 1023.95 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Bytes per second -> `"3.2 MiB/s"`
+pub fn rate(bytes_per_sec: f64) -> String {
+    format!("{}/s", bytes(bytes_per_sec.max(0.0) as u64))
+}
+
+/// `8040` -> `"2h 14m"` , the two largest units
+pub fn seconds(secs: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    match secs {
+        s if s < MINUTE => format!("{}s", s),
+        s if s < HOUR => format!("{}m {}s", s / MINUTE, s % MINUTE),
+        s if s < DAY => format!("{}h {}m", s / HOUR, s % HOUR / MINUTE),
+        s => format!("{}d {}h", s / DAY, s % DAY / HOUR),
+    }
+}
+
+pub fn duration(duration: Duration) -> String {
+    seconds(duration.as_secs())
+}
+
+/// Time left , `"unknown"` while nothing is coming in
+pub fn eta(secs: Option<u64>) -> String {
+    secs.map(seconds).unwrap_or_else(|| "unknown".to_string())
+}
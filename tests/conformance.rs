@@ -0,0 +1,428 @@
+//! Protocol conformance vectors
+//!
+//! Every file under `tests/fixtures` is bytes as they appear on the wire or on disk , checked against
+//! the parse we expect. Info hashes and piece hashes were worked out independently of this crate
+//! (plain SHA-1 / SHA-256 over the raw info dictionary and the source data) so a regression in the
+//! parsers can't hide behind a matching regression in the creator
+
+use bytes::Bytes;
+use mini_p2p_file_transfer_system::{
+    net::tracker::{Tracker, parse_compact_peers},
+    protocol::{
+        handshake::{Extensions, HANDSHAKE_LEN, Handshake},
+        info_hash::InfoHashes,
+        magnet::MagnetUri,
+        message::{MessageDecoder, PeerMessage},
+        peer::{PeerHost, PeerSource},
+        torrent::{Torrent, TorrentVersion},
+    },
+    storage::resume::{ResumeData, ResumeLoad},
+};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    fs::read(&path).unwrap_or_else(|e| panic!("{} : {}", path.display(), e))
+}
+
+fn hash20(hex: &str) -> [u8; 20] {
+    hex::decode(hex).unwrap().try_into().unwrap()
+}
+
+fn hash32(hex: &str) -> [u8; 32] {
+    hex::decode(hex).unwrap().try_into().unwrap()
+}
+
+const V1_SINGLE_HASH: &str = "c2c3c235e033663a9e4d9982068452eb62e065e9";
+
+#[test]
+fn handshake_parses_reserved_bits_and_ids() {
+    let bytes = fixture("handshake.bin");
+    assert_eq!(bytes.len(), HANDSHAKE_LEN);
+
+    let handshake = Handshake::parse(&bytes).unwrap();
+    assert_eq!(handshake.info_hash, hash20(V1_SINGLE_HASH));
+    assert_eq!(&handshake.peer_id, b"-qB4630-abcdefghijkl");
+    assert_eq!(
+        handshake.extensions(),
+        Extensions {
+            extension_protocol: true,
+            dht: true,
+            fast: true,
+        }
+    );
+    // Bits we don't know (Azureus messaging here) are kept as sent
+    assert_eq!(handshake.reserved, [0x80, 0, 0, 0, 0, 0x10, 0, 0x05]);
+    assert_eq!(handshake.to_bytes().as_slice(), bytes.as_slice());
+}
+
+#[test]
+fn handshake_rejects_other_protocols() {
+    let mut bytes = fixture("handshake.bin");
+    bytes[1..20].copy_from_slice(b"BitTorrent protocoX");
+    assert!(Handshake::parse(&bytes).is_err());
+    assert!(Handshake::parse(&fixture("handshake.bin")[..HANDSHAKE_LEN - 1]).is_err());
+}
+
+fn expected_messages() -> Vec<PeerMessage> {
+    vec![
+        PeerMessage::KeepAlive,
+        PeerMessage::Choke,
+        PeerMessage::Unchoke,
+        PeerMessage::Interested,
+        PeerMessage::NotInterested,
+        PeerMessage::Have { index: 7 },
+        PeerMessage::Bitfield(Bytes::from_static(&[0xe0])),
+        PeerMessage::Request {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        },
+        PeerMessage::Piece {
+            index: 2,
+            begin: 0,
+            block: Bytes::from_static(b"hello"),
+        },
+        PeerMessage::Cancel {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        },
+        PeerMessage::Port(6881),
+        PeerMessage::Extended {
+            id: 0,
+            payload: Bytes::from_static(b"d1:md11:ut_metadatai3eee"),
+        },
+    ]
+}
+
+fn decode_all(chunks: impl Iterator<Item = Vec<u8>>) -> Vec<PeerMessage> {
+    let mut decoder = MessageDecoder::new();
+    let mut messages = Vec::new();
+    for chunk in chunks {
+        decoder.feed(&chunk);
+        while let Some(message) = decoder.next_message().unwrap() {
+            messages.push(message);
+        }
+    }
+    assert_eq!(decoder.buffered(), 0);
+    messages
+}
+
+#[test]
+fn message_sequence_decodes_whatever_the_chunking() {
+    let stream = fixture("messages.bin");
+
+    for chunk_size in [1, 3, 7, 64, stream.len()] {
+        let chunks = stream.chunks(chunk_size).map(<[u8]>::to_vec);
+        assert_eq!(
+            decode_all(chunks),
+            expected_messages(),
+            "chunks of {}",
+            chunk_size
+        );
+    }
+}
+
+#[test]
+fn message_sequence_encodes_back_to_the_same_bytes() {
+    let encoded: Vec<u8> = expected_messages()
+        .iter()
+        .flat_map(|message| message.encode().to_vec())
+        .collect();
+    assert_eq!(encoded, fixture("messages.bin"));
+}
+
+#[test]
+fn malformed_messages_are_errors() {
+    for name in ["message_bad_have.bin", "message_unknown_id.bin"] {
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&fixture(name));
+        assert!(decoder.next_message().is_err(), "{}", name);
+    }
+}
+
+fn tracker() -> Tracker {
+    Tracker::new(String::from("http://tracker.example/announce"))
+}
+
+#[test]
+fn compact_tracker_response() {
+    let response = tracker()
+        .parse_tracker_response(&fixture("tracker_compact.bencode"))
+        .unwrap();
+
+    assert_eq!(response.interval, 1800);
+    assert_eq!(response.min_interval, Some(900));
+    assert_eq!(response.complete, Some(5));
+    assert_eq!(response.incomplete, Some(3));
+    assert_eq!(response.tracker_id.as_deref(), Some("abc123"));
+
+    let peers: Vec<Option<SocketAddr>> = response.peers.iter().map(|p| p.socket_addr()).collect();
+    assert_eq!(
+        peers,
+        vec![
+            Some("10.0.0.1:6881".parse().unwrap()),
+            Some("192.168.1.20:51413".parse().unwrap()),
+            Some("[2001:db8::1]:6881".parse().unwrap()),
+        ]
+    );
+    assert!(
+        response
+            .peers
+            .iter()
+            .all(|peer| peer.peer_id.is_none() && peer.source == PeerSource::Tracker)
+    );
+}
+
+#[test]
+fn dictionary_tracker_response() {
+    let response = tracker()
+        .parse_tracker_response(&fixture("tracker_dict.bencode"))
+        .unwrap();
+
+    assert_eq!(response.interval, 900);
+    assert_eq!(response.peers.len(), 2);
+
+    let first = &response.peers[0];
+    assert_eq!(first.host, PeerHost::Ip(IpAddr::from([127, 0, 0, 1])));
+    assert_eq!(first.port, 6881);
+    assert_eq!(first.peer_id, Some(*b"-TR2940-xxxxxxxxxxxx"));
+
+    let second = &response.peers[1];
+    assert_eq!(
+        second.host,
+        PeerHost::Name(String::from("seed.example.org"))
+    );
+    assert_eq!(second.port, 51413);
+    assert_eq!(second.peer_id, None);
+}
+
+#[test]
+fn tracker_failure_and_garbage() {
+    let error = tracker()
+        .parse_tracker_response(&fixture("tracker_failure.bencode"))
+        .unwrap_err();
+    assert!(error.to_string().contains("torrent not found"), "{}", error);
+
+    assert!(
+        tracker()
+            .parse_tracker_response(b"<html>502 Bad Gateway</html>")
+            .is_err()
+    );
+}
+
+#[test]
+fn compact_peer_list_drops_trailing_partial_entry() {
+    let peers = parse_compact_peers(&fixture("compact_peers.bin"), false);
+    let addrs: Vec<Option<SocketAddr>> = peers.iter().map(|p| p.socket_addr()).collect();
+    assert_eq!(
+        addrs,
+        vec![
+            Some("10.0.0.1:6881".parse().unwrap()),
+            Some("192.168.1.20:51413".parse().unwrap()),
+        ]
+    );
+}
+
+#[test]
+fn magnet_uris() {
+    let text = String::from_utf8(fixture("magnets.txt")).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+
+    let hex = MagnetUri::parse(lines[0]).unwrap();
+    assert_eq!(hex.info_hash, hash20(V1_SINGLE_HASH));
+    assert_eq!(hex.display_name.as_deref(), Some("alpha.bin"));
+    assert_eq!(hex.trackers, vec!["http://tracker.example/announce"]);
+    assert!(hex.peers.is_empty());
+    assert_eq!(MagnetUri::parse(&hex.to_uri()).unwrap(), hex);
+
+    // Same hash in base32 , numbered trackers with a duplicate and a peer to dial
+    let base32 = MagnetUri::parse(lines[1]).unwrap();
+    assert_eq!(base32.info_hash, hash20(V1_SINGLE_HASH));
+    assert_eq!(base32.display_name.as_deref(), Some("Some Name"));
+    assert_eq!(
+        base32.trackers,
+        vec!["udp://a.example:1337", "udp://b.example:1337"]
+    );
+    assert_eq!(
+        base32.peers,
+        vec!["10.0.0.1:6881".parse::<SocketAddr>().unwrap()]
+    );
+
+    assert!(MagnetUri::parse(lines[2]).is_err());
+}
+
+#[test]
+fn v1_single_file_torrent() {
+    let bytes = fixture("v1_single.torrent");
+    let torrent = Torrent::from_bytes(&bytes).unwrap();
+
+    assert_eq!(torrent.announce, "http://tracker.example/announce");
+    assert_eq!(torrent.name, "alpha.bin");
+    assert_eq!(torrent.length, 70000);
+    assert_eq!(torrent.piece_length, 32768);
+    assert!(torrent.files.is_none());
+    assert_eq!(torrent.info_hash, hash20(V1_SINGLE_HASH));
+    assert_eq!(torrent.info_hash_v2, None);
+    assert_eq!(
+        torrent.pieces,
+        vec![
+            hash20("05ce0abc2e37766ee861fbf8e24e42ceb322c7be"),
+            torrent.pieces[1],
+            hash20("cf6c71b0eb4c646b6cb34d822f6c1fbd13f955cf"),
+        ]
+    );
+
+    // Re-encoding keeps the info dictionary byte for byte
+    assert_eq!(torrent.to_bytes(), bytes);
+}
+
+#[test]
+fn v1_multi_file_torrent() {
+    let torrent = Torrent::from_bytes(&fixture("v1_multi.torrent")).unwrap();
+
+    assert_eq!(torrent.name, "set");
+    assert_eq!(torrent.length, 70000);
+    assert_eq!(torrent.pieces.len(), 3);
+    assert_eq!(
+        torrent.pieces[0],
+        hash20("be43ca060500cebf2d7a4dcd5b6db80a6be58a6b")
+    );
+    assert_eq!(
+        torrent.info_hash,
+        hash20("3ca748dccbe8563fe0572bcd2598a86f07743747")
+    );
+
+    let files: Vec<(Vec<String>, usize)> = torrent
+        .files
+        .unwrap()
+        .into_iter()
+        .map(|file| (file.path, file.length))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (vec![String::from("a.txt")], 20000),
+            (vec![String::from("sub"), String::from("b.bin")], 50000),
+        ]
+    );
+}
+
+#[test]
+fn v2_torrent_info_hashes() {
+    let hashes = InfoHashes::of_metainfo(&fixture("v2_multi.torrent")).unwrap();
+
+    let v2 = hash32("4eec7bf4b0649c405fd7fc445217dd3eb98772d475791d22817c65dead241de6");
+    assert_eq!(hashes.v1, None);
+    assert_eq!(hashes.v2, Some(v2));
+    assert!(!hashes.is_hybrid());
+    // The wire protocol only has room for 20 bytes
+    assert_eq!(
+        hashes.announce_hashes(),
+        vec![hashes.v2_truncated().unwrap()]
+    );
+    assert_eq!(hashes.primary()[..], v2[..20]);
+}
+
+#[test]
+fn v2_torrent() {
+    let bytes = fixture("v2_multi.torrent");
+    let torrent = Torrent::from_bytes(&bytes).unwrap();
+    let v2 = hash32("4eec7bf4b0649c405fd7fc445217dd3eb98772d475791d22817c65dead241de6");
+
+    assert_eq!(torrent.version, TorrentVersion::V2);
+    assert_eq!(torrent.name, "set");
+    assert_eq!(torrent.length, 70000);
+    assert_eq!(torrent.info_hash_v2, Some(v2));
+    assert_eq!(torrent.info_hash[..], v2[..20]);
+    assert_eq!(torrent.info_hashes().v1, None);
+    assert!(torrent.pieces.is_empty());
+    // Pieces don't span files , a.txt takes one and sub/b.bin two
+    assert_eq!(torrent.piece_count(), 3);
+
+    let tree = torrent.file_tree.as_ref().unwrap();
+    let files: Vec<(Vec<String>, usize)> = tree
+        .iter()
+        .map(|file| (file.path.clone(), file.length))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (vec![String::from("a.txt")], 20000),
+            (vec![String::from("sub"), String::from("b.bin")], 50000),
+        ]
+    );
+    assert_eq!(torrent.files.as_ref().unwrap().len(), 2);
+
+    // Only sub/b.bin is bigger than a piece , so only it has a piece layer
+    assert_eq!(torrent.piece_layers.len(), 1);
+    let layer = &torrent.piece_layers[&tree[1].pieces_root.unwrap()];
+    assert_eq!(layer.len(), 2);
+
+    assert_eq!(torrent.to_bytes(), bytes);
+}
+
+#[test]
+fn v2_torrent_resume_data_reloads() {
+    let torrent = Torrent::from_bytes(&fixture("v2_multi.torrent")).unwrap();
+    let mut data = ResumeData::new(torrent.info_hash, torrent.piece_count());
+    data.set_piece(0);
+    data.set_piece(2);
+
+    let path = std::env::temp_dir().join(format!("sekiro-v2-resume-{}", std::process::id()));
+    data.save(&path).unwrap();
+    let load = ResumeData::load(&path, &torrent);
+    fs::remove_file(&path).unwrap();
+
+    // v2 only torrents have no v1 piece list , the count comes from the file tree
+    match load {
+        ResumeLoad::Resumed(data) => {
+            assert!(data.has_piece(0) && !data.has_piece(1) && data.has_piece(2));
+        }
+        ResumeLoad::Recheck(reason) => panic!("Resume data rejected : {}", reason),
+    }
+}
+
+#[test]
+fn hybrid_torrent() {
+    let bytes = fixture("hybrid_multi.torrent");
+    let v1 = hash20("f998119582ca9c8c620bb3b4fca6ad77e1f6992b");
+    let v2 = hash32("f3770832ddfcb2500c76bba885054e95cf957d4e980628cbd704404bdc5689c3");
+
+    let hashes = InfoHashes::of_metainfo(&bytes).unwrap();
+    assert_eq!(hashes, InfoHashes::new(Some(v1), Some(v2)));
+    assert!(hashes.is_hybrid());
+    assert_eq!(
+        hashes.announce_hashes(),
+        vec![v1, hashes.v2_truncated().unwrap()]
+    );
+
+    let torrent = Torrent::from_bytes(&bytes).unwrap();
+    assert_eq!(torrent.version, TorrentVersion::Hybrid);
+    assert_eq!(torrent.info_hash, v1);
+    assert_eq!(torrent.info_hash_v2, Some(v2));
+    // The file tree has no pad files
+    assert_eq!(torrent.file_tree.as_ref().unwrap().len(), 2);
+    // v1 view of the data , a pad file lines sub/b.bin up with the second piece
+    assert_eq!(torrent.length, 32768 + 50000);
+    assert_eq!(torrent.pieces.len(), 3);
+    let files: Vec<(Vec<String>, usize)> = torrent
+        .files
+        .unwrap()
+        .into_iter()
+        .map(|file| (file.path, file.length))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            (vec![String::from("a.txt")], 20000),
+            (vec![String::from(".pad"), String::from("12768")], 12768),
+            (vec![String::from("sub"), String::from("b.bin")], 50000),
+        ]
+    );
+}
@@ -0,0 +1,104 @@
+//! Message stream encryption between our own two sides
+//!
+//! Both ends of the handshake run over loopback , so the key exchange only works out when both
+//! derive the same secret and keep their ciphers in step
+
+use mini_p2p_file_transfer_system::{
+    core::runtime::TokioRuntime,
+    net::{connect::ConnectionSetup, encryption::ConnectMode, peer_connection::PeerConnection},
+    protocol::{handshake::Handshake, message::PeerMessage},
+};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+const INFO_HASH: [u8; 20] = [7; 20];
+const WAIT: Duration = Duration::from_secs(5);
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// Takes one encrypted connection on `listener` , answering for INFO_HASH
+async fn accept_encrypted(listener: &TcpListener) -> PeerConnection {
+    let (stream, addr) = listener.accept().await.unwrap();
+    let runtime = TokioRuntime::new();
+    let answer = Handshake::new(INFO_HASH, [2; 20]);
+    let established = ConnectionSetup::accept_encrypted(
+        &runtime,
+        Box::new(stream),
+        addr,
+        &[[1; 20], INFO_HASH],
+        |_| Some(answer),
+    )
+    .await
+    .unwrap();
+    assert_eq!(established.mode, ConnectMode::Encrypted);
+    PeerConnection::start(established)
+}
+
+#[test]
+fn encrypted_connection_carries_messages_both_ways() {
+    runtime().block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepting = tokio::spawn(async move { accept_encrypted(&listener).await });
+
+        let runtime = TokioRuntime::new();
+        let ours = Handshake::new(INFO_HASH, [1; 20]);
+        let established =
+            ConnectionSetup::connect_in_mode(&runtime, addr, &ours, None, ConnectMode::Encrypted)
+                .await
+                .unwrap();
+        assert_eq!(established.mode, ConnectMode::Encrypted);
+        assert_eq!(established.remote.peer_id, [2; 20]);
+        let mut dialed = PeerConnection::start(established);
+        let mut accepted = accepting.await.unwrap();
+
+        let have = PeerMessage::Have { index: 42 };
+        dialed.send(PeerMessage::Interested).await.unwrap();
+        dialed.send(have.clone()).await.unwrap();
+        accepted.send(PeerMessage::Unchoke).await.unwrap();
+
+        let received = timeout(WAIT, accepted.recv()).await.unwrap();
+        assert_eq!(received, Some(PeerMessage::Interested));
+        assert_eq!(timeout(WAIT, accepted.recv()).await.unwrap(), Some(have));
+        let received = timeout(WAIT, dialed.recv()).await.unwrap();
+        assert_eq!(received, Some(PeerMessage::Unchoke));
+    });
+}
+
+#[test]
+fn peer_that_drops_plaintext_is_retried_encrypted() {
+    runtime().block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepting = tokio::spawn(async move {
+            // An encryption only peer hangs up on the plaintext handshake
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut first = [0u8; 20];
+            stream.read_exact(&mut first).await.unwrap();
+            assert_eq!(&first[1..20], b"BitTorrent protocol");
+            drop(stream);
+            accept_encrypted(&listener).await
+        });
+
+        let runtime = TokioRuntime::new();
+        let ours = Handshake::new(INFO_HASH, [1; 20]);
+        let attempts = ConnectionSetup::connect_trying(
+            &runtime,
+            addr,
+            &ours,
+            None,
+            &[ConnectMode::Plaintext, ConnectMode::Encrypted],
+        )
+        .await;
+        assert_eq!(attempts.rejected, vec![ConnectMode::Plaintext]);
+        assert_eq!(attempts.result.unwrap().mode, ConnectMode::Encrypted);
+        accepting.await.unwrap();
+    });
+}
@@ -0,0 +1,78 @@
+//! Teardown leaves nothing behind
+//!
+//! The resource counts are process wide , so everything lives in one test : tests running in
+//! parallel would see each other's connections and files
+
+use mini_p2p_file_transfer_system::{
+    core::{peer::Peer, resources::ResourceUsage},
+    net::{connect::ConnectionSetup, peer_connection::PeerConnection},
+    protocol::{creator::TorrentCreator, handshake::Handshake},
+    storage::files::{FileStorage, SEQUENTIAL_THRESHOLD},
+};
+use std::fs;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+const PIECE_LENGTH: usize = 16 * 1024;
+const GRACE: Duration = Duration::from_secs(5);
+
+#[test]
+fn teardown_releases_connections_tasks_and_files() {
+    assert!(ResourceUsage::now().is_idle());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let handshake = Handshake::new([7; 20], [1; 20]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepting = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let answer = Handshake::new([7; 20], [2; 20]);
+            PeerConnection::start(
+                ConnectionSetup::accept(stream, |_| Some(answer))
+                    .await
+                    .unwrap(),
+            )
+        });
+
+        let dialed = PeerConnection::dial(&Peer::new(addr, Instant::now()), &handshake, None)
+            .await
+            .unwrap();
+        let accepted = accepting.await.unwrap();
+        let usage = ResourceUsage::now();
+        assert_eq!(usage.sockets, 2);
+        assert_eq!(usage.tasks, 4);
+
+        drop(dialed);
+        drop(accepted);
+        let usage = ResourceUsage::settled(GRACE).await;
+        assert!(usage.is_idle(), "{}", usage);
+    });
+
+    let dir = std::env::temp_dir().join(format!("sekiro-resources-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let pieces = SEQUENTIAL_THRESHOLD + 2;
+    fs::write(dir.join("data.bin"), vec![1u8; pieces * PIECE_LENGTH]).unwrap();
+    let torrent = TorrentCreator::new(dir.join("data.bin"), "http://tracker.invalid/announce")
+        .with_piece_length(PIECE_LENGTH)
+        .create()
+        .unwrap();
+
+    // Pieces written back to back switch to the buffered writer , which keeps its file open
+    let mut storage = FileStorage::from(torrent, dir.join("download"));
+    fs::create_dir_all(dir.join("download")).unwrap();
+    for index in 0..pieces {
+        storage
+            .write_block(index, 0, &vec![1u8; PIECE_LENGTH])
+            .unwrap();
+    }
+    assert!(storage.is_sequential());
+    assert_eq!(ResourceUsage::now().file_handles, 1);
+
+    drop(storage);
+    assert!(ResourceUsage::now().is_idle());
+    fs::remove_dir_all(&dir).unwrap();
+}
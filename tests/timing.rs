@@ -0,0 +1,112 @@
+//! Timeouts run on the engine's clock
+//!
+//! A MockClock stands in for the real one , so time is moved forward by hand and nothing sleeps
+
+use mini_p2p_file_transfer_system::{
+    core::{
+        bind::BindTarget,
+        clock::{MockClock, SharedClock},
+        runtime::{BoxFuture, DatagramSocket, PeerStream, Runtime, TaskHandle, TokioRuntime},
+    },
+    net::{block_manager::BlockManager, piece_manager::PIECE_TIMEOUT},
+    protocol::torrent::Torrent,
+    storage::backend::MemoryStorage,
+};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn torrent() -> Torrent {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/v1_single.torrent");
+    Torrent::from_bytes(&fs::read(path).unwrap()).unwrap()
+}
+
+/// Tokio underneath , keeping the names of the threads the engine asked for
+#[derive(Debug)]
+struct CountingRuntime {
+    inner: TokioRuntime,
+    threads: Mutex<Vec<String>>,
+}
+
+impl Runtime for CountingRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) -> Box<dyn TaskHandle> {
+        self.inner.spawn(task)
+    }
+
+    fn spawn_blocking(&self, name: &str, work: Box<dyn FnOnce() + Send>) -> io::Result<()> {
+        self.threads.lock().unwrap().push(String::from(name));
+        self.inner.spawn_blocking(name, work)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.inner.sleep(duration)
+    }
+
+    fn clock(&self) -> SharedClock {
+        self.inner.clock()
+    }
+
+    fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        bind: Option<BindTarget>,
+    ) -> BoxFuture<'static, io::Result<Box<dyn PeerStream>>> {
+        self.inner.connect_tcp(addr, bind)
+    }
+
+    fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        bind: Option<BindTarget>,
+    ) -> BoxFuture<'static, io::Result<Box<dyn DatagramSocket>>> {
+        self.inner.bind_udp(addr, bind)
+    }
+}
+
+#[test]
+fn stalled_piece_is_requeued_once_the_clock_passes_the_timeout() {
+    let clock = MockClock::new();
+    let torrent = torrent();
+    let pieces = torrent.piece_count();
+    let storage = Box::new(MemoryStorage::new(torrent.clone()));
+    let mut manager = BlockManager::with_storage(torrent, storage)
+        .unwrap()
+        .with_clock(Arc::new(clock.clone()));
+
+    let index = manager.get_next_piece_to_download().unwrap();
+    assert!(manager.get_next_block_request(index).is_some());
+
+    clock.advance(PIECE_TIMEOUT);
+    assert!(manager.reassign_stalled_pieces().is_empty());
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(manager.reassign_stalled_pieces(), vec![index]);
+    // Picking took the piece out of the queue , it's only handed out again because it was requeued.
+    // The picker breaks ties at random , so it may not come back first
+    assert!((0..pieces).any(|_| manager.get_next_piece_to_download() == Some(index)));
+    assert!(manager.get_next_block_request(index).is_some());
+}
+
+#[test]
+fn block_manager_runs_on_the_runtime_it_is_given() {
+    let clock = MockClock::new();
+    let runtime = Arc::new(CountingRuntime {
+        inner: TokioRuntime::new().with_clock(Arc::new(clock.clone())),
+        threads: Mutex::new(Vec::new()),
+    });
+    let torrent = torrent();
+    let storage = Box::new(MemoryStorage::new(torrent.clone()));
+    let mut manager = BlockManager::with_storage(torrent, storage)
+        .unwrap()
+        .with_runtime(runtime.clone());
+    assert_eq!(*runtime.threads.lock().unwrap(), vec!["disk-io"]);
+
+    // Timeouts follow the runtime's clock
+    let index = manager.get_next_piece_to_download().unwrap();
+    assert!(manager.get_next_block_request(index).is_some());
+    clock.advance(PIECE_TIMEOUT + Duration::from_secs(1));
+    assert_eq!(manager.reassign_stalled_pieces(), vec![index]);
+}